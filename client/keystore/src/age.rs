@@ -0,0 +1,117 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! X25519 identities for age file encryption, derived from stored keys.
+//!
+//! The identity is derived from the raw secret key with HMAC-SHA256 keyed by a domain
+//! separator, so it reveals nothing about the signing key and is only used for key agreement.
+//! Identities and recipients are bech32 encoded like the `age` tool expects them.
+
+use bech32::{ToBase32, Variant};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Domain separator of the derivation, never used for anything else.
+const DOMAIN: &[u8] = b"substrate-keystore/age-x25519/v1";
+
+const SECRET_KEY_HRP: &str = "age-secret-key-";
+const RECIPIENT_HRP: &str = "age";
+
+/// An age X25519 identity, see [`LocalKeystore::age_identity_from`].
+///
+/// [`LocalKeystore::age_identity_from`]: crate::LocalKeystore::age_identity_from
+pub struct AgeIdentity {
+	secret: Zeroizing<[u8; 32]>,
+}
+
+impl AgeIdentity {
+	/// Derive the identity of a raw secret key.
+	pub(crate) fn derive(secret: &[u8]) -> Self {
+		let mut mac = Hmac::<Sha256>::new_varkey(DOMAIN).expect("HMAC takes keys of any size; qed");
+		mac.update(secret);
+		let mut key = Zeroizing::new([0u8; 32]);
+		key.copy_from_slice(&mac.finalize().into_bytes());
+		Self { secret: key }
+	}
+
+	/// The X25519 public key.
+	pub fn public_key(&self) -> [u8; 32] {
+		x25519_public(&self.secret)
+	}
+
+	/// The recipient to encrypt to, `age1...`.
+	pub fn to_recipient(&self) -> String {
+		bech32(RECIPIENT_HRP, &self.public_key())
+	}
+
+	/// The identity to decrypt with, `AGE-SECRET-KEY-1...`, as read by `age --identity`.
+	pub fn to_secret_string(&self) -> Zeroizing<String> {
+		Zeroizing::new(bech32(SECRET_KEY_HRP, &self.secret[..]).to_uppercase())
+	}
+}
+
+/// The X25519 public key of the secret key, as defined in RFC 7748.
+pub(crate) fn x25519_public(secret: &[u8; 32]) -> [u8; 32] {
+	PublicKey::from(&StaticSecret::from(*secret)).to_bytes()
+}
+
+/// The X25519 shared secret of the secret key and the public key of the other party.
+pub(crate) fn x25519_shared(secret: &[u8; 32], public: &[u8; 32]) -> Zeroizing<[u8; 32]> {
+	let shared = StaticSecret::from(*secret).diffie_hellman(&PublicKey::from(*public));
+	Zeroizing::new(shared.to_bytes())
+}
+
+/// Encode the data as BIP-173 bech32 string with the human readable part `hrp`.
+fn bech32(hrp: &str, data: &[u8]) -> String {
+	bech32::encode(hrp, data.to_base32(), Variant::Bech32)
+		.expect("the human readable parts are valid; qed")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encodings_match_the_specifications() {
+		// RFC 7748, section 6.1.
+		let alice = "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+		let mut secret = [0u8; 32];
+		secret.copy_from_slice(&hex::decode(alice).unwrap());
+		assert_eq!(
+			hex::encode(x25519_public(&secret)),
+			"8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a",
+		);
+		let bob = "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f";
+		let mut bob_public = [0u8; 32];
+		bob_public.copy_from_slice(&hex::decode(bob).unwrap());
+		assert_eq!(
+			hex::encode(&x25519_shared(&secret, &bob_public)[..]),
+			"4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+		);
+		// BIP-173 test vectors.
+		assert_eq!(bech32("a", &[]), "a12uel5l");
+		let data = hex::decode("00443214c74254b635cf84653a56d7c675be77df").unwrap();
+		assert_eq!(bech32("abcdef", &data), "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw");
+
+		let identity = AgeIdentity::derive(b"secret");
+		assert!(identity.to_recipient().starts_with("age1"));
+		assert!(identity.to_secret_string().starts_with("AGE-SECRET-KEY-1"));
+	}
+}
@@ -0,0 +1,438 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Read-only keystore of key files baked into a tar or zip archive.
+//!
+//! Archives are read with the `tar` and `zip` crates, zip entries may be stored or deflated.
+//! Files that are not key files, like directories and entries of other types, are skipped.
+
+use std::{collections::HashMap, io::Read};
+use async_trait::async_trait;
+use sp_core::crypto::{CryptoTypePublicPair, KeyTypeId, SecretString};
+use sp_application_crypto::{ed25519, sr25519, ecdsa, AppKey, AppPair};
+use sp_keystore::{
+	CryptoStore, Error as TraitError, SyncCryptoStore,
+	vrf::{VRFTranscriptData, VRFSignature},
+};
+use zeroize::Zeroizing;
+
+use crate::{Error, LocalKeystore, Result, local::parse_key_file};
+
+/// Upper bound of a decompressed zip entry, key files are tiny.
+const MAX_ENTRY_LEN: u64 = 1024 * 1024;
+
+/// A keystore serving the key files of an archive, which is never modified.
+///
+/// Meant for immutable deployments that ship their keys with the node image, in an archive
+/// whose integrity is checked before it is handed to the keystore. The archive is read once at
+/// construction: entries named like key files, in any directory of the archive, are the keys.
+/// Listing keys and signing work like with a [`LocalKeystore`], everything that would change
+/// the keys fails with [`Error::ReadOnly`].
+pub struct ArchiveKeystore(LocalKeystore);
+
+impl ArchiveKeystore {
+	/// Load the key files of a tar archive.
+	///
+	/// The password is the one the keys were stored with, see [`LocalKeystore::open`].
+	pub fn from_tar(archive: &[u8], password: Option<SecretString>) -> Result<Self> {
+		Self::from_entries(tar_entries(archive)?, password)
+	}
+
+	/// Load the key files of a zip archive.
+	///
+	/// The password is the one the keys were stored with, see [`LocalKeystore::open`].
+	pub fn from_zip(archive: &[u8], password: Option<SecretString>) -> Result<Self> {
+		Self::from_entries(zip_entries(archive)?, password)
+	}
+
+	fn from_entries(
+		entries: Vec<(String, Zeroizing<Vec<u8>>)>,
+		password: Option<SecretString>,
+	) -> Result<Self> {
+		let mut keys = HashMap::new();
+		for (path, content) in entries {
+			let name = path.rsplit('/').next().unwrap_or_default();
+			let key = match hex::decode(name) {
+				Ok(key) if key.len() > 4 && name.bytes().all(|b| !b.is_ascii_uppercase()) => key,
+				_ => continue,
+			};
+			let mut key_type = KeyTypeId::default();
+			key_type.0.copy_from_slice(&key[0..4]);
+			let (suri, _) = parse_key_file(&content)
+				.map_err(|e| invalid(&format!("key file {}: {}", path, e)))?;
+			keys.insert((key_type, key[4..].to_vec()), suri.to_string());
+		}
+		Ok(Self(LocalKeystore::in_memory_with_keys(keys, password)))
+	}
+
+	/// Get a key pair for the given public key.
+	///
+	/// Returns `Ok(None)` if the archive doesn't hold the key.
+	pub fn key_pair<Pair: AppPair>(&self, public: &<Pair as AppKey>::Public) -> Result<Option<Pair>> {
+		self.0.key_pair(public)
+	}
+}
+
+fn invalid(reason: &str) -> Error {
+	Error::InvalidArchive(reason.into())
+}
+
+/// Returns the files of a tar archive, by path.
+fn tar_entries(archive: &[u8]) -> Result<Vec<(String, Zeroizing<Vec<u8>>)>> {
+	let tar_error = |e: std::io::Error| invalid(&format!("malformed tar archive: {}", e));
+	let mut entries = Vec::new();
+	for entry in tar::Archive::new(archive).entries().map_err(tar_error)? {
+		let mut entry = entry.map_err(tar_error)?;
+		// Regular files, other entry types like directories or links are skipped.
+		if !entry.header().entry_type().is_file() {
+			continue;
+		}
+		let path = entry.path()
+			.map_err(tar_error)?
+			.to_str()
+			.ok_or_else(|| invalid("tar file name is not UTF-8"))?
+			.to_owned();
+		let mut content = Zeroizing::new(Vec::new());
+		entry.read_to_end(&mut content).map_err(tar_error)?;
+		entries.push((path, content));
+	}
+	Ok(entries)
+}
+
+/// Returns the files of a zip archive, by path.
+///
+/// Entries are checked against their CRC32 while they are read.
+fn zip_entries(archive: &[u8]) -> Result<Vec<(String, Zeroizing<Vec<u8>>)>> {
+	let zip_error = |e: zip::result::ZipError| invalid(&format!("malformed zip archive: {}", e));
+	let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive)).map_err(zip_error)?;
+	let mut entries = Vec::with_capacity(zip.len());
+	for index in 0..zip.len() {
+		let file = zip.by_index(index).map_err(zip_error)?;
+		if file.is_dir() {
+			continue;
+		}
+		if file.size() > MAX_ENTRY_LEN {
+			return Err(invalid(&format!("zip file {} is too large", file.name())));
+		}
+		let path = file.name().to_owned();
+		let mut content = Zeroizing::new(Vec::new());
+		file.take(MAX_ENTRY_LEN)
+			.read_to_end(&mut content)
+			.map_err(|e| invalid(&format!("zip file {}: {}", path, e)))?;
+		entries.push((path, content));
+	}
+	Ok(entries)
+}
+
+#[async_trait]
+impl CryptoStore for ArchiveKeystore {
+	async fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::keys(self, id)
+	}
+
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		SyncCryptoStore::sr25519_public_keys(self, id)
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		SyncCryptoStore::sr25519_generate_new(self, id, seed)
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		SyncCryptoStore::ed25519_public_keys(self, id)
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		SyncCryptoStore::ed25519_generate_new(self, id, seed)
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		SyncCryptoStore::ecdsa_public_keys(self, id)
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		SyncCryptoStore::ecdsa_generate_new(self, id, seed)
+	}
+
+	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> std::result::Result<(), ()> {
+		SyncCryptoStore::insert_unknown(self, id, suri, public)
+	}
+
+	async fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		SyncCryptoStore::remove_key(self, id, public)
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		SyncCryptoStore::has_keys(self, public_keys)
+	}
+
+	async fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		SyncCryptoStore::key_types(self)
+	}
+
+	async fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		SyncCryptoStore::fingerprint(self)
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::supported_keys(self, id, keys)
+	}
+
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::authority_keys(self, id, candidates)
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		SyncCryptoStore::sign_with(self, id, key, msg)
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		SyncCryptoStore::sr25519_vrf_sign(self, key_type, public, transcript_data)
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		SyncCryptoStore::ecdsa_sign_prehashed(self, id, public, msg)
+	}
+}
+
+impl SyncCryptoStore for ArchiveKeystore {
+	fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::keys(&self.0, id)
+	}
+
+	fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		SyncCryptoStore::sr25519_public_keys(&self.0, id)
+	}
+
+	fn sr25519_generate_new(
+		&self,
+		_id: KeyTypeId,
+		_seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		Err(Error::ReadOnly.into())
+	}
+
+	fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		SyncCryptoStore::ed25519_public_keys(&self.0, id)
+	}
+
+	fn ed25519_generate_new(
+		&self,
+		_id: KeyTypeId,
+		_seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		Err(Error::ReadOnly.into())
+	}
+
+	fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		SyncCryptoStore::ecdsa_public_keys(&self.0, id)
+	}
+
+	fn ecdsa_generate_new(
+		&self,
+		_id: KeyTypeId,
+		_seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		Err(Error::ReadOnly.into())
+	}
+
+	fn insert_unknown(
+		&self,
+		_key_type: KeyTypeId,
+		_suri: &str,
+		_public: &[u8],
+	) -> std::result::Result<(), ()> {
+		Err(())
+	}
+
+	fn remove_key(
+		&self,
+		_id: KeyTypeId,
+		_public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		Err(Error::ReadOnly.into())
+	}
+
+	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		SyncCryptoStore::has_keys(&self.0, public_keys)
+	}
+
+	fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		SyncCryptoStore::key_types(&self.0)
+	}
+
+	fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		SyncCryptoStore::fingerprint(&self.0)
+	}
+
+	fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::supported_keys(&self.0, id, keys)
+	}
+
+	fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::authority_keys(&self.0, id, candidates)
+	}
+
+	fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		SyncCryptoStore::sign_with(&self.0, id, key, msg)
+	}
+
+	fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		SyncCryptoStore::sr25519_vrf_sign(&self.0, key_type, public, transcript_data)
+	}
+
+	fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		SyncCryptoStore::ecdsa_sign_prehashed(&self.0, id, public, msg)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::{Pair, testing::{ED25519, SR25519}};
+
+	/// A key file of the key the secret URI derives to, named like the keystore names it.
+	fn key_file<P: Pair>(id: KeyTypeId, suri: &str) -> (String, Vec<u8>, P::Public) {
+		let public = P::from_string(suri, None).unwrap().public();
+		let name = hex::encode([&id.0[..], public.as_ref()].concat());
+		(format!("keys/{}", name), serde_json::to_vec(suri).unwrap(), public)
+	}
+
+	fn tar(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+		let mut builder = tar::Builder::new(Vec::new());
+		for (path, content) in files {
+			let mut header = tar::Header::new_ustar();
+			header.set_size(content.len() as u64);
+			header.set_mode(0o600);
+			builder.append_data(&mut header, path, &content[..]).unwrap();
+		}
+		builder.into_inner().unwrap()
+	}
+
+	fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+		use std::io::Write;
+
+		let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+		let options = zip::write::FileOptions::default()
+			.compression_method(zip::CompressionMethod::Deflated);
+		for (path, content) in files {
+			writer.start_file(path.as_str(), options).unwrap();
+			writer.write_all(content).unwrap();
+		}
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn archives_are_read_only_keystores() {
+		let (sr25519_path, sr25519_file, alice) = key_file::<sr25519::Pair>(SR25519, "//Alice");
+		let (ed25519_path, ed25519_file, bob) = key_file::<ed25519::Pair>(ED25519, "//Bob");
+		let files = [
+			(sr25519_path, sr25519_file),
+			(ed25519_path, ed25519_file),
+			("README".to_owned(), b"not a key".to_vec()),
+		];
+
+		for store in [
+			ArchiveKeystore::from_tar(&tar(&files), None).unwrap(),
+			ArchiveKeystore::from_zip(&zip(&files), None).unwrap(),
+		] {
+			assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![alice]);
+			assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, ED25519), vec![bob]);
+			let key = bob.into();
+			let signature = SyncCryptoStore::sign_with(&store, ED25519, &key, b"msg")
+				.unwrap()
+				.unwrap();
+			assert!(SyncCryptoStore::verify(&store, ED25519, &key, b"msg", &signature).unwrap());
+			assert!(matches!(
+				SyncCryptoStore::ed25519_generate_new(&store, ED25519, None),
+				Err(TraitError::ReadOnly),
+			));
+			assert!(matches!(
+				SyncCryptoStore::remove_key(&store, ED25519, &key),
+				Err(TraitError::ReadOnly),
+			));
+		}
+
+		// Flip a bit of the content of the first file.
+		let mut corrupt = zip(&files);
+		corrupt[30 + files[0].0.len() + 2] ^= 1;
+		assert!(matches!(ArchiveKeystore::from_zip(&corrupt, None), Err(Error::InvalidArchive(_))));
+	}
+}
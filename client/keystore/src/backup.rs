@@ -0,0 +1,452 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Encrypted keystore backups.
+//!
+//! A backup is a header followed by the SCALE encoded list of keys, encrypted with
+//! ChaCha20-Poly1305. The encryption key is derived from the backup password with
+//! PBKDF2-HMAC-SHA256 and the header is authenticated along with the keys.
+//!
+//! The header is the magic `skbk`, the format version, a flags byte, the salt, the number of
+//! KDF rounds as little endian `u32` and the nonce.
+//!
+//! A backup with the manifest flag is followed by a manifest listing the public of every key
+//! with a MAC over the key, so [`verify`] can check a backup without importing it. The manifest
+//! is followed by its MAC, its length as little endian `u32` and the magic `skmf`. All MACs are
+//! HMAC-SHA256 keyed with a key derived from the backup key.
+
+use std::io::Read;
+use chacha20poly1305::{
+	ChaCha20Poly1305, Key, Nonce,
+	aead::{Aead, NewAead, Payload},
+};
+use codec::{Decode, Encode, Input, Output};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{RngCore, rngs::OsRng};
+use sha2::Sha256;
+use sp_core::crypto::{ExposeSecret, KeyTypeId, SecretString};
+use zeroize::Zeroizing;
+
+use crate::{Result, Error};
+
+const MAGIC: &[u8; 4] = b"skbk";
+const VERSION: u8 = 1;
+/// The keys were compressed with zstd before they were encrypted.
+const FLAG_COMPRESSED: u8 = 0b1;
+pub(crate) const KDF_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 2 + SALT_LEN + 4 + NONCE_LEN;
+/// Upper bound of the decompressed keys, protecting the import against decompression bombs.
+const MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+/// The zstd compression level.
+const COMPRESSION_LEVEL: i32 = 3;
+/// The backup is followed by a manifest.
+const FLAG_MANIFEST: u8 = 0b10;
+const MANIFEST_MAGIC: &[u8; 4] = b"skmf";
+const MAC_LEN: usize = 32;
+/// Domain of the key the manifest MACs are keyed with.
+const MANIFEST_DOMAIN: &[u8] = b"keystore backup manifest";
+
+/// A key in a backup.
+pub(crate) struct BackupEntry {
+	pub key_type: KeyTypeId,
+	pub public: Vec<u8>,
+	pub suri: Zeroizing<String>,
+}
+
+impl Encode for BackupEntry {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.key_type.encode_to(dest);
+		self.public.encode_to(dest);
+		self.suri.as_str().encode_to(dest);
+	}
+}
+
+impl Decode for BackupEntry {
+	fn decode<I: Input>(input: &mut I) -> std::result::Result<Self, codec::Error> {
+		Ok(BackupEntry {
+			key_type: Decode::decode(input)?,
+			public: Decode::decode(input)?,
+			suri: Zeroizing::new(Decode::decode(input)?),
+		})
+	}
+}
+
+/// A key listed in the manifest of a backup.
+#[derive(Encode, Decode)]
+struct ManifestEntry {
+	key_type: KeyTypeId,
+	public: Vec<u8>,
+	mac: [u8; MAC_LEN],
+}
+
+/// The integrity of a backup, see [`verify`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+	/// The keys listed in the manifest of the backup.
+	pub keys: Vec<(KeyTypeId, Vec<u8>)>,
+	/// The encrypted keys were truncated or tampered with, so none of them could be checked.
+	pub damaged: bool,
+	/// Keys of the manifest that are not in the backup.
+	pub missing: Vec<(KeyTypeId, Vec<u8>)>,
+	/// Keys whose MAC does not match the one in the manifest.
+	pub mismatched: Vec<(KeyTypeId, Vec<u8>)>,
+	/// Keys in the backup that are not listed in the manifest.
+	pub unlisted: Vec<(KeyTypeId, Vec<u8>)>,
+}
+
+impl VerifyReport {
+	/// Returns `true` if every key of the backup matches the manifest.
+	pub fn is_intact(&self) -> bool {
+		!self.damaged
+			&& self.missing.is_empty()
+			&& self.mismatched.is_empty()
+			&& self.unlisted.is_empty()
+	}
+}
+
+pub(crate) fn derive_key(password: &SecretString, salt: &[u8], rounds: u32) -> Zeroizing<[u8; 32]> {
+	let mut key = Zeroizing::new([0u8; 32]);
+	pbkdf2::pbkdf2::<Hmac<Sha256>>(password.expose_secret().as_bytes(), salt, rounds, &mut key[..]);
+	key
+}
+
+fn invalid(reason: &str) -> Error {
+	Error::InvalidBackup(reason.into())
+}
+
+fn mac(key: &[u8], data: &[u8]) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any size; qed");
+	mac.update(data);
+	mac
+}
+
+fn manifest_key(key: &[u8; 32]) -> Zeroizing<[u8; MAC_LEN]> {
+	Zeroizing::new(mac(key, MANIFEST_DOMAIN).finalize().into_bytes().into())
+}
+
+fn entry_mac(manifest_key: &[u8], entry: &BackupEntry) -> Hmac<Sha256> {
+	mac(manifest_key, &Zeroizing::new(entry.encode()))
+}
+
+/// Encrypt the keys into a backup.
+pub(crate) fn seal(
+	entries: &[BackupEntry],
+	password: &SecretString,
+	compress: bool,
+) -> Result<Vec<u8>> {
+	encrypt(&Zeroizing::new(entries.encode()), password, compress)
+}
+
+/// Encrypt the keys into a backup followed by a manifest of the keys.
+pub(crate) fn seal_with_manifest(
+	entries: &[BackupEntry],
+	password: &SecretString,
+	compress: bool,
+) -> Result<Vec<u8>> {
+	let plain = Zeroizing::new(entries.encode());
+	let (mut backup, key) = encrypt_with_flags(&plain, password, compress, FLAG_MANIFEST)?;
+	let manifest_key = manifest_key(&key);
+	let manifest = entries.iter()
+		.map(|entry| ManifestEntry {
+			key_type: entry.key_type,
+			public: entry.public.clone(),
+			mac: entry_mac(&manifest_key[..], entry).finalize().into_bytes().into(),
+		})
+		.collect::<Vec<_>>()
+		.encode();
+	backup.extend_from_slice(&manifest);
+	backup.extend_from_slice(&mac(&manifest_key[..], &manifest).finalize().into_bytes());
+	backup.extend_from_slice(&(manifest.len() as u32).to_le_bytes());
+	backup.extend_from_slice(MANIFEST_MAGIC);
+	Ok(backup)
+}
+
+/// Encrypt arbitrary data in the format of a backup.
+pub(crate) fn encrypt(plain: &[u8], password: &SecretString, compress: bool) -> Result<Vec<u8>> {
+	encrypt_with_flags(plain, password, compress, 0).map(|(backup, _)| backup)
+}
+
+/// Encrypt the data with the given extra flags, returning it along with the key it was
+/// encrypted with.
+fn encrypt_with_flags(
+	plain: &[u8],
+	password: &SecretString,
+	compress: bool,
+	flags: u8,
+) -> Result<(Vec<u8>, Zeroizing<[u8; 32]>)> {
+	let mut plain = Zeroizing::new(plain.to_vec());
+	if compress {
+		plain = Zeroizing::new(zstd::stream::encode_all(&plain[..], COMPRESSION_LEVEL)?);
+	}
+
+	let mut salt = [0u8; SALT_LEN];
+	let mut nonce = [0u8; NONCE_LEN];
+	OsRng.fill_bytes(&mut salt);
+	OsRng.fill_bytes(&mut nonce);
+
+	let mut backup = Vec::with_capacity(HEADER_LEN + plain.len() + 16);
+	backup.extend_from_slice(MAGIC);
+	backup.push(VERSION);
+	backup.push(if compress { FLAG_COMPRESSED | flags } else { flags });
+	backup.extend_from_slice(&salt);
+	backup.extend_from_slice(&KDF_ROUNDS.to_le_bytes());
+	backup.extend_from_slice(&nonce);
+
+	let key = derive_key(password, &salt, KDF_ROUNDS);
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+	let encrypted = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: &plain, aad: &backup })
+		.map_err(|_| invalid("encryption failed"))?;
+	backup.extend_from_slice(&encrypted);
+	Ok((backup, key))
+}
+
+/// Returns `true` if the data looks like it was encrypted with [`encrypt`].
+pub(crate) fn is_encrypted(data: &[u8]) -> bool {
+	data.len() >= HEADER_LEN && data.starts_with(MAGIC) && data[MAGIC.len()] == VERSION
+}
+
+/// Decrypt the keys of a backup.
+///
+/// Returns [`Error::InvalidPassword`] if the backup can not be decrypted with the password,
+/// which is also the case for a backup that was tampered with.
+pub(crate) fn open(backup: &[u8], password: &SecretString) -> Result<Vec<BackupEntry>> {
+	let plain = decrypt(backup, password)?;
+	Vec::<BackupEntry>::decode(&mut &plain[..]).map_err(|_| invalid("malformed keys"))
+}
+
+/// The parts of an encrypted backup.
+struct Parsed<'a> {
+	header: &'a [u8],
+	flags: u8,
+	salt: &'a [u8],
+	rounds: u32,
+	nonce: &'a [u8],
+	encrypted: &'a [u8],
+	/// The encoded manifest and its MAC, if the backup has one.
+	manifest: Option<(&'a [u8], &'a [u8])>,
+}
+
+fn parse(backup: &[u8]) -> Result<Parsed<'_>> {
+	if backup.len() < HEADER_LEN || &backup[..MAGIC.len()] != MAGIC {
+		return Err(invalid("not a keystore backup"));
+	}
+	let (header, mut encrypted) = backup.split_at(HEADER_LEN);
+	let mut fields = &header[MAGIC.len()..];
+	let mut take = |len: usize| {
+		let (field, rest) = fields.split_at(len);
+		fields = rest;
+		field
+	};
+
+	if take(1)[0] != VERSION {
+		return Err(invalid("unsupported version"));
+	}
+	let flags = take(1)[0];
+	if flags & !(FLAG_COMPRESSED | FLAG_MANIFEST) != 0 {
+		return Err(invalid("unknown flags"));
+	}
+	let salt = take(SALT_LEN);
+	let mut rounds = [0u8; 4];
+	rounds.copy_from_slice(take(4));
+	let nonce = take(NONCE_LEN);
+
+	let mut manifest = None;
+	if flags & FLAG_MANIFEST != 0 {
+		let trailer_len = MAC_LEN + 4 + MANIFEST_MAGIC.len();
+		if encrypted.len() < trailer_len || !encrypted.ends_with(MANIFEST_MAGIC) {
+			return Err(invalid("missing manifest"));
+		}
+		let (rest, trailer) = encrypted.split_at(encrypted.len() - trailer_len);
+		let (mac, len) = trailer.split_at(MAC_LEN);
+		let mut manifest_len = [0u8; 4];
+		manifest_len.copy_from_slice(&len[..4]);
+		let manifest_len = u32::from_le_bytes(manifest_len) as usize;
+		if rest.len() < manifest_len {
+			return Err(invalid("missing manifest"));
+		}
+		let (rest, encoded) = rest.split_at(rest.len() - manifest_len);
+		encrypted = rest;
+		manifest = Some((encoded, mac));
+	}
+
+	Ok(Parsed {
+		header,
+		flags,
+		salt,
+		rounds: u32::from_le_bytes(rounds),
+		nonce,
+		encrypted,
+		manifest,
+	})
+}
+
+/// Decrypt data that was encrypted with [`encrypt`].
+pub(crate) fn decrypt(backup: &[u8], password: &SecretString) -> Result<Zeroizing<Vec<u8>>> {
+	let parsed = parse(backup)?;
+	decrypt_parsed(&parsed, &derive_key(password, parsed.salt, parsed.rounds))
+}
+
+fn decrypt_parsed(parsed: &Parsed<'_>, key: &[u8; 32]) -> Result<Zeroizing<Vec<u8>>> {
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+	let payload = Payload { msg: parsed.encrypted, aad: parsed.header };
+	let mut plain = Zeroizing::new(
+		cipher.decrypt(Nonce::from_slice(parsed.nonce), payload)
+			.map_err(|_| Error::InvalidPassword)?
+	);
+
+	if parsed.flags & FLAG_COMPRESSED != 0 {
+		let mut decompressed = Zeroizing::new(Vec::new());
+		zstd::stream::read::Decoder::new(&plain[..])?
+			.take(MAX_DECOMPRESSED_LEN as u64 + 1)
+			.read_to_end(&mut decompressed)?;
+		if decompressed.len() > MAX_DECOMPRESSED_LEN {
+			return Err(invalid("decompressed keys are too large"));
+		}
+		plain = decompressed;
+	}
+	Ok(plain)
+}
+
+/// Check every key of a backup against its manifest, without importing them.
+///
+/// Returns [`Error::InvalidPassword`] if the manifest can not be authenticated with the
+/// password, which is also the case for a manifest that was tampered with, and
+/// [`Error::InvalidBackup`] if the backup has no manifest.
+pub(crate) fn verify(backup: &[u8], password: &SecretString) -> Result<VerifyReport> {
+	let parsed = parse(backup)?;
+	let (manifest, manifest_mac) = parsed.manifest.ok_or_else(|| invalid("missing manifest"))?;
+	let key = derive_key(password, parsed.salt, parsed.rounds);
+	let manifest_key = manifest_key(&key);
+	mac(&manifest_key[..], manifest).verify(manifest_mac).map_err(|_| Error::InvalidPassword)?;
+	let manifest = Vec::<ManifestEntry>::decode(&mut &manifest[..])
+		.map_err(|_| invalid("malformed manifest"))?;
+
+	let mut report = VerifyReport {
+		keys: manifest.iter().map(|listed| (listed.key_type, listed.public.clone())).collect(),
+		..Default::default()
+	};
+	// The password is right, so a failure to decrypt means the keys were damaged.
+	let entries = match decrypt_parsed(&parsed, &key) {
+		Ok(plain) => Vec::<BackupEntry>::decode(&mut &plain[..]).ok(),
+		Err(Error::InvalidPassword) => None,
+		Err(e) => return Err(e),
+	};
+	let entries = match entries {
+		Some(entries) => entries,
+		None => {
+			report.damaged = true;
+			return Ok(report);
+		},
+	};
+
+	for listed in &manifest {
+		let entry = entries.iter()
+			.find(|entry| entry.key_type == listed.key_type && entry.public == listed.public);
+		match entry {
+			None => report.missing.push((listed.key_type, listed.public.clone())),
+			Some(entry) => if entry_mac(&manifest_key[..], entry).verify(&listed.mac).is_err() {
+				report.mismatched.push((listed.key_type, listed.public.clone()));
+			},
+		}
+	}
+	report.unlisted = entries.iter()
+		.filter(|entry| !report.keys.contains(&(entry.key_type, entry.public.clone())))
+		.map(|entry| (entry.key_type, entry.public.clone()))
+		.collect();
+	Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn secret(password: &str) -> SecretString {
+		SecretString::new(password.into())
+	}
+
+	fn entries(count: usize) -> Vec<BackupEntry> {
+		(0..count)
+			.map(|i| BackupEntry {
+				key_type: KeyTypeId(*b"test"),
+				public: vec![i as u8; 32],
+				suri: Zeroizing::new(format!("//Key{}", i)),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn compressed_and_uncompressed_backups_round_trip() {
+		let plain = seal(&entries(100), &secret("password"), false).unwrap();
+		let compressed = seal(&entries(100), &secret("password"), true).unwrap();
+		assert!(compressed.len() < plain.len());
+
+		for backup in &[plain, compressed] {
+			let keys = open(backup, &secret("password")).unwrap();
+			assert_eq!(keys.len(), 100);
+			assert_eq!(keys[42].suri.as_str(), "//Key42");
+			assert!(matches!(open(backup, &secret("wrong")), Err(Error::InvalidPassword)));
+		}
+	}
+
+	#[test]
+	fn tampered_header_is_rejected() {
+		let mut backup = seal(&entries(1), &secret("password"), true).unwrap();
+		// Clearing the compression flag must not go unnoticed.
+		backup[MAGIC.len() + 1] = 0;
+		assert!(matches!(open(&backup, &secret("password")), Err(Error::InvalidPassword)));
+
+		assert!(matches!(open(b"skbk", &secret("password")), Err(Error::InvalidBackup(_))));
+	}
+
+	#[test]
+	fn manifest_names_the_keys_that_do_not_match() {
+		let password = secret("password");
+		let backup = seal_with_manifest(&entries(3), &password, true).unwrap();
+		assert_eq!(open(&backup, &password).unwrap().len(), 3);
+		let report = verify(&backup, &password).unwrap();
+		assert!(report.is_intact());
+		assert_eq!(report.keys.len(), 3);
+		assert!(matches!(verify(&backup, &secret("wrong")), Err(Error::InvalidPassword)));
+		assert!(matches!(
+			verify(&seal(&entries(3), &password, true).unwrap(), &password),
+			Err(Error::InvalidBackup(_)),
+		));
+
+		let mut damaged = backup.clone();
+		damaged[HEADER_LEN] ^= 1;
+		assert!(verify(&damaged, &password).unwrap().damaged);
+
+		// Re-authenticate a manifest with a wrong MAC for the second key.
+		let parsed = parse(&backup).unwrap();
+		let manifest_key = manifest_key(&derive_key(&password, parsed.salt, parsed.rounds));
+		let encoded = parsed.manifest.unwrap().0;
+		let mut manifest = Vec::<ManifestEntry>::decode(&mut &encoded[..]).unwrap();
+		manifest[1].mac = [0; MAC_LEN];
+		let manifest = manifest.encode();
+		let mut forged = backup[..HEADER_LEN + parsed.encrypted.len()].to_vec();
+		forged.extend_from_slice(&manifest);
+		forged.extend_from_slice(&mac(&manifest_key[..], &manifest).finalize().into_bytes());
+		forged.extend_from_slice(&(manifest.len() as u32).to_le_bytes());
+		forged.extend_from_slice(MANIFEST_MAGIC);
+		let report = verify(&forged, &password).unwrap();
+		assert_eq!(report.mismatched, vec![(KeyTypeId(*b"test"), vec![1; 32])]);
+		assert!(!report.is_intact());
+	}
+}
@@ -0,0 +1,135 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! BIP32 hierarchical derivation of secp256k1 secret keys.
+//!
+//! Only the derivation of private child keys is implemented, extended keys are never
+//! serialized. Paths use the usual notation, e.g. `m/44'/60'/0'/0/0`, with `'` or `h` marking
+//! hardened indices.
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha512;
+use zeroize::Zeroizing;
+
+use crate::{Result, Error};
+
+/// Key of the HMAC deriving the master key from the seed.
+const MASTER_KEY: &[u8] = b"Bitcoin seed";
+/// Offset of the hardened indices.
+const HARDENED: u32 = 1 << 31;
+
+/// A secret key together with its chain code.
+struct ExtendedKey {
+	secret: secp256k1::SecretKey,
+	chain_code: Zeroizing<[u8; 32]>,
+}
+
+impl ExtendedKey {
+	/// Split the output of the HMAC into the secret key and the chain code.
+	fn from_hmac(output: &[u8]) -> Result<(secp256k1::SecretKey, Zeroizing<[u8; 32]>)> {
+		let mut secret = Zeroizing::new([0u8; 32]);
+		secret.copy_from_slice(&output[..32]);
+		let mut chain_code = Zeroizing::new([0u8; 32]);
+		chain_code.copy_from_slice(&output[32..]);
+		// Fails for the invalid keys of probability below 2^-127, BIP32 skips to the next index.
+		let secret = secp256k1::SecretKey::parse(&secret).map_err(|_| Error::InvalidSeed)?;
+		Ok((secret, chain_code))
+	}
+
+	fn master(seed: &[u8]) -> Result<Self> {
+		let (secret, chain_code) = Self::from_hmac(&hmac(MASTER_KEY, &[seed]))?;
+		Ok(Self { secret, chain_code })
+	}
+
+	fn child(&self, index: u32) -> Result<Self> {
+		let output = if index >= HARDENED {
+			let secret = Zeroizing::new(self.secret.serialize());
+			hmac(&self.chain_code[..], &[&[0], &secret[..], &index.to_be_bytes()])
+		} else {
+			let public = secp256k1::PublicKey::from_secret_key(&self.secret).serialize_compressed();
+			hmac(&self.chain_code[..], &[&public[..], &index.to_be_bytes()])
+		};
+		let (mut secret, chain_code) = Self::from_hmac(&output)?;
+		secret.tweak_add_assign(&self.secret).map_err(|_| Error::InvalidSeed)?;
+		Ok(Self { secret, chain_code })
+	}
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> Zeroizing<Vec<u8>> {
+	let mut mac = Hmac::<Sha512>::new_varkey(key).expect("HMAC takes keys of any size; qed");
+	for data in data {
+		mac.update(data);
+	}
+	Zeroizing::new(mac.finalize().into_bytes().to_vec())
+}
+
+/// Parse the path into its indices, failing with [`Error::InvalidPhrase`].
+fn parse_path(path: &str) -> Result<Vec<u32>> {
+	let invalid = || Error::InvalidPhrase(format!("Invalid BIP32 path: {}", path));
+	let mut components = path.split('/');
+	if components.next() != Some("m") {
+		return Err(invalid());
+	}
+	components
+		.map(|component| {
+			let (index, offset) = match component.strip_suffix(|c| c == '\'' || c == 'h') {
+				Some(index) => (index, HARDENED),
+				None => (component, 0),
+			};
+			if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+				return Err(invalid());
+			}
+			match index.parse::<u32>() {
+				Ok(index) if index < HARDENED => Ok(index + offset),
+				_ => Err(invalid()),
+			}
+		})
+		.collect()
+}
+
+/// Derive the secret key at the path from the seed.
+pub(crate) fn derive(seed: &[u8], path: &str) -> Result<Zeroizing<[u8; 32]>> {
+	let mut key = ExtendedKey::master(seed)?;
+	for index in parse_path(path)? {
+		key = key.child(index)?;
+	}
+	Ok(Zeroizing::new(key.secret.serialize()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn derivation_matches_the_bip32_test_vectors() {
+		// Test vector 1 of BIP32.
+		let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+		assert_eq!(
+			hex::encode(&derive(&seed, "m").unwrap()[..]),
+			"e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+		);
+		assert_eq!(
+			hex::encode(&derive(&seed, "m/0'/1/2h/2/1000000000").unwrap()[..]),
+			"471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+		);
+
+		for path in &["", "0/1", "m/", "m/+1", "m/2147483648", "m/1''", "m/x"] {
+			assert!(matches!(derive(&seed, path), Err(Error::InvalidPhrase(_))), "{}", path);
+		}
+	}
+}
@@ -0,0 +1,267 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Options for opening a [`LocalKeystore`].
+
+use std::{path::PathBuf, sync::Arc};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use rand::RngCore;
+use sp_core::crypto::{KeyTypeId, SecretString};
+
+use crate::{DirectoryLayout, EnclaveSigner, LocalKeystore, Result, StrayFilePolicy};
+
+/// Where the keystore directory is.
+pub(crate) enum Location {
+	/// The directory at the path, created if it does not exist.
+	Path(PathBuf),
+	/// The directory of an open file descriptor.
+	#[cfg(unix)]
+	Fd(RawFd),
+}
+
+/// Opens a [`LocalKeystore`] from filesystem with the given options.
+///
+/// Without any option set, [`LocalKeystoreBuilder::open`] behaves like [`LocalKeystore::open`]
+/// without a password: the directory is created if it does not exist, only accessible by the
+/// current user on unix. On unix, an existing directory accessible by group or others is
+/// restricted to the current user, and opening fails with [`Error::InsecurePermissions`] if
+/// that fails or one of its key files is accessible by group or others. Key files are created
+/// accessible by the current user only.
+///
+/// The keystore belongs to the process that opened it: a process forked off afterwards can
+/// read keys, but changes fail with [`Error::ForkedProcess`] until it re-opens the keystore.
+///
+/// [`Error::InsecurePermissions`]: crate::Error::InsecurePermissions
+/// [`Error::ForkedProcess`]: crate::Error::ForkedProcess
+pub struct LocalKeystoreBuilder {
+	pub(crate) location: Location,
+	pub(crate) password: Option<SecretString>,
+	pub(crate) layout: DirectoryLayout,
+	pub(crate) allow_network_fs: bool,
+	pub(crate) stray_file_policy: StrayFilePolicy,
+	pub(crate) required: Vec<(KeyTypeId, usize)>,
+	pub(crate) share_threshold: Option<u8>,
+	pub(crate) rng: Option<Box<dyn RngCore + Send>>,
+	pub(crate) enclave: Option<Arc<dyn EnclaveSigner>>,
+	pub(crate) durable: Option<(PathBuf, SecretString)>,
+	pub(crate) wal: bool,
+}
+
+impl LocalKeystoreBuilder {
+	/// Open the keystore in the directory at the given path.
+	pub fn new<T: Into<PathBuf>>(path: T) -> Self {
+		Self::with_location(Location::Path(path.into()))
+	}
+
+	/// Open the keystore in the directory of an open file descriptor, e.g. one passed by
+	/// systemd in `LISTEN_FDS`.
+	///
+	/// For sandboxed services that can not open the keystore directory by its path. The file
+	/// descriptor is duplicated, the caller keeps ownership of `dirfd` and may close it. All
+	/// files are accessed relative to the duplicate with `openat`, `renameat`, `unlinkat` and
+	/// the other `*at` syscalls, so the directory does not have to be reachable by path. Errors
+	/// name the files as if the directory was at `fd:<dirfd>`. FUSE file systems are not
+	/// recognized, see [`LocalKeystoreBuilder::allow_network_fs`]. Opening fails with
+	/// [`Error::NotADirectory`] if `dirfd` is not a directory.
+	///
+	/// [`Error::NotADirectory`]: crate::Error::NotADirectory
+	#[cfg(unix)]
+	pub fn at(dirfd: RawFd) -> Self {
+		Self::with_location(Location::Fd(dirfd))
+	}
+
+	fn with_location(location: Location) -> Self {
+		Self {
+			location,
+			password: None,
+			layout: DirectoryLayout::Flat,
+			allow_network_fs: false,
+			stray_file_policy: StrayFilePolicy::Ignore,
+			required: Vec::new(),
+			share_threshold: None,
+			rng: None,
+			enclave: None,
+			durable: None,
+			wal: false,
+		}
+	}
+
+	/// Encrypt and decrypt the keys with the password.
+	///
+	/// Replaces the shares set with [`LocalKeystoreBuilder::password_shares`].
+	pub fn password(mut self, password: impl Into<Option<SecretString>>) -> Self {
+		self.password = password.into();
+		if self.password.is_some() {
+			self.share_threshold = None;
+		}
+		self
+	}
+
+	/// Lay the key files out as given, [`DirectoryLayout::Flat`] by default.
+	///
+	/// The layout must match the one the directory is in, key files stored in another layout
+	/// are not found. Use [`LocalKeystore::migrate_layout`] to convert a directory.
+	pub fn layout(mut self, layout: DirectoryLayout) -> Self {
+		self.layout = layout;
+		self
+	}
+
+	/// Also open the keystore if the directory is on a network file system.
+	///
+	/// Renames are not atomic and locks are advisory at best on network file systems, and a
+	/// directory shared by several nodes gets corrupted, so opening fails with
+	/// [`Error::UnsupportedFilesystem`] by default for directories on NFS, SMB, CIFS, SMB2,
+	/// Ceph, AFS, Coda, GFS2 and OCFS2, and on the FUSE file systems sshfs, s3fs, rclone,
+	/// gcsfuse, goofys and GlusterFS. 9P is mostly used to share local directories with virtual
+	/// machines and WSL, so it only gets a warning. The file system is only detected on Linux.
+	///
+	/// [`Error::UnsupportedFilesystem`]: crate::Error::UnsupportedFilesystem
+	pub fn allow_network_fs(mut self) -> Self {
+		self.allow_network_fs = true;
+		self
+	}
+
+	/// Handle files in the directory that are not key files as given.
+	///
+	/// They are ignored by default, like [`StrayFilePolicy::Ignore`] does.
+	pub fn stray_file_policy(mut self, policy: StrayFilePolicy) -> Self {
+		self.stray_file_policy = policy;
+		self
+	}
+
+	/// Require keys of the given key types.
+	///
+	/// Every `(key_type, count)` requires at least `count` keys of the key type that derive from
+	/// their key file with the password, so a node missing its consensus keys fails at startup
+	/// instead of silently not authoring. Opening fails with [`Error::MissingRequiredKeys`]
+	/// listing the key types with too few keys.
+	///
+	/// [`Error::MissingRequiredKeys`]: crate::Error::MissingRequiredKeys
+	pub fn require(mut self, required: &[(KeyTypeId, usize)]) -> Self {
+		self.required.extend_from_slice(required);
+		self
+	}
+
+	/// Reconstruct the password from shares, see [`split_password`](crate::split_password).
+	///
+	/// The keystore is opened locked and without its password. Until `threshold` shares were
+	/// added with [`LocalKeystore::add_share`], signing fails with [`Error::Locked`], and so do
+	/// generating and inserting keys, which would need the password. Replaces the password set
+	/// with [`LocalKeystoreBuilder::password`].
+	///
+	/// [`Error::Locked`]: crate::Error::Locked
+	pub fn password_shares(mut self, threshold: u8) -> Self {
+		self.share_threshold = Some(threshold);
+		self.password = None;
+		self
+	}
+
+	/// Generate keys with the given random number generator, instead of the operating system's
+	/// one.
+	///
+	/// This is meant for tests that need reproducible keys: with a seeded generator, the same
+	/// keys are generated on every run. Never use it in production, anyone who knows the
+	/// generator and its seed knows every key generated by the keystore.
+	pub fn insecure_rng(mut self, rng: Box<dyn RngCore + Send>) -> Self {
+		self.rng = Some(rng);
+		self
+	}
+
+	/// Sign inside an enclave.
+	///
+	/// Listing keys and managing the key files works as usual, but keys are never derived
+	/// outside of the enclave: `sign_with` and `sr25519_vrf_sign` hand the stored secret of the
+	/// key to the signer. `ecdsa_sign_prehashed` is not supported and keys should be provisioned
+	/// sealed to the enclave, as keys generated by the keystore itself exist outside of it.
+	pub fn enclave(mut self, signer: Arc<dyn EnclaveSigner>) -> Self {
+		self.enclave = Some(signer);
+		self
+	}
+
+	/// Work on the directory as a fast tier and snapshot its keys to a durable one.
+	///
+	/// The directory is meant to be on a RAM backed file system, the keystore reads and writes
+	/// its key files there as usual. The keys on disk are snapshotted to `durable`, encrypted
+	/// with `snapshot_password` like a backup (see [`LocalKeystore::export_backup`]). A snapshot
+	/// is taken right after a key was generated and whenever
+	/// [`LocalKeystore::snapshot_to_durable`] is called, which should be done periodically. On
+	/// open, the keys of the snapshot that are missing in the directory are restored to it.
+	///
+	/// After a crash the keys are the ones of the last snapshot: keys inserted or removed since
+	/// then, e.g. with `insert_unknown`, are lost. Keys that are only held in memory are never
+	/// snapshotted.
+	pub fn durable<D: Into<PathBuf>>(
+		mut self,
+		durable: D,
+		snapshot_password: SecretString,
+	) -> Self {
+		self.durable = Some((durable.into(), snapshot_password));
+		self
+	}
+
+	/// Write every change to the key files to a write-ahead log first.
+	///
+	/// Inserting, generating, importing and removing keys on disk appends the change to the log
+	/// in the keystore directory and syncs it before any key file is touched; the log is
+	/// emptied once the change was applied. Changes that a crash interrupted are replayed on
+	/// open, see [`LocalKeystore::replay_wal`]. Like the key files, the log holds secret URIs.
+	pub fn wal(mut self) -> Self {
+		self.wal = true;
+		self
+	}
+
+	/// Open the keystore.
+	pub fn open(self) -> Result<LocalKeystore> {
+		LocalKeystore::from_builder(self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::testing::SR25519;
+	use sp_keystore::SyncCryptoStore;
+	use tempfile::TempDir;
+	use crate::Error;
+
+	#[test]
+	fn options_are_combined() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = || SecretString::new("password".into());
+		let builder = || LocalKeystoreBuilder::new(temp_dir.path())
+			.layout(DirectoryLayout::ByKeyType)
+			.password(password())
+			.wal();
+		let store = builder().open().unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		drop(store);
+
+		let store = builder().require(&[(SR25519, 1)]).open().unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(temp_dir.path().join(crate::wal::WAL_FILE).exists());
+		assert!(matches!(
+			builder().require(&[(SR25519, 2)]).open(),
+			Err(Error::MissingRequiredKeys(missing)) if missing == vec![SR25519],
+		));
+		assert!(matches!(
+			builder().password_shares(2).require(&[(SR25519, 1)]).open(),
+			Err(Error::MissingRequiredKeys(_)),
+		));
+	}
+}
@@ -0,0 +1,112 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Secret keys and signatures a [`LocalKeystore`] keeps in memory to sign faster.
+//!
+//! [`LocalKeystore`]: crate::LocalKeystore
+
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+use sp_application_crypto::{ecdsa, ed25519};
+use sp_core::crypto::{CryptoTypeId, CryptoTypePublicPair, KeyTypeId};
+use zeroize::Zeroizing;
+
+/// Secret keys held in memory while they are in use, see [`LocalKeystore::set_active_window`].
+///
+/// [`LocalKeystore::set_active_window`]: crate::LocalKeystore::set_active_window
+#[derive(Default)]
+pub(crate) struct ActiveKeys {
+	/// How long a key stays in memory after its last use, `None` if keys are not kept.
+	pub(crate) window: Option<Duration>,
+	/// Kept keys by key type, public key and pair type.
+	pub(crate) keys: HashMap<(KeyTypeId, Vec<u8>, &'static str), ActiveKey>,
+}
+
+/// A secret key held in memory.
+pub(crate) struct ActiveKey {
+	/// The raw secret key.
+	pub(crate) secret: Zeroizing<Vec<u8>>,
+	/// When the key is dropped, unless it is used again.
+	pub(crate) expires: Instant,
+}
+
+impl ActiveKeys {
+	/// Drop all keys whose window has passed.
+	pub(crate) fn purge(&mut self, now: Instant) {
+		self.keys.retain(|_, key| key.expires > now);
+	}
+
+	/// Drop all keys with the given key type and public key.
+	pub(crate) fn remove(&mut self, key_type: KeyTypeId, public: &[u8]) {
+		self.keys.retain(|(id, key, _), _| *id != key_type || key != public);
+	}
+}
+
+/// Signatures of deterministic signature schemes, see [`LocalKeystore::set_signature_cache`].
+///
+/// [`LocalKeystore::set_signature_cache`]: crate::LocalKeystore::set_signature_cache
+#[derive(Default)]
+pub(crate) struct SignatureCache {
+	/// The maximum number of cached signatures, `0` if nothing is cached.
+	pub(crate) capacity: usize,
+	/// Signatures by key type, key and blake2-256 hash of the message.
+	pub(crate) signatures: HashMap<(KeyTypeId, CryptoTypePublicPair, [u8; 32]), Vec<u8>>,
+	/// The cached entries, oldest first.
+	order: VecDeque<(KeyTypeId, CryptoTypePublicPair, [u8; 32])>,
+}
+
+impl SignatureCache {
+	/// Returns `true` if signatures of the crypto type are deterministic and may be cached.
+	///
+	/// sr25519 signatures are randomized, so repeating one is never right.
+	pub(crate) fn is_cacheable(scheme: CryptoTypeId) -> bool {
+		scheme == ed25519::CRYPTO_ID || scheme == ecdsa::CRYPTO_ID
+	}
+
+	/// Cache a signature, dropping the oldest one if the cache is full.
+	pub(crate) fn insert(
+		&mut self,
+		key: (KeyTypeId, CryptoTypePublicPair, [u8; 32]),
+		signature: Vec<u8>,
+	) {
+		if self.capacity == 0 || !Self::is_cacheable((key.1).0) {
+			return;
+		}
+		if self.signatures.insert(key.clone(), signature).is_none() {
+			self.order.push_back(key);
+		}
+		self.shrink();
+	}
+
+	/// Drop the oldest signatures until the cache is within its capacity.
+	pub(crate) fn shrink(&mut self) {
+		while self.order.len() > self.capacity {
+			if let Some(oldest) = self.order.pop_front() {
+				self.signatures.remove(&oldest);
+			}
+		}
+	}
+
+	/// Drop all signatures of the given key type and public key.
+	pub(crate) fn remove(&mut self, key_type: KeyTypeId, public: &[u8]) {
+		let matches = |(id, key, _): &(KeyTypeId, CryptoTypePublicPair, [u8; 32])| {
+			*id == key_type && key.1 == public
+		};
+		self.signatures.retain(|key, _| !matches(key));
+		self.order.retain(|key| !matches(key));
+	}
+}
@@ -0,0 +1,85 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Encrypting the secret URIs a [`LocalKeystore`] holds in memory.
+//!
+//! [`LocalKeystore`]: crate::LocalKeystore
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, aead::{Aead, NewAead}};
+use rand::{RngCore, rngs::OsRng};
+use zeroize::Zeroizing;
+
+/// Key encrypting the secret URIs of the keys in memory, see
+/// [`LocalKeystore::set_encrypt_memory`].
+///
+/// [`LocalKeystore::set_encrypt_memory`]: crate::LocalKeystore::set_encrypt_memory
+pub(crate) struct MemoryCipher {
+	/// Boxed, so that the locked memory never moves.
+	key: Box<Zeroizing<[u8; 32]>>,
+}
+
+/// Length of the nonce of an encrypted secret URI in memory.
+const MEMORY_NONCE_LEN: usize = 12;
+
+impl MemoryCipher {
+	/// Generate a new key and lock its memory.
+	pub(crate) fn new() -> Self {
+		let mut key = Box::new(Zeroizing::new([0u8; 32]));
+		OsRng.fill_bytes(&mut key[..]);
+		#[cfg(unix)]
+		// Safe, the memory is valid for as long as the key exists.
+		unsafe {
+			// Failing to lock, e.g. because the limit of locked memory is reached, is not fatal.
+			if libc::mlock(key.as_ptr() as *const libc::c_void, key.len()) != 0 {
+				log::debug!(target: "keystore", "Failed to lock the memory encryption key");
+			}
+		}
+		Self { key }
+	}
+
+	fn cipher(&self) -> ChaCha20Poly1305 {
+		ChaCha20Poly1305::new(Key::from_slice(&self.key[..]))
+	}
+
+	/// Encrypt the secret URI, returning the hex encoded nonce and ciphertext.
+	pub(crate) fn seal(&self, suri: &str) -> String {
+		let mut nonce = [0u8; MEMORY_NONCE_LEN];
+		OsRng.fill_bytes(&mut nonce);
+		let sealed = self.cipher().encrypt(Nonce::from_slice(&nonce), suri.as_bytes())
+			.expect("Encrypting a secret URI of a valid length never fails; qed");
+		hex::encode([&nonce[..], &sealed[..]].concat())
+	}
+
+	/// Decrypt a secret URI encrypted with [`MemoryCipher::seal`].
+	pub(crate) fn open(&self, sealed: &str) -> Option<Zeroizing<String>> {
+		let sealed = hex::decode(sealed).ok().filter(|s| s.len() >= MEMORY_NONCE_LEN)?;
+		let (nonce, sealed) = sealed.split_at(MEMORY_NONCE_LEN);
+		let plain = Zeroizing::new(self.cipher().decrypt(Nonce::from_slice(nonce), sealed).ok()?);
+		std::str::from_utf8(&plain).ok().map(|suri| Zeroizing::new(suri.to_owned()))
+	}
+}
+
+impl Drop for MemoryCipher {
+	fn drop(&mut self) {
+		#[cfg(unix)]
+		// Safe, the memory is still valid.
+		unsafe {
+			libc::munlock(self.key.as_ptr() as *const libc::c_void, self.key.len());
+		}
+	}
+}
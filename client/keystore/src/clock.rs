@@ -0,0 +1,103 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Source of the current time, replaceable to test time-dependent behavior.
+
+use std::time::{Duration, Instant, SystemTime};
+use parking_lot::Mutex;
+
+/// The clock the keystore reads the current time from, see [`LocalKeystore::set_clock`].
+///
+/// [`LocalKeystore::set_clock`]: crate::LocalKeystore::set_clock
+pub trait Clock: Send + Sync {
+	/// The current monotonic time, for windows and durations.
+	fn now(&self) -> Instant;
+
+	/// The current wall clock time, for timestamps and expiry times.
+	fn system_now(&self) -> SystemTime;
+}
+
+/// The clock of the operating system, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	fn system_now(&self) -> SystemTime {
+		SystemTime::now()
+	}
+}
+
+/// A clock that only moves when it is advanced, for tests.
+///
+/// Starts at the time it was created.
+#[derive(Debug)]
+pub struct MockClock {
+	start: Instant,
+	system_start: SystemTime,
+	elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+	/// A clock standing at the current time.
+	pub fn new() -> Self {
+		Self { start: Instant::now(), system_start: SystemTime::now(), elapsed: Default::default() }
+	}
+
+	/// Move the clock forward.
+	pub fn advance(&self, by: Duration) {
+		*self.elapsed.lock() += by;
+	}
+}
+
+impl Default for MockClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Clock for MockClock {
+	fn now(&self) -> Instant {
+		self.start + *self.elapsed.lock()
+	}
+
+	fn system_now(&self) -> SystemTime {
+		self.system_start + *self.elapsed.lock()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mock_clock_only_moves_when_advanced() {
+		let clock = MockClock::new();
+		let (now, system_now) = (clock.now(), clock.system_now());
+		std::thread::sleep(Duration::from_millis(10));
+		assert_eq!(clock.now(), now);
+		assert_eq!(clock.system_now(), system_now);
+
+		clock.advance(Duration::from_secs(60));
+		assert_eq!(clock.now(), now + Duration::from_secs(60));
+		assert_eq!(clock.system_now(), system_now + Duration::from_secs(60));
+	}
+}
@@ -0,0 +1,190 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Time-boxed delegation of signing with a single key, see [`LocalKeystore::issue_delegation`].
+//!
+//! A [`DelegationToken`] names a key and an expiry time and is signed by that key, so it can
+//! only be issued by a keystore holding the key. The signed message is the domain separator
+//! followed by the SCALE encoded key type, key and expiry.
+//!
+//! [`LocalKeystore::issue_delegation`]: crate::LocalKeystore::issue_delegation
+
+use std::{sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+use codec::{Decode, Encode};
+use sp_core::crypto::{CryptoTypePublicPair, KeyTypeId};
+use sp_keystore::{CryptoStore, Error as TraitError, verify_signature};
+
+use crate::{Clock, Error, Result, SystemClock};
+
+/// Domain separator of the signed tokens, never used for anything else.
+const DOMAIN: &[u8] = b"substrate-keystore/delegation/v1";
+
+/// A grant to sign with a key until a point in time, see [`DelegatedKeystore`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct DelegationToken {
+	key_type: KeyTypeId,
+	public: CryptoTypePublicPair,
+	/// Milliseconds since the unix epoch.
+	expires: u64,
+	signature: Vec<u8>,
+}
+
+impl DelegationToken {
+	/// The key type of the delegated key.
+	pub fn key_type(&self) -> KeyTypeId {
+		self.key_type
+	}
+
+	/// The delegated key.
+	pub fn public(&self) -> &CryptoTypePublicPair {
+		&self.public
+	}
+
+	/// When the token expires.
+	pub fn expires_at(&self) -> SystemTime {
+		UNIX_EPOCH + Duration::from_millis(self.expires)
+	}
+
+	/// Returns `true` if the token is signed by the delegated key.
+	pub fn verify(&self) -> bool {
+		let msg = grant_message(self.key_type, &self.public, self.expires);
+		verify_signature(&self.public, &msg, &self.signature).unwrap_or(false)
+	}
+}
+
+/// The expiry of a token issued at `now` with the given time to live, in milliseconds since the
+/// unix epoch.
+pub(crate) fn expiry(now: SystemTime, ttl: Duration) -> u64 {
+	let expires = now + ttl;
+	expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// The message the delegated key signs to issue a token.
+pub(crate) fn grant_message(
+	key_type: KeyTypeId,
+	public: &CryptoTypePublicPair,
+	expires: u64,
+) -> Vec<u8> {
+	let mut msg = DOMAIN.to_vec();
+	(key_type, public, expires).encode_to(&mut msg);
+	msg
+}
+
+/// The signature of a signing call, failing with [`Error::PairNotFound`] if the key is missing
+/// and with [`Error::PermissionDenied`] if signing failed otherwise.
+pub(crate) fn signed(
+	signature: std::result::Result<Option<Vec<u8>>, TraitError>,
+) -> Result<Vec<u8>> {
+	match signature {
+		Ok(Some(signature)) => Ok(signature),
+		Ok(None) | Err(TraitError::PairNotFound) => Err(Error::PairNotFound),
+		Err(TraitError::Unavailable) => Err(Error::Unavailable),
+		Err(e) => Err(Error::PermissionDenied(e.to_string())),
+	}
+}
+
+/// Build a token from the signature of its grant message.
+pub(crate) fn token(
+	key_type: KeyTypeId,
+	public: CryptoTypePublicPair,
+	expires: u64,
+	signature: Vec<u8>,
+) -> DelegationToken {
+	DelegationToken { key_type, public, expires, signature }
+}
+
+/// A keystore that signs with the key of a [`DelegationToken`] until the token expires.
+///
+/// Meant to be handed to a component that should only sign with one key for a limited time.
+/// Messages are signed by the wrapped keystore, which has to hold the key. Messages that look
+/// like delegation grants are refused, so the token can not be used to extend itself.
+pub struct DelegatedKeystore {
+	inner: Arc<dyn CryptoStore>,
+	token: DelegationToken,
+	clock: Arc<dyn Clock>,
+}
+
+impl DelegatedKeystore {
+	/// Sign with the key of the token through the wrapped keystore.
+	///
+	/// Fails with [`Error::PermissionDenied`] if the token is not signed by its key.
+	pub fn new(inner: Arc<dyn CryptoStore>, token: DelegationToken) -> Result<Self> {
+		if !token.verify() {
+			return Err(Error::PermissionDenied("invalid delegation token".into()))
+		}
+		Ok(Self { inner, token, clock: Arc::new(SystemClock) })
+	}
+
+	/// Check the expiry of the token against the given clock instead of the system clock.
+	pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+		Self { clock, ..self }
+	}
+
+	/// The token of this keystore.
+	pub fn token(&self) -> &DelegationToken {
+		&self.token
+	}
+
+	/// Sign the message with the delegated key, returning the SCALE encoded signature.
+	///
+	/// Fails with [`Error::Locked`] once the token expired.
+	pub async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+		if self.clock.system_now() >= self.token.expires_at() {
+			return Err(Error::Locked)
+		}
+		if msg.starts_with(DOMAIN) {
+			return Err(Error::PermissionDenied("message is a delegation grant".into()))
+		}
+		signed(self.inner.sign_with(self.token.key_type, &self.token.public, msg).await)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::testing::SR25519;
+	use sp_keystore::SyncCryptoStore;
+	use crate::LocalKeystore;
+
+	#[test]
+	fn delegation_expires() {
+		let store = Arc::new(LocalKeystore::in_memory());
+		let public = SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap();
+		let other = SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap();
+
+		let token = store.issue_delegation(SR25519, &public.into(), Duration::from_secs(60))
+			.unwrap();
+		assert!(token.verify());
+		let delegated = DelegatedKeystore::new(store.clone(), token.clone()).unwrap();
+		let signature = block_on(delegated.sign(b"msg")).unwrap();
+		assert!(verify_signature(&public.into(), b"msg", &signature).unwrap());
+		let grant = grant_message(SR25519, &public.into(), u64::MAX);
+		assert!(matches!(block_on(delegated.sign(&grant)), Err(Error::PermissionDenied(_))));
+
+		let forged = DelegationToken { public: other.into(), ..token };
+		assert!(matches!(
+			DelegatedKeystore::new(store.clone(), forged),
+			Err(Error::PermissionDenied(_)),
+		));
+
+		let expired = store.issue_delegation(SR25519, &public.into(), Duration::ZERO).unwrap();
+		let delegated = DelegatedKeystore::new(store, expired).unwrap();
+		assert!(matches!(block_on(delegated.sign(b"msg")), Err(Error::Locked)));
+	}
+}
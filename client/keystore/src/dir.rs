@@ -0,0 +1,476 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Access to the files of a keystore directory, by path or through a file descriptor.
+//!
+//! A keystore opened with [`LocalKeystoreBuilder::at`] has no path it can open, its files are
+//! accessed with the `openat` family of syscalls relative to the file descriptor instead. The
+//! paths passed to [`Dir`] are the same either way: a path below the root of the directory is
+//! resolved relative to the file descriptor, if there is one, any other path as it is.
+//!
+//! [`LocalKeystoreBuilder::at`]: crate::LocalKeystoreBuilder::at
+
+use std::{
+	fs::{self, File, Metadata},
+	io::{self, Read, Write},
+	path::{Path, PathBuf},
+};
+#[cfg(unix)]
+use std::{
+	ffi::{CStr, CString, OsStr},
+	os::unix::{ffi::OsStrExt, io::{FromRawFd, RawFd}},
+};
+
+#[cfg(unix)]
+use crate::Error;
+use crate::Result;
+
+/// The directory of a keystore.
+#[derive(Clone, Copy)]
+pub(crate) struct Dir<'a> {
+	root: &'a Path,
+	#[cfg(unix)]
+	fd: Option<&'a DirFd>,
+}
+
+impl<'a> Dir<'a> {
+	/// The directory at `root`, accessed by path.
+	pub fn new(root: &'a Path) -> Self {
+		Self {
+			root,
+			#[cfg(unix)]
+			fd: None,
+		}
+	}
+
+	/// The directory of `fd`, whose files are named as if it was at `root`.
+	#[cfg(unix)]
+	pub fn at(root: &'a Path, fd: &'a DirFd) -> Self {
+		Self { root, fd: Some(fd) }
+	}
+
+	/// The path of the directory.
+	pub fn root(&self) -> &'a Path {
+		self.root
+	}
+
+	/// The file descriptor and relative name `path` is accessed through.
+	///
+	/// Paths that are not below the root are resolved relative to the working directory, like
+	/// the rest of the standard library does.
+	#[cfg(unix)]
+	fn resolve(&self, path: &Path) -> io::Result<(RawFd, CString)> {
+		let (fd, name) = match (self.fd, path.strip_prefix(self.root)) {
+			(Some(fd), Ok(name)) if name.as_os_str().is_empty() => (fd.0, Path::new(".")),
+			(Some(fd), Ok(name)) => (fd.0, name),
+			_ => (libc::AT_FDCWD, path),
+		};
+		CString::new(name.as_os_str().as_bytes())
+			.map(|name| (fd, name))
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains a nul byte"))
+	}
+
+	/// Like [`Self::resolve`], but `None` if `path` is accessed by path.
+	#[cfg(unix)]
+	fn relative(&self, path: &Path) -> io::Result<Option<(RawFd, CString)>> {
+		match self.fd {
+			Some(_) if path.starts_with(self.root) => self.resolve(path).map(Some),
+			_ => Ok(None),
+		}
+	}
+
+	/// Open the file at `path` with the given `open` flags.
+	#[cfg(unix)]
+	fn open_at(fd: RawFd, name: &CStr, flags: libc::c_int, mode: u32) -> io::Result<File> {
+		// Safe, `openat` only reads the name, the returned file descriptor is owned.
+		unsafe {
+			let file = libc::openat(fd, name.as_ptr(), flags | libc::O_CLOEXEC, mode);
+			if file < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			Ok(File::from_raw_fd(file))
+		}
+	}
+
+	/// Like [`File::open`].
+	pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			return Self::open_at(fd, &name, libc::O_RDONLY, 0);
+		}
+		File::open(path)
+	}
+
+	/// Open the existing file at `path` for writing.
+	pub fn open_write<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			return Self::open_at(fd, &name, libc::O_WRONLY, 0);
+		}
+		fs::OpenOptions::new().write(true).open(path)
+	}
+
+	/// Open the file at `path` for appending, creating it if it does not exist.
+	pub fn open_append<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			return Self::open_at(fd, &name, libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND, 0o666);
+		}
+		fs::OpenOptions::new().create(true).append(true).open(path)
+	}
+
+	/// Create or truncate the file at `path` for writing, creating it with `mode` on unix.
+	pub fn create<P: AsRef<Path>>(&self, path: P, mode: u32) -> io::Result<File> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			return Self::open_at(fd, &name, libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC, mode);
+		}
+		let mut options = fs::OpenOptions::new();
+		options.write(true).create(true).truncate(true);
+		#[cfg(unix)]
+		std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+		#[cfg(not(unix))]
+		let _ = mode;
+		options.open(path)
+	}
+
+	/// Like [`fs::read`].
+	pub fn read<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+		let path = path.as_ref();
+		if self.is_by_path(path) {
+			return fs::read(path);
+		}
+		let mut content = Vec::new();
+		self.open(path)?.read_to_end(&mut content)?;
+		Ok(content)
+	}
+
+	/// Like [`fs::read_to_string`].
+	pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+		let path = path.as_ref();
+		let content = self.read(path)?;
+		String::from_utf8(content)
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "File is not UTF-8"))
+	}
+
+	/// Like [`fs::write`].
+	pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, content: C) -> io::Result<()> {
+		let path = path.as_ref();
+		if self.is_by_path(path) {
+			return fs::write(path, content);
+		}
+		self.create(path, 0o666)?.write_all(content.as_ref())
+	}
+
+	/// Like [`fs::metadata`].
+	pub fn metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<Metadata> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			// `O_PATH` does not need any permission on the file itself.
+			#[cfg(target_os = "linux")]
+			let flags = libc::O_PATH;
+			#[cfg(not(target_os = "linux"))]
+			let flags = libc::O_RDONLY | libc::O_NONBLOCK;
+			return Self::open_at(fd, &name, flags, 0)?.metadata();
+		}
+		fs::metadata(path)
+	}
+
+	/// Like [`fs::symlink_metadata`].
+	pub fn symlink_metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<Metadata> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			#[cfg(target_os = "linux")]
+			let flags = libc::O_PATH | libc::O_NOFOLLOW;
+			#[cfg(not(target_os = "linux"))]
+			let flags = libc::O_RDONLY | libc::O_NONBLOCK | libc::O_NOFOLLOW;
+			return Self::open_at(fd, &name, flags, 0)?.metadata();
+		}
+		fs::symlink_metadata(path)
+	}
+
+	/// Returns `true` if there is anything at `path`.
+	pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
+		let path = path.as_ref();
+		self.metadata(path).is_ok()
+	}
+
+	/// Returns `true` if there is a file at `path`.
+	pub fn is_file<P: AsRef<Path>>(&self, path: P) -> bool {
+		let path = path.as_ref();
+		self.metadata(path).map(|metadata| metadata.is_file()).unwrap_or(false)
+	}
+
+	/// Returns `true` if there is a directory at `path`.
+	pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool {
+		let path = path.as_ref();
+		self.metadata(path).map(|metadata| metadata.is_dir()).unwrap_or(false)
+	}
+
+	/// Returns the paths of the entries of the directory at `path`.
+	pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<PathBuf>> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			let dir = Self::open_at(fd, &name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+			return read_dir_fd(dir).map(|names| names.iter().map(|n| path.join(n)).collect());
+		}
+		fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect()
+	}
+
+	/// Like [`fs::create_dir_all`].
+	pub fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if self.relative(path)?.is_some() {
+			let relative = path.strip_prefix(self.root).expect("Checked by `relative`; qed");
+			let mut dir = self.root.to_path_buf();
+			for component in relative.components() {
+				dir.push(component);
+				let (fd, name) = self.resolve(&dir)?;
+				// Safe, `mkdirat` only reads the name.
+				if unsafe { libc::mkdirat(fd, name.as_ptr(), 0o777) } != 0 {
+					let error = io::Error::last_os_error();
+					if error.kind() != io::ErrorKind::AlreadyExists {
+						return Err(error);
+					}
+				}
+			}
+			return Ok(());
+		}
+		fs::create_dir_all(path)
+	}
+
+	/// Like [`fs::rename`].
+	pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+		let (from, to) = (from.as_ref(), to.as_ref());
+		#[cfg(unix)]
+		if !self.is_by_path(from) || !self.is_by_path(to) {
+			let ((from_fd, from), (to_fd, to)) = (self.resolve(from)?, self.resolve(to)?);
+			// Safe, `renameat` only reads the names.
+			let result = unsafe { libc::renameat(from_fd, from.as_ptr(), to_fd, to.as_ptr()) };
+			return check(result);
+		}
+		fs::rename(from, to)
+	}
+
+	/// Like [`fs::hard_link`].
+	pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+		let (from, to) = (from.as_ref(), to.as_ref());
+		#[cfg(unix)]
+		if !self.is_by_path(from) || !self.is_by_path(to) {
+			let ((from_fd, from), (to_fd, to)) = (self.resolve(from)?, self.resolve(to)?);
+			// Safe, `linkat` only reads the names.
+			let result = unsafe { libc::linkat(from_fd, from.as_ptr(), to_fd, to.as_ptr(), 0) };
+			return check(result);
+		}
+		fs::hard_link(from, to)
+	}
+
+	/// Like [`fs::remove_file`].
+	pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			// Safe, `unlinkat` only reads the name.
+			return check(unsafe { libc::unlinkat(fd, name.as_ptr(), 0) });
+		}
+		fs::remove_file(path)
+	}
+
+	/// Like [`fs::remove_dir`].
+	pub fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		if let Some((fd, name)) = self.relative(path)? {
+			// Safe, `unlinkat` only reads the name.
+			return check(unsafe { libc::unlinkat(fd, name.as_ptr(), libc::AT_REMOVEDIR) });
+		}
+		fs::remove_dir(path)
+	}
+
+	/// Set the permission bits of `path` to `mode`.
+	#[cfg(unix)]
+	pub fn set_mode<P: AsRef<Path>>(&self, path: P, mode: u32) -> io::Result<()> {
+		let path = path.as_ref();
+		if let Some((fd, name)) = self.relative(path)? {
+			// Safe, `fchmodat` only reads the name.
+			return check(unsafe { libc::fchmodat(fd, name.as_ptr(), mode as libc::mode_t, 0) });
+		}
+		use std::os::unix::fs::PermissionsExt;
+		fs::set_permissions(path, fs::Permissions::from_mode(mode))
+	}
+
+	/// Sync the directory at `path`, making renames and newly created files in it durable.
+	pub fn sync_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		self.open(path)?.sync_all()?;
+		#[cfg(not(unix))]
+		let _ = path;
+		Ok(())
+	}
+
+	/// Returns `true` if `path` is accessed by path rather than through the file descriptor.
+	fn is_by_path(&self, path: &Path) -> bool {
+		#[cfg(unix)]
+		return self.fd.is_none() || !path.starts_with(self.root);
+		#[cfg(not(unix))]
+		{
+			let _ = path;
+			true
+		}
+	}
+
+	/// The file descriptor of the directory, if it is accessed through one.
+	#[cfg(unix)]
+	pub fn fd(&self) -> Option<RawFd> {
+		self.fd.map(|fd| fd.0)
+	}
+}
+
+/// Turn the result of a syscall into an [`io::Result`].
+#[cfg(unix)]
+fn check(result: libc::c_int) -> io::Result<()> {
+	if result == 0 {
+		Ok(())
+	} else {
+		Err(io::Error::last_os_error())
+	}
+}
+
+/// Returns the names of the entries of the open directory, other than `.` and `..`.
+#[cfg(unix)]
+fn read_dir_fd(dir: File) -> io::Result<Vec<PathBuf>> {
+	use std::os::unix::io::IntoRawFd;
+
+	let fd = dir.into_raw_fd();
+	// Safe, the stream takes ownership of the file descriptor and is closed below, the entries
+	// are only read until the next call to `readdir`.
+	unsafe {
+		let stream = libc::fdopendir(fd);
+		if stream.is_null() {
+			let error = io::Error::last_os_error();
+			libc::close(fd);
+			return Err(error);
+		}
+		let mut names = Vec::new();
+		loop {
+			let entry = libc::readdir(stream);
+			if entry.is_null() {
+				break;
+			}
+			let name = CStr::from_ptr((*entry).d_name.as_ptr()).to_bytes();
+			if name != b"." && name != b".." {
+				names.push(PathBuf::from(OsStr::from_bytes(name)));
+			}
+		}
+		libc::closedir(stream);
+		Ok(names)
+	}
+}
+
+/// A duplicate of the file descriptor of a keystore directory, closed on drop.
+#[cfg(unix)]
+pub(crate) struct DirFd(RawFd);
+
+#[cfg(unix)]
+impl DirFd {
+	/// Duplicate the file descriptor, failing if it is not a directory.
+	///
+	/// `root` is the path the files of the directory are named by.
+	pub fn duplicate(fd: RawFd, root: &Path) -> Result<Self> {
+		// Safe, `fcntl` and `fstat` only access the memory passed to them.
+		unsafe {
+			let dup = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0);
+			if dup < 0 {
+				return Err(io::Error::last_os_error().into());
+			}
+			let dir = Self(dup);
+			let mut stat = std::mem::zeroed::<libc::stat>();
+			if libc::fstat(dup, &mut stat) != 0 {
+				return Err(io::Error::last_os_error().into());
+			}
+			if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+				return Err(Error::NotADirectory(root.into()));
+			}
+			Ok(dir)
+		}
+	}
+}
+
+#[cfg(unix)]
+impl Drop for DirFd {
+	fn drop(&mut self) {
+		// Safe, the file descriptor is owned.
+		unsafe {
+			libc::close(self.0);
+		}
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::*;
+	use std::os::unix::io::AsRawFd;
+	use tempfile::TempDir;
+
+	#[test]
+	fn files_are_accessed_relative_to_the_file_descriptor() {
+		let temp_dir = TempDir::new().unwrap();
+		let moved = TempDir::new().unwrap();
+		let fd = DirFd::duplicate(File::open(temp_dir.path()).unwrap().as_raw_fd(), Path::new("x"))
+			.unwrap();
+		// The directory is reached through the file descriptor only, not by its path.
+		let path = moved.path().join("keystore");
+		fs::rename(temp_dir.path(), &path).unwrap();
+		let root = Path::new("fd:keystore");
+		let dir = Dir::at(root, &fd);
+
+		dir.create_dir_all(root.join("a/b")).unwrap();
+		dir.write(root.join("a/b/file"), b"content").unwrap();
+		assert_eq!(fs::read(path.join("a/b/file")).unwrap(), b"content");
+		assert!(dir.is_dir(root.join("a")));
+		assert!(dir.is_file(root.join("a/b/file")));
+		assert!(!dir.exists(root.join("missing")));
+
+		dir.rename(root.join("a/b/file"), root.join("renamed")).unwrap();
+		dir.hard_link(root.join("renamed"), root.join("linked")).unwrap();
+		let mut names = dir.read_dir(root).unwrap();
+		names.sort();
+		assert_eq!(names, vec![root.join("a"), root.join("linked"), root.join("renamed")]);
+		assert_eq!(dir.read(root.join("linked")).unwrap(), b"content");
+
+		dir.open_append(root.join("renamed")).unwrap().write_all(b"!").unwrap();
+		assert_eq!(dir.read_to_string(root.join("renamed")).unwrap(), "content!");
+		dir.set_mode(root.join("renamed"), 0o600).unwrap();
+		dir.sync_dir(root).unwrap();
+
+		dir.remove_file(root.join("renamed")).unwrap();
+		dir.remove_dir(root.join("a/b")).unwrap();
+		assert!(!path.join("renamed").exists());
+		assert!(!path.join("a/b").exists());
+		assert!(!Path::new("fd:keystore").exists());
+	}
+}
@@ -0,0 +1,162 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Signing inside a secure enclave.
+
+use sp_core::{
+	crypto::{CryptoTypePublicPair, ExposeSecret, KeyTypeId, Pair as PairT, SecretString},
+	Encode,
+};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+use sp_keystore::{
+	Error as TraitError,
+	vrf::{VRFTranscriptData, VRFSignature, make_transcript},
+};
+
+/// Performs the operations that need a secret key, inside an enclave.
+///
+/// A keystore opened with [`LocalKeystoreBuilder::enclave`] keeps enumerating keys and
+/// managing the key files itself, but never derives a key pair: it reads the stored secret of
+/// the key and hands it to the signer as opaque bytes. The secret is sealed to the enclave in
+/// such deployments, so it is only ever usable inside of it.
+///
+/// [`LocalKeystoreBuilder::enclave`]: crate::LocalKeystoreBuilder::enclave
+pub trait EnclaveSigner: Send + Sync {
+	/// Sign the message with the key, given its stored secret.
+	///
+	/// Returns the SCALE encoded signature, or `None` if the secret does not belong to the key.
+	fn sign(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		secret: &[u8],
+		msg: &[u8],
+	) -> Result<Option<Vec<u8>>, TraitError>;
+
+	/// Produce a VRF signature for the transcript with the sr25519 key, given its stored secret.
+	///
+	/// Returns `None` if the secret does not belong to the key.
+	fn vrf_sign(
+		&self,
+		id: KeyTypeId,
+		public: &sr25519::Public,
+		secret: &[u8],
+		transcript_data: VRFTranscriptData,
+	) -> Result<Option<VRFSignature>, TraitError>;
+}
+
+/// An [`EnclaveSigner`] that signs in process, treating the stored secrets as the secret URIs
+/// they are in a plain keystore.
+///
+/// Meant for testing the enclave integration, it offers none of the protection of an enclave.
+pub struct PassthroughSigner {
+	password: Option<SecretString>,
+}
+
+impl PassthroughSigner {
+	/// Create a signer deriving keys with the given password.
+	pub fn new(password: Option<SecretString>) -> Self {
+		Self { password }
+	}
+
+	fn pair<Pair: PairT>(&self, secret: &[u8], public: &[u8]) -> Result<Option<Pair>, TraitError> {
+		let suri = std::str::from_utf8(secret)
+			.map_err(|_| TraitError::ValidationError("Secret is not a secret URI".into()))?;
+		let password = self.password.as_ref().map(|p| p.expose_secret().as_str());
+		let pair = Pair::from_string(suri, password)
+			.map_err(|_| TraitError::ValidationError("Invalid secret URI".into()))?;
+		Ok(if pair.public().as_ref() == public { Some(pair) } else { None })
+	}
+}
+
+impl EnclaveSigner for PassthroughSigner {
+	fn sign(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		secret: &[u8],
+		msg: &[u8],
+	) -> Result<Option<Vec<u8>>, TraitError> {
+		Ok(match key.0 {
+			ed25519::CRYPTO_ID =>
+				self.pair::<ed25519::Pair>(secret, &key.1)?.map(|pair| pair.sign(msg).encode()),
+			sr25519::CRYPTO_ID =>
+				self.pair::<sr25519::Pair>(secret, &key.1)?.map(|pair| pair.sign(msg).encode()),
+			ecdsa::CRYPTO_ID =>
+				self.pair::<ecdsa::Pair>(secret, &key.1)?.map(|pair| pair.sign(msg).encode()),
+			_ => return Err(TraitError::KeyNotSupported(id)),
+		})
+	}
+
+	fn vrf_sign(
+		&self,
+		_id: KeyTypeId,
+		public: &sr25519::Public,
+		secret: &[u8],
+		transcript_data: VRFTranscriptData,
+	) -> Result<Option<VRFSignature>, TraitError> {
+		let pair = self.pair::<sr25519::Pair>(secret, public.as_ref())?;
+		Ok(pair.map(|pair| {
+			let (inout, proof, _) = pair.as_ref().vrf_sign(make_transcript(transcript_data));
+			VRFSignature { output: inout.to_output(), proof }
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use sp_core::testing::{ECDSA, SR25519};
+	use sp_keystore::{SyncCryptoStore, vrf::VRFTranscriptValue};
+	use tempfile::TempDir;
+	use crate::LocalKeystoreBuilder;
+
+	#[test]
+	fn enclave_signs_for_the_keystore() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(temp_dir.path())
+			.enclave(Arc::new(PassthroughSigner::new(None)))
+			.open()
+			.unwrap();
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let signature = SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg")
+			.unwrap()
+			.unwrap();
+		let signature = <sr25519::Signature as codec::Decode>::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, b"msg", &public));
+
+		let transcript = VRFTranscriptData {
+			label: b"Test",
+			items: vec![("slot", VRFTranscriptValue::U64(1))],
+		};
+		assert!(SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &public, transcript)
+			.unwrap()
+			.is_some());
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &sr25519::Public::default().into(), b"msg")
+			.unwrap()
+			.is_none());
+
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		assert!(matches!(
+			SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &ecdsa, &[0u8; 32]),
+			Err(TraitError::KeyNotSupported(_)),
+		));
+	}
+}
@@ -0,0 +1,167 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Signing with a nonce chosen by the caller, see [`LocalKeystore::sign_with_nonce`].
+//!
+//! Only compiled with the `testing` feature. The signatures are made like the regular ones of
+//! each crypto type, except that the nonce is taken as given instead of being derived from the
+//! secret and the message or drawn at random.
+//!
+//! [`LocalKeystore::sign_with_nonce`]: crate::LocalKeystore::sign_with_nonce
+
+use codec::Encode;
+use curve25519_dalek::{
+	constants::{ED25519_BASEPOINT_TABLE, RISTRETTO_BASEPOINT_TABLE},
+	scalar::Scalar,
+};
+use schnorrkel::context::SigningTranscript;
+use sha2::{Digest, Sha512};
+use sp_core::{crypto::CryptoTypeId, hashing::blake2_256};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+
+use crate::{Error, Result};
+
+/// Sign the message with the raw secret of the crypto type and the given nonce.
+///
+/// Returns the SCALE encoded signature, like `sign_with` does.
+pub(crate) fn sign(
+	scheme: CryptoTypeId,
+	secret: &[u8],
+	public: &[u8],
+	msg: &[u8],
+	nonce: &[u8],
+) -> Result<Vec<u8>> {
+	let mut fixed = [0u8; 32];
+	if nonce.len() != fixed.len() {
+		return Err(Error::InvalidNonce)
+	}
+	fixed.copy_from_slice(nonce);
+
+	match scheme {
+		sr25519::CRYPTO_ID => sign_sr25519(secret, public, msg, fixed),
+		ed25519::CRYPTO_ID => sign_ed25519(secret, public, msg, fixed),
+		ecdsa::CRYPTO_ID => sign_ecdsa(secret, msg, fixed),
+		scheme => Err(Error::UnknownCryptoType(scheme)),
+	}
+}
+
+/// Schnorrkel signing in the `substrate` context, with `r` taken from the nonce.
+fn sign_sr25519(secret: &[u8], public: &[u8], msg: &[u8], nonce: [u8; 32]) -> Result<Vec<u8>> {
+	let mut key = [0u8; 32];
+	key.copy_from_slice(secret.get(..32).ok_or(Error::InvalidSeed)?);
+	let key = Scalar::from_canonical_bytes(key).ok_or(Error::InvalidSeed)?;
+	let r = Scalar::from_bytes_mod_order(nonce);
+	let big_r = (&r * &RISTRETTO_BASEPOINT_TABLE).compress();
+
+	let mut transcript = schnorrkel::signing_context(b"substrate").bytes(msg);
+	// The transcript has deprecated inherent methods of the same names.
+	SigningTranscript::proto_name(&mut transcript, b"Schnorr-sig");
+	SigningTranscript::commit_bytes(&mut transcript, b"sign:pk", public);
+	SigningTranscript::commit_bytes(&mut transcript, b"sign:R", big_r.as_bytes());
+	let mut challenge = [0u8; 64];
+	SigningTranscript::challenge_bytes(&mut transcript, b"sign:c", &mut challenge);
+	let s = Scalar::from_bytes_mod_order_wide(&challenge) * key + r;
+
+	let mut signature = [0u8; 64];
+	signature[..32].copy_from_slice(big_r.as_bytes());
+	signature[32..].copy_from_slice(s.as_bytes());
+	// Marks the signature as schnorrkel, not ed25519.
+	signature[63] |= 128;
+	Ok(sr25519::Signature::from_raw(signature).encode())
+}
+
+/// Ed25519 signing with `r` taken from the nonce instead of the hash of the secret and message.
+fn sign_ed25519(secret: &[u8], public: &[u8], msg: &[u8], nonce: [u8; 32]) -> Result<Vec<u8>> {
+	let hash = Sha512::digest(secret);
+	let mut key = [0u8; 32];
+	key.copy_from_slice(&hash[..32]);
+	key[0] &= 248;
+	key[31] &= 127;
+	key[31] |= 64;
+	let key = Scalar::from_bits(key);
+	let r = Scalar::from_bytes_mod_order(nonce);
+	let big_r = (&r * &ED25519_BASEPOINT_TABLE).compress();
+
+	let k = Scalar::from_hash(Sha512::new().chain(big_r.as_bytes()).chain(public).chain(msg));
+	let s = k * key + r;
+
+	let mut signature = [0u8; 64];
+	signature[..32].copy_from_slice(big_r.as_bytes());
+	signature[32..].copy_from_slice(s.as_bytes());
+	Ok(ed25519::Signature::from_raw(signature).encode())
+}
+
+/// ECDSA signing of the blake2 hash of the message, with `k` taken from the nonce.
+fn sign_ecdsa(secret: &[u8], msg: &[u8], nonce: [u8; 32]) -> Result<Vec<u8>> {
+	use secp256k1::curve::{ECMULT_GEN_CONTEXT, Scalar};
+
+	let mut raw = [0u8; 32];
+	raw.copy_from_slice(secret.get(..32).ok_or(Error::InvalidSeed)?);
+	let mut key = Scalar::default();
+	if bool::from(key.set_b32(&raw)) || key.is_zero() {
+		return Err(Error::InvalidSeed)
+	}
+	let mut k = Scalar::default();
+	if bool::from(k.set_b32(&nonce)) || k.is_zero() {
+		return Err(Error::InvalidNonce)
+	}
+	let mut message = Scalar::default();
+	let _ = message.set_b32(&blake2_256(msg));
+
+	let (r, s, recovery_id) = ECMULT_GEN_CONTEXT.sign_raw(&key, &message, &k)
+		.map_err(|_| Error::InvalidNonce)?;
+	key.clear();
+	k.clear();
+
+	let mut signature = [0u8; 65];
+	signature[..32].copy_from_slice(&r.b32());
+	signature[32..64].copy_from_slice(&s.b32());
+	signature[64] = recovery_id;
+	Ok(ecdsa::Signature::from_raw(signature).encode())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::{Pair, crypto::CryptoTypePublicPair};
+	use sp_keystore::verify_signature;
+
+	#[test]
+	fn reused_nonces_give_the_same_commitment() {
+		fn check<P: Pair>(scheme: CryptoTypeId) {
+			let pair = P::from_string("//Alice", None).unwrap();
+			let public = CryptoTypePublicPair(scheme, pair.public().as_ref().to_vec());
+			let nonce = [7u8; 32];
+			let first = sign(scheme, &pair.to_raw_vec(), &public.1, b"first", &nonce).unwrap();
+			let second = sign(scheme, &pair.to_raw_vec(), &public.1, b"second", &nonce).unwrap();
+
+			assert!(verify_signature(&public, b"first", &first).unwrap());
+			assert!(verify_signature(&public, b"second", &second).unwrap());
+			assert_eq!(first[..32], second[..32]);
+			assert_ne!(first, second);
+		}
+
+		check::<sr25519::Pair>(sr25519::CRYPTO_ID);
+		check::<ed25519::Pair>(ed25519::CRYPTO_ID);
+		check::<ecdsa::Pair>(ecdsa::CRYPTO_ID);
+		assert!(matches!(
+			sign(ecdsa::CRYPTO_ID, &[1; 32], &[], b"msg", &[0; 32]),
+			Err(Error::InvalidNonce),
+		));
+	}
+}
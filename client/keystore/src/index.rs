@@ -0,0 +1,126 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Persisted index of the key files.
+//!
+//! The index maps every key to the path of its key file, relative to the keystore directory,
+//! so that looking up a key does not need to touch the directory. It is a cache: the key files
+//! stay authoritative, an index that does not match them is rebuilt.
+//!
+//! The index file is the magic `skix`, the format version and the SCALE encoded map.
+
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+use codec::{Decode, Encode};
+use sp_core::crypto::KeyTypeId;
+
+use crate::dir::Dir;
+
+/// Name of the index file in the keystore directory.
+///
+/// It is not named like a key file, so it is never mistaken for one.
+pub(crate) const INDEX_FILE: &str = "index.bin";
+
+const MAGIC: &[u8; 4] = b"skix";
+const VERSION: u8 = 1;
+
+/// The key files of a keystore, by key type and raw public key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct KeyIndex {
+	files: BTreeMap<(KeyTypeId, Vec<u8>), String>,
+}
+
+impl KeyIndex {
+	/// Load the index from the keystore directory.
+	///
+	/// Returns `None` if the index is missing or can not be decoded.
+	pub fn load(dir: Dir) -> Option<Self> {
+		let content = dir.read(dir.root().join(INDEX_FILE)).ok()?;
+		let encoded = content.strip_prefix(&MAGIC[..])?;
+		match encoded.split_first() {
+			Some((&VERSION, mut files)) => {
+				let files = Decode::decode(&mut files).ok()?;
+				Some(Self { files })
+			},
+			_ => None,
+		}
+	}
+
+	/// Write the index to the keystore directory.
+	///
+	/// The index is replaced atomically, but not synced: losing an update only means that the
+	/// index is rebuilt on the next start.
+	pub fn store(&self, dir: Dir) -> std::io::Result<()> {
+		let mut content = MAGIC.to_vec();
+		content.push(VERSION);
+		self.files.encode_to(&mut content);
+
+		let path = dir.root().join(INDEX_FILE);
+		let copy = path.with_extension("tmp");
+		dir.write(&copy, content)?;
+		dir.rename(&copy, &path)
+	}
+
+	/// Returns the path of the key file of the given key, relative to the keystore directory.
+	pub fn get(&self, key_type: KeyTypeId, public: &[u8]) -> Option<PathBuf> {
+		self.files.get(&(key_type, public.to_vec())).map(PathBuf::from)
+	}
+
+	/// Record the key file of the given key.
+	///
+	/// Paths that aren't valid unicode are not recorded, the key file is found without the
+	/// index then.
+	pub fn insert(&mut self, key_type: KeyTypeId, public: &[u8], file: &Path) {
+		if let Some(file) = file.to_str() {
+			self.files.insert((key_type, public.to_vec()), file.to_owned());
+		}
+	}
+
+	/// Returns the number of recorded key files.
+	pub fn len(&self) -> usize {
+		self.files.len()
+	}
+
+	/// Forget the key file of the given key.
+	pub fn remove(&mut self, key_type: KeyTypeId, public: &[u8]) {
+		self.files.remove(&(key_type, public.to_vec()));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+	use tempfile::TempDir;
+
+	#[test]
+	fn corrupt_index_is_not_loaded() {
+		let temp_dir = TempDir::new().unwrap();
+		assert_eq!(KeyIndex::load(Dir::new(temp_dir.path())), None);
+
+		let mut index = KeyIndex::default();
+		index.insert(KeyTypeId(*b"test"), &[1; 32], Path::new("74657374"));
+		index.store(Dir::new(temp_dir.path())).unwrap();
+		assert_eq!(KeyIndex::load(Dir::new(temp_dir.path())), Some(index));
+
+		let path = temp_dir.path().join(INDEX_FILE);
+		let mut content = fs::read(&path).unwrap();
+		content.truncate(content.len() - 1);
+		fs::write(&path, content).unwrap();
+		assert_eq!(KeyIndex::load(Dir::new(temp_dir.path())), None);
+	}
+}
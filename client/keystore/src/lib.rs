@@ -20,12 +20,79 @@
 
 #![warn(missing_docs)]
 use std::io;
-use sp_core::crypto::KeyTypeId;
+use sp_core::crypto::{CryptoTypeId, KeyTypeId};
 use sp_keystore::Error as TraitError;
 
 /// Local keystore implementation
+mod age;
+pub use age::AgeIdentity;
+mod archive;
+pub use archive::ArchiveKeystore;
+mod backup;
+pub use backup::VerifyReport;
+mod bip32;
+mod builder;
+pub use builder::LocalKeystoreBuilder;
+mod cache;
+mod cipher;
+mod clock;
+pub use clock::{Clock, MockClock, SystemClock};
+mod delegation;
+mod dir;
+pub use delegation::{DelegatedKeystore, DelegationToken};
+#[cfg(feature = "testing")]
+mod forced_nonce;
+mod index;
+mod pause;
+pub use pause::{MAX_WRITE_PAUSE, WritePauseGuard};
+mod pjs;
+pub use pjs::PjsMeta;
+mod wal;
+mod limit;
 mod local;
-pub use local::LocalKeystore;
+pub use local::{
+	LocalKeystore, LockedKeystore, DuplicateGroup, KeystoreSnapshot, PreflightReport, RecoveryIndex,
+	RecoveryReport, ReplicaReport, ReservationToken, UnlockProgress, DiskUsage, public_for_scheme,
+	verify_replica,
+};
+mod policy;
+pub use policy::{
+	DecryptFailurePolicy, DirectoryLayout, OverwritePolicy, SignHook, SingletonPolicy,
+	StrayFilePolicy, SuriPolicy,
+};
+mod enclave;
+pub use enclave::{EnclaveSigner, PassthroughSigner};
+mod metrics;
+pub use metrics::{HistogramSnapshot, KeystoreErrorEvent, SigningHistogram, SigningMetrics};
+mod queue;
+pub use queue::{SignRequest, SignResult, SignSender, SignWorker};
+mod scoped;
+pub use scoped::{Operation, Scope, ScopedKeystore};
+mod registry;
+pub use registry::{BackendFactory, BackendRegistry, FILE_SCHEME};
+#[cfg(feature = "sqlite")]
+pub use registry::SQLITE_SCHEME;
+mod routing;
+pub use routing::RoutingKeystore;
+mod session;
+pub use session::SessionKeys;
+mod shadow;
+pub use shadow::ShadowKeystore;
+mod shamir;
+mod sidecar;
+pub use sidecar::KeyMetadata;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteKeystore;
+pub use shamir::split_password;
+mod transfer;
+pub use transfer::{TransferChallenge, TransferState};
+pub mod well_known;
+#[cfg(unix)]
+pub mod uds;
+#[cfg(unix)]
+pub mod pkcs11;
 
 /// Keystore error.
 #[derive(Debug, derive_more::Display, derive_more::From)]
@@ -34,12 +101,37 @@ pub enum Error {
 	Io(io::Error),
 	/// JSON error.
 	Json(serde_json::Error),
+	/// Error of the SQLite database.
+	#[cfg(feature = "sqlite")]
+	Sqlite(rusqlite::Error),
+	/// IO error on a file of the keystore.
+	#[display(fmt="IO error on {}: {}", "path.display()", error)]
+	#[from(ignore)]
+	File {
+		/// The file the error occurred on.
+		path: std::path::PathBuf,
+		/// The error.
+		error: io::Error,
+	},
+	/// A key file that does not hold a secret URI.
+	#[display(fmt="Malformed key file {}: {}", "path.display()", error)]
+	#[from(ignore)]
+	MalformedKeyFile {
+		/// The key file.
+		path: std::path::PathBuf,
+		/// The error parsing it.
+		error: serde_json::Error,
+	},
 	/// Invalid password.
 	#[display(fmt="Invalid password")]
 	InvalidPassword,
 	/// Invalid BIP39 phrase
-	#[display(fmt="Invalid recovery phrase (BIP39) data")]
-	InvalidPhrase,
+	#[display(fmt="Invalid recovery phrase (BIP39) data: {}", _0)]
+	InvalidPhrase(String),
+	/// The crypto type is not supported
+	#[display(fmt="Unknown crypto type {:?}", _0)]
+	#[from(ignore)]
+	UnknownCryptoType(CryptoTypeId),
 	/// Invalid seed
 	#[display(fmt="Invalid seed")]
 	InvalidSeed,
@@ -49,6 +141,181 @@ pub enum Error {
 	/// Keystore unavailable
 	#[display(fmt="Keystore unavailable")]
 	Unavailable,
+	/// The requested key pair does not exist
+	#[display(fmt="Key pair not found")]
+	PairNotFound,
+	/// The key exists, but can not produce a VRF signature
+	#[display(fmt="VRF signing is not available for this key")]
+	VrfNotAvailable,
+	/// Ephemeral keys are disabled
+	#[display(fmt="Keys that are only held in memory are disabled")]
+	EphemeralDisabled,
+	/// The backup is malformed
+	#[display(fmt="Invalid backup: {}", _0)]
+	#[from(ignore)]
+	InvalidBackup(String),
+	/// Imported keys collide with existing keys
+	#[display(fmt="Keys already exist: {:?}", _0)]
+	KeysExist(Vec<(KeyTypeId, Vec<u8>)>),
+	/// A key with the same public key already exists under the key type
+	#[display(fmt="Key already exists under the key type")]
+	KeyCollision,
+	/// The public key does not derive from the secret URI
+	#[display(fmt="Public key {} does not derive from the secret URI", "hex::encode(expected)")]
+	#[from(ignore)]
+	PublicKeyMismatch {
+		/// The public key the secret URI was inserted under.
+		expected: Vec<u8>,
+		/// The public keys the secret URI derives to with every supported crypto type.
+		derived: Vec<Vec<u8>>,
+	},
+	/// The limit of keys on disk is reached
+	#[display(fmt="Limit of {} keys on disk reached", limit)]
+	#[from(ignore)]
+	KeyLimitReached {
+		/// The configured limit.
+		limit: usize,
+	},
+	/// The keystore directory is inconsistent
+	#[display(fmt="Keystore is inconsistent: {}", "_0.join(\"; \")")]
+	#[from(ignore)]
+	Inconsistent(Vec<String>),
+	/// The keystore directory holds files that are not key files
+	#[display(fmt="Unknown files in the keystore directory: {:?}", _0)]
+	#[from(ignore)]
+	StrayFiles(Vec<std::path::PathBuf>),
+	/// The keystore can not be modified
+	#[display(fmt="Keystore is read-only")]
+	ReadOnly,
+	/// A session key can not be produced
+	#[display(fmt="Invalid session key of type {:?}: {}", key_type, reason)]
+	#[from(ignore)]
+	InvalidSessionKey {
+		/// The key type of the session key.
+		key_type: KeyTypeId,
+		/// Why the key can not be produced.
+		reason: String,
+	},
+	/// The key archive is malformed
+	#[display(fmt="Invalid key archive: {}", _0)]
+	#[from(ignore)]
+	InvalidArchive(String),
+	/// A polkadot-js keystore JSON file is malformed
+	#[display(fmt="Invalid polkadot-js keystore file: {}", _0)]
+	#[from(ignore)]
+	InvalidPjsJson(String),
+	/// The response of a key transfer is malformed or not encrypted to this destination
+	#[display(fmt="Invalid key transfer: {}", _0)]
+	#[from(ignore)]
+	InvalidTransfer(String),
+	/// The keystore path is not a directory
+	#[display(fmt="Keystore path {} is not a directory", "_0.display()")]
+	#[from(ignore)]
+	NotADirectory(std::path::PathBuf),
+	/// The keystore directory is on a file system keystores must not be used on
+	#[display(
+		fmt="Keystore directory {} is on a network file system ({})",
+		"path.display()", filesystem,
+	)]
+	#[from(ignore)]
+	UnsupportedFilesystem {
+		/// The keystore directory.
+		path: std::path::PathBuf,
+		/// The name of the file system.
+		filesystem: String,
+	},
+	/// The keystore directory can not be listed
+	#[display(fmt="Keystore directory {} is not readable", "_0.display()")]
+	#[from(ignore)]
+	DirectoryNotReadable(std::path::PathBuf),
+	/// The keystore directory or a key file is accessible by group or others
+	#[display(
+		fmt="{} is accessible by group or others, restrict it with `chmod go-rwx {}`",
+		"_0.display()", "_0.display()",
+	)]
+	#[from(ignore)]
+	InsecurePermissions(std::path::PathBuf),
+	/// The keystore holds too few keys of the required key types
+	#[display(fmt="Missing required keys of the key types {:?}", _0)]
+	#[from(ignore)]
+	MissingRequiredKeys(Vec<KeyTypeId>),
+	/// A restored key does not derive from its secret URI
+	#[display(
+		fmt="Key {} of type {:?} does not derive from its secret URI",
+		"hex::encode(public)", key_type,
+	)]
+	#[from(ignore)]
+	CorruptKeyFile {
+		/// The key type of the key.
+		key_type: KeyTypeId,
+		/// The public key.
+		public: Vec<u8>,
+	},
+	/// The message to sign is longer than allowed
+	#[display(fmt="Message of {} bytes is longer than the limit of {} bytes", len, max)]
+	#[from(ignore)]
+	MessageTooLarge {
+		/// The length of the message.
+		len: usize,
+		/// The configured limit.
+		max: usize,
+	},
+	/// The keystore is used in a process forked from the one that opened it
+	#[display(
+		fmt="Keystore was opened by process {}, re-open it in process {}",
+		opened_by, current,
+	)]
+	#[from(ignore)]
+	ForkedProcess {
+		/// The process that opened the keystore.
+		opened_by: u32,
+		/// The current process.
+		current: u32,
+	},
+	/// A password share is malformed or does not fit the other shares
+	#[display(fmt="Invalid password share: {}", _0)]
+	#[from(ignore)]
+	InvalidShare(String),
+	/// The alias is empty or contains whitespace
+	#[display(fmt="Invalid alias {:?}", _0)]
+	#[from(ignore)]
+	InvalidAlias(String),
+	/// Another key already has the alias
+	#[display(fmt="Alias {:?} is already used by another key", _0)]
+	#[from(ignore)]
+	AliasExists(String),
+	/// No key with the requested address prefix was found
+	#[display(fmt="No key with the address prefix found in {} attempts", attempts)]
+	#[from(ignore)]
+	VanityNotFound {
+		/// The number of keys tried.
+		attempts: usize,
+	},
+	/// The nonce is not a valid nonce of the crypto type
+	#[cfg(feature = "testing")]
+	#[display(fmt="Invalid nonce")]
+	InvalidNonce,
+	/// The keystore is locked and does not sign
+	#[display(fmt="Keystore is locked")]
+	Locked,
+	/// The writes to the keystore stayed paused for too long
+	#[display(fmt="Writes to the keystore are paused")]
+	WritesPaused,
+	/// All slots for signing operations are taken
+	#[display(fmt="Limit of {} concurrent signing operations reached", limit)]
+	#[from(ignore)]
+	SignLimitReached {
+		/// The configured limit.
+		limit: usize,
+	},
+	/// No backend is registered for the scheme of the URI
+	#[display(fmt="No keystore backend for {:?}", _0)]
+	#[from(ignore)]
+	UnknownBackend(String),
+	/// The signing hook refused to sign
+	#[display(fmt="Signing refused: {}", _0)]
+	#[from(ignore)]
+	PermissionDenied(String),
 }
 
 /// Keystore Result
@@ -58,12 +325,52 @@ impl From<Error> for TraitError {
 	fn from(error: Error) -> Self {
 		match error {
 			Error::KeyNotSupported(id) => TraitError::KeyNotSupported(id),
-			Error::InvalidSeed | Error::InvalidPhrase | Error::InvalidPassword => {
+			Error::InvalidSeed |
+			Error::InvalidPhrase(_) |
+			Error::InvalidPassword |
+			Error::InvalidBackup(_) |
+			Error::KeysExist(_) |
+			Error::KeyCollision |
+			Error::KeyLimitReached { .. } |
+			Error::PublicKeyMismatch { .. } |
+			Error::Inconsistent(_) |
+			Error::StrayFiles(_) |
+			Error::InvalidArchive(_) |
+			Error::InvalidPjsJson(_) |
+			Error::InvalidTransfer(_) |
+			Error::NotADirectory(_) |
+			Error::DirectoryNotReadable(_) |
+			Error::InsecurePermissions(_) |
+			Error::UnsupportedFilesystem { .. } |
+			Error::MissingRequiredKeys(_) |
+			Error::InvalidSessionKey { .. } |
+			Error::InvalidShare(_) |
+			Error::InvalidAlias(_) |
+			Error::AliasExists(_) |
+			Error::VanityNotFound { .. } |
+			Error::UnknownBackend(_) |
+			Error::CorruptKeyFile { .. } |
+			Error::MessageTooLarge { .. } |
+			Error::EphemeralDisabled => {
 				TraitError::ValidationError(error.to_string())
 			},
-			Error::Unavailable => TraitError::Unavailable,
+			Error::Unavailable |
+			Error::Locked |
+			Error::WritesPaused |
+			Error::SignLimitReached { .. } => TraitError::Unavailable,
+			Error::ReadOnly => TraitError::ReadOnly,
+			Error::PermissionDenied(_) => TraitError::PermissionDenied,
+			Error::PairNotFound => TraitError::PairNotFound,
+			Error::VrfNotAvailable => TraitError::VrfNotAvailable,
+			Error::UnknownCryptoType(id) => TraitError::UnknownCryptoType(id),
 			Error::Io(e) => TraitError::Other(e.to_string()),
 			Error::Json(e) => TraitError::Other(e.to_string()),
+			#[cfg(feature = "sqlite")]
+			Error::Sqlite(e) => TraitError::Other(e.to_string()),
+			Error::File { .. } | Error::MalformedKeyFile { .. } | Error::ForkedProcess { .. } =>
+				TraitError::Other(error.to_string()),
+			#[cfg(feature = "testing")]
+			Error::InvalidNonce => TraitError::ValidationError(error.to_string()),
 		}
 	}
 }
@@ -73,6 +380,10 @@ impl std::error::Error for Error {
 		match self {
 			Error::Io(ref err) => Some(err),
 			Error::Json(ref err) => Some(err),
+			#[cfg(feature = "sqlite")]
+			Error::Sqlite(ref err) => Some(err),
+			Error::File { ref error, .. } => Some(error),
+			Error::MalformedKeyFile { ref error, .. } => Some(error),
 			_ => None,
 		}
 	}
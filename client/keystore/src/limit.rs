@@ -0,0 +1,158 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Bounding the number of signing operations that run at once, see
+//! [`LocalKeystore::set_max_concurrent_signs`].
+//!
+//! [`LocalKeystore::set_max_concurrent_signs`]: crate::LocalKeystore::set_max_concurrent_signs
+
+use std::{cell::Cell, sync::Arc};
+use async_lock::{Semaphore, SemaphoreGuardArc};
+use parking_lot::RwLock;
+use crate::{Error, Result};
+
+thread_local! {
+	/// Whether the operation running on this thread holds a permit already, see
+	/// [`SignLimiter::run`].
+	// A `const` initializer needs Rust 1.59.
+	#[allow(clippy::missing_const_for_thread_local)]
+	static PERMITTED: Cell<bool> = Cell::new(false);
+}
+
+/// The limit used by default: one operation per core, leaving one core for block authoring.
+pub(crate) fn default_max() -> usize {
+	num_cpus::get().saturating_sub(1).max(1)
+}
+
+/// Lets at most `max` signing operations run at once, the others wait for a free slot.
+///
+/// The limit is [`default_max`] by default.
+pub(crate) struct SignLimiter {
+	/// The slots along with their number.
+	semaphore: RwLock<Option<(Arc<Semaphore>, usize)>>,
+}
+
+impl Default for SignLimiter {
+	fn default() -> Self {
+		let limiter = SignLimiter { semaphore: RwLock::new(None) };
+		limiter.set_max(Some(default_max()));
+		limiter
+	}
+}
+
+impl SignLimiter {
+	/// Change the limit, `None` lets every operation run right away.
+	///
+	/// A limit of zero is treated as one. Operations that are running or waiting already are
+	/// counted against the previous limit.
+	pub fn set_max(&self, max: Option<usize>) {
+		let max = max.map(|max| max.max(1));
+		*self.semaphore.write() = max.map(|max| (Arc::new(Semaphore::new(max)), max));
+	}
+
+	/// Wait until fewer than `max` operations run and count this one in until the permit is
+	/// dropped. Returns `None` if there is no limit.
+	pub async fn acquire(&self) -> Option<SignPermit> {
+		let (semaphore, _) = self.semaphore.read().clone()?;
+		Some(semaphore.acquire_arc().await)
+	}
+
+	/// Like [`SignLimiter::acquire`], but fails with [`Error::SignLimitReached`] instead of
+	/// waiting if all slots are taken.
+	///
+	/// Sync callers may run on the threads of an executor, waiting would park them. Returns
+	/// `Ok(None)` right away if the operation running on this thread holds a permit already.
+	pub fn try_acquire(&self) -> Result<Option<SignPermit>> {
+		if PERMITTED.with(Cell::get) {
+			return Ok(None);
+		}
+		match self.semaphore.read().clone() {
+			Some((semaphore, limit)) => semaphore.try_acquire_arc()
+				.map(Some)
+				.ok_or(Error::SignLimitReached { limit }),
+			None => Ok(None),
+		}
+	}
+
+	/// Wait for a free slot without blocking the thread, then run `operation` with it.
+	///
+	/// Signing in `operation` does not wait for another slot.
+	pub async fn run<R>(&self, operation: impl FnOnce() -> R) -> R {
+		struct Reset(bool);
+
+		impl Drop for Reset {
+			fn drop(&mut self) {
+				PERMITTED.with(|permitted| permitted.set(self.0));
+			}
+		}
+
+		let _permit = self.acquire().await;
+		let _reset = Reset(PERMITTED.with(|permitted| permitted.replace(true)));
+		operation()
+	}
+}
+
+/// A running signing operation, see [`SignLimiter::acquire`].
+pub(crate) type SignPermit = SemaphoreGuardArc;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{FutureExt, executor::block_on};
+
+	#[test]
+	fn the_limit_defaults_to_all_cores_but_one() {
+		let limiter = SignLimiter::default();
+		let permits = (0..default_max()).map(|_| limiter.try_acquire().unwrap().unwrap())
+			.collect::<Vec<_>>();
+		assert!(matches!(
+			limiter.try_acquire(),
+			Err(Error::SignLimitReached { limit }) if limit == default_max()
+		));
+		drop(permits);
+		assert!(limiter.try_acquire().unwrap().is_some());
+	}
+
+	#[test]
+	fn excess_sync_operations_fail() {
+		let limiter = SignLimiter::default();
+		limiter.set_max(Some(2));
+		let first = limiter.try_acquire().unwrap();
+		let _second = limiter.try_acquire().unwrap();
+		assert!(matches!(limiter.try_acquire(), Err(Error::SignLimitReached { limit: 2 })));
+		drop(first);
+		assert!(limiter.try_acquire().unwrap().is_some());
+
+		limiter.set_max(None);
+		let more = (0..8).map(|_| limiter.try_acquire().unwrap()).collect::<Vec<_>>();
+		assert!(more.iter().all(Option::is_none));
+	}
+
+	#[test]
+	fn async_operations_await_a_free_slot() {
+		let limiter = SignLimiter::default();
+		limiter.set_max(Some(1));
+		let running = limiter.try_acquire().unwrap();
+
+		let mut waiting = Box::pin(limiter.run(|| limiter.try_acquire().unwrap().is_none()));
+		assert!((&mut waiting).now_or_never().is_none());
+		drop(running);
+		assert!(block_on(waiting));
+		assert!(limiter.try_acquire().unwrap().is_some());
+	}
+}
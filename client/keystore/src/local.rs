@@ -18,725 +18,8038 @@
 //! Local keystore implementation
 
 use std::{
-	collections::{HashMap, HashSet},
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
 	fs::{self, File},
-	io::Write,
-	path::PathBuf,
-	sync::Arc,
+	io::{self, Write},
+	path::{Path, PathBuf},
+	sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}},
+	time::{Duration, SystemTime},
 };
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use crate::dir::DirFd;
 use async_trait::async_trait;
-use parking_lot::RwLock;
+use futures::channel::mpsc;
+use bip39::{Language, Mnemonic};
+use parking_lot::{Mutex, RwLock};
+use rand::{RngCore, rngs::OsRng};
 use sp_core::{
-	crypto::{CryptoTypePublicPair, KeyTypeId, Pair as PairT, ExposeSecret, SecretString, Public},
+	crypto::{
+		CryptoTypeId, CryptoTypePublicPair, KeyTypeId, Pair as PairT, ExposeSecret, SecretString,
+		SecretStringError, Public, Ss58AddressFormat, Ss58Codec,
+	},
 	sr25519::{Public as Sr25519Public, Pair as Sr25519Pair},
-	Encode,
+	Decode, Encode,
 };
 use sp_keystore::{
 	CryptoStore,
 	SyncCryptoStorePtr,
 	Error as TraitError,
 	SyncCryptoStore,
+	keys_fingerprint, Attestation,
 	vrf::{VRFTranscriptData, VRFSignature, make_transcript},
 };
-use sp_application_crypto::{ed25519, sr25519, ecdsa, AppPair, AppKey, IsWrappedBy};
+use sp_application_crypto::{ed25519, sr25519, ecdsa, AppPair, AppKey, AppPublic, IsWrappedBy};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::{Result, Error};
+use crate::{
+	AgeIdentity, Clock, Result, Error, EnclaveSigner, KeystoreErrorEvent, LocalKeystoreBuilder,
+	SigningMetrics, SignResult, SignSender, SignWorker, SystemClock,
+	backup::{self, BackupEntry, VerifyReport},
+	bip32,
+	builder::Location,
+	cache::{ActiveKey, ActiveKeys, SignatureCache},
+	cipher::MemoryCipher,
+	delegation::{self, DelegationToken},
+	limit::SignLimiter,
+	pause::{MAX_WRITE_PAUSE, WriteGate, WritePauseGuard, WriteTicket},
+	pjs::{self, PjsMeta, PjsPair},
+	policy::{
+		DecryptFailurePolicy, DirectoryLayout, OverwritePolicy, SignHook, SingletonPolicy,
+		StrayFilePolicy, SuriPolicy,
+	},
+	sidecar::{self, KeyMetadata, SIDECAR_EXTENSIONS},
+	shamir::PendingShares,
+	dir::Dir,
+	index::{INDEX_FILE, KeyIndex},
+	transfer::{self, TransferChallenge, TransferPayload, TransferState},
+	wal::{WAL_FILE, Wal, WalRecord},
+	well_known::{self, ConsensusKeyType},
+};
+
+/// Extensions of the copies an interrupted update can leave next to a key file.
+///
+/// The order is the order in which copies are tried when the key file is unusable: a `.tmp`
+/// copy holds a write that was not moved into place yet, a `.bak` copy the previous content.
+const RECOVERY_EXTENSIONS: [&str; 2] = ["tmp", "bak"];
+
+/// Name of the snapshot in the durable directory of a tiered keystore.
+const SNAPSHOT_FILE: &str = "keystore.snapshot";
+
+/// Default of the length in bytes of the longest message `sign_with` signs.
+const DEFAULT_MAX_SIGN_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Mode of the keystore directories created on open.
+#[cfg(unix)]
+const KEYSTORE_DIR_MODE: u32 = 0o700;
+
+/// Mode of the key files written by the keystore.
+#[cfg(unix)]
+const KEY_FILE_MODE: u32 = 0o600;
+
+/// How far unlocking a keystore with password shares is, see [`LocalKeystore::add_share`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockProgress {
+	/// More shares are needed.
+	Pending {
+		/// The number of distinct shares added.
+		added: usize,
+		/// The number of shares needed.
+		threshold: usize,
+	},
+	/// The password was reconstructed and the keystore is unlocked.
+	Unlocked,
+}
+
+/// A key generated by [`LocalKeystore::generate_reserved`] that is not stored yet.
+///
+/// Pass it to [`LocalKeystore::commit`] to store the key or to [`LocalKeystore::discard`] to
+/// drop it. Only the keystore that generated the key knows the token.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use]
+pub struct ReservationToken(u64);
+
+/// Keys that were generated but are neither stored nor discarded yet.
+#[derive(Default)]
+struct Reservations {
+	/// The token of the next reservation.
+	next: u64,
+	keys: HashMap<u64, Reservation>,
+}
+
+/// A reserved key, the secret URI is zeroized when it is dropped.
+struct Reservation {
+	key_type: KeyTypeId,
+	public: CryptoTypePublicPair,
+	suri: Zeroizing<String>,
+}
+
+/// A view of a [`LocalKeystore`] that is locked for exclusive use.
+///
+/// See [`LocalKeystore::with_exclusive`].
+pub struct LockedKeystore<'a> {
+	inner: RefCell<&'a mut KeystoreInner>,
+	writing: Option<WriteTicket>,
+}
+
+impl LockedKeystore<'_> {
+	/// Returns `true` if the key with the given key type and raw public key exists.
+	pub fn contains(&self, id: KeyTypeId, public: &[u8]) -> Result<bool> {
+		self.inner.borrow().contains_key(public, id)
+	}
+
+	/// Generate a new key of the given crypto type, like the `*_generate_new` methods do
+	/// without a seed.
+	pub fn generate(&self, id: KeyTypeId, scheme: CryptoTypeId) -> Result<CryptoTypePublicPair> {
+		self.check_writing()?;
+		self.inner.borrow_mut().generate_by_scheme(id, scheme)
+	}
+
+	/// Insert a key, like `insert_unknown` does.
+	pub fn insert(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> Result<()> {
+		self.check_writing()?;
+		self.inner.borrow_mut().insert_unknown(id, suri, public)
+	}
+
+	/// Fails with [`Error::WritesPaused`] if the view was handed out while writes were paused.
+	fn check_writing(&self) -> Result<()> {
+		self.writing.as_ref().map(|_| ()).ok_or(Error::WritesPaused)
+	}
+}
 
 /// A local based keystore that is either memory-based or filesystem-based.
+///
+/// Key files are read lazily: opening the keystore only looks at the file names, the content of a
+/// key file is read when its key is listed or used, e.g. to sign. Listing a key type reads its
+/// key files to leave out the ones that fail to parse. Call [`LocalKeystore::prefetch`] to read
+/// the key files of some key types up front.
 pub struct LocalKeystore(RwLock<KeystoreInner>);
 
 impl LocalKeystore {
 	/// Create a local keystore from filesystem.
+	///
+	/// Opens the directory like [`LocalKeystoreBuilder::open`], use [`LocalKeystoreBuilder`] for
+	/// the other options.
 	pub fn open<T: Into<PathBuf>>(path: T, password: Option<SecretString>) -> Result<Self> {
-		let inner = KeystoreInner::open(path, password)?;
-		Ok(Self(RwLock::new(inner)))
+		LocalKeystoreBuilder::new(path).password(password).open()
 	}
 
-	/// Create a local keystore in memory.
-	pub fn in_memory() -> Self {
-		let inner = KeystoreInner::new_in_memory();
-		Self(RwLock::new(inner))
+	/// Open the keystore with the options of the builder.
+	pub(crate) fn from_builder(builder: LocalKeystoreBuilder) -> Result<Self> {
+		let LocalKeystoreBuilder {
+			location,
+			password,
+			layout,
+			allow_network_fs,
+			stray_file_policy,
+			required,
+			share_threshold,
+			rng,
+			enclave,
+			durable,
+			wal,
+		} = builder;
+		let mut inner = match location {
+			Location::Path(path) =>
+				KeystoreInner::open_with_options(path, password, layout, allow_network_fs)?,
+			#[cfg(unix)]
+			Location::Fd(dirfd) =>
+				KeystoreInner::open_at(dirfd, password, layout, allow_network_fs)?,
+		};
+		if let Some(threshold) = share_threshold {
+			inner.pending_shares = Some(PendingShares::new(threshold));
+			inner.unlocked.store(false, Ordering::SeqCst);
+		}
+		inner.rng = rng.map(Mutex::new);
+		inner.enclave = enclave;
+		if wal {
+			inner.wal = inner.path.as_deref().map(Wal::new);
+			inner.replay_wal()?;
+		}
+		if let Some((dir, snapshot_password)) = durable {
+			inner.open_durable(dir, snapshot_password)?;
+		}
+		inner.check_stray_files(stray_file_policy)?;
+		inner.check_required(&required)?;
+		Ok(Self(RwLock::new(inner)))
 	}
 
-	/// Get a key pair for the given public key.
+	/// Add a share of the password of a keystore opened with
+	/// [`LocalKeystoreBuilder::password_shares`].
 	///
-	/// Returns `Ok(None)` if the key doesn't exist, `Ok(Some(_))` if the key exists and
-	/// `Err(_)` when something failed.
-	pub fn key_pair<Pair: AppPair>(&self, public: &<Pair as AppKey>::Public) -> Result<Option<Pair>> {
-		self.0.read().key_pair::<Pair>(public)
+	/// Once enough distinct shares were added, the password is reconstructed from them and the
+	/// keystore unlocked; the shares are zeroized. Fails with [`Error::InvalidShare`] for
+	/// malformed shares, shares of another threshold and if the keystore does not wait for
+	/// shares. A wrong share is only noticed once the reconstructed password fails to derive
+	/// the keys.
+	pub fn add_share(&self, share: &[u8]) -> Result<UnlockProgress> {
+		let mut inner = self.0.write();
+		let pending = inner.pending_shares.as_mut()
+			.ok_or_else(|| Error::InvalidShare("The keystore does not wait for shares".into()))?;
+		match pending.add(share)? {
+			Some(password) => {
+				inner.pending_shares = None;
+				inner.password = Some(password);
+				inner.unlocked.store(true, Ordering::SeqCst);
+				let key_types = inner.all_public_keys()?.into_iter().map(|(key_type, _)| key_type);
+				inner.invalidated(key_types);
+				Ok(UnlockProgress::Unlocked)
+			},
+			None => Ok(UnlockProgress::Pending {
+				added: pending.added(),
+				threshold: pending.threshold(),
+			}),
+		}
 	}
-}
 
-#[async_trait]
-impl CryptoStore for LocalKeystore {
-	async fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
-		SyncCryptoStore::keys(self, id)
+	/// Snapshot the keys on disk to the durable directory.
+	///
+	/// The previous snapshot is replaced atomically. Does nothing unless the keystore was
+	/// opened with [`LocalKeystoreBuilder::durable`].
+	pub fn snapshot_to_durable(&self) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.read().snapshot_to_durable()
 	}
 
-	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
-		SyncCryptoStore::sr25519_public_keys(self, id)
+	/// Generate a new key of the given consensus key type, with the crypto type of the engine.
+	pub fn generate_consensus(&self, key_type: ConsensusKeyType) -> Result<CryptoTypePublicPair> {
+		let _writing = self.writing()?;
+		self.0.write().generate_by_scheme(key_type.key_type(), key_type.crypto())
 	}
 
-	async fn sr25519_generate_new(
+	/// Generate a new key of the given crypto type without storing it.
+	///
+	/// The key is only held in memory until it is stored with [`LocalKeystore::commit`] or
+	/// dropped with [`LocalKeystore::discard`], so its public key can be inspected before
+	/// deciding to keep it. Keys that are neither are dropped along with the keystore.
+	pub fn generate_reserved(
 		&self,
 		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<sr25519::Public, TraitError> {
-		SyncCryptoStore::sr25519_generate_new(self, id, seed)
-	}
-
-	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
-		SyncCryptoStore::ed25519_public_keys(self, id)
+		scheme: CryptoTypeId,
+	) -> Result<(CryptoTypePublicPair, ReservationToken)> {
+		let _writing = self.writing()?;
+		self.0.write().generate_reserved(id, scheme)
 	}
 
-	async fn ed25519_generate_new(
-		&self,
-		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<ed25519::Public, TraitError> {
-		SyncCryptoStore::ed25519_generate_new(self, id, seed)
+	/// Store a key that was generated with [`LocalKeystore::generate_reserved`].
+	///
+	/// Fails with [`Error::PairNotFound`] if the token belongs to another keystore.
+	pub fn commit(&self, token: ReservationToken) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().commit_reserved(token)
 	}
 
-	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
-		SyncCryptoStore::ecdsa_public_keys(self, id)
+	/// Drop a key that was generated with [`LocalKeystore::generate_reserved`].
+	pub fn discard(&self, token: ReservationToken) {
+		self.0.write().reservations.keys.remove(&token.0);
 	}
 
-	async fn ecdsa_generate_new(
+	/// Replace a key by a new one of the same crypto type, returning the new key and a linkage
+	/// signature.
+	///
+	/// The linkage is the raw old public key followed by the raw new public key, signed with
+	/// the old key; publishing it lets verifiers accept the new key in place of the old one.
+	/// The old key is only removed once the linkage is signed, and the new key is only kept if
+	/// that succeeds. The linkage passes the signing hook like any message.
+	pub fn rekey_with_linkage(
 		&self,
 		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<ecdsa::Public, TraitError> {
-		SyncCryptoStore::ecdsa_generate_new(self, id, seed)
+		old_public: &CryptoTypePublicPair,
+	) -> Result<(CryptoTypePublicPair, Vec<u8>)> {
+		let _writing = self.writing()?;
+		let mut inner = self.0.write();
+		match old_public.0 {
+			sr25519::CRYPTO_ID => inner.rekey_by_type::<sr25519::Pair>(id, &old_public.1),
+			ed25519::CRYPTO_ID => inner.rekey_by_type::<ed25519::Pair>(id, &old_public.1),
+			ecdsa::CRYPTO_ID => inner.rekey_by_type::<ecdsa::Pair>(id, &old_public.1),
+			_ => Err(Error::KeyNotSupported(id)),
+		}
 	}
 
-	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> std::result::Result<(), ()> {
-		SyncCryptoStore::insert_unknown(self, id, suri, public)
+	/// Generate a new key of the default crypto type, see [`LocalKeystore::set_default_scheme`].
+	///
+	/// Behaves like the `*_generate_new` methods without a seed. Fails with
+	/// [`Error::KeyNotSupported`] if the default crypto type is not supported.
+	pub fn generate_default(&self, id: KeyTypeId) -> Result<CryptoTypePublicPair> {
+		let _writing = self.writing()?;
+		let mut inner = self.0.write();
+		let scheme = inner.default_scheme;
+		inner.generate_by_scheme(id, scheme)
 	}
 
-	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
-		SyncCryptoStore::has_keys(self, public_keys)
+	/// Generate a new BABE key.
+	pub fn generate_babe(&self) -> Result<sr25519::Public> {
+		let _writing = self.writing()?;
+		self.0.write().generate_by_type::<sr25519::Pair>(well_known::BABE).map(|p| p.public())
 	}
 
-	async fn supported_keys(
-		&self,
-		id: KeyTypeId,
-		keys: Vec<CryptoTypePublicPair>,
-	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
-		SyncCryptoStore::supported_keys(self, id, keys)
+	/// Returns all BABE keys.
+	pub fn babe_keys(&self) -> Vec<sr25519::Public> {
+		SyncCryptoStore::sr25519_public_keys(self, well_known::BABE)
 	}
 
-	async fn sign_with(
-		&self,
-		id: KeyTypeId,
-		key: &CryptoTypePublicPair,
-		msg: &[u8],
-	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
-		SyncCryptoStore::sign_with(self, id, key, msg)
+	/// Generate a new GRANDPA key.
+	pub fn generate_grandpa(&self) -> Result<ed25519::Public> {
+		let _writing = self.writing()?;
+		self.0.write().generate_by_type::<ed25519::Pair>(well_known::GRANDPA).map(|p| p.public())
 	}
 
-	async fn sr25519_vrf_sign(
-		&self,
-		key_type: KeyTypeId,
-		public: &sr25519::Public,
-		transcript_data: VRFTranscriptData,
-	) -> std::result::Result<Option<VRFSignature>, TraitError> {
-		SyncCryptoStore::sr25519_vrf_sign(self, key_type, public, transcript_data)
+	/// Returns all GRANDPA keys.
+	pub fn grandpa_keys(&self) -> Vec<ed25519::Public> {
+		SyncCryptoStore::ed25519_public_keys(self, well_known::GRANDPA)
 	}
 
-	async fn ecdsa_sign_prehashed(
-		&self,
-		id: KeyTypeId,
-		public: &ecdsa::Public,
-		msg: &[u8; 32],
-	) -> std::result::Result<Option<ecdsa::Signature>, TraitError> {
-		SyncCryptoStore::ecdsa_sign_prehashed(self, id, public, msg)
+	/// Returns the ed25519 key the node identifies itself with on the network.
+	///
+	/// The key is the single key of the reserved key type [`well_known::NODE_IDENTITY`], so the
+	/// network identity is managed and backed up like the other keys. If there is none, one is
+	/// generated when `create` is set; otherwise this fails with [`Error::PairNotFound`]. Fails
+	/// with [`Error::Inconsistent`] if there are several.
+	pub fn node_identity(&self, create: bool) -> Result<ed25519::Public> {
+		let _writing = self.writing()?;
+		self.0.write().node_identity(create)
 	}
-}
 
-impl SyncCryptoStore for LocalKeystore {
-	fn keys(
-		&self,
-		id: KeyTypeId
-	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
-		let raw_keys = self.0.read().raw_public_keys(id)?;
-		Ok(raw_keys.into_iter()
-			.fold(Vec::new(), |mut v, k| {
-				v.push(CryptoTypePublicPair(sr25519::CRYPTO_ID, k.clone()));
-				v.push(CryptoTypePublicPair(ed25519::CRYPTO_ID, k.clone()));
-				v.push(CryptoTypePublicPair(ecdsa::CRYPTO_ID, k));
-				v
-			}))
+	/// Apply the changes in the write-ahead log that the key files do not reflect yet.
+	///
+	/// Returns the number of applied changes. Does nothing unless the keystore was opened with
+	/// [`LocalKeystoreBuilder::wal`].
+	pub fn replay_wal(&mut self) -> Result<usize> {
+		self.0.get_mut().replay_wal()
 	}
 
-	fn supported_keys(
-		&self,
-		id: KeyTypeId,
-		keys: Vec<CryptoTypePublicPair>
-	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
-		let all_keys = SyncCryptoStore::keys(self, id)?
-			.into_iter()
-			.collect::<HashSet<_>>();
-		Ok(keys.into_iter().filter(|key| all_keys.contains(key)).collect::<Vec<_>>())
+	/// Check that the keystore directory is consistent, meant to be called before starting to
+	/// use the keystore.
+	///
+	/// Every key file must be readable and derive to its public key with the password of the
+	/// keystore, otherwise the check fails with [`Error::Inconsistent`] listing the broken files.
+	/// Issues the keystore copes with are returned as warnings: copies left by interrupted
+	/// updates (see [`LocalKeystore::recover_interrupted`]), key files in the legacy format, a
+	/// stale key index and changes in the write-ahead log that were not applied yet.
+	pub fn preflight_check(&self) -> Result<PreflightReport> {
+		self.0.read().preflight_check()
 	}
 
-	fn sign_with(
-		&self,
-		id: KeyTypeId,
-		key: &CryptoTypePublicPair,
-		msg: &[u8],
-	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
-		match key.0 {
-			ed25519::CRYPTO_ID => {
-				let pub_key = ed25519::Public::from_slice(key.1.as_slice());
-				let key_pair = self.0.read()
-					.key_pair_by_type::<ed25519::Pair>(&pub_key, id)
-					.map_err(|e| TraitError::from(e))?;
-				key_pair.map(|k| k.sign(msg).encode()).map(Ok).transpose()
-			}
-			sr25519::CRYPTO_ID => {
-				let pub_key = sr25519::Public::from_slice(key.1.as_slice());
-				let key_pair = self.0.read()
-					.key_pair_by_type::<sr25519::Pair>(&pub_key, id)
-					.map_err(|e| TraitError::from(e))?;
-				key_pair.map(|k| k.sign(msg).encode()).map(Ok).transpose()
-			},
-			ecdsa::CRYPTO_ID => {
-				let pub_key = ecdsa::Public::from_slice(key.1.as_slice());
-				let key_pair = self.0.read()
-					.key_pair_by_type::<ecdsa::Pair>(&pub_key, id)
-					.map_err(|e| TraitError::from(e))?;
-				key_pair.map(|k| k.sign(msg).encode()).map(Ok).transpose()
-			}
-			_ => Err(TraitError::KeyNotSupported(id))
-		}
+	/// Read the key files of the given key types now, instead of when their keys are used.
+	///
+	/// Brings the files into the operating system's cache and, if an active window is set, keeps
+	/// the derived keys in memory like using them does, see [`LocalKeystore::set_active_window`].
+	/// Keys whose secret can not be read are handled according to the [`DecryptFailurePolicy`].
+	/// Returns the number of keys read.
+	pub fn prefetch(&self, ids: &[KeyTypeId]) -> Result<usize> {
+		self.0.read().prefetch(ids)
 	}
 
-	fn sr25519_public_keys(&self, key_type: KeyTypeId) -> Vec<sr25519::Public> {
-		self.0.read().raw_public_keys(key_type)
-			.map(|v| {
-				v.into_iter()
-				 .map(|k| sr25519::Public::from_slice(k.as_slice()))
-				 .collect()
-			})
-			.unwrap_or_default()
+	/// Create a local keystore in memory.
+	pub fn in_memory() -> Self {
+		let inner = KeystoreInner::new_in_memory();
+		Self(RwLock::new(inner))
 	}
 
-	fn sr25519_generate_new(
-		&self,
-		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<sr25519::Public, TraitError> {
-		let pair = match seed {
-			Some(seed) => self.0.write().insert_ephemeral_from_seed_by_type::<sr25519::Pair>(seed, id),
-			None => self.0.write().generate_by_type::<sr25519::Pair>(id),
-		}.map_err(|e| -> TraitError { e.into() })?;
+	/// Create a local keystore in memory holding the given keys, by key type and raw public key.
+	///
+	/// The secret URIs are derived with the password like the ones of key files.
+	pub(crate) fn in_memory_with_keys(
+		keys: HashMap<(KeyTypeId, Vec<u8>), String>,
+		password: Option<SecretString>,
+	) -> Self {
+		let mut inner = KeystoreInner::new_in_memory();
+		inner.additional = keys;
+		inner.password = password;
+		Self(RwLock::new(inner))
+	}
 
-		Ok(pair.public())
+	/// Get a key pair for the given public key.
+	///
+	/// Returns `Ok(None)` if the key doesn't exist, `Ok(Some(_))` if the key exists and
+	/// `Err(_)` when something failed. The content of the key file and the secret URI read from
+	/// it are zeroized once the pair is derived.
+	pub fn key_pair<Pair: AppPair>(&self, public: &<Pair as AppKey>::Public) -> Result<Option<Pair>> {
+		self.0.read().key_pair::<Pair>(public)
 	}
 
-	fn ed25519_public_keys(&self, key_type: KeyTypeId) -> Vec<ed25519::Public> {
-		self.0.read().raw_public_keys(key_type)
-			.map(|v| {
-				v.into_iter()
-				 .map(|k| ed25519::Public::from_slice(k.as_slice()))
-				 .collect()
-			})
-			.unwrap_or_default()
+	/// Returns `true` if the given application key exists, like `has_keys` for a single key.
+	///
+	/// The key type and the raw public key are taken from the application key, so they can not
+	/// be mixed up.
+	pub fn has_key<Public: AppPublic>(&self, public: &Public) -> bool {
+		SyncCryptoStore::has_keys(self, &[(public.to_raw_vec(), Public::ID)])
 	}
 
-	fn ed25519_generate_new(
-		&self,
-		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<ed25519::Public, TraitError> {
-		let pair = match seed {
-			Some(seed) => self.0.write().insert_ephemeral_from_seed_by_type::<ed25519::Pair>(seed, id),
-			None => self.0.write().generate_by_type::<ed25519::Pair>(id),
-		}.map_err(|e| -> TraitError { e.into() })?;
+	/// Insert the given application key, like `insert_unknown` does.
+	pub fn insert_key<Public: AppPublic>(&self, suri: &str, public: &Public) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().insert_unknown(Public::ID, suri, public.as_slice())
+	}
 
-		Ok(pair.public())
+	/// Remove the given application key, like `remove_key` does.
+	pub fn remove_app_key<Public: AppPublic>(&self, public: &Public) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().remove_key(public.as_slice(), Public::ID)
 	}
 
-	fn ecdsa_public_keys(&self, key_type: KeyTypeId) -> Vec<ecdsa::Public> {
-		self.0.read().raw_public_keys(key_type)
-			.map(|v| {
-				v.into_iter()
-					.map(|k| ecdsa::Public::from_slice(k.as_slice()))
-					.collect()
-			})
-			.unwrap_or_default()
+	/// Run `f` with the keystore locked for exclusive use.
+	///
+	/// Sequences like "generate a key unless it exists" are racy when done with separate calls.
+	/// Everything done through the [`LockedKeystore`] view happens atomically with respect to
+	/// all other users of the keystore.
+	///
+	/// This blocks the calling thread: while writes are paused, for up to [`MAX_WRITE_PAUSE`],
+	/// and then until all other users of the keystore released it. Call it on a blocking
+	/// thread when running on an async executor. If writes stay paused for longer, the view can
+	/// only be read and changes fail with [`Error::WritesPaused`].
+	///
+	/// The keystore stays locked until `f` returns. `f` must only use the keystore through the
+	/// view: calling any `SyncCryptoStore` or `LocalKeystore` method on the same keystore from
+	/// inside `f` deadlocks on the write lock, and awaiting a `CryptoStore` method does too.
+	pub fn with_exclusive<R>(&self, f: impl FnOnce(&LockedKeystore) -> R) -> R {
+		let writing = self.writing().ok();
+		let mut inner = self.0.write();
+		f(&LockedKeystore { inner: RefCell::new(&mut *inner), writing })
 	}
 
-	fn ecdsa_generate_new(
-		&self,
-		id: KeyTypeId,
-		seed: Option<&str>,
-	) -> std::result::Result<ecdsa::Public, TraitError> {
-		let pair = match seed {
-			Some(seed) => self.0.write().insert_ephemeral_from_seed_by_type::<ecdsa::Pair>(seed, id),
-			None => self.0.write().generate_by_type::<ecdsa::Pair>(id),
-		}.map_err(|e| -> TraitError { e.into() })?;
+	/// Find the public keys that are stored under more than one key type.
+	///
+	/// The same public key under e.g. BABE and GRANDPA usually means that a seed was reused,
+	/// which defeats the separation of the keys. Only the public keys are compared, no key file
+	/// is read. Returns the public keys with their key types, both sorted.
+	pub fn find_cross_type_reuse(&self) -> Result<Vec<(Vec<u8>, Vec<KeyTypeId>)>> {
+		let mut key_types = BTreeMap::<Vec<u8>, BTreeSet<KeyTypeId>>::new();
+		for (key_type, public) in self.0.read().all_public_keys()? {
+			key_types.entry(public).or_default().insert(key_type);
+		}
+		Ok(key_types.into_iter()
+			.filter(|(_, key_types)| key_types.len() > 1)
+			.map(|(public, key_types)| (public, key_types.into_iter().collect()))
+			.collect())
+	}
 
-		Ok(pair.public())
+	/// Returns how many bytes the keystore takes on disk.
+	///
+	/// Only the file sizes are read, nothing is decrypted. Ephemeral keys and in-memory
+	/// keystores take no disk space.
+	pub fn disk_usage(&self) -> Result<DiskUsage> {
+		self.0.read().disk_usage()
 	}
 
-	fn insert_unknown(&self, key_type: KeyTypeId, suri: &str, public: &[u8])
-		-> std::result::Result<(), ()>
-	{
-		self.0.write().insert_unknown(key_type, suri, public).map_err(|_| ())
+	/// Find the keys of the given key type that are stored in more than one file.
+	///
+	/// See [`DuplicateGroup`] for how copies of the same key are recognized.
+	pub fn find_duplicates(&self, id: KeyTypeId) -> Result<Vec<DuplicateGroup>> {
+		self.0.read().find_duplicates(id)
 	}
 
-	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
-		public_keys.iter()
-			.all(|(p, t)| self.0.read().key_phrase_by_type(&p, *t).ok().flatten().is_some())
+	/// Remove redundant copies of the keys of the given key type.
+	///
+	/// One copy of each key is kept and moved to its canonical file name. If `keep_newest` is
+	/// set the most recently modified copy is kept, otherwise the oldest one.
+	///
+	/// Returns the number of files that were removed.
+	pub fn dedup(&self, id: KeyTypeId, keep_newest: bool) -> Result<usize> {
+		let _writing = self.writing()?;
+		self.0.write().dedup(id, keep_newest)
 	}
 
-	fn sr25519_vrf_sign(
+	/// Generate a new key of the given crypto type together with its recovery phrase.
+	///
+	/// The key is stored like any generated key, but the returned BIP39 phrase is only handed
+	/// to the caller so it can be recorded offline; it is never written to disk. If the
+	/// keystore has a password, the key is recovered from the phrase together with the password.
+	pub fn generate_with_phrase(
 		&self,
-		key_type: KeyTypeId,
-		public: &Sr25519Public,
-		transcript_data: VRFTranscriptData,
-	) -> std::result::Result<Option<VRFSignature>, TraitError> {
-		let transcript = make_transcript(transcript_data);
-		let pair = self.0.read().key_pair_by_type::<Sr25519Pair>(public, key_type)?;
-
-		if let Some(pair) = pair {
-			let (inout, proof, _) = pair.as_ref().vrf_sign(transcript);
-			Ok(Some(VRFSignature {
-				output: inout.to_output(),
-				proof,
-			}))
-		} else {
-			Ok(None)
+		id: KeyTypeId,
+		scheme: CryptoTypeId,
+	) -> Result<(CryptoTypePublicPair, String)> {
+		let _writing = self.writing()?;
+		let mut inner = self.0.write();
+		match scheme {
+			sr25519::CRYPTO_ID => inner.generate_with_phrase_by_type::<sr25519::Pair>(id)
+				.map(|(pair, phrase)| (pair.public().into(), phrase)),
+			ed25519::CRYPTO_ID => inner.generate_with_phrase_by_type::<ed25519::Pair>(id)
+				.map(|(pair, phrase)| (pair.public().into(), phrase)),
+			ecdsa::CRYPTO_ID => inner.generate_with_phrase_by_type::<ecdsa::Pair>(id)
+				.map(|(pair, phrase)| (pair.public().into(), phrase)),
+			_ => Err(Error::KeyNotSupported(id)),
 		}
 	}
 
-	fn ecdsa_sign_prehashed(
+	/// Insert a key of the given crypto type from its raw seed.
+	///
+	/// Unlike `insert_unknown`, the seed is not parsed as a secret URI, so binary seeds are
+	/// imported without converting them to a string first. sr25519 also takes the 64 bytes of a
+	/// secret key in the format of schnorrkel, keys inserted from it can not be derived from.
+	/// Fails with [`Error::InvalidSeed`] if the seed does not have a length the crypto type
+	/// takes.
+	pub fn insert_raw_seed(
 		&self,
 		id: KeyTypeId,
-		public: &ecdsa::Public,
-		msg: &[u8; 32],
-	) -> std::result::Result<Option<ecdsa::Signature>, TraitError> {
-		let pair = self.0.read()
-			.key_pair_by_type::<ecdsa::Pair>(public, id)?;
-		
-		pair.map(|k| k.sign_prehashed(msg)).map(Ok).transpose()
+		scheme: CryptoTypeId,
+		seed: &[u8],
+	) -> Result<CryptoTypePublicPair> {
+		let _writing = self.writing()?;
+		let mut inner = self.0.write();
+		match scheme {
+			sr25519::CRYPTO_ID => inner.insert_raw_seed_by_type::<sr25519::Pair>(id, seed)
+				.map(|p| p.public().into()),
+			ed25519::CRYPTO_ID => inner.insert_raw_seed_by_type::<ed25519::Pair>(id, seed)
+				.map(|p| p.public().into()),
+			ecdsa::CRYPTO_ID => inner.insert_raw_seed_by_type::<ecdsa::Pair>(id, seed)
+				.map(|p| p.public().into()),
+			_ => Err(Error::KeyNotSupported(id)),
+		}
 	}
-}
 
-impl Into<SyncCryptoStorePtr> for LocalKeystore {
-	fn into(self) -> SyncCryptoStorePtr {
-		Arc::new(self)
+	/// Generate and store a key whose SS58 address for the network starts with `prefix`.
+	///
+	/// Random seeds are tried on all cores until the address of one starts with the prefix,
+	/// only that key is stored, like [`LocalKeystore::insert_raw_seed`] does. Every character
+	/// of the prefix multiplies the expected number of attempts by 58, so prefixes of more than
+	/// a few characters take hours and longer; the first characters of an address are also
+	/// fixed by the network. Fails with [`Error::VanityNotFound`] once `max_attempts` keys were
+	/// tried, right away if the prefix contains characters that never occur in addresses. The
+	/// keys are random even if the keystore derives generated keys from a master secret.
+	pub fn generate_vanity(
+		&self,
+		id: KeyTypeId,
+		scheme: CryptoTypeId,
+		prefix: &str,
+		network: Ss58AddressFormat,
+		max_attempts: usize,
+	) -> Result<CryptoTypePublicPair> {
+		let seed = match scheme {
+			sr25519::CRYPTO_ID => vanity_seed::<sr25519::Pair>(prefix, network, max_attempts),
+			ed25519::CRYPTO_ID => vanity_seed::<ed25519::Pair>(prefix, network, max_attempts),
+			ecdsa::CRYPTO_ID => vanity_seed::<ecdsa::Pair>(prefix, network, max_attempts),
+			_ => return Err(Error::KeyNotSupported(id)),
+		};
+		let seed = seed.ok_or(Error::VanityNotFound { attempts: max_attempts })?;
+		self.insert_raw_seed(id, scheme, &seed)
 	}
-}
 
-impl Into<Arc<dyn CryptoStore>> for LocalKeystore {
-	fn into(self) -> Arc<dyn CryptoStore> {
-		Arc::new(self)
+	/// Derive a child of an ecdsa key with BIP32, store it and return its public key.
+	///
+	/// The master key has to be stored under the key type with a BIP39 phrase as its secret
+	/// URI. The derivation starts from the standard BIP39 seed of the phrase, with the password
+	/// of the keystore as the passphrase, so the keys match those of wallets like MetaMask for
+	/// the same phrase, e.g. `m/44'/60'/0'/0/0` for the first Ethereum account. The master key
+	/// itself is the substrate derivation of the phrase and not part of the BIP32 tree. The
+	/// child is stored under the same key type as a raw seed. Fails with
+	/// [`Error::PairNotFound`] if the master key doesn't exist and with [`Error::InvalidPhrase`]
+	/// if its secret URI is not a plain BIP39 phrase or `path` is not a BIP32 path.
+	pub fn ecdsa_derive_bip32(
+		&self,
+		id: KeyTypeId,
+		master: &ecdsa::Public,
+		path: &str,
+	) -> Result<ecdsa::Public> {
+		let _writing = self.writing()?;
+		let mut inner = self.0.write();
+		let phrase = inner.key_phrase_by_type(master.as_ref(), id)?
+			.map(Zeroizing::new)
+			.ok_or(Error::PairNotFound)?;
+		let mnemonic = Mnemonic::from_phrase(&phrase, Language::English)
+			.map_err(|_| Error::InvalidPhrase("The master key is not a BIP39 phrase".into()))?;
+		let seed = bip39::Seed::new(&mnemonic, inner.password().unwrap_or_default());
+		let child = bip32::derive(seed.as_bytes(), path)?;
+		inner.insert_raw_seed_by_type::<ecdsa::Pair>(id, &child[..]).map(|pair| pair.public())
 	}
-}
-
-/// A local key store.
-///
-/// Stores key pairs in a file system store + short lived key pairs in memory.
-///
-/// Every pair that is being generated by a `seed`, will be placed in memory.
-struct KeystoreInner {
-	path: Option<PathBuf>,
-	/// Map over `(KeyTypeId, Raw public key)` -> `Key phrase/seed`
-	additional: HashMap<(KeyTypeId, Vec<u8>), String>,
-	password: Option<SecretString>,
-}
 
-impl KeystoreInner {
-	/// Open the store at the given path.
+	/// Insert many keys of the given crypto type at once.
 	///
-	/// Optionally takes a password that will be used to encrypt/decrypt the keys.
-	fn open<T: Into<PathBuf>>(path: T, password: Option<SecretString>) -> Result<Self> {
-		let path = path.into();
-		fs::create_dir_all(&path)?;
+	/// Every item is a key type together with the secret URI of the key. All secret URIs are
+	/// validated before anything is written and the public keys are returned in the order of
+	/// `items`.
+	///
+	/// With `durable` set this behaves like inserting every key with `insert_unknown`. Without
+	/// it, the key files are not synced to disk one by one, only the directory is synced once at
+	/// the end. This trades crash safety for speed and is meant for provisioning large test
+	/// networks: keys written shortly before a crash may be lost or truncated.
+	pub fn insert_bulk(
+		&self,
+		scheme: CryptoTypeId,
+		items: Vec<(KeyTypeId, String)>,
+		durable: bool,
+	) -> Result<Vec<CryptoTypePublicPair>> {
+		let _writing = self.writing()?;
+		self.0.read().insert_bulk(scheme, items, durable)
+	}
 
-		let instance = Self { path: Some(path), additional: HashMap::new(), password };
-		Ok(instance)
+	/// Limit the number of keys on disk.
+	///
+	/// Protects against runaway key creation, e.g. by a buggy automation generating session
+	/// keys. Once `max` keys of any key type are on disk, generating or inserting a new key
+	/// fails with [`Error::KeyLimitReached`]; overwriting an existing key is still possible.
+	/// Keys are not limited by default.
+	pub fn set_max_persisted_keys(&self, max: Option<usize>) {
+		self.0.write().max_persisted_keys = max;
 	}
 
-	/// Get the password for this store.
-	fn password(&self) -> Option<&str> {
-		self.password.as_ref()
-			.map(|p| p.expose_secret())
-			.map(|p| p.as_str())
+	/// Allow or forbid keys that are only held in memory.
+	///
+	/// Ephemeral keys are allowed by default. When forbidden, generating a key from a seed (and
+	/// generating any key in an in-memory keystore) fails with [`Error::EphemeralDisabled`], so
+	/// every key the node can sign with has a durable record on disk.
+	pub fn set_allow_ephemeral(&self, allow: bool) {
+		self.0.write().allow_ephemeral = allow;
 	}
 
-	/// Create a new in-memory store.
-	fn new_in_memory() -> Self {
-		Self {
-			path: None,
-			additional: HashMap::new(),
-			password: None
+	/// Rewrite all legacy plain text key files in the current format.
+	///
+	/// Very old keystores stored the secret URI as plain text instead of a JSON string. Such
+	/// files are still read and upgraded one by one when their key is used; this upgrades all of
+	/// them at once. Every file is replaced atomically.
+	///
+	/// Returns the number of files that were upgraded.
+	pub fn upgrade_plaintext(&self) -> Result<usize> {
+		let _writing = self.writing()?;
+		self.0.write().upgrade_plaintext()
+	}
+
+	/// Rename key files with upper case hex names to their canonical lower case names.
+	///
+	/// Opening the keystore does not rename anything: a key file with an upper case name is used
+	/// as long as its key has no file with the canonical name. On a read-only file system nothing
+	/// is renamed, which is logged.
+	///
+	/// Returns the number of renamed files.
+	pub fn normalize_file_names(&self) -> Result<usize> {
+		let _writing = self.writing()?;
+		self.0.write().normalize_file_names()
+	}
+
+	/// Move all key files to the given directory layout.
+	///
+	/// Files are moved with their names, so copies left by interrupted updates are moved along
+	/// with their key file. Subdirectories of the previous layout are removed once empty. The
+	/// keystore uses the new layout afterwards.
+	///
+	/// Returns the number of files that were moved.
+	pub fn migrate_layout(&self, layout: DirectoryLayout) -> Result<usize> {
+		let _writing = self.writing()?;
+		self.0.write().migrate_layout(layout)
+	}
+
+	/// Resolve updates of key files that were interrupted half way.
+	///
+	/// Every key file with a left over `.tmp` or `.bak` copy is checked. If the key file is
+	/// intact, the copies are removed. Otherwise it is restored from the first usable copy,
+	/// trying `.tmp` before `.bak`. Key files without any usable copy are left untouched and
+	/// reported as unresolved.
+	pub fn recover_interrupted(&self) -> Result<RecoveryReport> {
+		let _writing = self.writing()?;
+		self.0.write().recover_interrupted()
+	}
+
+	/// Take a snapshot of the keystore state for diagnostics.
+	///
+	/// The snapshot never includes secret URIs, seeds or the password, so it can be attached
+	/// to bug reports. It serializes with serde, for example to JSON.
+	pub fn debug_snapshot(&self) -> Result<KeystoreSnapshot> {
+		self.0.read().debug_snapshot()
+	}
+
+	/// Keep secret keys in memory for the given window after they were last used.
+	///
+	/// By default, every signing operation reads the key file and derives the key again. With
+	/// an active window, a derived key is kept in memory and reused until `window` passed
+	/// without it being used; every use extends the window. Expired keys are zeroized and
+	/// dropped on the next signing operation, so on mostly idle nodes secret keys only spend
+	/// the active window in memory. A zero window disables keeping keys and drops all kept keys.
+	///
+	/// Kept keys do not notice changes of their key files made outside of the keystore. The
+	/// window is measured with a monotonic clock, so changes of the system time don't expire
+	/// kept keys early or late.
+	pub fn set_active_window(&self, window: Duration) {
+		let inner = self.0.read();
+		let mut active = inner.active.lock();
+		if window == Duration::default() {
+			active.window = None;
+			active.keys.clear();
+		} else {
+			active.window = Some(window);
 		}
 	}
 
-	/// Get the key phrase for the given public key and key type from the in-memory store.
-	fn get_additional_pair(
+	/// Set the note of the key with the given key type and raw public key.
+	///
+	/// The note is encrypted with the password of the keystore, like a backup, and stored next
+	/// to the key file. It never appears in snapshots of the keystore state. Fails with
+	/// [`Error::InvalidPassword`] if the keystore has no password and with
+	/// [`Error::PairNotFound`] if the key is not stored on disk.
+	pub fn set_encrypted_note(&self, id: KeyTypeId, public: &[u8], note: &str) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.read().set_encrypted_note(public, id, note)
+	}
+
+	/// Returns the note of the key with the given key type and raw public key, if any.
+	///
+	/// Fails with [`Error::InvalidPassword`] if the note can not be decrypted with the password
+	/// of the keystore.
+	pub fn encrypted_note(&self, id: KeyTypeId, public: &[u8]) -> Result<Option<String>> {
+		self.0.read().encrypted_note(public, id)
+	}
+
+	/// Add an alias to the key with the given key type and raw public key.
+	///
+	/// Aliases are names for keys that tools can accept in place of the public key, see
+	/// [`LocalKeystore::resolve_alias`]. A key can have any number of aliases, they are stored
+	/// next to its key file. Fails with [`Error::AliasExists`] if another key has the alias,
+	/// with [`Error::InvalidAlias`] if it is empty or contains whitespace and with
+	/// [`Error::PairNotFound`] if the key is not stored on disk. Adding an alias the key
+	/// already has does nothing.
+	pub fn add_alias(&self, id: KeyTypeId, public: &[u8], alias: &str) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().add_alias(public, id, alias)
+	}
+
+	/// Returns the key type and raw public key of the key with the given alias, if any.
+	pub fn resolve_alias(&self, alias: &str) -> Result<Option<(KeyTypeId, Vec<u8>)>> {
+		self.0.read().resolve_alias(alias)
+	}
+
+	/// Returns the aliases of the key with the given key type and raw public key.
+	pub fn aliases(&self, id: KeyTypeId, public: &[u8]) -> Result<Vec<String>> {
+		self.0.read().aliases(public, id)
+	}
+
+	/// Returns the metadata of the key with the given key type and raw public key.
+	///
+	/// Keys without metadata have the default, empty metadata. Fails with
+	/// [`Error::PairNotFound`] if the key is not stored on disk.
+	pub fn key_metadata(&self, id: KeyTypeId, public: &[u8]) -> Result<KeyMetadata> {
+		self.0.read().key_metadata(public, id)
+	}
+
+	/// Update the metadata of the key with the given key type and raw public key.
+	///
+	/// The metadata is read, passed to `f` and written back while holding the write lock of
+	/// the keystore for the whole duration of `f`, so concurrent updates never clobber each
+	/// other; `f` should be quick and must not call back into the keystore. Fails with
+	/// [`Error::PairNotFound`] if the key is not stored on disk.
+	pub fn update_metadata<F: FnOnce(&mut KeyMetadata)>(
 		&self,
+		id: KeyTypeId,
 		public: &[u8],
-		key_type: KeyTypeId,
-	) -> Option<&String> {
-		let key = (key_type, public.to_vec());
-		self.additional.get(&key)
+		f: F,
+	) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().update_metadata(public, id, f)
 	}
 
-	/// Insert the given public/private key pair with the given key type.
+	/// Encrypt the aliases of the keys with the password of the keystore, or store them as
+	/// plain text.
 	///
-	/// Does not place it into the file system store.
-	fn insert_ephemeral_pair<Pair: PairT>(&mut self, pair: &Pair, seed: &str, key_type: KeyTypeId) {
-		let key = (key_type, pair.public().to_raw_vec());
-		self.additional.insert(key, seed.into());
+	/// Disabled by default. When enabled, the aliases of all keys are encrypted right away and
+	/// whenever they change, so they can not be read from the directory without the password.
+	/// Aliases are decrypted transparently on access; resolving an alias decrypts the aliases
+	/// of every key that has any. Fails with [`Error::InvalidPassword`] if the keystore has no
+	/// password or an alias file can not be decrypted with it.
+	pub fn set_encrypt_aliases(&self, encrypt: bool) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().set_encrypt_aliases(encrypt)
 	}
 
-	/// Insert a new key with anonymous crypto.
+	/// Issue a token that lets a [`DelegatedKeystore`] sign with the key for the next `ttl`.
 	///
-	/// Places it into the file system store, if a path is configured.
-	fn insert_unknown(&self, key_type: KeyTypeId, suri: &str, public: &[u8]) -> Result<()> {
-		if let Some(path) = self.key_file_path(public, key_type) {
-			let mut file = File::create(path).map_err(Error::Io)?;
-			serde_json::to_writer(&file, &suri).map_err(Error::Json)?;
-			file.flush().map_err(Error::Io)?;
+	/// The token is signed with the key itself, passing the signing hook like any message, so
+	/// it can not be forged without the key. Fails with [`Error::PairNotFound`] if there is no
+	/// such key.
+	///
+	/// [`DelegatedKeystore`]: crate::DelegatedKeystore
+	pub fn issue_delegation(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		ttl: Duration,
+	) -> Result<DelegationToken> {
+		let expires = delegation::expiry(self.0.read().clock.system_now(), ttl);
+		let msg = delegation::grant_message(id, public, expires);
+		let signature = delegation::signed(SyncCryptoStore::sign_with(self, id, public, &msg))?;
+		Ok(delegation::token(id, public.clone(), expires, signature))
+	}
+
+	/// Sign the message with the key and the given nonce, returning the SCALE encoded signature.
+	///
+	/// **Dangerous: signing two messages with the same nonce reveals the secret key.** Only meant
+	/// for security tests that need crafted signatures, e.g. to reproduce nonce reuse or to fuzz
+	/// verifiers, and only compiled with the `testing` feature, which must never be enabled in
+	/// production builds. The 32 byte nonce is used as is: it is the scalar `r` of sr25519 and
+	/// ed25519 signatures and `k` of ecdsa signatures. The signing hook is not consulted and
+	/// the signature is not cached. Fails with [`Error::InvalidNonce`] if the nonce does not
+	/// have 32 bytes or is not a valid ecdsa nonce, and with [`Error::PairNotFound`] if there
+	/// is no such key.
+	#[cfg(feature = "testing")]
+	pub fn sign_with_nonce(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+		nonce: &[u8],
+	) -> Result<Vec<u8>> {
+		let secret = self.0.read().secret(&key.1, id)?.ok_or(Error::PairNotFound)?;
+		crate::forced_nonce::sign(key.0, &secret, &key.1, msg, nonce)
+	}
+
+	/// Derive an age X25519 identity from the key with the given key type and raw public key.
+	///
+	/// The identity is derived deterministically from the secret of the key in a separate
+	/// domain, so the backed up key also recovers the identity while neither reveals the other.
+	/// Fails with [`Error::PairNotFound`] if the key does not exist or can not be derived with
+	/// the password.
+	pub fn age_identity_from(&self, id: KeyTypeId, public: &[u8]) -> Result<AgeIdentity> {
+		let secret = self.0.read().secret(public, id)?.ok_or(Error::PairNotFound)?;
+		Ok(AgeIdentity::derive(&secret))
+	}
+
+	/// Start receiving a key from another keystore.
+	///
+	/// The challenge is sent to the source, which answers it with [`LocalKeystore::respond`];
+	/// the state stays here and completes the transfer with
+	/// [`LocalKeystore::complete_receive`]. The secret of the key is encrypted to an ephemeral
+	/// key of this call, so it is never in plaintext on the wire and a response can only be
+	/// decrypted once. The channel still has to authenticate the source, the challenge does not
+	/// tell who answers it.
+	pub fn begin_receive(&self) -> (TransferChallenge, TransferState) {
+		transfer::begin()
+	}
+
+	/// Answer the challenge of a destination with the key with the given key type and public
+	/// key, encrypted to the destination.
+	///
+	/// Fails with [`Error::PairNotFound`] if the key doesn't exist or does not derive from its
+	/// secret with the password of this keystore, and with [`Error::InvalidTransfer`] if the
+	/// challenge is malformed.
+	pub fn respond(
+		&self,
+		challenge: &TransferChallenge,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> Result<Vec<u8>> {
+		let inner = self.0.read();
+		let suri = inner.key_phrase_by_type(&public.1, id)?.ok_or(Error::PairNotFound)?;
+		match public_from_suri(public.0, &suri, inner.password()) {
+			Some(Ok(derived)) if derived == *public => (),
+			_ => return Err(Error::PairNotFound),
 		}
-		Ok(())
+		let payload = TransferPayload { key_type: id, public: public.clone(), suri };
+		transfer::seal(challenge, &payload)
 	}
 
-	/// Generate a new key.
+	/// Complete receiving a key with the response of the source and insert it.
 	///
-	/// Places it into the file system store, if a path is configured. Otherwise insert
-	/// it into the memory cache only.
-	fn generate_by_type<Pair: PairT>(&mut self, key_type: KeyTypeId) -> Result<Pair> {
-		let (pair, phrase, _) = Pair::generate_with_phrase(self.password());
-		if let Some(path) = self.key_file_path(pair.public().as_slice(), key_type) {
-			let mut file = File::create(path)?;
-			serde_json::to_writer(&file, &phrase)?;
-			file.flush()?;
-		} else {
-			self.insert_ephemeral_pair(&pair, &phrase, key_type);
+	/// The key is inserted like with `insert_unknown`, so the keystores have to use the same
+	/// password for the key to derive, and a keystore in memory does not keep it. Fails with
+	/// [`Error::InvalidTransfer`] if the response is malformed or was not encrypted to `state`.
+	/// Returns the received key.
+	pub fn complete_receive(
+		&self,
+		state: TransferState,
+		response: &[u8],
+	) -> Result<CryptoTypePublicPair> {
+		let _writing = self.writing()?;
+		let payload = transfer::open(state, response)?;
+		self.0.write().insert_unknown(payload.key_type, &payload.suri, &payload.public.1)?;
+		Ok(payload.public.clone())
+	}
+
+	/// Returns the exact contents of the key file of the key with the given key type and raw
+	/// public key, for forensic tools.
+	///
+	/// The file is neither parsed nor decrypted. Key files hold the secret URI as written by
+	/// the keystore, which the password only protects if it was set when the key was derived,
+	/// so the contents must be handled like the secret itself. Fails with
+	/// [`Error::PairNotFound`] if the key is not stored on disk.
+	pub fn raw_file_bytes(&self, id: KeyTypeId, public: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+		self.0.read().raw_file_bytes(public, id)
+	}
+
+	/// Returns the path and size of every file in the keystore directory and its
+	/// subdirectories, sorted by path, for forensic tools.
+	///
+	/// Unlike the key listing, file names are not checked, so malformed files and files the
+	/// keystore does not know are listed too. In-memory keystores have no files.
+	pub fn list_raw_files(&self) -> Result<Vec<(PathBuf, u64)>> {
+		self.0.read().list_raw_files()
+	}
+
+	/// Returns when the key with the given key type and raw public key last signed something.
+	///
+	/// Uses are recorded by `sign_with`, `sr25519_vrf_sign` and `ecdsa_sign_prehashed`. Returns
+	/// `None` for keys that were never used since use tracking exists, and for unknown keys.
+	/// Uses of keys stored on disk persist across restarts.
+	pub fn last_used(&self, id: KeyTypeId, public: &[u8]) -> Result<Option<SystemTime>> {
+		self.0.read().last_used(public, id)
+	}
+
+	/// Report the latencies of signing operations to the given metrics.
+	///
+	/// `on_sign_complete` is called after every `sign_with`, `sr25519_vrf_sign` and
+	/// `ecdsa_sign_prehashed` that produced a signature. The reported duration only covers the
+	/// signing itself, not acquiring locks and reading the key. `on_generate` is called for
+	/// every generated key and `on_error` for the errors also sent to the error sink.
+	pub fn set_signing_metrics(&self, metrics: Arc<dyn SigningMetrics>) {
+		self.0.write().metrics = Some(metrics);
+	}
+
+	/// Mirror the errors of the keystore operations to the given channel, for alerting.
+	///
+	/// Every error returned by an operation of the `CryptoStore` and `SyncCryptoStore` traits,
+	/// except for the infallible listings, is also sent to the channel along with the name of
+	/// the operation and its key type. The other methods of [`LocalKeystore`] are not mirrored.
+	/// The channel is purely observational: the operation returns the same error, and an event
+	/// that does not fit into a full channel is dropped without blocking.
+	pub fn set_error_sink(&self, sink: mpsc::Sender<KeystoreErrorEvent>) {
+		self.0.write().error_sink = Some(Mutex::new(sink));
+	}
+
+	/// Limit how many signing operations run at once, `None` for no limit.
+	///
+	/// By default as many operations run as there are cores but one, so a burst of signing
+	/// requests, e.g. from a flood of consensus messages, can not crowd out block authoring.
+	/// This bounds concurrency, not the rate of signatures. The async signing methods await a
+	/// free slot without blocking their thread and then hold it for the whole operation. The
+	/// sync ones do not wait, they fail with [`TraitError::Unavailable`] if all slots are taken
+	/// and only hold one for the signing itself, not for loading the key. A limit of zero is
+	/// treated as one.
+	pub fn set_max_concurrent_signs(&self, max: Option<usize>) {
+		self.0.read().sign_limiter.set_max(max);
+	}
+
+	/// Read the current time from the given clock instead of the system clock.
+	///
+	/// The clock is used for the active window, the last use of keys, the expiry of issued
+	/// delegation tokens and the durations reported to the signing metrics, so tests can
+	/// advance a [`MockClock`](crate::MockClock) instead of sleeping. File modification times
+	/// are still set by the file system.
+	pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+		self.0.write().clock = clock;
+	}
+
+	/// Call `callback` with the key type whenever the keys of a key type change.
+	///
+	/// Keys are added by generating, inserting and importing them and removed by `remove_key`;
+	/// moving a key to another key type changes both key types, and reconstructing the password
+	/// with [`LocalKeystore::add_share`] changes all of them. Caches of keys or public keys can
+	/// drop their entries of the key type. Several callbacks can be registered. They are called
+	/// while the keystore is locked, so they must not call into the keystore.
+	pub fn on_invalidate(&self, callback: Box<dyn Fn(KeyTypeId) + Send + Sync>) {
+		self.0.write().invalidation_callbacks.push(callback);
+	}
+
+	/// Run the operation, mirroring its error to the error sink and the metrics.
+	fn reported<T, E: std::fmt::Display>(
+		&self,
+		operation: &'static str,
+		key_type: Option<KeyTypeId>,
+		run: impl FnOnce() -> std::result::Result<T, E>,
+	) -> std::result::Result<T, E> {
+		let result = run();
+		if let Err(error) = &result {
+			let inner = self.0.read();
+			if let Some(metrics) = &inner.metrics {
+				metrics.on_error(operation, key_type);
+			}
+			if let Some(sink) = &inner.error_sink {
+				let event = KeystoreErrorEvent { operation, key_type, error: error.to_string() };
+				// A full or closed channel drops the event.
+				let _ = sink.lock().try_send(event);
+			}
 		}
-		Ok(pair)
+		result
 	}
 
-	/// Create a new key from seed.
+	/// Derive every generated key from the given master secret URI.
 	///
-	/// Does not place it into the file system store.
-	fn insert_ephemeral_from_seed_by_type<Pair: PairT>(
-		&mut self,
-		seed: &str,
-		key_type: KeyTypeId,
-	) -> Result<Pair> {
-		let pair = Pair::from_string(seed, None).map_err(|_| Error::InvalidSeed)?;
-		self.insert_ephemeral_pair(&pair, seed, key_type);
-		Ok(pair)
+	/// Keys generated without a seed are then hard derived children `//0`, `//1`, ... of the
+	/// master, in the order they are generated, instead of being generated from fresh
+	/// randomness. Each key file holds the derivation path of its key, so the whole keystore can
+	/// be reconstructed from the master phrase and the number of generated keys. Derivation
+	/// continues after the highest index of the existing children of the master. Keys generated
+	/// with [`LocalKeystore::generate_with_phrase`] are not derived.
+	///
+	/// Returns the index of the next derived key.
+	pub fn set_hd_root(&self, master_suri: &str) -> Result<u32> {
+		let _writing = self.writing()?;
+		self.0.write().set_hd_root(master_suri)
 	}
 
-	/// Get the key phrase for a given public key and key type.
-	fn key_phrase_by_type(&self, public: &[u8], key_type: KeyTypeId) -> Result<Option<String>> {
-		if let Some(phrase) = self.get_additional_pair(public, key_type) {
-			return Ok(Some(phrase.clone()))
+	/// Returns the derivation paths of the keys below the master of [`LocalKeystore::set_hd_root`].
+	///
+	/// Together with the master phrase, the index is enough to rebuild the derived keys with
+	/// [`LocalKeystore::recover_from_phrase`]. Without a master, no key has a path.
+	pub fn recovery_index(&self) -> Result<RecoveryIndex> {
+		self.0.read().recovery_index()
+	}
+
+	/// Rebuild the derived keys of the index in the keystore at `path`.
+	///
+	/// Opens the keystore at `path` with the password, creating it if needed, and stores every
+	/// key of the index that has a derivation path, derived from the master `phrase`. Keys
+	/// without a path, e.g. generated from fresh randomness, can not be recovered and are
+	/// skipped with a warning. Fails with [`Error::PublicKeyMismatch`] if a derived key is not
+	/// the listed key, e.g. because the phrase or the password is wrong. Returns the number of
+	/// recovered keys, including keys that were already in the keystore.
+	pub fn recover_from_phrase<T: Into<PathBuf>>(
+		path: T,
+		phrase: &str,
+		index: &RecoveryIndex,
+		password: Option<SecretString>,
+	) -> Result<usize> {
+		let store = Self::open(path, password)?;
+		let _writing = store.writing()?;
+		let mut inner = store.0.write();
+		let mut recovered = 0;
+		for (key_type, public, derivation) in &index.keys {
+			let derivation = match derivation {
+				Some(derivation) => derivation,
+				None => {
+					log::warn!(
+						target: "keystore",
+						"Skipped recovering the {:?} key 0x{}, it was not derived from the master",
+						key_type,
+						hex::encode(public),
+					);
+					continue
+				},
+			};
+			if !inner.contains_key(public, *key_type)? {
+				let suri = Zeroizing::new(format!("{}{}", phrase, derivation));
+				inner.insert_unknown(*key_type, &suri, public)?;
+			}
+			recovered += 1;
 		}
+		Ok(recovered)
+	}
 
-		let path = if let Some(path) = self.key_file_path(public, key_type) {
-			path
-		} else {
-			return Ok(None);
+	/// Keep the secret URIs of the keys that are only held in memory encrypted.
+	///
+	/// Disabled by default. When enabled, the secret URIs are encrypted with a key that is
+	/// generated for the process and never leaves it; on unix the memory of that key is locked,
+	/// so it is never swapped out. A secret URI is only decrypted while the key is loaded, e.g.
+	/// to sign, and zeroized right after. This shrinks the time secrets spend in memory as
+	/// plain text, at the cost of a decryption on every use of a key. Keys that are kept by
+	/// [`LocalKeystore::set_active_window`] are not encrypted.
+	pub fn set_encrypt_memory(&self, encrypt: bool) {
+		self.0.write().set_encrypt_memory(encrypt)
+	}
+
+	/// Check that the secret URIs inserted with `insert_unknown` derive to the given public key.
+	///
+	/// Enabled by default: a secret URI that doesn't derive to the public key with any of the
+	/// supported crypto types is rejected with [`Error::PublicKeyMismatch`], instead of being
+	/// stored under a key it can't sign for. Disable it to insert secrets of a crypto type the
+	/// keystore can't derive.
+	pub fn set_verify_inserted_public(&self, verify: bool) {
+		self.0.write().verify_inserted_public = verify;
+	}
+
+	/// Make the sr25519 signatures of `sign_with` deterministic.
+	///
+	/// By default sr25519 signatures are randomized: signing the same message twice gives two
+	/// different, equally valid signatures. In deterministic mode the signing nonce is derived
+	/// from the secret key and the message only, like the nonce of ed25519, so signing the same
+	/// message with the same key always gives the same signature. This is meant for tests and
+	/// protocols that need reproducible signatures; it doesn't weaken the key, but it reveals
+	/// when the same message was signed twice. VRF signatures and signatures made by an
+	/// enclave are not affected.
+	pub fn set_deterministic_sr25519(&self, deterministic: bool) {
+		self.0.write().deterministic_sr25519 = deterministic;
+	}
+
+	/// Returns whether sr25519 signatures are deterministic.
+	pub fn deterministic_sr25519(&self) -> bool {
+		self.0.read().deterministic_sr25519
+	}
+
+	/// Cache up to `capacity` signatures made by `sign_with`, `0` disables the cache.
+	///
+	/// Signing the same message with the same key again returns the cached signature, without
+	/// touching the secret key. This is only correct for deterministic signature schemes, so
+	/// only ed25519 and ecdsa signatures are cached; sr25519 signatures are randomized and
+	/// never cached, nor are VRF signatures. The signing hook still sees every message. When
+	/// the cache is full, the oldest signature is dropped.
+	pub fn set_signature_cache(&self, capacity: usize) {
+		let inner = self.0.read();
+		let mut cache = inner.signatures.lock();
+		cache.capacity = capacity;
+		cache.shrink();
+	}
+
+	/// Sign the message with the key, bypassing the signature cache.
+	fn sign_uncached(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		self.sign_many_uncached(id, key, &[msg])
+			.map(|signatures| signatures.and_then(|s| s.into_iter().next()))
+	}
+
+	/// Sign every message with the key, looking the key up once.
+	///
+	/// Bypasses the signature cache. Returns the signatures in the order of the messages.
+	fn sign_many_uncached(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> std::result::Result<Option<Vec<Vec<u8>>>, TraitError> {
+		if let Some(signatures) = self.in_enclave(id, &key.1, |enclave, secret| {
+			msgs.iter().map(|msg| enclave.sign(id, key, secret, msg)).collect()
+		}) {
+			return signatures;
+		}
+
+		let signatures: Option<Result<Vec<Vec<u8>>>> = match key.0 {
+			ed25519::CRYPTO_ID => {
+				let pub_key = ed25519::Public::from_slice(key.1.as_slice());
+				let key_pair = self.0.read()
+					.key_pair_by_type::<ed25519::Pair>(&pub_key, id)
+					.map_err(|e| TraitError::from(e))?;
+				key_pair.map(|k| {
+					msgs.iter().map(|msg| self.timed(id, || k.sign(msg).encode())).collect()
+				})
+			}
+			sr25519::CRYPTO_ID => {
+				let pub_key = sr25519::Public::from_slice(key.1.as_slice());
+				let (key_pair, deterministic) = {
+					let inner = self.0.read();
+					let key_pair = inner.key_pair_by_type::<sr25519::Pair>(&pub_key, id)
+						.map_err(|e| TraitError::from(e))?;
+					(key_pair, inner.deterministic_sr25519)
+				};
+				key_pair.map(|k| msgs.iter().map(|msg| self.timed(id, || if deterministic {
+					sign_sr25519_deterministic(&k, msg).encode()
+				} else {
+					k.sign(msg).encode()
+				})).collect())
+			},
+			ecdsa::CRYPTO_ID => {
+				let pub_key = ecdsa::Public::from_slice(key.1.as_slice());
+				let key_pair = self.0.read()
+					.key_pair_by_type::<ecdsa::Pair>(&pub_key, id)
+					.map_err(|e| TraitError::from(e))?;
+				key_pair.map(|k| {
+					msgs.iter().map(|msg| self.timed(id, || k.sign(msg).encode())).collect()
+				})
+			}
+			_ => return Err(TraitError::KeyNotSupported(id))
+		};
+		let signatures = signatures.transpose().map_err(TraitError::from)?;
+
+		if signatures.is_some() {
+			self.record_use(id, &key.1);
+		}
+		Ok(signatures)
+	}
+
+	/// Create a queue of signing requests and the worker answering them.
+	///
+	/// Meant for services that sign at a high rate: requests are pushed with the returned
+	/// [`SignSender`] and answered by [`SignWorker::run`], which signs all queued requests for
+	/// the same key after looking the key up once, instead of reading and deriving the key for
+	/// every message. At most `capacity` requests are queued, plus one for every clone of the
+	/// sender; pushing onto a full queue fails with `Unavailable`. Every message passes the
+	/// signing hook like with `sign_with`, but the signature cache is bypassed.
+	pub fn signing_queue(&self, capacity: usize) -> (SignSender, SignWorker<'_>) {
+		let (sender, receiver) = mpsc::channel(capacity);
+		(SignSender::new(sender), SignWorker::new(self, receiver))
+	}
+
+	/// Sign every message with the key, answering the queued requests of a [`SignWorker`].
+	pub(crate) fn sign_queued(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> Vec<SignResult> {
+		let checks = msgs.iter()
+			.map(|msg| self.0.read().check_sign_hook(id, key, msg))
+			.collect::<Vec<_>>();
+		let allowed = msgs.iter()
+			.zip(&checks)
+			.filter(|(_, check)| check.is_ok())
+			.map(|(msg, _)| *msg)
+			.collect::<Vec<_>>();
+
+		let signatures: Vec<SignResult> = match self.sign_many_uncached(id, key, &allowed) {
+			Ok(Some(signatures)) => signatures.into_iter().map(|s| Ok(Some(s))).collect(),
+			Ok(None) => allowed.iter().map(|_| Ok(None)).collect(),
+			// Errors can't be cloned, every message gets its own.
+			Err(_) => allowed.iter().map(|msg| self.sign_uncached(id, key, msg)).collect(),
+		};
+		let mut signatures = signatures.into_iter();
+		checks.into_iter()
+			.map(|check| match check {
+				Ok(()) => signatures.next().expect("One signature per allowed message; qed"),
+				Err(e) => Err(e.into()),
+			})
+			.collect()
+	}
+
+	/// Run the signing operation once the concurrency limit allows it, reporting how long it
+	/// took to the signing metrics.
+	fn timed<R>(&self, id: KeyTypeId, sign: impl FnOnce() -> R) -> Result<R> {
+		let (limiter, metrics, clock) = {
+			let inner = self.0.read();
+			(inner.sign_limiter.clone(), inner.metrics.clone(), inner.clock.clone())
+		};
+		let _permit = limiter.try_acquire()?;
+		let metrics = match metrics {
+			Some(metrics) => metrics,
+			None => return Ok(sign()),
+		};
+		let start = clock.now();
+		let result = sign();
+		metrics.on_sign_complete(id, clock.now().saturating_duration_since(start));
+		Ok(result)
+	}
+
+	/// Run a signing operation in the enclave, handing it the stored secret of the key.
+	///
+	/// Returns `None` if no enclave is used.
+	fn in_enclave<R>(
+		&self,
+		id: KeyTypeId,
+		public: &[u8],
+		sign: impl FnOnce(&dyn EnclaveSigner, &[u8]) -> std::result::Result<Option<R>, TraitError>,
+	) -> Option<std::result::Result<Option<R>, TraitError>> {
+		let inner = self.0.read();
+		let enclave = inner.enclave.clone()?;
+		let secret = match inner.key_phrase_by_type(public, id) {
+			Ok(Some(secret)) => Zeroizing::new(secret),
+			Ok(None) => return Some(Ok(None)),
+			Err(e) => return Some(Err(e.into())),
 		};
+		drop(inner);
+
+		let signature = self.timed(id, || sign(&*enclave, secret.as_bytes()))
+			.map_err(TraitError::from)
+			.and_then(|signature| signature);
+		if let Ok(Some(_)) = signature {
+			self.record_use(id, public);
+		}
+		Some(signature)
+	}
+
+	/// Record that the key was used to sign, see [`LocalKeystore::last_used`].
+	///
+	/// Signing does not wait for paused writes, the use is not recorded then.
+	fn record_use(&self, id: KeyTypeId, public: &[u8]) {
+		let inner = self.0.read();
+		if let Some(_writing) = inner.write_gate.try_enter() {
+			inner.record_use(public, id);
+		}
+	}
+
+	/// Pause all changes to the keystore directory until the returned guard is dropped.
+	///
+	/// Waits for the changes in flight to finish; changes made while the writes are paused
+	/// wait for them to resume, so the directory can be copied consistently, e.g. with
+	/// `cp -a`, while the node keeps running. Listing keys and signing go on as usual, but
+	/// signing does not record the last use of keys. A change that waits for longer than
+	/// [`MAX_WRITE_PAUSE`] fails with [`Error::WritesPaused`], so a forgotten guard does not
+	/// stall consensus forever; keep the pause short.
+	pub fn pause_writes(&self) -> WritePauseGuard {
+		let gate = self.0.read().write_gate.clone();
+		gate.pause()
+	}
+
+	/// Enter the write gate to change the keystore directory, see
+	/// [`LocalKeystore::pause_writes`].
+	fn writing(&self) -> Result<WriteTicket> {
+		let gate = self.0.read().write_gate.clone();
+		gate.enter(MAX_WRITE_PAUSE)
+	}
+
+	/// Move a key that was inserted under the wrong key type to another key type.
+	///
+	/// Only the key file is moved, along with the record of its last use; the secret is not
+	/// read. The file is linked under its new name before the old name is removed, so the key
+	/// is never lost. Fails with [`Error::KeyCollision`] if a key with the same public key
+	/// already exists under `to`, and with [`Error::PairNotFound`] if there is no such key
+	/// under `from`.
+	pub fn retype_key(&self, from: KeyTypeId, public: &[u8], to: KeyTypeId) -> Result<()> {
+		let _writing = self.writing()?;
+		self.0.write().retype_key(from, public, to)
+	}
+
+	/// Returns the keys whose key files were modified after the given time.
+	///
+	/// Meant for incremental backups: only keys that changed since the last run need to be
+	/// copied. Keys that are only held in memory are never returned.
+	pub fn keys_modified_since(&self, since: SystemTime) -> Result<Vec<(KeyTypeId, Vec<u8>)>> {
+		self.0.read().keys_modified_since(since)
+	}
+
+	/// Export all keys into a backup encrypted with the given password.
+	///
+	/// With `compress` set the keys are compressed with zstd before they are encrypted, which
+	/// makes backups of large keystores considerably smaller. Whether a backup is compressed is
+	/// recorded in its header, [`LocalKeystore::import_backup`] detects it. The backup embeds a
+	/// manifest of its keys, see [`LocalKeystore::verify_backup`].
+	pub fn export_backup(&self, password: &SecretString, compress: bool) -> Result<Vec<u8>> {
+		self.0.read().export_backup(password, compress)
+	}
+
+	/// Check the integrity of a backup created with [`LocalKeystore::export_backup`] without
+	/// importing it.
+	///
+	/// Every key is checked against the MAC the manifest of the backup lists for it, the report
+	/// names the keys that are missing, do not match or are not listed. Returns
+	/// [`Error::InvalidPassword`] if the manifest can not be authenticated with the password,
+	/// which is also the case for a manifest that was tampered with, and
+	/// [`Error::InvalidBackup`] for a backup without a manifest.
+	pub fn verify_backup(backup: &[u8], password: &SecretString) -> Result<VerifyReport> {
+		backup::verify(backup, password)
+	}
+
+	/// Export a single key as a keystore JSON file of polkadot-js, encrypted with the given
+	/// password.
+	///
+	/// The file can be imported into the polkadot-js UI, which shows the key with the given
+	/// metadata. Returns [`Error::PairNotFound`] if the key doesn't exist and
+	/// [`Error::KeyNotSupported`] if its crypto is not supported by polkadot-js.
+	pub fn export_pjs_json(
+		&self,
+		id: KeyTypeId,
+		public: &[u8],
+		password: &SecretString,
+		meta: PjsMeta,
+	) -> Result<Vec<u8>> {
+		let json = self.0.read().export_pjs_json(id, public, password, meta)?;
+		Ok(serde_json::to_vec(&json)?)
+	}
+
+	/// Export a single key as polkadot-js keystore JSON without metadata, see
+	/// [`LocalKeystore::export_pjs_json`].
+	pub fn export_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		password: &str,
+	) -> Result<serde_json::Value> {
+		let password = SecretString::new(password.into());
+		self.0.read().export_pjs_json(id, &public.1, &password, PjsMeta::default())
+	}
+
+	/// Import a key from polkadot-js keystore JSON, encrypted with the given password.
+	///
+	/// The key is inserted like [`LocalKeystore::insert_raw_seed`] does and its public key is
+	/// returned. Returns [`Error::InvalidPassword`] if the JSON can not be decrypted with the
+	/// password and [`Error::InvalidPjsJson`] if it is malformed. polkadot-js only stores the
+	/// expanded secret key of sr25519 keys, so they are stored as their secret key, see
+	/// [`LocalKeystore::insert_raw_seed`], and can not be derived from.
+	pub fn import_key(
+		&self,
+		id: KeyTypeId,
+		json: &serde_json::Value,
+		password: &str,
+	) -> Result<CryptoTypePublicPair> {
+		let (scheme, seed) = pjs::import(id, json, &SecretString::new(password.into()))?;
+		self.insert_raw_seed(id, scheme, &seed)
+	}
+
+	/// Import a key from a keystore JSON file of polkadot-js, see [`LocalKeystore::import_key`].
+	///
+	/// Returns [`Error::InvalidPjsJson`] if the file is not JSON.
+	pub fn import_pjs_json(
+		&self,
+		id: KeyTypeId,
+		json: &[u8],
+		password: &SecretString,
+	) -> Result<CryptoTypePublicPair> {
+		let json = serde_json::from_slice(json)
+			.map_err(|e| Error::InvalidPjsJson(format!("the file is not JSON: {}", e)))?;
+		let (scheme, seed) = pjs::import(id, &json, password)?;
+		self.insert_raw_seed(id, scheme, &seed)
+	}
+
+	/// Import the keys of a backup created with [`LocalKeystore::export_backup`].
+	///
+	/// Keys that already exist are handled according to `overwrite`. With
+	/// [`OverwritePolicy::Error`] all keys are checked before anything is written, so either all
+	/// keys are imported or the import fails with [`Error::KeysExist`] listing the colliding keys.
+	/// Returns [`Error::InvalidPassword`] if the backup can not be decrypted with the given
+	/// password and [`Error::InvalidBackup`] if it is malformed. Every key is checked to derive
+	/// from its secret URI with the password of this keystore before anything is written, a key
+	/// that does not, because the backup was damaged or made by a keystore with another
+	/// password, fails the import with [`Error::CorruptKeyFile`].
+	///
+	/// Returns the number of imported keys.
+	pub fn import_backup(
+		&self,
+		backup: &[u8],
+		password: &SecretString,
+		overwrite: OverwritePolicy,
+	) -> Result<usize> {
+		let _writing = self.writing()?;
+		self.0.write().import_backup(backup, password, overwrite)
+	}
+
+	/// Set the policy imported secret URIs have to satisfy.
+	///
+	/// The policy is consulted before a secret URI is inserted with `insert_unknown` or used by
+	/// one of the `*_generate_new` methods with a seed. A rejection is returned as
+	/// [`Error::InvalidPhrase`] carrying the policy's message.
+	pub fn set_suri_policy(&self, policy: SuriPolicy) {
+		self.0.write().set_suri_policy(policy);
+	}
+
+	/// Set how many keys of the key type generating keys may create.
+	///
+	/// Applies to the `*_generate_new` methods without a seed and to the consensus and node
+	/// identity helpers. The check and the generation happen under the keystore lock, so of
+	/// concurrent calls under [`SingletonPolicy::Singleton`] only the first creates a key.
+	pub fn set_singleton_policy(&self, id: KeyTypeId, policy: SingletonPolicy) {
+		self.0.write().set_singleton_policy(id, policy);
+	}
+
+	/// Set the hook every message has to pass before it is signed with `sign_with`.
+	///
+	/// The hook receives the key type, the key and the message, and can refuse to sign by
+	/// returning an error. The refusal is returned as [`TraitError::PermissionDenied`] and its
+	/// message is logged. The hook runs on the signing hot path with the keystore locked, so it
+	/// should be cheap and must not call back into the keystore.
+	pub fn set_sign_hook(&self, hook: SignHook) {
+		self.0.write().sign_hook = Some(hook);
+	}
+
+	/// Set what reading the secrets of all keys, e.g. to back them up or to prefetch them, does
+	/// with keys whose secret can not be read. Defaults to [`DecryptFailurePolicy::Fail`].
+	pub fn set_decrypt_failure_policy(&self, policy: DecryptFailurePolicy) {
+		self.0.write().decrypt_failure_policy = policy;
+	}
+
+	/// Returns the keys of the given key type that are stored, but whose secret can not be read.
+	///
+	/// See [`DecryptFailurePolicy`] for what makes a secret unreadable. Unlike the key listing,
+	/// this includes keys whose key file fails to parse.
+	pub fn unreadable_keys(&self, id: KeyTypeId) -> Result<Vec<Vec<u8>>> {
+		self.0.read().unreadable_keys(id)
+	}
+
+	/// Set the length in bytes of the longest message `sign_with` and the signing queue sign.
+	///
+	/// Longer messages are rejected with [`Error::MessageTooLarge`] before they are hashed or
+	/// passed to the signing hook. Defaults to 16 MiB. VRF transcripts and the pre-hashed
+	/// messages of `ecdsa_sign_prehashed` are not affected, their size is bounded by their
+	/// types.
+	pub fn set_max_sign_message_len(&self, max: usize) {
+		self.0.write().max_sign_message_len = max;
+	}
+
+	/// Overwrite key files with zeros before they are unlinked, disabled by default.
+	///
+	/// Applies to the key files removed by `remove_key` and [`LocalKeystore::dedup`]. This is
+	/// best effort: on copy-on-write and log-structured file systems, SSDs and file systems
+	/// journaling data, the old content can remain on disk. [`KeystoreSnapshot::copy_on_write`]
+	/// tells whether the keystore directory is on a file system known to defeat it.
+	pub fn set_secure_delete(&self, enabled: bool) {
+		self.0.write().secure_delete = enabled;
+	}
+
+	/// Set the crypto type [`LocalKeystore::generate_default`] generates, sr25519 by default.
+	///
+	/// Only that method is affected, every other way of generating keys names its crypto type.
+	pub fn set_default_scheme(&self, scheme: CryptoTypeId) {
+		self.0.write().default_scheme = scheme;
+	}
+
+	/// Lock the keystore, refusing to sign until it is unlocked.
+	///
+	/// While locked, `sign_with`, `sr25519_vrf_sign`, `ecdsa_sign_prehashed` and the signing
+	/// queue fail with [`Error::Locked`], returned as [`TraitError::Unavailable`] by the traits.
+	/// Listing, generating and managing keys keep working. A node can open its keystore locked
+	/// so it does not author before an operator unlocks it.
+	pub fn lock(&self) {
+		self.0.read().unlocked.store(false, Ordering::SeqCst);
+	}
+
+	/// Unlock the keystore with its password.
+	///
+	/// The password is the one the keystore was opened with, there is no separate unlock
+	/// password; a keystore opened without a password is unlocked with an empty one. Fails with
+	/// [`Error::InvalidPassword`] and stays locked if the password does not match.
+	pub fn unlock(&self, password: &SecretString) -> Result<()> {
+		let inner = self.0.read();
+		if inner.pending_shares.is_some() {
+			return Err(Error::Locked);
+		}
+		let expected = inner.password().unwrap_or_default().as_bytes();
+		if !bool::from(expected.ct_eq(password.expose_secret().as_bytes())) {
+			return Err(Error::InvalidPassword);
+		}
+		inner.unlocked.store(true, Ordering::SeqCst);
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl CryptoStore for LocalKeystore {
+	async fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::keys(self, id)
+	}
+
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		SyncCryptoStore::sr25519_public_keys(self, id)
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		SyncCryptoStore::sr25519_generate_new(self, id, seed)
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		SyncCryptoStore::ed25519_public_keys(self, id)
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		SyncCryptoStore::ed25519_generate_new(self, id, seed)
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		SyncCryptoStore::ecdsa_public_keys(self, id)
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		SyncCryptoStore::ecdsa_generate_new(self, id, seed)
+	}
+
+	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> std::result::Result<(), ()> {
+		SyncCryptoStore::insert_unknown(self, id, suri, public)
+	}
+
+	async fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		SyncCryptoStore::remove_key(self, id, public)
+	}
+
+	async fn remove_keys(
+		&self,
+		items: &[(KeyTypeId, Vec<u8>)],
+	) -> Vec<std::result::Result<(), TraitError>> {
+		SyncCryptoStore::remove_keys(self, items)
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		SyncCryptoStore::has_keys(self, public_keys)
+	}
+
+	async fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		SyncCryptoStore::has_key_exact(self, id, public)
+	}
+
+	async fn which_keys_present(&self, queries: &[(Vec<u8>, KeyTypeId)]) -> Vec<bool> {
+		SyncCryptoStore::which_keys_present(self, queries)
+	}
+
+	async fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		SyncCryptoStore::key_types(self)
+	}
+
+	async fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		SyncCryptoStore::fingerprint(self)
+	}
+
+	async fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> std::result::Result<Attestation, TraitError> {
+		SyncCryptoStore::attest_contents(self, signing_id, signing_key)
+	}
+
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> std::result::Result<(), TraitError> {
+		SyncCryptoStore::preload(self, keys)
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::supported_keys(self, id, keys)
+	}
+
+	async fn vrf_capable_keys(
+		&self,
+		id: KeyTypeId,
+	) -> std::result::Result<Vec<sr25519::Public>, TraitError> {
+		SyncCryptoStore::vrf_capable_keys(self, id)
+	}
+
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		let supported = SyncCryptoStore::supported_keys(self, id, candidates.to_vec())?;
+		let inner = self.0.read();
+		let mut usable = Vec::with_capacity(supported.len());
+		for key in supported {
+			if inner.is_usable(&key, id)? {
+				usable.push(key);
+			}
+		}
+		Ok(usable)
+	}
+
+	async fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::keys_not_in(self, id, authorities)
+	}
+
+	/// Lists every key once, under the crypto type its secret derives it with.
+	async fn keys_by_type(
+		&self,
+		id: KeyTypeId,
+	) -> std::result::Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, TraitError> {
+		let mut grouped = HashMap::<_, Vec<_>>::new();
+		for CryptoTypePublicPair(crypto, public) in self.0.read().typed_keys(id)? {
+			grouped.entry(crypto).or_default().push(public);
+		}
+		Ok(grouped)
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		let limiter = self.0.read().sign_limiter.clone();
+		limiter.run(|| SyncCryptoStore::sign_with(self, id, key, msg)).await
+	}
+
+	async fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> std::result::Result<Vec<Vec<u8>>, TraitError> {
+		let limiter = self.0.read().sign_limiter.clone();
+		limiter.run(|| SyncCryptoStore::sign_with_many(self, id, key, msgs)).await
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		let limiter = self.0.read().sign_limiter.clone();
+		limiter.run(|| SyncCryptoStore::sr25519_vrf_sign(self, key_type, public, transcript_data))
+			.await
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		let limiter = self.0.read().sign_limiter.clone();
+		limiter.run(|| SyncCryptoStore::ecdsa_sign_prehashed(self, id, public, msg)).await
+	}
+}
+
+impl SyncCryptoStore for LocalKeystore {
+	fn keys(
+		&self,
+		id: KeyTypeId
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.reported("keys", Some(id), || {
+			let raw_keys = self.0.read().listed_public_keys(id)?;
+			Ok(raw_keys.into_iter()
+				.fold(Vec::new(), |mut v, k| {
+					v.push(CryptoTypePublicPair(sr25519::CRYPTO_ID, k.clone()));
+					v.push(CryptoTypePublicPair(ed25519::CRYPTO_ID, k.clone()));
+					v.push(CryptoTypePublicPair(ecdsa::CRYPTO_ID, k));
+					v
+				}))
+		})
+	}
+
+	/// Lists every key once, under the crypto type its secret derives it with. Keys are removed
+	/// whatever the crypto type `remove_key` is given, so a key is only listed if no authority
+	/// has its public key.
+	fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		let keys = self.0.read().typed_keys(id)?;
+		Ok(keys.into_iter()
+			.filter(|key| !authorities.iter().any(|authority| authority.1 == key.1))
+			.collect())
+	}
+
+	fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		let mut all_keys = SyncCryptoStore::keys(self, id)?
+			.into_iter()
+			.collect::<HashSet<_>>();
+		// Removing a returned key from the set keeps duplicates in `keys` from being returned
+		// twice.
+		Ok(keys.into_iter().filter(|key| all_keys.remove(key)).collect::<Vec<_>>())
+	}
+
+	fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		self.reported("sign_with", Some(id), || {
+			self.0.read().check_sign_hook(id, key, msg)?;
+
+			let enabled = self.0.read().signatures.lock().capacity > 0;
+			if !enabled || !SignatureCache::is_cacheable(key.0) {
+				return self.sign_uncached(id, key, msg);
+			}
+
+			let cache_key = (id, key.clone(), sp_core::hashing::blake2_256(msg));
+			let cached = self.0.read().signatures.lock().signatures.get(&cache_key).cloned();
+			if let Some(signature) = cached {
+				self.record_use(id, &key.1);
+				return Ok(Some(signature));
+			}
+			let signature = self.sign_uncached(id, key, msg)?;
+			if let Some(signature) = &signature {
+				self.0.read().signatures.lock().insert(cache_key, signature.clone());
+			}
+			Ok(signature)
+		})
+	}
+
+	fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> std::result::Result<Vec<Vec<u8>>, TraitError> {
+		self.reported("sign_with_many", Some(id), || {
+			for msg in msgs {
+				self.0.read().check_sign_hook(id, key, msg)?;
+			}
+			// Loads the key once for all messages, bypassing the signature cache.
+			self.sign_many_uncached(id, key, msgs)?.ok_or(TraitError::PairNotFound)
+		})
+	}
+
+	fn sr25519_public_keys(&self, key_type: KeyTypeId) -> Vec<sr25519::Public> {
+		self.0.read().listed_public_keys(key_type)
+			.map(|v| {
+				v.into_iter()
+				 .map(|k| sr25519::Public::from_slice(k.as_slice()))
+				 .collect()
+			})
+			.unwrap_or_default()
+	}
+	fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		self.reported("sr25519_generate_new", Some(id), || {
+			let _writing = self.writing()?;
+			let pair = match seed {
+				Some(seed) => self.0.write()
+					.insert_ephemeral_from_seed_by_type::<sr25519::Pair>(seed, id),
+				None => self.0.write().generate_by_type::<sr25519::Pair>(id),
+			}.map_err(|e| -> TraitError { e.into() })?;
+
+			Ok(pair.public())
+		})
+	}
+
+	fn ed25519_public_keys(&self, key_type: KeyTypeId) -> Vec<ed25519::Public> {
+		self.0.read().listed_public_keys(key_type)
+			.map(|v| {
+				v.into_iter()
+				 .map(|k| ed25519::Public::from_slice(k.as_slice()))
+				 .collect()
+			})
+			.unwrap_or_default()
+	}
+
+	fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		self.reported("ed25519_generate_new", Some(id), || {
+			let _writing = self.writing()?;
+			let pair = match seed {
+				Some(seed) => self.0.write()
+					.insert_ephemeral_from_seed_by_type::<ed25519::Pair>(seed, id),
+				None => self.0.write().generate_by_type::<ed25519::Pair>(id),
+			}.map_err(|e| -> TraitError { e.into() })?;
+
+			Ok(pair.public())
+		})
+	}
+
+	fn ecdsa_public_keys(&self, key_type: KeyTypeId) -> Vec<ecdsa::Public> {
+		self.0.read().listed_public_keys(key_type)
+			.map(|v| {
+				v.into_iter()
+					.map(|k| ecdsa::Public::from_slice(k.as_slice()))
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		self.reported("ecdsa_generate_new", Some(id), || {
+			let _writing = self.writing()?;
+			let pair = match seed {
+				Some(seed) => self.0.write()
+					.insert_ephemeral_from_seed_by_type::<ecdsa::Pair>(seed, id),
+				None => self.0.write().generate_by_type::<ecdsa::Pair>(id),
+			}.map_err(|e| -> TraitError { e.into() })?;
+
+			Ok(pair.public())
+		})
+	}
+
+	fn insert_unknown(&self, key_type: KeyTypeId, suri: &str, public: &[u8])
+		-> std::result::Result<(), ()>
+	{
+		let result = self.writing()
+			.and_then(|_writing| self.0.write().insert_unknown(key_type, suri, public));
+		self.reported("insert_unknown", Some(key_type), || result).map_err(|_| ())
+	}
+
+	fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		self.reported("remove_key", Some(id), || {
+			match public.0 {
+				sr25519::CRYPTO_ID | ed25519::CRYPTO_ID | ecdsa::CRYPTO_ID => (),
+				_ => return Err(TraitError::KeyNotSupported(id)),
+			}
+			let _writing = self.writing()?;
+			self.0.write().remove_key(&public.1, id).map_err(Into::into)
+		})
+	}
+
+	/// All keys are removed under one lock and the key directories are synced once at the end.
+	fn remove_keys(
+		&self,
+		items: &[(KeyTypeId, Vec<u8>)],
+	) -> Vec<std::result::Result<(), TraitError>> {
+		let results = match self.writing() {
+			Ok(_writing) => self.0.write().remove_keys(items),
+			// The write gate only fails while writes are paused, which fails every removal.
+			Err(_) => items.iter().map(|_| Err(Error::WritesPaused)).collect(),
+		};
+		results.into_iter()
+			.zip(items)
+			.map(|(result, (id, _))| self.reported("remove_keys", Some(*id), || {
+				result.map_err(TraitError::from)
+			}))
+			.collect()
+	}
+
+	fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		self.reported("key_types", None, || {
+			let key_types = self.0.read().all_public_keys()?
+				.into_iter()
+				.map(|(id, _)| id)
+				.collect::<BTreeSet<_>>();
+			Ok(key_types.into_iter().collect())
+		})
+	}
+
+	// Reads the keys of all key types at once, the default reads the directory once per type.
+	fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		self.reported("fingerprint", None, || {
+			Ok(keys_fingerprint(self.0.read().all_public_keys()?))
+		})
+	}
+
+	fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> std::result::Result<Attestation, TraitError> {
+		self.reported("attest_contents", Some(signing_id), || {
+			let keys = self.0.read().all_public_keys()?;
+			Attestation::sign(keys, signing_key.clone(), |msg| {
+				SyncCryptoStore::sign_with(self, signing_id, signing_key, msg)
+			})
+		})
+	}
+
+	/// Preloading reads the key files, which brings them into the operating system's cache, and
+	/// checks that they derive to their public keys. If an active window is set, the derived keys
+	/// are kept in memory like using them does, see [`LocalKeystore::set_active_window`].
+	fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> std::result::Result<(), TraitError> {
+		self.reported("preload", None, || {
+			let inner = self.0.read();
+			let failures = keys.iter()
+				.filter_map(|(id, public)| {
+					let failure = match inner.key_phrase_by_type(public, *id) {
+						Ok(Some(phrase)) if inner.phrase_matches(&phrase, public) => {
+							inner.activate(public, *id);
+							return None;
+						},
+						Ok(Some(_)) => Error::InvalidPassword,
+						Ok(None) => Error::PairNotFound,
+						Err(e) => e,
+					};
+					Some(format!("{}{}: {}", hex::encode(id.0), hex::encode(public), failure))
+				})
+				.collect::<Vec<_>>();
+
+			if failures.is_empty() {
+				Ok(())
+			} else {
+				Err(TraitError::ValidationError(
+					format!("Failed to preload {} keys: {}", failures.len(), failures.join(", ")),
+				))
+			}
+		})
+	}
+
+	/// Reads the key files of the key type, as the keys of other crypto types are listed as
+	/// sr25519 keys too.
+	fn vrf_capable_keys(
+		&self,
+		id: KeyTypeId,
+	) -> std::result::Result<Vec<sr25519::Public>, TraitError> {
+		let inner = self.0.read();
+		let mut capable = Vec::new();
+		for public in inner.listed_public_keys(id)? {
+			if public.len() == 32 && !inner.is_non_sr25519_key(&public, id)? {
+				capable.push(sr25519::Public::from_slice(&public));
+			}
+		}
+		Ok(capable)
+	}
+
+	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		let inner = self.0.read();
+		public_keys.iter().all(|(p, t)| inner.contains_key(&p, *t).unwrap_or(false))
+	}
+
+	/// Keys in the index and ephemeral keys are found without reading a key file or a query list.
+	fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		self.0.read().contains_key(public, id).unwrap_or(false)
+	}
+
+	/// The keys of all queried key types are listed at once, instead of looking up every key
+	/// on its own.
+	fn which_keys_present(&self, queries: &[(Vec<u8>, KeyTypeId)]) -> Vec<bool> {
+		self.0.read().keys_present(queries).unwrap_or_else(|_| vec![false; queries.len()])
+	}
+
+	fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &Sr25519Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		self.reported("sr25519_vrf_sign", Some(key_type), || {
+			self.0.read().check_unlocked()?;
+			let enclave_data = transcript_data.clone();
+			if let Some(signature) = self.in_enclave(key_type, public.as_ref(), |enclave, secret| {
+				enclave.vrf_sign(key_type, public, secret, enclave_data)
+			}) {
+				return signature;
+			}
+
+			let transcript = make_transcript(transcript_data);
+			let inner = self.0.read();
+			let pair = match inner.key_pair_by_type::<Sr25519Pair>(public, key_type) {
+				Err(Error::InvalidPassword)
+					if inner.is_non_sr25519_key(public.as_ref(), key_type)? =>
+					return Err(Error::VrfNotAvailable.into()),
+				pair => pair?,
+			};
+			// The lock is only needed to derive the pair. Releasing it before the VRF is computed
+			// lets VRF signatures of several threads be computed in parallel and keeps them from
+			// holding up writers. The secret key of the pair is zeroized when it is dropped.
+			drop(inner);
+
+			if let Some(pair) = pair {
+				let (inout, proof, _) =
+					self.timed(key_type, || pair.as_ref().vrf_sign(transcript))?;
+				self.record_use(key_type, public.as_ref());
+				Ok(Some(VRFSignature {
+					output: inout.to_output(),
+					proof,
+				}))
+			} else {
+				Ok(None)
+			}
+		})
+	}
+
+	fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		self.reported("ecdsa_sign_prehashed", Some(id), || {
+			self.0.read().check_unlocked()?;
+			if self.0.read().enclave.is_some() {
+				return Err(TraitError::KeyNotSupported(id));
+			}
+			let pair = match self.0.read().key_pair_by_type::<ecdsa::Pair>(public, id) {
+				Ok(Some(pair)) => pair,
+				Ok(None) => return Err(TraitError::PairNotFound),
+				// The secret derives to another key with ecdsa, so it is a key of another crypto
+				// type.
+				Err(Error::InvalidPassword) => return Err(TraitError::KeyNotSupported(id)),
+				Err(e) => return Err(e.into()),
+			};
+
+			let signature = self.timed(id, || pair.sign_prehashed(msg))?;
+			self.record_use(id, public.as_ref());
+			Ok(signature)
+		})
+	}
+}
+
+impl Into<SyncCryptoStorePtr> for LocalKeystore {
+	fn into(self) -> SyncCryptoStorePtr {
+		Arc::new(self)
+	}
+}
+
+impl Into<Arc<dyn CryptoStore>> for LocalKeystore {
+	fn into(self) -> Arc<dyn CryptoStore> {
+		Arc::new(self)
+	}
+}
+
+/// A set of files in the keystore directory that hold the same key.
+///
+/// A botched manual copy can leave a key in several files, e.g. with different casing of the hex
+/// encoded name or with an extra extension. Files are grouped by the public key their stored
+/// phrase derives to, so only files that really hold the key are reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+	/// The key type the files are stored under.
+	pub key_type: KeyTypeId,
+	/// The raw public key held by all files.
+	pub public: Vec<u8>,
+	/// The files holding the key, sorted by path.
+	pub files: Vec<PathBuf>,
+}
+
+/// The outcome of [`LocalKeystore::recover_interrupted`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+	/// Key files that were restored from a left over copy.
+	pub restored: Vec<PathBuf>,
+	/// Left over copies that were removed.
+	pub discarded: Vec<PathBuf>,
+	/// Key files that are unusable and have no usable copy.
+	pub unresolved: Vec<PathBuf>,
+}
+
+/// The outcome of [`LocalKeystore::preflight_check`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PreflightReport {
+	/// The number of checked key files.
+	pub keys: usize,
+	/// Issues that do not keep the keystore from working, meant to be logged.
+	pub warnings: Vec<String>,
+}
+
+/// The disk space taken by a keystore, see [`LocalKeystore::disk_usage`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+	/// Bytes of all files below.
+	pub total: u64,
+	/// Bytes of the key files.
+	pub key_files: u64,
+	/// Bytes of the copies left by interrupted updates.
+	pub temporary: u64,
+	/// Bytes of the key index and the write-ahead log.
+	pub index: u64,
+	/// Bytes of the files stored next to the key files, like notes and aliases.
+	pub sidecars: u64,
+	/// Bytes of the files in the key directories the keystore does not know.
+	pub other: u64,
+	/// Bytes of the key files, their copies and sidecar files per key type.
+	pub by_key_type: BTreeMap<KeyTypeId, u64>,
+}
+
+/// How the keys of a keystore derive from its master, see [`LocalKeystore::recovery_index`].
+///
+/// Holds no secrets, so it can be kept in plain text, SCALE encoded, next to the backup of the
+/// master phrase.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct RecoveryIndex {
+	/// The key type, raw public key and derivation path below the master of every key on disk.
+	///
+	/// The path is `None` for keys that were not derived from the master.
+	pub keys: Vec<(KeyTypeId, Vec<u8>, Option<String>)>,
+}
+
+/// The outcome of [`verify_replica`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReplicaReport {
+	/// Keys of the primary keystore that are missing in the replica.
+	pub missing: Vec<(KeyTypeId, Vec<u8>)>,
+	/// Keys of the replica that are not in the primary keystore.
+	pub extra: Vec<(KeyTypeId, Vec<u8>)>,
+	/// Keys of both keystores with a different secret, or a secret that can not be loaded.
+	pub mismatched: Vec<(KeyTypeId, Vec<u8>)>,
+}
+
+impl ReplicaReport {
+	/// Returns `true` if both keystores hold exactly the same key material.
+	pub fn is_replica(&self) -> bool {
+		self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+	}
+}
+
+/// The state of a keystore, without any secrets, see [`LocalKeystore::debug_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct KeystoreSnapshot {
+	/// The keystore directory, `None` for keystores in memory.
+	pub path: Option<PathBuf>,
+	/// How the key files are laid out, for example `ByKeyType`.
+	pub layout: String,
+	/// The number of keys per hex encoded key type, in memory and on disk.
+	pub key_counts: BTreeMap<String, usize>,
+	/// The names of all files in the key directories, relative to the keystore directory.
+	///
+	/// Key files are named after their key type and public key, neither of which is secret.
+	pub files: Vec<String>,
+	/// The number of keys that are only held in memory.
+	pub ephemeral_keys: usize,
+	/// Whether keys that are only held in memory are allowed.
+	pub allow_ephemeral: bool,
+	/// Whether a password is set. The password itself is never included.
+	pub has_password: bool,
+	/// Whether a secret URI policy is set.
+	pub has_suri_policy: bool,
+	/// Whether secret key operations are delegated to an enclave.
+	pub enclave: bool,
+	/// The active window in milliseconds, if keys are kept in memory after use.
+	pub active_window_ms: Option<u128>,
+	/// The number of keys currently kept in memory.
+	pub active_keys: usize,
+	/// Whether key files are overwritten before they are removed, see
+	/// [`LocalKeystore::set_secure_delete`].
+	pub secure_delete: bool,
+	/// Whether the keystore directory is on a copy-on-write or log-structured file system, on
+	/// which overwriting key files does not remove their old content. `None` if not known.
+	pub copy_on_write: Option<bool>,
+}
+
+/// Check that `replica` holds exactly the same key material as `primary`.
+///
+/// Unlike comparing fingerprints, this compares the secrets: for every key in both keystores
+/// the secret key is derived with the password of each keystore and both are compared in
+/// constant time. The secrets are zeroized afterwards. All keys are reported sorted.
+pub fn verify_replica(primary: &LocalKeystore, replica: &LocalKeystore) -> Result<ReplicaReport> {
+	let primary = primary.0.read();
+	let replica = replica.0.read();
+	let primary_keys = primary.all_public_keys()?.into_iter().collect::<BTreeSet<_>>();
+	let replica_keys = replica.all_public_keys()?.into_iter().collect::<BTreeSet<_>>();
+
+	let mut report = ReplicaReport {
+		missing: primary_keys.difference(&replica_keys).cloned().collect(),
+		extra: replica_keys.difference(&primary_keys).cloned().collect(),
+		mismatched: Vec::new(),
+	};
+	for (key_type, public) in primary_keys.intersection(&replica_keys) {
+		let matches = match (primary.secret(public, *key_type)?, replica.secret(public, *key_type)?) {
+			(Some(a), Some(b)) => bool::from(a.ct_eq(&b)),
+			_ => false,
+		};
+		if !matches {
+			report.mismatched.push((*key_type, public.clone()));
+		}
+	}
+	Ok(report)
+}
+
+/// Read the secret URI from the key file at `path`.
+///
+/// Returns `None` if the file can not be read or holds neither format of a key file.
+fn read_suri(dir: Dir, path: &Path) -> Option<Zeroizing<String>> {
+	read_key_file(dir, path).ok().map(|(suri, _)| suri)
+}
+
+/// Read the secret URI from the key file at `path`.
+///
+/// Key files hold the secret URI as a JSON string. Very old keystores stored it as plain text,
+/// which is still read; the returned flag is set for such legacy files. The secret URI is
+/// zeroized when dropped, like the content read from the file.
+fn read_key_file(dir: Dir, path: &Path) -> Result<(Zeroizing<String>, bool)> {
+	let content = dir.read(path).map_err(|error| Error::File { path: path.into(), error })?;
+	parse_key_file(&Zeroizing::new(content)).map_err(|e| match e {
+		Error::Json(error) => Error::MalformedKeyFile { path: path.into(), error },
+		e => e,
+	})
+}
+
+/// Parse the content of a key file, see [`read_key_file`].
+pub(crate) fn parse_key_file(content: &[u8]) -> Result<(Zeroizing<String>, bool)> {
+	match serde_json::from_slice::<String>(content) {
+		Ok(suri) => Ok((Zeroizing::new(suri), false)),
+		// A JSON string that fails to parse is a damaged file, not plain text.
+		Err(e) => match std::str::from_utf8(content).map(str::trim) {
+			Ok(suri) if !suri.is_empty() && !suri.starts_with('"') => {
+				Ok((Zeroizing::new(suri.to_owned()), true))
+			},
+			_ => Err(e.into()),
+		},
+	}
+}
+
+/// Randomness source of deterministic sr25519 signatures, which never adds any randomness.
+///
+/// schnorrkel derives the signing nonce from the secret nonce of the key and the transcript of
+/// the message, mixed with randomness from the given source. Without randomness the nonce is
+/// still secret and unique per key and message.
+struct NoRandomness;
+
+impl RngCore for NoRandomness {
+	fn next_u32(&mut self) -> u32 {
+		0
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		0
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8]) {
+		dest.zeroize();
+	}
+
+	fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand::Error> {
+		self.fill_bytes(dest);
+		Ok(())
+	}
+}
+
+impl rand::CryptoRng for NoRandomness {}
+
+/// Sign the message like `sr25519::Pair::sign` does, but with a deterministic nonce.
+fn sign_sr25519_deterministic(pair: &sr25519::Pair, msg: &[u8]) -> sr25519::Signature {
+	let context = schnorrkel::signing_context(b"substrate");
+	let transcript = schnorrkel::context::attach_rng(context.bytes(msg), NoRandomness);
+	pair.as_ref().sign(transcript).into()
+}
+
+/// Create the keystore directory at `path` and any missing parents, if it doesn't exist.
+///
+/// On unix the created directories are only accessible by the current user. An existing path
+/// must be a directory the process can list.
+fn create_keystore_dir(path: &Path) -> Result<()> {
+	match fs::metadata(path) {
+		Ok(metadata) if metadata.is_dir() => return match fs::read_dir(path) {
+			Ok(_) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::PermissionDenied =>
+				Err(Error::DirectoryNotReadable(path.into())),
+			Err(e) => Err(e.into()),
+		},
+		Ok(_) => return Err(Error::NotADirectory(path.into())),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+		Err(e) => return Err(e.into()),
+	}
+
+	let mut builder = fs::DirBuilder::new();
+	builder.recursive(true);
+	#[cfg(unix)]
+	std::os::unix::fs::DirBuilderExt::mode(&mut builder, KEYSTORE_DIR_MODE);
+	builder.create(path).map_err(|e| {
+		io::Error::new(
+			e.kind(),
+			format!("Failed to create the keystore directory {}: {}", path.display(), e),
+		).into()
+	})
+}
+
+/// Returns `true` if the error is about writing to a read-only file system.
+fn is_read_only_fs(error: &io::Error) -> bool {
+	#[cfg(unix)]
+	return error.raw_os_error() == Some(libc::EROFS);
+	#[cfg(not(unix))]
+	return {
+		let _ = error;
+		false
+	};
+}
+
+/// Returns `true` if the file system of the directory at `dir` ignores the case of file names.
+///
+/// Checked by creating a probe file with a lower case name and looking for its upper case name.
+fn is_case_insensitive(dir: Dir) -> io::Result<bool> {
+	let probe = dir.root().join(".keystore-case-probe");
+	dir.create(&probe, 0o666)?;
+	let insensitive = dir.exists(dir.root().join(".KEYSTORE-CASE-PROBE"));
+	dir.remove_file(&probe)?;
+	Ok(insensitive)
+}
+
+/// Write the secret URI to the key file at `path`.
+///
+/// The content is the secret URI as a compact JSON string, which has a single encoding: the
+/// same key gives byte-identical files on every machine, so key directories can be compared
+/// by checksum.
+///
+/// The content is written to the `.tmp` copy of the key file first that is then moved into
+/// place, so the key file is never left half written: an interrupted write at worst leaves the
+/// copy behind, which `recover_interrupted` resolves. If `sync` is set, the file and its
+/// directory are synced to disk before returning.
+fn write_key_file(dir: Dir, path: &Path, suri: &str, sync: bool) -> Result<()> {
+	let write = || -> io::Result<()> {
+		if let Some(parent) = path.parent() {
+			dir.create_dir_all(parent)?;
+		}
+		let copy = path.with_extension(RECOVERY_EXTENSIONS[0]);
+		let mut file = dir.create(&copy, KEY_FILE_MODE)?;
+		serde_json::to_writer(&file, suri)?;
+		file.flush()?;
+		if sync {
+			file.sync_all()?;
+		}
+		dir.rename(&copy, path)?;
+		#[cfg(unix)]
+		if let (true, Some(parent)) = (sync, path.parent()) {
+			dir.open(parent)?.sync_all()?;
+		}
+		Ok(())
+	};
+	write().map_err(|error| Error::File { path: path.into(), error })
+}
+
+/// Overwrite the content of the file at `path` with zeros and sync it.
+///
+/// Files with more than one link are left as they are, their content is still in use.
+fn overwrite_file(dir: Dir, path: &Path) -> io::Result<()> {
+	let mut file = dir.open_write(path)?;
+	let metadata = file.metadata()?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+		if metadata.nlink() > 1 {
+			return Ok(());
+		}
+	}
+	let zeros = [0u8; 4096];
+	let mut left = metadata.len();
+	while left > 0 {
+		let len = left.min(zeros.len() as u64) as usize;
+		file.write_all(&zeros[..len])?;
+		left -= len as u64;
+	}
+	file.sync_all()
+}
+
+/// The magic number of the file system of `dir`, if it can be determined.
+#[cfg(target_os = "linux")]
+fn filesystem_magic(dir: Dir) -> Option<i64> {
+	use std::os::unix::ffi::OsStrExt;
+	// Safe, `statfs` and `fstatfs` only write to the struct passed to them.
+	unsafe {
+		let mut stat = std::mem::zeroed::<libc::statfs>();
+		let result = match dir.fd() {
+			Some(fd) => libc::fstatfs(fd, &mut stat),
+			None => {
+				let path = std::ffi::CString::new(dir.root().as_os_str().as_bytes()).ok()?;
+				libc::statfs(path.as_ptr(), &mut stat)
+			},
+		};
+		if result != 0 {
+			return None;
+		}
+		Some(stat.f_type as i64)
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_magic(_dir: Dir) -> Option<i64> {
+	None
+}
+
+/// Whether the file system of `dir` writes changes to new blocks, like copy-on-write and
+/// log-structured file systems do, so overwriting a file leaves its old content on disk.
+///
+/// Returns `None` if it is not known.
+fn is_copy_on_write(dir: Dir) -> Option<bool> {
+	// The magic numbers of btrfs, ZFS, F2FS, NILFS and bcachefs.
+	const COPY_ON_WRITE: [i64; 5] = [0x9123_683e, 0x2fc1_2fc1, 0xf2f5_2010, 0x3434, 0xca45_1a4e];
+	filesystem_magic(dir).map(|magic| COPY_ON_WRITE.contains(&magic))
+}
+
+/// Network file systems, by magic number, see [`LocalKeystoreBuilder::allow_network_fs`].
+const NETWORK_FILESYSTEMS: [(i64, &str); 9] = [
+	(0x6969, "NFS"),
+	(0x517b, "SMB"),
+	(0xff53_4d42, "CIFS"),
+	(0xfe53_4d42, "SMB2"),
+	(0x00c3_6400, "Ceph"),
+	(0x5346_414f, "AFS"),
+	(0x7375_7245, "Coda"),
+	(0x0116_1970, "GFS2"),
+	(0x7461_636f, "OCFS2"),
+];
+
+/// Magic number of 9P, which may or may not be backed by a remote machine.
+const P9_MAGIC: i64 = 0x0102_1997;
+
+/// Magic number of FUSE file systems, whose subtype tells whether they are network file systems.
+const FUSE_MAGIC: i64 = 0x6573_5546;
+
+/// FUSE file systems backed by remote storage, by subtype.
+const NETWORK_FUSE_FILESYSTEMS: [&str; 6] =
+	["sshfs", "s3fs", "rclone", "gcsfuse", "goofys", "glusterfs"];
+
+/// The name of the network file system `dir` is on, if it is on one.
+fn network_filesystem(dir: Dir) -> Option<&'static str> {
+	match filesystem_magic(dir)? {
+		P9_MAGIC => {
+			log::warn!(
+				target: "keystore",
+				"Keystore directory {} is on 9P, it must not be shared with another machine",
+				dir.root().display(),
+			);
+			None
+		},
+		FUSE_MAGIC => {
+			let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+			let subtype = fuse_subtype(&mountinfo, &fs::canonicalize(dir.root()).ok()?)?;
+			NETWORK_FUSE_FILESYSTEMS.iter().find(|known| **known == subtype).copied()
+		},
+		magic => NETWORK_FILESYSTEMS.iter()
+			.find(|(known, _)| *known == magic)
+			.map(|(_, name)| *name),
+	}
+}
+
+/// The subtype of the FUSE file system `dir` is mounted on, from the content of
+/// `/proc/self/mountinfo`.
+///
+/// The file system type of a FUSE mount is `fuse.<subtype>`. Mounts without it name the subtype
+/// in front of the source instead, as in `sshfs#host:dir`.
+fn fuse_subtype(mountinfo: &str, dir: &Path) -> Option<String> {
+	// Spaces, tabs, new lines and backslashes in mount points are escaped as octal.
+	let unescape = |field: &str| {
+		let mut unescaped = Vec::new();
+		let mut bytes = field.bytes();
+		while let Some(byte) = bytes.next() {
+			let code = bytes.clone().take(3).collect::<Vec<_>>();
+			match std::str::from_utf8(&code).ok().and_then(|c| u8::from_str_radix(c, 8).ok()) {
+				Some(escaped) if byte == b'\\' && code.len() == 3 => {
+					unescaped.push(escaped);
+					bytes.nth(2);
+				},
+				_ => unescaped.push(byte),
+			}
+		}
+		PathBuf::from(String::from_utf8_lossy(&unescaped).into_owned())
+	};
+
+	let mut mount: Option<(PathBuf, String)> = None;
+	for line in mountinfo.lines() {
+		let (fields, filesystem) = match line.split_once(" - ") {
+			Some(split) => split,
+			None => continue,
+		};
+		let mount_point = match fields.split(' ').nth(4) {
+			Some(mount_point) => unescape(mount_point),
+			None => continue,
+		};
+		// The last of several mounts on the same mount point is the visible one.
+		let deeper = match &mount {
+			Some((point, _)) => mount_point.components().count() >= point.components().count(),
+			None => true,
+		};
+		if dir.starts_with(&mount_point) && deeper {
+			mount = Some((mount_point, filesystem.to_owned()));
+		}
+	}
+
+	let (_, filesystem) = mount?;
+	let mut fields = filesystem.split(' ');
+	match (fields.next()?, fields.next()) {
+		(filesystem, _) if filesystem.starts_with("fuse.") => Some(filesystem[5..].to_owned()),
+		("fuse", Some(source)) => source.split_once('#').map(|(subtype, _)| subtype.to_owned()),
+		_ => None,
+	}
+}
+
+/// Derive the public key of the secret URI for the given crypto type.
+///
+/// Returns `None` if the crypto type is not supported.
+pub(crate) fn public_from_suri(
+	scheme: CryptoTypeId,
+	suri: &str,
+	password: Option<&str>,
+) -> Option<std::result::Result<CryptoTypePublicPair, SecretStringError>> {
+	fn public<Pair: PairT>(
+		suri: &str,
+		password: Option<&str>,
+	) -> std::result::Result<CryptoTypePublicPair, SecretStringError>
+	where
+		Pair::Public: Into<CryptoTypePublicPair>,
+	{
+		pair_from_suri::<Pair>(suri, password).map(|pair| pair.public().into())
+	}
+
+	Some(match scheme {
+		sr25519::CRYPTO_ID => public::<sr25519::Pair>(suri, password),
+		ed25519::CRYPTO_ID => public::<ed25519::Pair>(suri, password),
+		ecdsa::CRYPTO_ID => public::<ecdsa::Pair>(suri, password),
+		_ => return None,
+	})
+}
+
+/// Derive the public key of a raw seed under the given crypto type, without storing anything.
+///
+/// Meant for planning migrations between crypto types offline; the same seed derives to
+/// unrelated public keys under different crypto types. Fails with [`Error::InvalidSeed`] if
+/// the crypto type does not take seeds of that length and with [`Error::UnknownCryptoType`]
+/// if it is not supported.
+pub fn public_for_scheme(seed: &[u8], scheme: CryptoTypeId) -> Result<Vec<u8>> {
+	fn public<Pair: PairT>(seed: &[u8]) -> Result<Vec<u8>> {
+		Pair::from_seed_slice(seed)
+			.map(|pair| pair.public().to_raw_vec())
+			.map_err(|_| Error::InvalidSeed)
+	}
+
+	match scheme {
+		sr25519::CRYPTO_ID => public::<sr25519::Pair>(seed),
+		ed25519::CRYPTO_ID => public::<ed25519::Pair>(seed),
+		ecdsa::CRYPTO_ID => public::<ecdsa::Pair>(seed),
+		_ => Err(Error::UnknownCryptoType(scheme)),
+	}
+}
+
+/// The secret URI of a raw seed, which is its hex encoding.
+fn seed_suri(seed: &[u8]) -> Zeroizing<String> {
+	let hex_seed = Zeroizing::new(hex::encode(seed));
+	let mut suri = Zeroizing::new(String::with_capacity(hex_seed.len() + 2));
+	suri.push_str("0x");
+	suri.push_str(&hex_seed);
+	suri
+}
+
+/// Search random seeds for a key whose SS58 address starts with `prefix`, on all cores.
+fn vanity_seed<Pair: PairT>(
+	prefix: &str,
+	network: Ss58AddressFormat,
+	max_attempts: usize,
+) -> Option<Zeroizing<Vec<u8>>>
+where
+	Pair::Public: Ss58Codec,
+{
+	const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+	if !prefix.chars().all(|c| ALPHABET.contains(c)) {
+		return None
+	}
+
+	let attempts = Arc::new(AtomicUsize::new(0));
+	let done = Arc::new(AtomicBool::new(false));
+	let found = Arc::new(Mutex::new(None));
+	let workers = (0..num_cpus::get())
+		.map(|_| {
+			let (attempts, done, found) = (attempts.clone(), done.clone(), found.clone());
+			let prefix = prefix.to_owned();
+			std::thread::spawn(move || {
+				let mut seed = Pair::Seed::default();
+				while !done.load(Ordering::Relaxed) &&
+					attempts.fetch_add(1, Ordering::Relaxed) < max_attempts
+				{
+					OsRng.fill_bytes(seed.as_mut());
+					let public = Pair::from_seed(&seed).public();
+					if public.to_ss58check_with_version(network).starts_with(&prefix) &&
+						!done.swap(true, Ordering::Relaxed)
+					{
+						*found.lock() = Some(Zeroizing::new(seed.as_ref().to_vec()));
+					}
+				}
+				seed.as_mut().zeroize();
+			})
+		})
+		.collect::<Vec<_>>();
+	for worker in workers {
+		worker.join().expect("Searching seeds does not panic; qed");
+	}
+	let found = found.lock().take();
+	found
+}
+
+/// Returns `true` if the phrase derives to the given public key with the given crypto.
+fn phrase_derives<Pair: PairT>(phrase: &str, password: Option<&str>, public: &[u8]) -> bool {
+	pair_from_suri::<Pair>(phrase, password)
+		.map(|pair| pair.public().as_slice() == public)
+		.unwrap_or(false)
+}
+
+/// Export the key in the polkadot-js format if the phrase derives to it with the given crypto.
+fn pjs_export<Pair: PjsPair>(
+	phrase: &str,
+	password: Option<&str>,
+	public: &[u8],
+	pjs_password: &SecretString,
+	meta: &PjsMeta,
+) -> Option<Result<serde_json::Value>> {
+	pair_from_suri::<Pair>(phrase, password).ok()
+		.filter(|pair| pair.public().as_slice() == public)
+		.map(|pair| pjs::export(&pair, pjs_password, meta.clone()))
+}
+
+/// Create the key pair of a secret URI, like `Pair::from_string`.
+///
+/// Also accepts the hex encoding of a full secret key where the crypto type has one, as
+/// sr25519 does: polkadot-js only stores the secret key, not the seed it was expanded from.
+/// Secret keys can not be derived from.
+fn pair_from_suri<Pair: PairT>(
+	suri: &str,
+	password: Option<&str>,
+) -> std::result::Result<Pair, SecretStringError> {
+	Pair::from_string(suri, password).or_else(|error| {
+		let secret = suri.strip_prefix("0x")
+			.and_then(|secret| hex::decode(secret).ok())
+			.map(Zeroizing::new);
+		match secret {
+			Some(secret) if secret.len() != Pair::Seed::default().as_ref().len() =>
+				Pair::from_seed_slice(&secret).map_err(|_| error),
+			_ => Err(error),
+		}
+	})
+}
+
+/// A local key store.
+///
+/// Stores key pairs in a file system store + short lived key pairs in memory.
+///
+/// Every pair that is being generated by a `seed`, will be placed in memory.
+struct KeystoreInner {
+	path: Option<PathBuf>,
+	/// Map over `(KeyTypeId, Raw public key)` -> `Key phrase/seed`
+	///
+	/// The phrases are encrypted with `memory_cipher`, if set.
+	additional: HashMap<(KeyTypeId, Vec<u8>), String>,
+	/// Key encrypting the phrases of `additional`, if enabled.
+	memory_cipher: Option<MemoryCipher>,
+	password: Option<SecretString>,
+	/// Whether keys that are only held in memory are allowed.
+	allow_ephemeral: bool,
+	/// The maximum number of keys on disk, if limited.
+	max_persisted_keys: Option<usize>,
+	/// Whether sr25519 signatures are made without randomness.
+	deterministic_sr25519: bool,
+	/// Whether inserted secret URIs have to derive to the public key they are inserted under.
+	verify_inserted_public: bool,
+	/// The master generated keys are derived from, if any.
+	hd_root: Option<HdRoot>,
+	/// Keys that were generated, but not stored yet.
+	reservations: Reservations,
+	/// Key types limited to one key per crypto type, see [`SingletonPolicy`].
+	singletons: HashSet<KeyTypeId>,
+	/// Policy imported secret URIs have to satisfy.
+	suri_policy: Option<SuriPolicy>,
+	/// Hook every message has to pass before it is signed.
+	sign_hook: Option<SignHook>,
+	/// The length of the longest message that is signed.
+	max_sign_message_len: usize,
+	/// Whether key files are overwritten before they are unlinked.
+	secure_delete: bool,
+	/// The crypto type of the keys generated by [`LocalKeystore::generate_default`].
+	default_scheme: CryptoTypeId,
+	/// Whether signing is allowed, see [`LocalKeystore::lock`].
+	unlocked: AtomicBool,
+	/// The shares of the password collected so far, while it is not reconstructed yet.
+	pending_shares: Option<PendingShares>,
+	/// The process that opened the keystore, see [`Error::ForkedProcess`].
+	opened_by: u32,
+	/// Whether a read in a forked process was warned about.
+	fork_warned: AtomicBool,
+	/// Random number generator replacing the operating system's one when generating keys.
+	rng: Option<Mutex<Box<dyn RngCore + Send>>>,
+	/// Signer performing all operations that need a secret key, if any.
+	enclave: Option<Arc<dyn EnclaveSigner>>,
+	/// Receives the latencies of signing operations.
+	metrics: Option<Arc<dyn SigningMetrics>>,
+	/// Receives the errors of the keystore operations.
+	error_sink: Option<Mutex<mpsc::Sender<KeystoreErrorEvent>>>,
+	/// What reading the secrets of all keys does with unreadable secrets.
+	decrypt_failure_policy: DecryptFailurePolicy,
+	/// Whether the aliases of the keys are encrypted, see [`LocalKeystore::set_encrypt_aliases`].
+	encrypt_aliases: bool,
+	/// Pauses the changes to the directory, see [`LocalKeystore::pause_writes`].
+	write_gate: Arc<WriteGate>,
+	/// Where the current time is read from, see [`LocalKeystore::set_clock`].
+	clock: Arc<dyn Clock>,
+	/// Bounds the concurrent signing operations, see
+	/// [`LocalKeystore::set_max_concurrent_signs`].
+	sign_limiter: Arc<SignLimiter>,
+	/// Called with the key types whose keys changed, see [`LocalKeystore::on_invalidate`].
+	invalidation_callbacks: Vec<Box<dyn Fn(KeyTypeId) + Send + Sync>>,
+	/// When the keys that are only held in memory were last used.
+	ephemeral_last_used: Mutex<HashMap<(KeyTypeId, Vec<u8>), SystemTime>>,
+	/// Secret keys that were used recently.
+	active: Mutex<ActiveKeys>,
+	/// Signatures that were made recently.
+	signatures: Mutex<SignatureCache>,
+	/// How the key files are laid out in `path`.
+	layout: DirectoryLayout,
+	/// Index of the key files in `path`.
+	index: Mutex<KeyIndex>,
+	/// Where the keys on disk are snapshotted to, if anywhere.
+	durable: Option<DurableTier>,
+	/// Log the changes to the key files are written to first, if any.
+	wal: Option<Wal>,
+	/// The file descriptor `path` resolves through, if opened with [`LocalKeystoreBuilder::at`].
+	#[cfg(unix)]
+	dirfd: Option<DirFd>,
+}
+
+/// The master secret URI of a keystore that derives its keys.
+struct HdRoot {
+	master: Zeroizing<String>,
+	/// The index of the next derived key.
+	next: u32,
+}
+
+impl HdRoot {
+	/// The secret URI of the child with the given index.
+	fn child(&self, index: u32) -> Zeroizing<String> {
+		Zeroizing::new(format!("{}//{}", self.master.as_str(), index))
+	}
+}
+
+/// The durable directory of a tiered keystore.
+struct DurableTier {
+	dir: PathBuf,
+	/// Password the snapshot is encrypted with.
+	password: SecretString,
+}
+
+impl KeystoreInner {
+	/// Open the store at the given path.
+	///
+	/// Optionally takes a password that will be used to encrypt/decrypt the keys.
+	#[cfg(test)]
+	fn open<T: Into<PathBuf>>(path: T, password: Option<SecretString>) -> Result<Self> {
+		Self::open_with_options(path, password, DirectoryLayout::Flat, false)
+	}
+
+	/// Open the store in the directory of the file descriptor.
+	#[cfg(unix)]
+	fn open_at(
+		dirfd: RawFd,
+		password: Option<SecretString>,
+		layout: DirectoryLayout,
+		allow_network_fs: bool,
+	) -> Result<Self> {
+		// The files are named below a path that is never opened, so they read well in errors.
+		let path = PathBuf::from(format!("fd:{}", dirfd));
+		let dir = DirFd::duplicate(dirfd, &path)?;
+		let mut instance = Self::new_on_disk(path, password, layout);
+		instance.dirfd = Some(dir);
+		instance.load(allow_network_fs)
+	}
+
+	/// The keystore directory, at an empty path if the keystore only exists in memory.
+	fn dir(&self) -> Dir<'_> {
+		let root = self.path.as_deref().unwrap_or_else(|| Path::new(""));
+		#[cfg(unix)]
+		if let Some(fd) = &self.dirfd {
+			return Dir::at(root, fd);
+		}
+		Dir::new(root)
+	}
+
+	/// Open the store at the given path, with the key files laid out as given, refusing
+	/// directories on network file systems unless they are allowed.
+	///
+	/// The persisted index of the key files is validated against the directory and rebuilt if
+	/// it is missing, corrupt or stale.
+	fn open_with_options<T: Into<PathBuf>>(
+		path: T,
+		password: Option<SecretString>,
+		layout: DirectoryLayout,
+		allow_network_fs: bool,
+	) -> Result<Self> {
+		let path = path.into();
+		create_keystore_dir(&path)?;
+		Self::new_on_disk(path, password, layout).load(allow_network_fs)
+	}
+
+	/// A store in the directory at `path` that was not loaded yet.
+	fn new_on_disk(path: PathBuf, password: Option<SecretString>, layout: DirectoryLayout) -> Self {
+		Self {
+			path: Some(path),
+			additional: HashMap::new(),
+			memory_cipher: None,
+			password,
+			allow_ephemeral: true,
+			max_persisted_keys: None,
+			deterministic_sr25519: false,
+			verify_inserted_public: true,
+			hd_root: None,
+			reservations: Reservations::default(),
+			singletons: HashSet::new(),
+			suri_policy: None,
+			sign_hook: None,
+			max_sign_message_len: DEFAULT_MAX_SIGN_MESSAGE_LEN,
+			secure_delete: false,
+			default_scheme: sr25519::CRYPTO_ID,
+			unlocked: AtomicBool::new(true),
+			pending_shares: None,
+			opened_by: std::process::id(),
+			fork_warned: AtomicBool::new(false),
+			rng: None,
+			enclave: None,
+			metrics: None,
+			error_sink: None,
+			encrypt_aliases: false,
+			clock: Arc::new(SystemClock),
+			sign_limiter: Default::default(),
+			decrypt_failure_policy: DecryptFailurePolicy::Fail,
+			write_gate: Default::default(),
+			invalidation_callbacks: Vec::new(),
+			ephemeral_last_used: Mutex::new(HashMap::new()),
+			active: Mutex::new(ActiveKeys::default()),
+			signatures: Mutex::new(SignatureCache::default()),
+			layout,
+			index: Mutex::new(KeyIndex::default()),
+			durable: None,
+			wal: None,
+			#[cfg(unix)]
+			dirfd: None,
+		}
+	}
+
+	/// Check the directory and index the key files in it, refusing directories on network file
+	/// systems unless they are allowed.
+	fn load(self, allow_network_fs: bool) -> Result<Self> {
+		if !allow_network_fs {
+			if let Some(filesystem) = network_filesystem(self.dir()) {
+				let path = self.dir().root().to_path_buf();
+				return Err(Error::UnsupportedFilesystem { path, filesystem: filesystem.into() });
+			}
+		}
+		self.rebuild_index()?;
+		#[cfg(unix)]
+		self.check_permissions()?;
+		Ok(self)
+	}
+
+	/// Fails with [`Error::InsecurePermissions`] if one of the key files is accessible by group
+	/// or others.
+	///
+	/// The keystore directory only reveals public keys, so if it is accessible by group or others
+	/// it is restricted to the current user instead, with a warning. Key files may have leaked
+	/// their secret already, so they are left for the operator to fix.
+	#[cfg(unix)]
+	fn check_permissions(&self) -> Result<()> {
+		use std::os::unix::fs::PermissionsExt;
+
+		let root = match &self.path {
+			Some(root) => root,
+			None => return Ok(()),
+		};
+		let check = |path: &Path, forbidden: u32| match self.dir().metadata(path) {
+			Ok(metadata) if metadata.permissions().mode() & forbidden != 0 =>
+				Err(Error::InsecurePermissions(path.into())),
+			Ok(_) => Ok(()),
+			Err(error) => Err(Error::File { path: path.into(), error }),
+		};
+		let mode = match self.dir().metadata(root) {
+			Ok(metadata) => metadata.permissions().mode(),
+			Err(error) => return Err(Error::File { path: root.clone(), error }),
+		};
+		if mode & 0o077 != 0 {
+			log::warn!(
+				target: "keystore",
+				"Keystore directory {} is accessible by group or others, restricting it to the \
+				current user",
+				root.display(),
+			);
+			self.dir().set_mode(root, mode & !0o077)
+				.map_err(|_| Error::InsecurePermissions(root.clone()))?;
+		}
+		for (key_type, public) in self.all_public_keys()? {
+			if let Some(path) = self.indexed_key_file(&public, key_type) {
+				check(&path, 0o077)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Fail with [`Error::MissingRequiredKeys`] unless the keystore holds the required keys.
+	fn check_required(&self, required: &[(KeyTypeId, usize)]) -> Result<()> {
+		let mut missing = Vec::new();
+		for (key_type, count) in required {
+			if self.usable_key_count(*key_type)? < *count {
+				missing.push(*key_type);
+			}
+		}
+		if missing.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::MissingRequiredKeys(missing))
+		}
+	}
+
+	/// Generate a new key pair together with its phrase and seed.
+	///
+	/// Uses the configured random number generator, if any.
+	fn generate_pair<Pair: PairT>(&self) -> (Pair, String, Pair::Seed) {
+		let rng = match &self.rng {
+			Some(rng) => rng,
+			None => return Pair::generate_with_phrase(self.password()),
+		};
+
+		let mut entropy = Zeroizing::new([0u8; 16]);
+		rng.lock().fill_bytes(&mut entropy[..]);
+		let mnemonic = Mnemonic::from_entropy(&entropy[..], Language::English)
+			.expect("16 bytes are valid entropy for a 12 word phrase; qed");
+		let phrase = mnemonic.phrase().to_owned();
+		let (pair, seed) = Pair::from_phrase(&phrase, self.password())
+			.expect("All English phrases are valid; qed");
+		(pair, phrase, seed)
+	}
+
+	/// Get the password for this store.
+	fn password(&self) -> Option<&str> {
+		self.password.as_ref()
+			.map(|p| p.expose_secret())
+			.map(|p| p.as_str())
+	}
+
+	/// Set the policy imported secret URIs have to satisfy.
+	fn set_suri_policy(&mut self, policy: SuriPolicy) {
+		self.suri_policy = Some(policy);
+	}
+
+	/// Set the singleton policy of the key type.
+	fn set_singleton_policy(&mut self, key_type: KeyTypeId, policy: SingletonPolicy) {
+		match policy {
+			SingletonPolicy::Singleton => self.singletons.insert(key_type),
+			SingletonPolicy::Multiple => self.singletons.remove(&key_type),
+		};
+	}
+
+	/// Check the given secret URI against the configured policy.
+	fn check_suri_policy(&self, suri: &str) -> Result<()> {
+		match &self.suri_policy {
+			Some(policy) => policy(suri).map_err(Error::InvalidPhrase),
+			None => Ok(()),
+		}
+	}
+
+	/// Fail with [`Error::Locked`] while the password is not reconstructed from its shares.
+	fn check_password_known(&self) -> Result<()> {
+		match self.pending_shares {
+			Some(_) => Err(Error::Locked),
+			None => Ok(()),
+		}
+	}
+
+	/// Fail with [`Error::Locked`] while the keystore is locked.
+	fn check_unlocked(&self) -> Result<()> {
+		if self.unlocked.load(Ordering::SeqCst) {
+			Ok(())
+		} else {
+			Err(Error::Locked)
+		}
+	}
+
+	/// Fail with [`Error::ForkedProcess`] in a process other than the one that opened the
+	/// keystore.
+	///
+	/// A forked child inherits the open files of its parent, so changes it makes race with
+	/// those of the parent.
+	fn check_not_forked(&self) -> Result<()> {
+		let current = std::process::id();
+		if current == self.opened_by {
+			return Ok(());
+		}
+		log::warn!(
+			target: "keystore",
+			"Refused to change the keystore opened by process {} in process {}",
+			self.opened_by,
+			current,
+		);
+		Err(Error::ForkedProcess { opened_by: self.opened_by, current })
+	}
+
+	/// Warn once about reads in a process other than the one that opened the keystore.
+	fn warn_if_forked(&self) {
+		let current = std::process::id();
+		if current != self.opened_by && !self.fork_warned.swap(true, Ordering::SeqCst) {
+			log::warn!(
+				target: "keystore",
+				"Keystore opened by process {} is used in process {}, re-open it",
+				self.opened_by,
+				current,
+			);
+		}
+	}
+
+	/// Check the message about to be signed against the configured hook.
+	///
+	/// Signing is refused altogether while the keystore is locked, and messages longer than
+	/// the limit are refused before they reach the hook.
+	fn check_sign_hook(&self, key_type: KeyTypeId, key: &CryptoTypePublicPair, msg: &[u8]) -> Result<()> {
+		self.check_unlocked()?;
+		if msg.len() > self.max_sign_message_len {
+			return Err(Error::MessageTooLarge { len: msg.len(), max: self.max_sign_message_len });
+		}
+		let hook = match &self.sign_hook {
+			Some(hook) => hook,
+			None => return Ok(()),
+		};
+		hook(key_type, key, msg).map_err(|reason| {
+			log::warn!(target: "keystore", "Refused to sign with a {:?} key: {}", key_type, reason);
+			Error::PermissionDenied(reason)
+		})
+	}
+
+	/// Check that the secret URI derives to the public key it is inserted under.
+	fn check_inserted_public(&self, suri: &str, public: &[u8]) -> Result<()> {
+		if !self.verify_inserted_public || self.phrase_matches(suri, public) {
+			return Ok(());
+		}
+
+		let derived = [sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID].iter()
+			.filter_map(|scheme| public_from_suri(*scheme, suri, self.password())?.ok())
+			.map(|public| public.1)
+			.collect();
+		Err(Error::PublicKeyMismatch { expected: public.to_vec(), derived })
+	}
+
+	/// Check that the given key can be written to disk without exceeding the key limit.
+	fn check_key_limit(&self, public: &[u8], key_type: KeyTypeId) -> Result<()> {
+		let limit = match self.max_persisted_keys {
+			Some(limit) => limit,
+			None => return Ok(()),
+		};
+		let index = self.index.lock();
+		if index.get(key_type, public).is_none() && index.len() >= limit {
+			log::warn!(
+				target: "keystore",
+				"Refused to store a new {:?} key, {} keys on disk",
+				key_type,
+				limit,
+			);
+			return Err(Error::KeyLimitReached { limit });
+		}
+		Ok(())
+	}
+
+	/// Create a new in-memory store.
+	fn new_in_memory() -> Self {
+		Self {
+			path: None,
+			additional: HashMap::new(),
+			memory_cipher: None,
+			password: None,
+			allow_ephemeral: true,
+			max_persisted_keys: None,
+			deterministic_sr25519: false,
+			verify_inserted_public: true,
+			hd_root: None,
+			reservations: Reservations::default(),
+			singletons: HashSet::new(),
+			suri_policy: None,
+			sign_hook: None,
+			max_sign_message_len: DEFAULT_MAX_SIGN_MESSAGE_LEN,
+			secure_delete: false,
+			default_scheme: sr25519::CRYPTO_ID,
+			unlocked: AtomicBool::new(true),
+			pending_shares: None,
+			opened_by: std::process::id(),
+			fork_warned: AtomicBool::new(false),
+			rng: None,
+			enclave: None,
+			metrics: None,
+			error_sink: None,
+			encrypt_aliases: false,
+			clock: Arc::new(SystemClock),
+			sign_limiter: Default::default(),
+			decrypt_failure_policy: DecryptFailurePolicy::Fail,
+			write_gate: Default::default(),
+			invalidation_callbacks: Vec::new(),
+			ephemeral_last_used: Mutex::new(HashMap::new()),
+			active: Mutex::new(ActiveKeys::default()),
+			signatures: Mutex::new(SignatureCache::default()),
+			layout: DirectoryLayout::Flat,
+			index: Mutex::new(KeyIndex::default()),
+			durable: None,
+			wal: None,
+			#[cfg(unix)]
+			dirfd: None,
+		}
+	}
+
+	/// Get the key phrase for the given public key and key type from the in-memory store.
+	fn get_additional_pair(
+		&self,
+		public: &[u8],
+		key_type: KeyTypeId,
+	) -> Option<Zeroizing<String>> {
+		let key = (key_type, public.to_vec());
+		let phrase = self.additional.get(&key)?;
+		match &self.memory_cipher {
+			Some(cipher) => cipher.open(phrase),
+			None => Some(Zeroizing::new(phrase.clone())),
+		}
+	}
+
+	/// Hold the phrase of a key in memory, encrypted if enabled.
+	fn insert_additional(&mut self, key: (KeyTypeId, Vec<u8>), phrase: &str) {
+		let phrase = match &self.memory_cipher {
+			Some(cipher) => cipher.seal(phrase),
+			None => phrase.into(),
+		};
+		self.additional.insert(key, phrase);
+	}
+
+	/// Encrypt the phrases of the keys in memory or decrypt them.
+	fn set_encrypt_memory(&mut self, encrypt: bool) {
+		if encrypt == self.memory_cipher.is_some() {
+			return;
+		}
+		let keys = self.additional.keys().cloned().collect::<Vec<_>>();
+		let phrases = keys.into_iter()
+			.filter_map(|(key_type, public)| {
+				let phrase = self.get_additional_pair(&public, key_type)?;
+				Some(((key_type, public), phrase))
+			})
+			.collect::<Vec<_>>();
+		self.memory_cipher = if encrypt { Some(MemoryCipher::new()) } else { None };
+		for (key, phrase) in phrases {
+			if let Some(mut old) = self.additional.remove(&key) {
+				old.zeroize();
+			}
+			self.insert_additional(key, &phrase);
+		}
+	}
+
+	/// Insert the given public/private key pair with the given key type.
+	///
+	/// Does not place it into the file system store. Fails if ephemeral keys are disabled.
+	fn insert_ephemeral_pair<Pair: PairT>(
+		&mut self,
+		pair: &Pair,
+		seed: &str,
+		key_type: KeyTypeId,
+	) -> Result<()> {
+		if !self.allow_ephemeral {
+			return Err(Error::EphemeralDisabled);
+		}
+
+		let key = (key_type, pair.public().to_raw_vec());
+		self.insert_additional(key, seed);
+		self.invalidated(Some(key_type));
+		Ok(())
+	}
+
+	/// Insert a new key with anonymous crypto.
+	///
+	/// Places it into the file system store if a path is configured, keeps it in memory
+	/// otherwise.
+	fn insert_unknown(&mut self, key_type: KeyTypeId, suri: &str, public: &[u8]) -> Result<()> {
+		self.check_password_known()?;
+		self.check_suri_policy(suri)?;
+		self.check_inserted_public(suri, public)?;
+
+		if let Some(path) = self.key_file_path(public, key_type) {
+			self.check_key_limit(public, key_type)?;
+			let record = || WalRecord::Insert {
+				key_type,
+				public: public.to_vec(),
+				suri: suri.into(),
+			};
+			self.logged(|| vec![record()], || {
+				write_key_file(self.dir(), &path, suri, true)?;
+				self.index_added(Some((key_type, public)));
+				Ok(())
+			})?;
+		} else {
+			if !self.allow_ephemeral {
+				return Err(Error::EphemeralDisabled);
+			}
+			self.insert_additional((key_type, public.to_vec()), suri);
+			self.invalidated(Some(key_type));
+		}
+		Ok(())
+	}
+
+	/// Remove the key with the given public key and key type.
+	///
+	/// Removes it from memory and unlinks its file, if any. The file is found by its name, so
+	/// nothing needs to be decrypted.
+	fn remove_key(&mut self, public: &[u8], key_type: KeyTypeId) -> Result<()> {
+		let in_memory = self.additional.remove(&(key_type, public.to_vec())).is_some();
+		if in_memory {
+			self.invalidated(Some(key_type));
+		}
+		self.ephemeral_last_used.lock().remove(&(key_type, public.to_vec()));
+		self.active.lock().remove(key_type, public);
+		self.signatures.lock().remove(key_type, public);
+
+		let on_disk = match self.key_file_path(public, key_type) {
+			Some(path) if self.dir().exists(&path) => {
+				let record = || WalRecord::Remove { key_type, public: public.to_vec() };
+				self.logged(|| vec![record()], || self.remove_key_file(&path, public, key_type))?;
+				true
+			},
+			_ => false,
+		};
+
+		if in_memory || on_disk {
+			Ok(())
+		} else {
+			Err(Error::PairNotFound)
+		}
+	}
+
+	/// Remove the given keys, syncing the directories of the removed key files once.
+	fn remove_keys(&mut self, items: &[(KeyTypeId, Vec<u8>)]) -> Vec<Result<()>> {
+		let mut dirs = BTreeSet::new();
+		let results = items.iter()
+			.map(|(key_type, public)| {
+				let dir = self.key_file_path(public, *key_type)
+					.and_then(|path| path.parent().map(Path::to_path_buf));
+				let result = self.remove_key(public, *key_type);
+				if result.is_ok() {
+					dirs.extend(dir);
+				}
+				result
+			})
+			.collect();
+		for dir in dirs {
+			if let Err(e) = self.dir().sync_dir(&dir) {
+				log::warn!(target: "keystore", "Failed to sync {}: {}", dir.display(), e);
+			}
+		}
+		results
+	}
+
+	/// Remove the key file at `path` of the given key, along with its sidecar files.
+	fn remove_key_file(&self, path: &Path, public: &[u8], key_type: KeyTypeId) -> Result<()> {
+		self.unlink_key_file(path)?;
+		for extension in &SIDECAR_EXTENSIONS {
+			let _ = self.dir().remove_file(path.with_extension(extension));
+		}
+		self.index_removed(public, key_type);
+		Ok(())
+	}
+
+	/// Unlink the key file at `path`, overwriting it first if secure delete is enabled.
+	///
+	/// Overwriting is best effort, a failure is logged and the file is unlinked anyway.
+	fn unlink_key_file(&self, path: &Path) -> io::Result<()> {
+		if self.secure_delete {
+			if let Err(e) = overwrite_file(self.dir(), path) {
+				log::warn!(
+					target: "keystore",
+					"Failed to overwrite key file {} before removing it: {}",
+					path.display(),
+					e,
+				);
+			}
+		}
+		self.dir().remove_file(path)
+	}
+
+	/// Apply a change to the key files, appending it to the write-ahead log first if one is
+	/// used.
+	///
+	/// The log is emptied afterwards, also if applying the change failed: the failure is
+	/// returned to the caller, so a later replay must not apply the change either.
+	fn logged<R>(
+		&self,
+		records: impl FnOnce() -> Vec<WalRecord>,
+		apply: impl FnOnce() -> Result<R>,
+	) -> Result<R> {
+		self.check_not_forked()?;
+		let wal = match &self.wal {
+			Some(wal) => wal,
+			None => return apply(),
+		};
+		wal.append(self.dir(), &records())?;
+		let result = apply();
+		wal.checkpoint(self.dir())?;
+		result
+	}
+
+	/// Apply the changes in the write-ahead log that the key files do not reflect yet and empty
+	/// the log.
+	fn replay_wal(&self) -> Result<usize> {
+		self.check_not_forked()?;
+		let wal = match &self.wal {
+			Some(wal) => wal,
+			None => return Ok(0),
+		};
+
+		let mut applied = 0;
+		for record in wal.read(self.dir())? {
+			match &record {
+				WalRecord::Insert { key_type, public, suri } => {
+					let path = self.key_file_path(public, *key_type)
+						.expect("A log is only used on disk; qed");
+					let stored = read_suri(self.dir(), &path);
+					if stored.as_deref().map(String::as_str) != Some(suri.as_str()) {
+						write_key_file(self.dir(), &path, suri, true)?;
+						self.index_added(Some((*key_type, &public[..])));
+						applied += 1;
+					}
+				},
+				WalRecord::Remove { key_type, public } => {
+					let path = self.key_file_path(public, *key_type)
+						.expect("A log is only used on disk; qed");
+					if self.dir().exists(&path) {
+						self.remove_key_file(&path, public, *key_type)?;
+						applied += 1;
+					}
+				},
+			}
+		}
+		wal.checkpoint(self.dir())?;
+		Ok(applied)
+	}
+
+	/// Insert many keys of the given crypto type at once.
+	///
+	/// All secret URIs are validated before anything is written. Unless `durable` is set, the
+	/// key files are not synced one by one; the directory is synced once after all files were
+	/// written instead.
+	fn insert_bulk(
+		&self,
+		scheme: CryptoTypeId,
+		items: Vec<(KeyTypeId, String)>,
+		durable: bool,
+	) -> Result<Vec<CryptoTypePublicPair>> {
+		self.check_password_known()?;
+		let keys = items.into_iter()
+			.map(|(key_type, suri)| {
+				self.check_suri_policy(&suri)?;
+				let public = public_from_suri(scheme, &suri, self.password())
+					.ok_or(Error::KeyNotSupported(key_type))?
+					.map_err(|_| Error::InvalidSeed)?;
+				Ok((key_type, suri, public))
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		if self.path.is_some() {
+			let records = || keys.iter()
+				.map(|(key_type, suri, public)| WalRecord::Insert {
+					key_type: *key_type,
+					public: public.1.clone(),
+					suri: suri.clone(),
+				})
+				.collect();
+			self.logged(records, || {
+				let mut dirs = BTreeSet::new();
+				for (key_type, suri, public) in &keys {
+					let file = self.key_file_path(&public.1, *key_type)
+						.expect("A path is configured; qed");
+					write_key_file(self.dir(), &file, suri, durable)?;
+					dirs.extend(file.parent().map(Path::to_path_buf));
+				}
+				if !durable {
+					for dir in dirs {
+						self.dir().sync_dir(&dir)?;
+					}
+				}
+				self.index_added(keys.iter().map(|(key_type, _, public)| (*key_type, &*public.1)));
+				Ok(())
+			})?;
+		}
+
+		Ok(keys.into_iter().map(|(_, _, public)| public).collect())
+	}
+
+	/// Generate a new key.
+	///
+	/// Places it into the file system store, if a path is configured. Otherwise insert
+	/// it into the memory cache only.
+	///
+	/// Returns the existing key instead if the key type is a singleton.
+	fn generate_by_type<Pair: PairT>(&mut self, key_type: KeyTypeId) -> Result<Pair> {
+		if self.singletons.contains(&key_type) {
+			for public in self.raw_public_keys(key_type)? {
+				if public.len() != std::mem::size_of::<Pair::Public>() {
+					continue;
+				}
+				let public = Pair::Public::from_slice(&public);
+				if let Some(pair) = self.key_pair_by_type::<Pair>(&public, key_type)? {
+					return Ok(pair);
+				}
+			}
+		}
+		self.generate_new_by_type(key_type)
+	}
+
+	/// Generate a new key pair of the given type regardless of the singleton policy.
+	fn generate_new_by_type<Pair: PairT>(&mut self, key_type: KeyTypeId) -> Result<Pair> {
+		let (pair, suri) = self.generated_suri::<Pair>()?;
+		self.store_generated(&pair, &suri, key_type)?;
+		self.advance_hd_root();
+		Ok(pair)
+	}
+
+	/// Returns a new key and its secret URI, without storing it.
+	///
+	/// With a master, this is the next child of the master; call
+	/// [`KeystoreInner::advance_hd_root`] once the key is used.
+	fn generated_suri<Pair: PairT>(&self) -> Result<(Pair, Zeroizing<String>)> {
+		match &self.hd_root {
+			Some(root) => {
+				let suri = root.child(root.next);
+				let pair = Pair::from_string(&suri, self.password())
+					.map_err(|e| Error::InvalidPhrase(format!("{:?}", e)))?;
+				Ok((pair, suri))
+			},
+			None => {
+				let (pair, phrase, _) = self.generate_pair::<Pair>();
+				Ok((pair, Zeroizing::new(phrase)))
+			},
+		}
+	}
+
+	/// Move on to the next child of the master, if any.
+	fn advance_hd_root(&mut self) {
+		if let Some(root) = &mut self.hd_root {
+			root.next += 1;
+		}
+	}
+
+	/// Replace a key of the given pair type by a new one, see
+	/// [`LocalKeystore::rekey_with_linkage`].
+	fn rekey_by_type<Pair: PairT>(
+		&mut self,
+		key_type: KeyTypeId,
+		old_public: &[u8],
+	) -> Result<(CryptoTypePublicPair, Vec<u8>)>
+	where
+		CryptoTypePublicPair: From<Pair::Public>,
+	{
+		let old = Pair::Public::from_slice(old_public);
+		let old_pair = self.key_pair_by_type::<Pair>(&old, key_type)?.ok_or(Error::PairNotFound)?;
+		let new = self.generate_new_by_type::<Pair>(key_type)?.public();
+		let linkage = [old_public, new.as_slice()].concat();
+
+		let signature = match self.check_sign_hook(key_type, &old.into(), &linkage) {
+			Ok(()) => old_pair.sign(&linkage).as_ref().to_vec(),
+			Err(e) => {
+				self.remove_key(new.as_slice(), key_type)?;
+				return Err(e);
+			},
+		};
+		self.remove_key(old_public, key_type)?;
+		Ok((new.into(), signature))
+	}
+
+	/// Returns the node identity key, generating it if there is none and `create` is set.
+	fn node_identity(&mut self, create: bool) -> Result<ed25519::Public> {
+		let mut keys = self.raw_public_keys(well_known::NODE_IDENTITY)?
+			.into_iter()
+			.filter(|public| public.len() == std::mem::size_of::<ed25519::Public>());
+		match (keys.next(), keys.next()) {
+			(Some(public), None) => Ok(ed25519::Public::from_slice(&public)),
+			(None, _) if create => {
+				let pair = self.generate_by_type::<ed25519::Pair>(well_known::NODE_IDENTITY)?;
+				Ok(pair.public())
+			},
+			(None, _) => Err(Error::PairNotFound),
+			(Some(_), Some(_)) =>
+				Err(Error::Inconsistent(vec!["several node identity keys".into()])),
+		}
+	}
+
+	/// Generate a new key of the given crypto type and hold it until it is committed.
+	fn generate_reserved(
+		&mut self,
+		key_type: KeyTypeId,
+		scheme: CryptoTypeId,
+	) -> Result<(CryptoTypePublicPair, ReservationToken)> {
+		let (public, suri): (CryptoTypePublicPair, _) = match scheme {
+			sr25519::CRYPTO_ID => self.generated_suri::<sr25519::Pair>()
+				.map(|(pair, suri)| (pair.public().into(), suri))?,
+			ed25519::CRYPTO_ID => self.generated_suri::<ed25519::Pair>()
+				.map(|(pair, suri)| (pair.public().into(), suri))?,
+			ecdsa::CRYPTO_ID => self.generated_suri::<ecdsa::Pair>()
+				.map(|(pair, suri)| (pair.public().into(), suri))?,
+			_ => return Err(Error::KeyNotSupported(key_type)),
+		};
+		// A discarded child of the master is skipped, it is never handed out twice.
+		self.advance_hd_root();
+
+		let token = self.reservations.next;
+		self.reservations.next += 1;
+		let reservation = Reservation { key_type, public: public.clone(), suri };
+		self.reservations.keys.insert(token, reservation);
+		Ok((public, ReservationToken(token)))
+	}
+
+	/// Store a reserved key.
+	///
+	/// The key stays reserved if storing it fails.
+	fn commit_reserved(&mut self, token: ReservationToken) -> Result<()> {
+		let reservation = self.reservations.keys.remove(&token.0).ok_or(Error::PairNotFound)?;
+		let result = match reservation.public.0 {
+			sr25519::CRYPTO_ID => self.store_reserved::<sr25519::Pair>(&reservation),
+			ed25519::CRYPTO_ID => self.store_reserved::<ed25519::Pair>(&reservation),
+			ecdsa::CRYPTO_ID => self.store_reserved::<ecdsa::Pair>(&reservation),
+			_ => unreachable!("Only keys of supported crypto types are reserved; qed"),
+		};
+		if result.is_err() {
+			self.reservations.keys.insert(token.0, reservation);
+		}
+		result
+	}
+
+	/// Store a reserved key of the given pair type.
+	fn store_reserved<Pair: PairT>(&mut self, reservation: &Reservation) -> Result<()> {
+		let pair = Pair::from_string(&reservation.suri, self.password())
+			.map_err(|e| Error::InvalidPhrase(format!("{:?}", e)))?;
+		self.store_generated(&pair, &reservation.suri, reservation.key_type)
+	}
+
+	/// Derive the generated keys from the given master secret URI.
+	///
+	/// Returns the index of the next derived key, which follows the highest index of the
+	/// existing children of the master.
+	fn set_hd_root(&mut self, master: &str) -> Result<u32> {
+		self.check_suri_policy(master)?;
+		sr25519::Pair::from_string(master, self.password())
+			.map_err(|e| Error::InvalidPhrase(format!("{:?}", e)))?;
+
+		let prefix = format!("{}//", master);
+		let mut next = 0;
+		for (key_type, public) in self.all_public_keys()? {
+			let phrase = match self.key_phrase_by_type(&public, key_type) {
+				Ok(Some(phrase)) => Zeroizing::new(phrase),
+				_ => continue,
+			};
+			let index = phrase.strip_prefix(&prefix)
+				.filter(|index| index.bytes().all(|b| b.is_ascii_digit()))
+				.and_then(|index| index.parse::<u32>().ok());
+			if let Some(index) = index {
+				next = next.max(index.saturating_add(1));
+			}
+		}
+
+		self.hd_root = Some(HdRoot { master: Zeroizing::new(master.into()), next });
+		Ok(next)
+	}
+
+	/// Returns the keys on disk with their derivation path below the master, if any.
+	fn recovery_index(&self) -> Result<RecoveryIndex> {
+		let mut index = RecoveryIndex::default();
+		for (key_type, public) in self.all_public_keys()? {
+			if self.additional.contains_key(&(key_type, public.clone())) {
+				continue;
+			}
+			let derivation = match (&self.hd_root, self.key_phrase_by_type(&public, key_type)?) {
+				(Some(root), Some(phrase)) => {
+					let phrase = Zeroizing::new(phrase);
+					phrase.strip_prefix(root.master.as_str())
+						.filter(|path| path.starts_with("//"))
+						.map(String::from)
+				},
+				_ => None,
+			};
+			index.keys.push((key_type, public, derivation));
+		}
+		Ok(index)
+	}
+
+	/// Generate a new key of the given crypto type.
+	fn generate_by_scheme(
+		&mut self,
+		key_type: KeyTypeId,
+		scheme: CryptoTypeId,
+	) -> Result<CryptoTypePublicPair> {
+		match scheme {
+			sr25519::CRYPTO_ID =>
+				self.generate_by_type::<sr25519::Pair>(key_type).map(|p| p.public().into()),
+			ed25519::CRYPTO_ID =>
+				self.generate_by_type::<ed25519::Pair>(key_type).map(|p| p.public().into()),
+			ecdsa::CRYPTO_ID =>
+				self.generate_by_type::<ecdsa::Pair>(key_type).map(|p| p.public().into()),
+			_ => Err(Error::KeyNotSupported(key_type)),
+		}
+	}
+
+	/// Generate a new key and return it together with its recovery phrase.
+	///
+	/// Only the raw seed is stored, so the phrase never ends up on disk. Intermediate buffers
+	/// holding the seed are zeroized before returning.
+	fn generate_with_phrase_by_type<Pair: PairT>(
+		&mut self,
+		key_type: KeyTypeId,
+	) -> Result<(Pair, String)> {
+		let (pair, phrase, mut seed) = self.generate_pair::<Pair>();
+
+		let suri = seed_suri(seed.as_ref());
+		seed.as_mut().zeroize();
+
+		self.store_generated(&pair, &suri, key_type)?;
+		Ok((pair, phrase))
+	}
+
+	/// Insert a key from its raw seed.
+	///
+	/// Places it into the file system store, if a path is configured. Otherwise insert
+	/// it into the memory cache only.
+	fn insert_raw_seed_by_type<Pair: PairT>(
+		&mut self,
+		key_type: KeyTypeId,
+		seed: &[u8],
+	) -> Result<Pair> {
+		let pair = Pair::from_seed_slice(seed).map_err(|_| Error::InvalidSeed)?;
+		self.store_generated(&pair, &seed_suri(seed), key_type)?;
+		Ok(pair)
+	}
+
+	/// Store a generated key.
+	///
+	/// Places it into the file system store, if a path is configured. Otherwise insert
+	/// it into the memory cache only.
+	fn store_generated<Pair: PairT>(
+		&mut self,
+		pair: &Pair,
+		suri: &str,
+		key_type: KeyTypeId,
+	) -> Result<()> {
+		self.check_password_known()?;
+		let public = pair.public();
+		if let Some(path) = self.key_file_path(public.as_slice(), key_type) {
+			self.check_key_limit(public.as_slice(), key_type)?;
+			let record = || WalRecord::Insert {
+				key_type,
+				public: public.to_raw_vec(),
+				suri: suri.into(),
+			};
+			self.logged(|| vec![record()], || {
+				write_key_file(self.dir(), &path, suri, true)?;
+				self.index_added(Some((key_type, public.as_slice())));
+				Ok(())
+			})?;
+			self.snapshot_to_durable()?;
+		} else {
+			self.insert_ephemeral_pair(pair, suri, key_type)?;
+		}
+		self.generated(key_type);
+		Ok(())
+	}
+
+	/// Report a generated key to the metrics.
+	fn generated(&self, key_type: KeyTypeId) {
+		if let Some(metrics) = &self.metrics {
+			metrics.on_generate(key_type);
+		}
+	}
+
+	/// Create a new key from seed.
+	///
+	/// Does not place it into the file system store.
+	fn insert_ephemeral_from_seed_by_type<Pair: PairT>(
+		&mut self,
+		seed: &str,
+		key_type: KeyTypeId,
+	) -> Result<Pair> {
+		self.check_suri_policy(seed)?;
+
+		let pair = Pair::from_string(seed, None).map_err(|_| Error::InvalidSeed)?;
+		self.insert_ephemeral_pair(&pair, seed, key_type)?;
+		self.generated(key_type);
+		Ok(pair)
+	}
+
+	/// Get the key phrase for a given public key and key type.
+	///
+	/// The phrase is zeroized when dropped.
+	fn key_phrase_by_type(
+		&self,
+		public: &[u8],
+		key_type: KeyTypeId,
+	) -> Result<Option<Zeroizing<String>>> {
+		Ok(self.stored_phrase(public, key_type)?.map(|(phrase, _)| phrase))
+	}
+
+	/// Get the key phrase for the given public key and key type, together with whether it is
+	/// stored in a legacy plain text key file.
+	fn stored_phrase(
+		&self,
+		public: &[u8],
+		key_type: KeyTypeId,
+	) -> Result<Option<(Zeroizing<String>, bool)>> {
+		self.warn_if_forked();
+		if let Some(phrase) = self.get_additional_pair(public, key_type) {
+			return Ok(Some((Zeroizing::new(phrase.to_string()), false)))
+		}
+
+		let path = if let Some(path) = self.indexed_key_file(public, key_type) {
+			path
+		} else {
+			return Ok(None);
+		};
+
+		if self.dir().exists(&path) {
+			read_key_file(self.dir(), &path).map(Some)
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Get a key pair for the given public key and key type.
+	fn key_pair_by_type<Pair: PairT>(
+		&self,
+		public: &Pair::Public,
+		key_type: KeyTypeId,
+	) -> Result<Option<Pair>> {
+		let now = self.clock.now();
+		let cache_key = (key_type, public.to_raw_vec(), std::any::type_name::<Pair>());
+		let window = {
+			let mut active = self.active.lock();
+			active.purge(now);
+			let window = active.window;
+			if let (Some(window), Some(key)) = (window, active.keys.get_mut(&cache_key)) {
+				key.expires = now + window;
+				return Pair::from_seed_slice(&key.secret).map(Some).map_err(|_| Error::InvalidSeed);
+			}
+			window
+		};
+
+		let pair = self.key_pair_from_storage::<Pair>(public, key_type)?;
+		if let (Some(window), Some(pair)) = (window, &pair) {
+			let key = ActiveKey { secret: Zeroizing::new(pair.to_raw_vec()), expires: now + window };
+			self.active.lock().keys.insert(cache_key, key);
+		}
+		Ok(pair)
+	}
+
+	/// Get a key pair for the given public key and key type from its stored secret.
+	fn key_pair_from_storage<Pair: PairT>(
+		&self,
+		public: &Pair::Public,
+		key_type: KeyTypeId,
+	) -> Result<Option<Pair>> {
+		let pair = self.stored_phrase(public.as_slice(), key_type).and_then(|stored| match stored {
+			Some((phrase, legacy)) => {
+				let pair = self.pair_from_phrase(&phrase, public)?;
+				if legacy {
+					self.upgrade_key_file(public.as_slice(), key_type, &phrase);
+				}
+				Ok(Some(pair))
+			},
+			None => Ok(None),
+		});
+
+		match pair {
+			Err(e) => self.recover_pair(public, key_type).map(Some).ok_or(e),
+			pair => pair,
+		}
+	}
+
+	/// Rewrite a legacy plain text key file in the current format.
+	///
+	/// Failing to do so does not prevent using the key, so it is only logged.
+	fn upgrade_key_file(&self, public: &[u8], key_type: KeyTypeId, phrase: &str) {
+		if let Some(path) = self.key_file_path(public, key_type) {
+			if let Err(e) = write_key_file(self.dir(), &path, phrase, true) {
+				log::warn!(
+					target: "keystore",
+					"Failed to upgrade the plain text key file {}: {}",
+					path.display(),
+					e,
+				);
+			}
+		}
+	}
+
+	/// Rewrite all legacy plain text key files in the current format.
+	///
+	/// Files are only rewritten if they hold the key their name claims.
+	fn upgrade_plaintext(&self) -> Result<usize> {
+		let mut upgraded = 0;
+		for (key_type, public) in self.all_public_keys()? {
+			let path = match self.key_file_path(&public, key_type) {
+				Some(path) if self.dir().is_file(&path) => path,
+				_ => continue,
+			};
+			match read_key_file(self.dir(), &path) {
+				Ok((phrase, true)) if self.phrase_matches(&phrase, &public) => {
+					write_key_file(self.dir(), &path, &phrase, true)?;
+					upgraded += 1;
+				},
+				_ => continue,
+			}
+		}
+		Ok(upgraded)
+	}
+
+	/// Create the key pair from the phrase and check that it matches the given public key.
+	fn pair_from_phrase<Pair: PairT>(&self, phrase: &str, public: &Pair::Public) -> Result<Pair> {
+		let pair = pair_from_suri::<Pair>(
+			phrase,
+			self.password(),
+		).map_err(|e| Error::InvalidPhrase(format!("{:?}", e)))?;
+
+		if &pair.public() == public {
+			Ok(pair)
+		} else {
+			Err(Error::InvalidPassword)
+		}
+	}
+
+	/// Load the key pair from a copy left over by an interrupted update of its key file.
+	fn recover_pair<Pair: PairT>(&self, public: &Pair::Public, key_type: KeyTypeId) -> Option<Pair> {
+		let path = self.key_file_path(public.as_slice(), key_type)?;
+		RECOVERY_EXTENSIONS.iter().find_map(|extension| {
+			let copy = path.with_extension(extension);
+			let pair = self.pair_from_phrase(&read_suri(self.dir(), &copy)?, public).ok()?;
+			log::warn!(
+				target: "keystore",
+				"Key file {} is unusable, using {} instead. Recover the keystore to restore it.",
+				path.display(),
+				copy.display(),
+			);
+			Some(pair)
+		})
+	}
+
+	/// Resolve updates of key files that were interrupted half way.
+	fn recover_interrupted(&self) -> Result<RecoveryReport> {
+		self.check_not_forked()?;
+		let mut report = RecoveryReport::default();
+
+		// Group the left over copies by their key file, sorted for a deterministic outcome.
+		let mut pending = BTreeMap::<PathBuf, Vec<PathBuf>>::new();
+		for copy in self.stored_files()? {
+			let recoverable = copy.extension()
+				.and_then(|e| e.to_str())
+				.map(|e| RECOVERY_EXTENSIONS.contains(&e))
+				.unwrap_or(false);
+			if recoverable && self.dir().is_file(&copy) {
+				pending.entry(copy.with_extension("")).or_default().push(copy);
+			}
+		}
+
+		for (file, mut copies) in pending {
+			let public = match file.file_name().and_then(|n| n.to_str()).map(hex::decode) {
+				Some(Ok(name)) if name.len() > 4 => name[4..].to_vec(),
+				_ => continue,
+			};
+			copies.sort_by_key(|copy| {
+				RECOVERY_EXTENSIONS.iter().position(|e| copy.extension() == Some(e.as_ref()))
+			});
+
+			let usable = |path: &Path| match read_suri(self.dir(), path) {
+				Some(suri) => self.phrase_matches(&suri, &public),
+				None => false,
+			};
+
+			if !usable(&file) {
+				match copies.iter().position(|copy| usable(copy)) {
+					Some(index) => {
+						self.dir().rename(copies.remove(index), &file)?;
+						report.restored.push(file);
+					},
+					None => {
+						report.unresolved.push(file);
+						continue;
+					},
+				}
+			}
+
+			for copy in copies {
+				self.dir().remove_file(&copy)?;
+				report.discarded.push(copy);
+			}
+		}
+
+		for dir in self.key_dirs()? {
+			self.dir().sync_dir(&dir)?;
+		}
+		self.rebuild_index()?;
+		Ok(report)
+	}
+
+	/// Returns `true` if the key with the given public key and key type exists.
+	///
+	/// Keys in the index are found without reading their key file. Other keys are still looked
+	/// up on disk, so the index can never hide a key, e.g. one written by another process.
+	fn contains_key(&self, public: &[u8], key_type: KeyTypeId) -> Result<bool> {
+		if self.additional.contains_key(&(key_type, public.to_vec())) {
+			return Ok(true)
+		}
+		if let (Some(root), Some(file)) = (&self.path, self.index.lock().get(key_type, public)) {
+			if self.dir().is_file(root.join(file)) {
+				return Ok(true)
+			}
+		}
+		Ok(self.key_phrase_by_type(public, key_type)?.is_some())
+	}
+
+	/// Read the secret URIs of all keys of the given key types.
+	fn prefetch(&self, ids: &[KeyTypeId]) -> Result<usize> {
+		let mut read = 0;
+		for (key_type, public) in self.all_public_keys()? {
+			if ids.contains(&key_type) && self.listed_phrase(&public, key_type)?.is_some() {
+				self.activate(&public, key_type);
+				read += 1;
+			}
+		}
+		Ok(read)
+	}
+
+	/// Keep the key in memory if an active window is set, like using it does.
+	///
+	/// The key is derived with every crypto type its length fits, it is kept for the one its
+	/// secret URI derives it with.
+	fn activate(&self, public: &[u8], key_type: KeyTypeId) {
+		fn keep<Pair: PairT>(inner: &KeystoreInner, public: &[u8], key_type: KeyTypeId) -> bool {
+			public.len() == std::mem::size_of::<Pair::Public>() &&
+				matches!(
+					inner.key_pair_by_type::<Pair>(&Pair::Public::from_slice(public), key_type),
+					Ok(Some(_)),
+				)
+		}
+
+		if self.active.lock().window.is_none() {
+			return;
+		}
+		for keep in [keep::<sr25519::Pair>, keep::<ed25519::Pair>, keep::<ecdsa::Pair>] {
+			if keep(self, public, key_type) {
+				break;
+			}
+		}
+	}
+
+	/// Read the secret URI of a key and check that it derives the public key.
+	fn checked_phrase(
+		&self,
+		public: &[u8],
+		key_type: KeyTypeId,
+	) -> Result<Option<Zeroizing<String>>> {
+		match self.key_phrase_by_type(public, key_type)? {
+			Some(phrase) if !self.phrase_matches(&phrase, public) =>
+				Err(Error::CorruptKeyFile { key_type, public: public.to_vec() }),
+			phrase => Ok(phrase),
+		}
+	}
+
+	/// Read the secret URI of a listed key, applying the [`DecryptFailurePolicy`].
+	fn listed_phrase(
+		&self,
+		public: &[u8],
+		key_type: KeyTypeId,
+	) -> Result<Option<Zeroizing<String>>> {
+		match self.checked_phrase(public, key_type) {
+			Err(e) if self.decrypt_failure_policy == DecryptFailurePolicy::SkipAndWarn => {
+				log::warn!(
+					target: "keystore",
+					"Skipping key {} of type {:?}: {}",
+					hex::encode(public),
+					key_type,
+					e,
+				);
+				Ok(None)
+			},
+			result => result,
+		}
+	}
+
+	/// Returns the keys of the key type whose secret can not be read.
+	fn unreadable_keys(&self, id: KeyTypeId) -> Result<Vec<Vec<u8>>> {
+		Ok(self.raw_public_keys(id)?
+			.into_iter()
+			.filter(|public| self.checked_phrase(public, id).is_err())
+			.collect())
+	}
+
+	/// Returns whether each of the keys is held, listing the keys once.
+	fn keys_present(&self, queries: &[(Vec<u8>, KeyTypeId)]) -> Result<Vec<bool>> {
+		let key_types = queries.iter().map(|(_, key_type)| *key_type).collect::<HashSet<_>>();
+		let mut by_type = HashMap::<KeyTypeId, HashSet<Vec<u8>>>::new();
+		if !key_types.is_empty() {
+			for (key_type, public) in self.all_public_keys()? {
+				if key_types.contains(&key_type) {
+					by_type.entry(key_type).or_default().insert(public);
+				}
+			}
+		}
+		Ok(queries.iter()
+			.map(|(public, key_type)| {
+				matches!(by_type.get(key_type), Some(keys) if keys.contains(public))
+			})
+			.collect())
+	}
+
+	/// Get the file path for the given public key and key type, preferring the index.
+	fn indexed_key_file(&self, public: &[u8], key_type: KeyTypeId) -> Option<PathBuf> {
+		let root = self.path.as_ref()?;
+		match self.index.lock().get(key_type, public) {
+			Some(file) => Some(root.join(file)),
+			None => self.key_file_path(public, key_type),
+		}
+	}
+
+	/// Rebuild the index of the key files from the directory.
+	///
+	/// The persisted index is only rewritten if it doesn't match the directory.
+	fn rebuild_index(&self) -> Result<()> {
+		let root = match &self.path {
+			Some(root) => root,
+			None => return Ok(()),
+		};
+
+		let index = self.scan_index(root)?;
+		if KeyIndex::load(self.dir()).as_ref() != Some(&index) {
+			log::debug!(target: "keystore", "Rebuilding the key index of {}", root.display());
+			self.store_index(&index);
+		}
+		*self.index.lock() = index;
+		Ok(())
+	}
+
+	/// Rename key files with upper case hex names to their canonical lower case names.
+	///
+	/// The keystore writes lower case names, but key files copied into the directory may have
+	/// upper case ones. A case-insensitive file system finds such a file under its canonical
+	/// name, a case-sensitive one does not; renaming them makes both behave the same. On a
+	/// case-sensitive file system, a file whose canonical name is taken by another file is left
+	/// alone, the keystore only uses the file with the canonical name.
+	///
+	/// Stops without an error on a read-only file system. Returns the number of renamed files.
+	fn normalize_file_names(&self) -> Result<usize> {
+		let root = match &self.path {
+			Some(root) => root,
+			None => return Ok(0),
+		};
+
+		let mut renames = Vec::new();
+		for file in self.stored_files()? {
+			let name = match file.file_name().and_then(|n| n.to_str()) {
+				Some(name) if name.bytes().any(|b| b.is_ascii_uppercase()) => name,
+				_ => continue,
+			};
+			let key = match hex::decode(name) {
+				Ok(key) if key.len() > 4 => key,
+				_ => continue,
+			};
+			let mut key_type = KeyTypeId::default();
+			key_type.0.copy_from_slice(&key[0..4]);
+			let target = self.key_file_path(&key[4..], key_type)
+				.expect("A path is configured; qed");
+			renames.push((file, target));
+		}
+		if renames.is_empty() {
+			return Ok(0);
+		}
+
+		let read_only = |error: &io::Error| {
+			log::warn!(
+				target: "keystore",
+				"Not renaming key files to lower case names in {}: {}",
+				root.display(),
+				error,
+			);
+		};
+		let case_insensitive = match is_case_insensitive(self.dir()) {
+			Ok(case_insensitive) => case_insensitive,
+			Err(e) if is_read_only_fs(&e) => {
+				read_only(&e);
+				return Ok(0);
+			},
+			Err(e) => return Err(e.into()),
+		};
+		let mut renamed = 0;
+		for (file, target) in renames {
+			if !case_insensitive && self.dir().exists(&target) {
+				log::warn!(
+					target: "keystore",
+					"Ignoring key file {}, its canonical name {} is taken",
+					file.display(),
+					target.display(),
+				);
+				continue;
+			}
+			if let Some(dir) = target.parent() {
+				self.dir().create_dir_all(dir)?;
+			}
+			// A rename that only changes the case is ignored by some case-insensitive file
+			// systems, so the file is moved through the `.tmp` copy of its canonical name, which
+			// `recover_interrupted` restores if the rename is interrupted.
+			let temp = target.with_extension(RECOVERY_EXTENSIONS[0]);
+			match self.dir().rename(&file, &temp) {
+				Err(e) if is_read_only_fs(&e) => {
+					read_only(&e);
+					break;
+				},
+				result => result?,
+			}
+			self.dir().rename(&temp, &target)?;
+			renamed += 1;
+		}
+		log::debug!(target: "keystore", "Renamed {} key files to lower case names", renamed);
+		if renamed > 0 {
+			self.rebuild_index()?;
+		}
+		Ok(renamed)
+	}
+
+	/// Build the index of the key files in the directory at `root`.
+	fn scan_index(&self, root: &Path) -> Result<KeyIndex> {
+		let mut index = KeyIndex::default();
+		for path in self.stored_files()? {
+			let name = match path.file_name().and_then(|n| n.to_str()) {
+				Some(name) => name,
+				None => continue,
+			};
+			let key = match hex::decode(name) {
+				Ok(key) if key.len() > 4 => key,
+				_ => continue,
+			};
+			let mut key_type = KeyTypeId::default();
+			key_type.0.copy_from_slice(&key[0..4]);
+			// A key file with an upper case name is found until `normalize_file_names` renames
+			// it, unless its key also has a key file with the canonical name.
+			let canonical = path.with_file_name(name.to_ascii_lowercase());
+			if self.key_file_path(&key[4..], key_type).as_ref() == Some(&canonical) &&
+				(path == canonical || index.get(key_type, &key[4..]).is_none())
+			{
+				index.insert(key_type, &key[4..], path.strip_prefix(root).unwrap_or(&path));
+			}
+		}
+		Ok(index)
+	}
+
+	/// Returns the files in the key directories that are not key files, see
+	/// [`StrayFilePolicy`].
+	fn stray_files(&self) -> Result<Vec<PathBuf>> {
+		let mut stray = Vec::new();
+		for file in self.stored_files()? {
+			if !self.dir().is_file(&file) {
+				continue;
+			}
+			let name = match file.file_name().and_then(|n| n.to_str()) {
+				Some(name) => name,
+				None => {
+					stray.push(file);
+					continue;
+				},
+			};
+			if name == INDEX_FILE || name == WAL_FILE {
+				continue;
+			}
+
+			let mut parts = name.splitn(2, '.');
+			let stem = parts.next().unwrap_or_default();
+			let known_extension = match parts.next() {
+				Some(e) => SIDECAR_EXTENSIONS.contains(&e) ||
+					RECOVERY_EXTENSIONS.contains(&e),
+				None => true,
+			};
+			let is_key_file = known_extension && match hex::decode(stem) {
+				Ok(key) if key.len() > 4 => {
+					let mut key_type = KeyTypeId::default();
+					key_type.0.copy_from_slice(&key[0..4]);
+					self.key_file_path(&key[4..], key_type) ==
+						Some(file.with_file_name(stem.to_ascii_lowercase()))
+				},
+				_ => false,
+			};
+			if !is_key_file {
+				stray.push(file);
+			}
+		}
+		stray.sort();
+		Ok(stray)
+	}
+
+	/// Returns the disk space taken by the keystore directory.
+	fn disk_usage(&self) -> Result<DiskUsage> {
+		let mut usage = DiskUsage::default();
+		let root = match &self.path {
+			Some(root) => root,
+			None => return Ok(usage),
+		};
+
+		for name in &[INDEX_FILE, WAL_FILE] {
+			match self.dir().metadata(root.join(name)) {
+				Ok(metadata) => usage.index += metadata.len(),
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+				Err(e) => return Err(e.into()),
+			}
+		}
+
+		for file in self.stored_files()? {
+			let metadata = self.dir().metadata(&file)?;
+			let name = file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+			if !metadata.is_file() || name == INDEX_FILE || name == WAL_FILE {
+				continue;
+			}
+
+			let size = metadata.len();
+			let mut parts = name.splitn(2, '.');
+			let stem = parts.next().unwrap_or_default();
+			let key_type = match hex::decode(stem) {
+				Ok(key) if key.len() > 4 => {
+					let mut key_type = KeyTypeId::default();
+					key_type.0.copy_from_slice(&key[0..4]);
+					let path = self.key_file_path(&key[4..], key_type);
+					Some(key_type).filter(|_| path == Some(file.with_file_name(stem)))
+				},
+				_ => None,
+			};
+			let category = match (key_type, parts.next()) {
+				(Some(_), None) => &mut usage.key_files,
+				(Some(_), Some(e)) if SIDECAR_EXTENSIONS.contains(&e) => &mut usage.sidecars,
+				(Some(_), Some(e)) if RECOVERY_EXTENSIONS.contains(&e) => &mut usage.temporary,
+				_ => {
+					usage.other += size;
+					continue;
+				},
+			};
+			*category += size;
+			if let Some(key_type) = key_type {
+				*usage.by_key_type.entry(key_type).or_default() += size;
+			}
+		}
+		usage.total =
+			usage.key_files + usage.temporary + usage.index + usage.sidecars + usage.other;
+		Ok(usage)
+	}
+
+	/// Handle the stray files in the key directories according to the policy.
+	fn check_stray_files(&self, policy: StrayFilePolicy) -> Result<()> {
+		if policy == StrayFilePolicy::Ignore {
+			return Ok(());
+		}
+
+		let stray = self.stray_files()?;
+		if stray.is_empty() {
+			return Ok(());
+		}
+		match policy {
+			StrayFilePolicy::Error => Err(Error::StrayFiles(stray)),
+			_ => {
+				for file in stray {
+					log::warn!(
+						target: "keystore",
+						"Unknown file in the keystore directory: {}",
+						file.display(),
+					);
+				}
+				Ok(())
+			},
+		}
+	}
+
+	/// Check that the keystore directory is consistent.
+	fn preflight_check(&self) -> Result<PreflightReport> {
+		let mut report = PreflightReport::default();
+		let mut errors = Vec::new();
+		let root = match &self.path {
+			Some(root) => root,
+			None => return Ok(report),
+		};
+
+		for file in self.stored_files()? {
+			let is_copy = file.extension()
+				.and_then(|e| e.to_str())
+				.map(|e| RECOVERY_EXTENSIONS.contains(&e))
+				.unwrap_or(false);
+			if is_copy {
+				report.warnings.push(
+					format!("Left over copy of an interrupted update: {}", file.display()),
+				);
+			}
+		}
+
+		for (key_type, public) in self.all_public_keys()? {
+			if self.additional.contains_key(&(key_type, public.clone())) {
+				continue;
+			}
+			report.keys += 1;
+			let file = self.key_file_path(&public, key_type).expect("A path is configured; qed");
+			match self.stored_phrase(&public, key_type) {
+				Ok(Some((phrase, legacy))) => {
+					if !self.phrase_matches(&phrase, &public) {
+						errors.push(format!(
+							"Key file does not derive to its public key, is the password wrong? {}",
+							file.display(),
+						));
+					} else if legacy {
+						report.warnings.push(
+							format!("Key file in the legacy format: {}", file.display()),
+						);
+					}
+				},
+				Ok(None) => (),
+				Err(e) => errors.push(
+					format!("Key file can not be read: {}: {}", file.display(), e),
+				),
+			}
+		}
+
+		if KeyIndex::load(self.dir()) != Some(self.scan_index(root)?) {
+			report.warnings.push("The key index is stale and is rebuilt on the next open".into());
+		}
+		if let Some(wal) = &self.wal {
+			let pending = wal.read(self.dir())?.len();
+			if pending > 0 {
+				report.warnings.push(
+					format!("{} changes in the write-ahead log are not applied", pending),
+				);
+			}
+		}
+
+		if errors.is_empty() {
+			Ok(report)
+		} else {
+			Err(Error::Inconsistent(errors))
+		}
+	}
+
+	/// Record key files that were just written in the index.
+	fn index_added<'a>(&self, keys: impl IntoIterator<Item = (KeyTypeId, &'a [u8])>) {
+		let root = match &self.path {
+			Some(root) => root,
+			None => return,
+		};
+		let mut index = self.index.lock();
+		let mut key_types = Vec::new();
+		for (key_type, public) in keys {
+			let file = self.key_file_path(public, key_type).expect("A path is configured; qed");
+			index.insert(key_type, public, file.strip_prefix(root).unwrap_or(&file));
+			key_types.push(key_type);
+		}
+		self.store_index(&index);
+		drop(index);
+		self.invalidated(key_types);
+	}
+
+	/// Forget a key file that was just removed in the index.
+	fn index_removed(&self, public: &[u8], key_type: KeyTypeId) {
+		let mut index = self.index.lock();
+		index.remove(key_type, public);
+		self.store_index(&index);
+		drop(index);
+		self.invalidated(Some(key_type));
+	}
+
+	/// Call the invalidation callbacks once for each of the key types.
+	fn invalidated(&self, key_types: impl IntoIterator<Item = KeyTypeId>) {
+		if self.invalidation_callbacks.is_empty() {
+			return;
+		}
+		let key_types = key_types.into_iter().collect::<BTreeSet<_>>();
+		for key_type in key_types {
+			for callback in &self.invalidation_callbacks {
+				callback(key_type);
+			}
+		}
+	}
+
+	/// Persist the index of the key files.
+	///
+	/// The index is rebuilt when the keystore is opened, so failing to persist it is only
+	/// logged.
+	fn store_index(&self, index: &KeyIndex) {
+		if self.path.is_some() {
+			if let Err(e) = index.store(self.dir()) {
+				log::warn!(target: "keystore", "Failed to persist the key index: {}", e);
+			}
+		}
+	}
+
+	/// Get the file path for the given public key and key type.
+	///
+	/// Returns `None` if the keystore only exists in-memory and there isn't any path to provide.
+	fn key_file_path(&self, public: &[u8], key_type: KeyTypeId) -> Option<PathBuf> {
+		let mut buf = self.key_dir(public, key_type)?;
+		let key_type = hex::encode(key_type.0);
+		let key = hex::encode(public);
+		buf.push(key_type + key.as_str());
+		Some(buf)
+	}
+
+	/// Get the directory the key file for the given public key and key type is stored in.
+	fn key_dir(&self, public: &[u8], key_type: KeyTypeId) -> Option<PathBuf> {
+		let mut buf = self.path.as_ref()?.clone();
+		match self.layout {
+			DirectoryLayout::Flat => (),
+			DirectoryLayout::ByKeyType => buf.push(hex::encode(key_type.0)),
+			DirectoryLayout::ByKeyTypeAndPublic => {
+				buf.push(hex::encode(key_type.0));
+				buf.push(hex::encode(public.get(..1).unwrap_or_default()));
+			},
+		}
+		Some(buf)
+	}
+
+	/// Returns the existing directories key files are stored in.
+	fn key_dirs(&self) -> Result<Vec<PathBuf>> {
+		let path = match &self.path {
+			Some(path) => path,
+			None => return Ok(Vec::new()),
+		};
+
+		// Subdirectories of `dirs` that are named after `len` hex encoded bytes.
+		let subdirs = |dirs: Vec<PathBuf>, len: usize| -> Result<Vec<PathBuf>> {
+			let mut subdirs = Vec::new();
+			for dir in dirs {
+				for subdir in self.dir().read_dir(dir)? {
+					let is_shard = subdir.file_name()
+						.and_then(|n| n.to_str())
+						.map(|n| n.len() == 2 * len && hex::decode(n).is_ok())
+						.unwrap_or(false);
+					if is_shard && self.dir().is_dir(&subdir) {
+						subdirs.push(subdir);
+					}
+				}
+			}
+			Ok(subdirs)
+		};
+
+		let root = vec![path.clone()];
+		match self.layout {
+			DirectoryLayout::Flat => Ok(root),
+			DirectoryLayout::ByKeyType => subdirs(root, 4),
+			DirectoryLayout::ByKeyTypeAndPublic => subdirs(subdirs(root, 4)?, 1),
+		}
+	}
+
+	/// Returns every entry of the directories key files are stored in.
+	fn stored_files(&self) -> Result<Vec<PathBuf>> {
+		let mut files = Vec::new();
+		for dir in self.key_dirs()? {
+			files.extend(self.dir().read_dir(dir)?);
+		}
+		Ok(files)
+	}
+
+	/// Move all key files to the given directory layout.
+	fn migrate_layout(&mut self, layout: DirectoryLayout) -> Result<usize> {
+		self.check_not_forked()?;
+		let files = self.stored_files()?;
+		let old_dirs = self.key_dirs()?;
+		self.layout = layout;
+		let mut moved = 0;
+
+		for file in files {
+			if !self.dir().is_file(&file) {
+				continue;
+			}
+			// Copies left by interrupted updates are moved along with their key file.
+			let name = match file.file_name().and_then(|n| n.to_str()) {
+				Some(name) => name.to_owned(),
+				None => continue,
+			};
+			let key = match hex::decode(name.split('.').next().unwrap_or_default()) {
+				Ok(key) if key.len() > 4 => key,
+				_ => continue,
+			};
+			let mut key_type = KeyTypeId::default();
+			key_type.0.copy_from_slice(&key[0..4]);
+
+			let dir = self.key_dir(&key[4..], key_type).expect("Files are only found on disk; qed");
+			let target = dir.join(&name);
+			if target != file {
+				self.dir().create_dir_all(&dir)?;
+				self.dir().rename(&file, &target)?;
+				moved += 1;
+			}
+		}
+
+		// Remove the subdirectories of the previous layout that are empty now, failing to remove
+		// a directory that is not empty is expected.
+		let root = self.path.clone().unwrap_or_default();
+		for dir in old_dirs {
+			for dir in dir.ancestors().take_while(|dir| *dir != root) {
+				let _ = self.dir().remove_dir(dir);
+			}
+		}
+
+		for dir in self.key_dirs()?.iter().chain(Some(&root)) {
+			self.dir().sync_dir(dir)?;
+		}
+		self.rebuild_index()?;
+		Ok(moved)
+	}
+
+	/// Returns the path of the key file of a key on disk that exists.
+	fn existing_key_file(&self, public: &[u8], key_type: KeyTypeId) -> Result<PathBuf> {
+		match self.indexed_key_file(public, key_type) {
+			Some(path) if self.dir().exists(&path) => Ok(path),
+			_ => Err(Error::PairNotFound),
+		}
+	}
+
+	/// Encrypt the note of a key and write it next to its key file.
+	fn set_encrypted_note(&self, public: &[u8], key_type: KeyTypeId, note: &str) -> Result<()> {
+		let key_file = self.existing_key_file(public, key_type)?;
+		sidecar::write_note(&self.dir(), &key_file, self.password.as_ref(), note)
+	}
+
+	/// Read and decrypt the note of a key.
+	fn encrypted_note(&self, public: &[u8], key_type: KeyTypeId) -> Result<Option<String>> {
+		let key_file = self.existing_key_file(public, key_type)?;
+		sidecar::read_note(&self.dir(), &key_file, self.password.as_ref())
+	}
+
+	/// Write the aliases of the key file at `key_file`, encrypted if enabled.
+	fn write_aliases(&self, key_file: &Path, aliases: &[String]) -> Result<()> {
+		let password = match self.encrypt_aliases {
+			true => Some(self.password.as_ref().ok_or(Error::InvalidPassword)?),
+			false => None,
+		};
+		sidecar::write_aliases(&self.dir(), key_file, aliases, password)
+	}
+
+	/// Returns the aliases of a key.
+	fn aliases(&self, public: &[u8], key_type: KeyTypeId) -> Result<Vec<String>> {
+		let key_file = self.existing_key_file(public, key_type)?;
+		sidecar::read_aliases(&self.dir(), &key_file, self.password.as_ref())
+	}
+
+	/// Returns the metadata of a key.
+	fn key_metadata(&self, public: &[u8], key_type: KeyTypeId) -> Result<KeyMetadata> {
+		sidecar::read_metadata(&self.dir(), &self.existing_key_file(public, key_type)?)
+	}
+
+	/// Apply `f` to the metadata of a key and write it back.
+	fn update_metadata(
+		&mut self,
+		public: &[u8],
+		key_type: KeyTypeId,
+		f: impl FnOnce(&mut KeyMetadata),
+	) -> Result<()> {
+		let key_file = self.existing_key_file(public, key_type)?;
+		let mut metadata = sidecar::read_metadata(&self.dir(), &key_file)?;
+		f(&mut metadata);
+		sidecar::write_metadata(&self.dir(), &key_file, &metadata)
+	}
+
+	/// Enable or disable encrypting the aliases and rewrite the aliases of all keys.
+	fn set_encrypt_aliases(&mut self, encrypt: bool) -> Result<()> {
+		if encrypt && self.password.is_none() {
+			return Err(Error::InvalidPassword)
+		}
+		let mut rewrites = Vec::new();
+		for (key_type, public) in self.all_public_keys()? {
+			let key_file = match self.indexed_key_file(&public, key_type) {
+				Some(path) => path,
+				None => continue,
+			};
+			if sidecar::has_aliases(&self.dir(), &key_file) {
+				let password = self.password.as_ref();
+				let aliases = sidecar::read_aliases(&self.dir(), &key_file, password)?;
+				rewrites.push((key_file, aliases));
+			}
+		}
+
+		self.encrypt_aliases = encrypt;
+		for (key_file, aliases) in rewrites {
+			self.write_aliases(&key_file, &aliases)?;
+		}
+		Ok(())
+	}
+
+	/// Add an alias to a key, unless another key already has it.
+	fn add_alias(&self, public: &[u8], key_type: KeyTypeId, alias: &str) -> Result<()> {
+		if alias.is_empty() || alias.chars().any(char::is_whitespace) {
+			return Err(Error::InvalidAlias(alias.into()))
+		}
+		let key_file = self.existing_key_file(public, key_type)?;
+		match self.resolve_alias(alias)? {
+			Some(owner) if owner == (key_type, public.to_vec()) => return Ok(()),
+			Some(_) => return Err(Error::AliasExists(alias.into())),
+			None => (),
+		}
+
+		let mut aliases = sidecar::read_aliases(&self.dir(), &key_file, self.password.as_ref())?;
+		aliases.push(alias.into());
+		self.write_aliases(&key_file, &aliases)
+	}
+
+	/// Returns the key with the given alias.
+	fn resolve_alias(&self, alias: &str) -> Result<Option<(KeyTypeId, Vec<u8>)>> {
+		for (key_type, public) in self.all_public_keys()? {
+			let key_file = match self.indexed_key_file(&public, key_type) {
+				Some(path) => path,
+				None => continue,
+			};
+			let aliases = sidecar::read_aliases(&self.dir(), &key_file, self.password.as_ref())?;
+			if aliases.iter().any(|known| known == alias) {
+				return Ok(Some((key_type, public)))
+			}
+		}
+		Ok(None)
+	}
+
+	/// Returns the contents of the key file of the given key.
+	fn raw_file_bytes(&self, public: &[u8], key_type: KeyTypeId) -> Result<Zeroizing<Vec<u8>>> {
+		let path = self.key_file_path(public, key_type).ok_or(Error::PairNotFound)?;
+		match self.dir().read(&path) {
+			Ok(content) => Ok(Zeroizing::new(content)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::PairNotFound),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Returns every file in the keystore directory tree with its size.
+	fn list_raw_files(&self) -> Result<Vec<(PathBuf, u64)>> {
+		let mut files = Vec::new();
+		let mut dirs = self.path.iter().cloned().collect::<Vec<_>>();
+		while let Some(dir) = dirs.pop() {
+			for entry in self.dir().read_dir(dir)? {
+				let metadata = self.dir().symlink_metadata(&entry)?;
+				if metadata.is_dir() {
+					dirs.push(entry);
+				} else if metadata.is_file() {
+					files.push((entry, metadata.len()));
+				}
+			}
+		}
+		files.sort();
+		Ok(files)
+	}
+
+	/// Returns when the given key was last used to sign.
+	fn last_used(&self, public: &[u8], key_type: KeyTypeId) -> Result<Option<SystemTime>> {
+		let key = (key_type, public.to_vec());
+		if self.additional.contains_key(&key) {
+			return Ok(self.ephemeral_last_used.lock().get(&key).copied())
+		}
+
+		match self.key_file_path(public, key_type) {
+			Some(key_file) => sidecar::read_last_used(&self.dir(), &key_file),
+			None => Ok(None),
+		}
+	}
+
+	/// Record that the given key was just used to sign.
+	///
+	/// For keys on disk the time is written to the sidecar file, without syncing it. Failing to
+	/// record a use does not fail the signing operation.
+	fn record_use(&self, public: &[u8], key_type: KeyTypeId) {
+		self.record_use_at(public, key_type, self.clock.system_now())
+	}
+
+	/// Record that the given key was used to sign at the given time.
+	///
+	/// The recorded time never goes back: if the system clock is behind the last recorded use,
+	/// it was set back and the last use is kept.
+	fn record_use_at(&self, public: &[u8], key_type: KeyTypeId, now: SystemTime) {
+		if let Ok(Some(last)) = self.last_used(public, key_type) {
+			if let Ok(skew) = last.duration_since(now) {
+				if !skew.is_zero() {
+					log::warn!(
+						target: "keystore",
+						"System clock is {:?} behind the last use of a key, was it set back?",
+						skew,
+					);
+				}
+				return
+			}
+		}
+
+		let key = (key_type, public.to_vec());
+		if self.additional.contains_key(&key) {
+			self.ephemeral_last_used.lock().insert(key, now);
+			return
+		}
+
+		if let Some(key_file) = self.key_file_path(public, key_type) {
+			sidecar::write_last_used(&self.dir(), &key_file, now);
+		}
+	}
+
+	/// Export a single key as a keystore JSON file of polkadot-js.
+	fn export_pjs_json(
+		&self,
+		key_type: KeyTypeId,
+		public: &[u8],
+		pjs_password: &SecretString,
+		meta: PjsMeta,
+	) -> Result<serde_json::Value> {
+		let phrase = match self.key_phrase_by_type(public, key_type)? {
+			Some(phrase) => Zeroizing::new(phrase),
+			None => return Err(Error::PairNotFound),
+		};
+		let password = self.password();
+		pjs_export::<sr25519::Pair>(&phrase, password, public, pjs_password, &meta)
+			.or_else(|| pjs_export::<ed25519::Pair>(&phrase, password, public, pjs_password, &meta))
+			.or_else(|| pjs_export::<ecdsa::Pair>(&phrase, password, public, pjs_password, &meta))
+			.unwrap_or(Err(Error::KeyNotSupported(key_type)))
+	}
+
+	/// Export all keys into an encrypted backup.
+	fn export_backup(&self, password: &SecretString, compress: bool) -> Result<Vec<u8>> {
+		backup::seal_with_manifest(&self.backup_entries(true)?, password, compress)
+	}
+
+	/// Returns the keys to back up, optionally including the keys only held in memory.
+	fn backup_entries(&self, ephemeral: bool) -> Result<Vec<BackupEntry>> {
+		let mut entries = Vec::new();
+		for (key_type, public) in self.all_public_keys()? {
+			if !ephemeral && self.additional.contains_key(&(key_type, public.clone())) {
+				continue;
+			}
+			if let Some(suri) = self.listed_phrase(&public, key_type)? {
+				entries.push(BackupEntry { key_type, public, suri });
+			}
+		}
+		Ok(entries)
+	}
+
+	/// Restore the keys of the snapshot in the durable directory, if any, and snapshot to the
+	/// directory from now on.
+	fn open_durable(&mut self, dir: PathBuf, password: SecretString) -> Result<()> {
+		self.dir().create_dir_all(&dir)?;
+		match self.dir().read(dir.join(SNAPSHOT_FILE)) {
+			Ok(snapshot) => {
+				self.import_backup(&snapshot, &password, OverwritePolicy::Skip)?;
+			},
+			Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+			Err(e) => return Err(e.into()),
+		}
+		self.durable = Some(DurableTier { dir, password });
+		Ok(())
+	}
+
+	/// Snapshot the keys on disk to the durable directory, if there is one.
+	fn snapshot_to_durable(&self) -> Result<()> {
+		let durable = match &self.durable {
+			Some(durable) => durable,
+			None => return Ok(()),
+		};
+		let entries = self.backup_entries(false)?;
+		let snapshot = backup::seal(&entries, &durable.password, true)?;
+
+		let path = durable.dir.join(SNAPSHOT_FILE);
+		let copy = path.with_extension("tmp");
+		let mut file = File::create(&copy)?;
+		file.write_all(&snapshot)?;
+		file.sync_all()?;
+		self.dir().rename(&copy, &path)?;
+		self.dir().sync_dir(&durable.dir)
+	}
+
+	/// Import the keys of an encrypted backup.
+	///
+	/// Places them into the file system store, if a path is configured. Otherwise insert them
+	/// into the memory cache only, which fails if ephemeral keys are disabled.
+	fn import_backup(
+		&mut self,
+		backup: &[u8],
+		password: &SecretString,
+		overwrite: OverwritePolicy,
+	) -> Result<usize> {
+		self.check_not_forked()?;
+		let mut entries = backup::open(backup, password)?;
+		if self.path.is_none() && !self.allow_ephemeral {
+			return Err(Error::EphemeralDisabled);
+		}
+		let corrupt = entries.iter().find(|entry| !self.phrase_matches(&entry.suri, &entry.public));
+		if let Some(entry) = corrupt {
+			return Err(Error::CorruptKeyFile {
+				key_type: entry.key_type,
+				public: entry.public.clone(),
+			});
+		}
+
+		if overwrite != OverwritePolicy::Overwrite {
+			let mut existing = Vec::new();
+			for entry in &entries {
+				if self.key_phrase_by_type(&entry.public, entry.key_type)?.is_some() {
+					existing.push((entry.key_type, entry.public.clone()));
+				}
+			}
+			if overwrite == OverwritePolicy::Error && !existing.is_empty() {
+				return Err(Error::KeysExist(existing));
+			}
+			entries.retain(|entry| !existing.contains(&(entry.key_type, entry.public.clone())));
+		}
+
+		if self.path.is_some() {
+			let records = || entries.iter()
+				.map(|entry| WalRecord::Insert {
+					key_type: entry.key_type,
+					public: entry.public.clone(),
+					suri: entry.suri.to_string(),
+				})
+				.collect();
+			self.logged(records, || {
+				for entry in &entries {
+					let path = self.key_file_path(&entry.public, entry.key_type)
+						.expect("A path is configured; qed");
+					write_key_file(self.dir(), &path, &entry.suri, true)?;
+				}
+				self.index_added(entries.iter().map(|entry| (entry.key_type, &entry.public[..])));
+				Ok(())
+			})?;
+		} else {
+			for entry in &entries {
+				self.insert_additional((entry.key_type, entry.public.clone()), &entry.suri);
+			}
+			self.invalidated(entries.iter().map(|entry| entry.key_type));
+		}
+		Ok(entries.len())
+	}
+
+	/// Returns a list of raw public keys filtered by `KeyTypeId`
+	fn raw_public_keys(&self, id: KeyTypeId) -> Result<Vec<Vec<u8>>> {
+		self.warn_if_forked();
+		Ok(self.all_public_keys()?
+			.into_iter()
+			.filter_map(|(key_type, public)| if key_type == id { Some(public) } else { None })
+			.collect())
+	}
+
+	/// Returns the raw public keys of the key type, leaving out keys whose key file fails to
+	/// parse, e.g. because writing it was cut short.
+	///
+	/// Like files with an invalid name, such key files are skipped. They are still reported by
+	/// `unreadable_keys`.
+	fn listed_public_keys(&self, id: KeyTypeId) -> Result<Vec<Vec<u8>>> {
+		Ok(self.raw_public_keys(id)?
+			.into_iter()
+			.filter(|public| match self.stored_phrase(public, id) {
+				Err(Error::MalformedKeyFile { path, error }) => {
+					log::debug!(
+						target: "keystore",
+						"Skipping malformed key file {}: {}",
+						path.display(),
+						error,
+					);
+					false
+				},
+				_ => true,
+			})
+			.collect())
+	}
+
+	/// Move the key with the given public key from one key type to another.
+	fn retype_key(&mut self, from: KeyTypeId, public: &[u8], to: KeyTypeId) -> Result<()> {
+		self.check_not_forked()?;
+		let exists = |key_type| {
+			let on_disk = |path: PathBuf| self.dir().exists(path);
+			self.additional.contains_key(&(key_type, public.to_vec())) ||
+				self.key_file_path(public, key_type).map(on_disk).unwrap_or(false)
+		};
+		if !exists(from) {
+			return Err(Error::PairNotFound);
+		}
+		if from == to || exists(to) {
+			return Err(Error::KeyCollision);
+		}
+
+		self.active.lock().remove(from, public);
+		self.signatures.lock().remove(from, public);
+		let key = (from, public.to_vec());
+		if let Some(phrase) = self.additional.remove(&key) {
+			self.additional.insert((to, public.to_vec()), phrase);
+			self.invalidated(vec![from, to]);
+			let mut last_used = self.ephemeral_last_used.lock();
+			if let Some(used) = last_used.remove(&key) {
+				last_used.insert((to, public.to_vec()), used);
+			}
+			return Ok(());
+		}
+
+		let source = self.key_file_path(public, from).expect("The key exists on disk; qed");
+		let target = self.key_file_path(public, to).expect("The key exists on disk; qed");
+		if let Some(dir) = target.parent() {
+			self.dir().create_dir_all(dir)?;
+		}
+		// Linking fails if the target exists, unlike renaming, which would replace it.
+		self.dir().hard_link(&source, &target).map_err(|e| match e.kind() {
+			io::ErrorKind::AlreadyExists => Error::KeyCollision,
+			_ => e.into(),
+		})?;
+		self.dir().remove_file(&source)?;
+		self.index_removed(public, from);
+		self.index_added(Some((to, public)));
+
+		for extension in &SIDECAR_EXTENSIONS {
+			let sidecar = source.with_extension(extension);
+			if self.dir().exists(&sidecar) {
+				self.dir().rename(&sidecar, target.with_extension(extension))?;
+			}
+		}
+
+		let mut dirs = BTreeSet::new();
+		dirs.extend(source.parent().map(Path::to_path_buf));
+		dirs.extend(target.parent().map(Path::to_path_buf));
+		for dir in dirs {
+			self.dir().sync_dir(&dir)?;
+		}
+		Ok(())
+	}
+
+	/// Take a snapshot of the keystore state, without any secrets.
+	fn debug_snapshot(&self) -> Result<KeystoreSnapshot> {
+		let mut key_counts = BTreeMap::new();
+		for (key_type, _) in self.all_public_keys()? {
+			*key_counts.entry(hex::encode(key_type.0)).or_default() += 1;
+		}
+
+		let mut files = Vec::new();
+		if let Some(root) = &self.path {
+			for file in self.stored_files()? {
+				let name = file.strip_prefix(root).unwrap_or(&file);
+				files.push(name.to_string_lossy().into_owned());
+			}
+		}
+		files.sort();
+
+		let active = self.active.lock();
+		Ok(KeystoreSnapshot {
+			path: self.path.clone(),
+			layout: format!("{:?}", self.layout),
+			key_counts,
+			files,
+			ephemeral_keys: self.additional.len(),
+			allow_ephemeral: self.allow_ephemeral,
+			has_password: self.password.is_some(),
+			has_suri_policy: self.suri_policy.is_some(),
+			enclave: self.enclave.is_some(),
+			active_window_ms: active.window.map(|window| window.as_millis()),
+			active_keys: active.keys.len(),
+			secure_delete: self.secure_delete,
+			copy_on_write: self.path.as_ref().and_then(|_| is_copy_on_write(self.dir())),
+		})
+	}
+
+	/// Returns the keys whose key files were modified after the given time.
+	fn keys_modified_since(&self, since: SystemTime) -> Result<Vec<(KeyTypeId, Vec<u8>)>> {
+		let mut keys = Vec::new();
+		for path in self.stored_files()? {
+			let key = match path.file_name().and_then(|n| n.to_str()).map(hex::decode) {
+				Some(Ok(key)) if key.len() > 4 => key,
+				_ => continue,
+			};
+			let metadata = self.dir().metadata(&path)?;
+			if metadata.is_file() && metadata.modified()? > since {
+				let mut key_type = KeyTypeId::default();
+				key_type.0.copy_from_slice(&key[0..4]);
+				keys.push((key_type, key[4..].to_vec()));
+			}
+		}
+		Ok(keys)
+	}
+
+	/// Returns the key types and raw public keys of all keys, in memory and on disk.
+	///
+	/// A key that is held in memory and stored on disk, or stored in several files, is only
+	/// returned once, at its first occurrence.
+	fn all_public_keys(&self) -> Result<Vec<(KeyTypeId, Vec<u8>)>> {
+		let mut public_keys: Vec<(KeyTypeId, Vec<u8>)> = self.additional.keys()
+			.cloned()
+			.collect();
+
+		for path in self.stored_files()? {
+			// skip directories and non-unicode file names (hex is unicode)
+			if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+				match hex::decode(name) {
+					Ok(ref hex) if hex.len() > 4 => {
+						let mut key_type = KeyTypeId::default();
+						key_type.0.copy_from_slice(&hex[0..4]);
+						public_keys.push((key_type, hex[4..].to_vec()));
+					}
+					_ => continue,
+				}
+			}
+		}
+
+		let mut seen = HashSet::new();
+		public_keys.retain(|key| seen.insert(key.clone()));
+		Ok(public_keys)
+	}
+
+	/// Returns `true` if the phrase derives to the given public key with any supported crypto.
+	fn phrase_matches(&self, phrase: &str, public: &[u8]) -> bool {
+		let password = self.password();
+		phrase_derives::<sr25519::Pair>(phrase, password, public) ||
+			phrase_derives::<ed25519::Pair>(phrase, password, public) ||
+			phrase_derives::<ecdsa::Pair>(phrase, password, public)
+	}
+
+	/// Returns `true` if the stored secret of the given key derives to it with its crypto type.
+	fn is_usable(&self, key: &CryptoTypePublicPair, key_type: KeyTypeId) -> Result<bool> {
+		let phrase = match self.key_phrase_by_type(&key.1, key_type)? {
+			Some(phrase) => phrase,
+			None => return Ok(false),
+		};
+		let password = self.password();
+		Ok(match key.0 {
+			sr25519::CRYPTO_ID => phrase_derives::<sr25519::Pair>(&phrase, password, &key.1),
+			ed25519::CRYPTO_ID => phrase_derives::<ed25519::Pair>(&phrase, password, &key.1),
+			ecdsa::CRYPTO_ID => phrase_derives::<ecdsa::Pair>(&phrase, password, &key.1),
+			_ => false,
+		})
+	}
+
+	/// The keys of the key type, each under the crypto type its secret URI derives it with.
+	///
+	/// Keys that derive with no crypto type are left out.
+	fn typed_keys(&self, key_type: KeyTypeId) -> Result<Vec<CryptoTypePublicPair>> {
+		let mut keys = Vec::new();
+		for public in self.listed_public_keys(key_type)? {
+			for crypto in [sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID] {
+				let key = CryptoTypePublicPair(crypto, public.clone());
+				if self.is_usable(&key, key_type)? {
+					keys.push(key);
+					break;
+				}
+			}
+		}
+		Ok(keys)
+	}
+
+	/// Returns the number of keys of the key type that derive from their secret URI with any
+	/// crypto type.
+	fn usable_key_count(&self, key_type: KeyTypeId) -> Result<usize> {
+		let mut count = 0;
+		for public in self.listed_public_keys(key_type)? {
+			for crypto in [sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID] {
+				if self.is_usable(&CryptoTypePublicPair(crypto, public.clone()), key_type)? {
+					count += 1;
+					break;
+				}
+			}
+		}
+		Ok(count)
+	}
+
+	/// Get the raw secret key of the given key.
+	///
+	/// Returns `None` if the key doesn't exist or its phrase does not derive to it.
+	fn secret(&self, public: &[u8], key_type: KeyTypeId) -> Result<Option<Zeroizing<Vec<u8>>>> {
+		fn raw<Pair: PairT>(phrase: &str, password: Option<&str>, public: &[u8]) -> Option<Vec<u8>> {
+			pair_from_suri::<Pair>(phrase, password).ok()
+				.filter(|pair| pair.public().as_slice() == public)
+				.map(|pair| pair.to_raw_vec())
+		}
+
+		let phrase = match self.key_phrase_by_type(public, key_type) {
+			Ok(Some(phrase)) => Zeroizing::new(phrase),
+			Ok(None) | Err(Error::MalformedKeyFile { .. }) => return Ok(None),
+			Err(e) => return Err(e),
+		};
+		let password = self.password();
+		Ok(raw::<sr25519::Pair>(&phrase, password, public)
+			.or_else(|| raw::<ed25519::Pair>(&phrase, password, public))
+			.or_else(|| raw::<ecdsa::Pair>(&phrase, password, public))
+			.map(Zeroizing::new))
+	}
+
+	/// Returns `true` if the given key exists, but holds a key of another crypto than sr25519.
+	fn is_non_sr25519_key(&self, public: &[u8], key_type: KeyTypeId) -> Result<bool> {
+		let password = self.password();
+		Ok(match self.key_phrase_by_type(public, key_type)? {
+			Some(phrase) => phrase_derives::<ed25519::Pair>(&phrase, password, public) ||
+				phrase_derives::<ecdsa::Pair>(&phrase, password, public),
+			None => false,
+		})
+	}
+
+	/// Find the keys of the given key type that are stored in more than one file.
+	fn find_duplicates(&self, key_type: KeyTypeId) -> Result<Vec<DuplicateGroup>> {
+		let prefix = hex::encode(key_type.0);
+
+		let mut groups = BTreeMap::<Vec<u8>, Vec<PathBuf>>::new();
+		for file in self.stored_files()? {
+			if !self.dir().is_file(&file) {
+				continue;
+			}
+
+			// Ignore casing and anything after the first dot, the phrase decides what the
+			// file really holds.
+			let name = match file.file_name().and_then(|n| n.to_str()) {
+				Some(name) => name.to_lowercase(),
+				None => continue,
+			};
+			let stem = name.split('.').next().unwrap_or_default();
+			if !stem.starts_with(&prefix) {
+				continue;
+			}
+			let public = match hex::decode(&stem[prefix.len()..]) {
+				Ok(public) if !public.is_empty() => public,
+				_ => continue,
+			};
+
+			let phrase = self.dir().open(&file).ok()
+				.and_then(|f| serde_json::from_reader::<_, String>(f).ok());
+			if let Some(phrase) = phrase {
+				if self.phrase_matches(&phrase, &public) {
+					groups.entry(public).or_default().push(file);
+				}
+			}
+		}
+
+		Ok(groups.into_iter()
+			.filter(|(_, files)| files.len() > 1)
+			.map(|(public, mut files)| {
+				files.sort();
+				DuplicateGroup { key_type, public, files }
+			})
+			.collect())
+	}
+
+	/// Remove redundant copies of the keys of the given key type.
+	///
+	/// The kept copy is moved to the canonical file name before any other copy is removed, so
+	/// the last copy of a key is never deleted.
+	fn dedup(&self, key_type: KeyTypeId, keep_newest: bool) -> Result<usize> {
+		self.check_not_forked()?;
+		let mut removed = 0;
+		for group in self.find_duplicates(key_type)? {
+			let canonical = self.key_file_path(&group.public, key_type)
+				.expect("Duplicates are only found in a file system store; qed");
+
+			let mut files = group.files.into_iter()
+				.map(|file| Ok((self.dir().metadata(&file)?.modified()?, file)))
+				.collect::<Result<Vec<_>>>()?;
+			files.sort_by_key(|(modified, _)| *modified);
+			let keep = if keep_newest { files.pop() } else { Some(files.remove(0)) }
+				.map(|(_, file)| file)
+				.expect("A group holds at least two files; qed");
+
+			if keep != canonical {
+				self.dir().rename(&keep, &canonical)?;
+			}
+			for (_, file) in files {
+				if file != canonical {
+					self.unlink_key_file(&file)?;
+				}
+				removed += 1;
+			}
+		}
+
+		self.rebuild_index()?;
+		Ok(removed)
+	}
+
+	/// Get a key pair for the given public key.
+	///
+	/// Returns `Ok(None)` if the key doesn't exist, `Ok(Some(_))` if the key exists or `Err(_)` when
+	/// something failed.
+	pub fn key_pair<Pair: AppPair>(&self, public: &<Pair as AppKey>::Public) -> Result<Option<Pair>> {
+		self.key_pair_by_type::<Pair::Generic>(IsWrappedBy::from_ref(public), Pair::ID)
+			.map(|v| v.map(Into::into))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempfile::TempDir;
+	use sp_core::{
+		Pair,
+		crypto::Ss58Codec,
+		testing::{ECDSA, ED25519, SR25519},
+	};
+	use sp_application_crypto::{ed25519, sr25519, AppPublic};
+	use std::{
+		fs,
+		str::FromStr,
+		time::UNIX_EPOCH,
+	};
+	use futures::executor::block_on;
+	use codec::Decode;
+
+	const TEST_KEY_TYPE: KeyTypeId = KeyTypeId(*b"test");
+
+	impl KeystoreInner {
+		fn insert_ephemeral_from_seed<Pair: AppPair>(&mut self, seed: &str) -> Result<Pair> {
+			self.insert_ephemeral_from_seed_by_type::<Pair::Generic>(seed, Pair::ID).map(Into::into)
+		}
+
+		fn public_keys<Public: AppPublic>(&self) -> Result<Vec<Public>> {
+			self.listed_public_keys(Public::ID)
+				.map(|v| {
+					v.into_iter()
+						.map(|k| Public::from_slice(k.as_slice()))
+						.collect()
+				})
+		}
+
+		fn generate<Pair: AppPair>(&mut self) -> Result<Pair> {
+			self.generate_by_type::<Pair::Generic>(Pair::ID).map(Into::into)
+		}
+	}
+
+	#[test]
+	fn basic_store() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+
+		assert!(store.public_keys::<ed25519::AppPublic>().unwrap().is_empty());
+
+		let key: ed25519::AppPair = store.generate().unwrap();
+		let key2: ed25519::AppPair = store.key_pair(&key.public()).unwrap().unwrap();
+
+		assert_eq!(key.public(), key2.public());
+
+		assert_eq!(store.public_keys::<ed25519::AppPublic>().unwrap()[0], key.public());
+	}
+
+	#[test]
+	fn has_keys_works() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let key: ed25519::AppPair = store.0.write().generate().unwrap();
+		let key2 = ed25519::Pair::generate().0;
+
+		assert!(
+			!SyncCryptoStore::has_keys(&store, &[(key2.public().to_vec(), ed25519::AppPublic::ID)])
+		);
+
+		assert!(
+			!SyncCryptoStore::has_keys(
+				&store,
+				&[
+					(key2.public().to_vec(), ed25519::AppPublic::ID),
+					(key.public().to_raw_vec(), ed25519::AppPublic::ID),
+				],
+			)
+		);
+
+		assert!(
+			SyncCryptoStore::has_keys(&store, &[(key.public().to_raw_vec(), ed25519::AppPublic::ID)])
+		);
+	}
+
+	#[test]
+	fn test_insert_ephemeral_from_seed() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+
+		let pair: ed25519::AppPair = store.insert_ephemeral_from_seed(
+			"0x3d97c819d68f9bafa7d6e79cb991eebcd77d966c5334c0b94d9e1fa7ad0869dc"
+		).unwrap();
+		assert_eq!(
+			"5DKUrgFqCPV8iAXx9sjy1nyBygQCeiUYRFWurZGhnrn3HJCA",
+			pair.public().to_ss58check()
+		);
+
+		drop(store);
+		let store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+		// Keys generated from seed should not be persisted!
+		assert!(store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().is_none());
+	}
+
+	#[test]
+	fn password_being_used() {
+		let password = String::from("password");
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = KeystoreInner::open(
+			temp_dir.path(),
+			Some(FromStr::from_str(password.as_str()).unwrap()),
+		).unwrap();
+
+		let pair: ed25519::AppPair = store.generate().unwrap();
+		assert_eq!(
+			pair.public(),
+			store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().unwrap().public(),
+		);
+
+		// Without the password the key should not be retrievable
+		let store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+		assert!(store.key_pair::<ed25519::AppPair>(&pair.public()).is_err());
+
+		let store = KeystoreInner::open(
+			temp_dir.path(),
+			Some(FromStr::from_str(password.as_str()).unwrap()),
+		).unwrap();
+		assert_eq!(
+			pair.public(),
+			store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().unwrap().public(),
+		);
+	}
+
+	#[test]
+	fn public_keys_are_returned() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+
+		let mut keys = Vec::new();
+		for i in 0..10 {
+			keys.push(store.generate::<ed25519::AppPair>().unwrap().public());
+			keys.push(store.insert_ephemeral_from_seed::<ed25519::AppPair>(
+				&format!("0x3d97c819d68f9bafa7d6e79cb991eebcd7{}d966c5334c0b94d9e1fa7ad0869dc", i),
+			).unwrap().public());
+		}
+
+		// Generate a key of a different type
+		store.generate::<sr25519::AppPair>().unwrap();
+
+		keys.sort();
+		let mut store_pubs = store.public_keys::<ed25519::AppPublic>().unwrap();
+		store_pubs.sort();
+
+		assert_eq!(keys, store_pubs);
+	}
+
+	#[test]
+	fn has_keys_detailed_reports_every_query_in_order() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let absent = sr25519::Pair::generate().0.public();
+
+		let queries = vec![
+			(sr25519.to_vec(), SR25519),
+			(absent.to_vec(), SR25519),
+			(ed25519.to_vec(), ED25519),
+			(sr25519.to_vec(), ED25519),
+			(sr25519.to_vec(), SR25519),
+		];
+		let expected = queries.iter()
+			.cloned()
+			.zip(vec![true, false, true, false, true])
+			.collect::<Vec<_>>();
+		assert_eq!(block_on(CryptoStore::has_keys_detailed(&store, &queries)), expected);
+		assert_eq!(SyncCryptoStore::has_keys_detailed(&store, &queries), expected);
+	}
+
+	#[test]
+	fn store_unknown_and_extract_it() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+
+		let secret_uri = "//Alice";
+		let key_pair = sr25519::AppPair::from_string(secret_uri, None).expect("Generates key pair");
+
+		store.insert_unknown(
+			SR25519,
+			secret_uri,
+			key_pair.public().as_ref(),
+		).expect("Inserts unknown key");
+
+		let store_key_pair = store.key_pair_by_type::<sr25519::AppPair>(
+			&key_pair.public(),
+			SR25519,
+		).expect("Gets key pair from keystore").unwrap();
+
+		assert_eq!(key_pair.public(), store_key_pair.public());
+	}
+
+	#[test]
+	fn basic_store_in_memory() {
+		let mut store = KeystoreInner::new_in_memory();
+		assert!(store.public_keys::<ed25519::AppPublic>().unwrap().is_empty());
+
+		let key: ed25519::AppPair = store.generate().unwrap();
+		let key2: ed25519::AppPair = store.key_pair(&key.public()).unwrap().unwrap();
+		assert_eq!(key.public(), key2.public());
+		assert_eq!(store.public_keys::<ed25519::AppPublic>().unwrap()[0], key.public());
+	}
+
+	#[test]
+	fn store_unknown_and_extract_it_in_memory() {
+		let mut store = KeystoreInner::new_in_memory();
+		let key_pair = sr25519::AppPair::from_string("//Alice", None).unwrap();
+		store.insert_unknown(SR25519, "//Alice", key_pair.public().as_ref()).unwrap();
+
+		let store_key_pair = store.key_pair_by_type::<sr25519::AppPair>(&key_pair.public(), SR25519)
+			.unwrap()
+			.unwrap();
+		assert_eq!(key_pair.public(), store_key_pair.public());
+	}
+
+	#[test]
+	fn known_secrets_round_trip_through_the_key_files() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let seed = format!("0x{}", hex::encode([3u8; 32]));
+		let pair = sr25519::AppPair::from_string(&seed, None).unwrap();
+		SyncCryptoStore::insert_unknown(&store, SR25519, &seed, pair.public().as_ref()).unwrap();
+		let generated = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		drop(store);
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let read = store.key_pair::<sr25519::AppPair>(&pair.public()).unwrap().unwrap();
+		assert_eq!(read.to_raw_vec(), pair.to_raw_vec());
+		let phrase = store.0.read().key_phrase_by_type(pair.public().as_ref(), SR25519).unwrap();
+		assert_eq!(phrase.as_deref().map(String::as_str), Some(seed.as_str()));
+		let generated = ed25519::AppPublic::from(generated);
+		assert!(store.key_pair::<ed25519::AppPair>(&generated).unwrap().is_some());
+	}
+
+	#[test]
+	fn store_ignores_files_with_invalid_name() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let file_name = temp_dir.path().join(hex::encode(&SR25519.0[..2]));
+		fs::write(file_name, "test").expect("Invalid file is written");
+
+		assert!(
+			SyncCryptoStore::sr25519_public_keys(&store, SR25519).is_empty(),
+		);
+	}
+
+	#[test]
+	fn generate_with_seed_is_not_stored() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let _alice_tmp_key = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).unwrap();
+
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 1);
+
+		drop(store);
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 0);
+	}
+
+	#[test]
+	fn generate_can_be_fetched_in_memory() {
+		let store = LocalKeystore::in_memory();
+		SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).unwrap();
+
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 1);
+		SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 2);
+	}
+
+	#[test]
+	fn duplicates_are_found_and_removed() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let other = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let canonical = store.0.read().key_file_path(public.as_ref(), TEST_KEY_TYPE).unwrap();
+		let name = canonical.file_name().unwrap().to_str().unwrap().to_owned();
+
+		let upper = temp_dir.path().join(name.to_uppercase());
+		let extension = temp_dir.path().join(format!("{}.json", name));
+		fs::copy(&canonical, &upper).unwrap();
+		fs::copy(&canonical, &extension).unwrap();
+
+		let groups = store.find_duplicates(TEST_KEY_TYPE).unwrap();
+		assert_eq!(groups.len(), 1);
+		assert_eq!(groups[0].public, public.to_raw_vec());
+		assert_eq!(groups[0].files.len(), 3);
+
+		assert_eq!(store.dedup(TEST_KEY_TYPE, true).unwrap(), 2);
+		assert!(store.find_duplicates(TEST_KEY_TYPE).unwrap().is_empty());
+		assert!(canonical.exists());
+		assert!(!upper.exists());
+		assert!(!extension.exists());
+
+		let mut keys = SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE);
+		keys.sort();
+		let mut expected = vec![public, other];
+		expected.sort();
+		assert_eq!(keys, expected);
+		assert!(store.0.read().key_pair_by_type::<sr25519::Pair>(&public, TEST_KEY_TYPE).unwrap().is_some());
+	}
+
+	#[test]
+	fn ephemeral_keys_can_be_disabled() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		store.set_allow_ephemeral(false);
+
+		assert!(matches!(
+			store.0.write().insert_ephemeral_from_seed::<sr25519::AppPair>("//Alice"),
+			Err(Error::EphemeralDisabled),
+		));
+		assert!(SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).is_err());
+		assert!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).is_empty());
+
+		// Persisted keys are still fine.
+		SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 1);
+
+		let store = LocalKeystore::in_memory();
+		store.set_allow_ephemeral(false);
+		assert!(SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).is_err());
+	}
+
+	#[test]
+	fn suri_policy_is_enforced() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		store.set_suri_policy(Box::new(|suri: &str| {
+			if suri.contains("///") {
+				Ok(())
+			} else {
+				Err("a password is required".into())
+			}
+		}));
+
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		match store.0.write().insert_unknown(SR25519, "//Alice", pair.public().as_ref()) {
+			Err(Error::InvalidPhrase(reason)) => assert_eq!(reason, "a password is required"),
+			_ => panic!("Insecure secret URI is rejected"),
+		}
+		assert!(SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).is_err());
+		assert!(SyncCryptoStore::sr25519_public_keys(&store, SR25519).is_empty());
+
+		let pair = sr25519::Pair::from_string("//Alice///secret", None).unwrap();
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Alice///secret", pair.public().as_ref())
+			.unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![pair.public()]);
+	}
+
+	#[test]
+	fn generate_with_phrase_does_not_store_the_phrase() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = "password";
+		let store = LocalKeystore::open(
+			temp_dir.path(),
+			Some(FromStr::from_str(password).unwrap()),
+		).unwrap();
+
+		let (public, phrase) = store.generate_with_phrase(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+		assert_eq!(public.0, ed25519::CRYPTO_ID);
+
+		let recovered = ed25519::Pair::from_phrase(&phrase, Some(password)).unwrap().0;
+		assert_eq!(recovered.public().to_raw_vec(), public.1);
+
+		let path = store.0.read().key_file_path(&public.1, TEST_KEY_TYPE).unwrap();
+		let contents = fs::read_to_string(path).unwrap();
+		assert!(!contents.contains(&phrase));
+
+		drop(store);
+		let store = LocalKeystore::open(
+			temp_dir.path(),
+			Some(FromStr::from_str(password).unwrap()),
+		).unwrap();
+		let pair = store.0.read()
+			.key_pair_by_type::<ed25519::Pair>(&recovered.public(), TEST_KEY_TYPE)
+			.unwrap()
+			.unwrap();
+		assert_eq!(pair.public(), recovered.public());
+
+		assert!(matches!(
+			store.generate_with_phrase(TEST_KEY_TYPE, CryptoTypeId(*b"none")),
+			Err(Error::KeyNotSupported(_)),
+		));
+	}
+
+	#[test]
+	fn vrf_sign_with_non_sr25519_key_is_not_available() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let pair = ed25519::Pair::from_string("//Alice", None).unwrap();
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Alice", pair.public().as_ref()).unwrap();
+
+		let transcript = VRFTranscriptData {
+			label: b"Test",
+			items: vec![],
+		};
+		let public = Sr25519Public::from_slice(pair.public().as_ref());
+		assert!(matches!(
+			SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &public, transcript.clone()),
+			Err(TraitError::VrfNotAvailable),
+		));
+
+		let public = Sr25519Public::from_slice(&[0u8; 32]);
+		assert!(SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &public, transcript).unwrap().is_none());
+
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		assert_eq!(SyncCryptoStore::vrf_capable_keys(&store, SR25519).unwrap(), vec![sr25519]);
+	}
+
+	#[test]
+	fn insert_bulk_works() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let items = vec![
+			(SR25519, "//Alice".to_string()),
+			(SR25519, "//Bob".to_string()),
+			(TEST_KEY_TYPE, "//Charlie".to_string()),
+		];
+		let publics = store.insert_bulk(sr25519::CRYPTO_ID, items, false).unwrap();
+
+		let expected = ["//Alice", "//Bob", "//Charlie"].iter()
+			.map(|suri| sr25519::Pair::from_string(suri, None).unwrap().public())
+			.collect::<Vec<_>>();
+		assert_eq!(publics, expected.iter().map(|p| CryptoTypePublicPair::from(*p)).collect::<Vec<_>>());
+
+		let mut stored = SyncCryptoStore::sr25519_public_keys(&store, SR25519);
+		stored.sort();
+		let mut alice_and_bob = expected[..2].to_vec();
+		alice_and_bob.sort();
+		assert_eq!(stored, alice_and_bob);
+		assert!(
+			store.0.read().key_pair_by_type::<sr25519::Pair>(&expected[2], TEST_KEY_TYPE).unwrap().is_some()
+		);
+
+		// Nothing is written if one of the secret URIs is invalid.
+		let items = vec![
+			(ED25519, "//Alice".to_string()),
+			(ED25519, "no valid uri".to_string()),
+		];
+		assert!(store.insert_bulk(ed25519::CRYPTO_ID, items, true).is_err());
+		assert!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).is_empty());
+	}
+
+	#[test]
+	fn remove_key_through_sync_interface() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let ephemeral = SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice")).unwrap();
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).len(), 2);
+
+		SyncCryptoStore::remove_key(&store, ED25519, &public.into()).unwrap();
+		SyncCryptoStore::remove_key(&store, ED25519, &ephemeral.into()).unwrap();
+		assert!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).is_empty());
+
+		assert!(matches!(
+			SyncCryptoStore::remove_key(&store, ED25519, &public.into()),
+			Err(TraitError::PairNotFound),
+		));
+		assert!(matches!(
+			block_on(CryptoStore::remove_key(&store, ED25519, &public.into())),
+			Err(TraitError::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn prehashed_ecdsa_signatures_recover_to_the_key() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let digest = [7u8; 32];
+
+		let signature = block_on(CryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &public, &digest))
+			.unwrap();
+		assert_eq!(signature.recover_prehashed(&digest), Some(public.clone()));
+
+		let absent = ecdsa::Pair::from_string("//Alice", None).unwrap().public();
+		let signature = SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &absent, &digest);
+		assert!(matches!(signature, Err(TraitError::PairNotFound)));
+
+		// A key file holding a secret that does not derive to its ecdsa public key.
+		let other = ecdsa::Pair::from_string("//Bob", None).unwrap().public();
+		let file = store.0.read().key_file_path(other.as_ref(), ECDSA).unwrap();
+		write_key_file(Dir::new(temp_dir.path()), &file, "//Charlie", true).unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let signature = SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &other, &digest);
+		assert!(matches!(signature, Err(TraitError::KeyNotSupported(ECDSA))));
+	}
+
+	#[test]
+	fn removed_keys_stay_removed_after_reopening() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = || Some(FromStr::from_str("password").unwrap());
+		let store = LocalKeystore::open(temp_dir.path(), password()).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		block_on(CryptoStore::remove_key(&store, ED25519, &public.into())).unwrap();
+		assert!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).is_empty());
+		drop(store);
+
+		let store = LocalKeystore::open(temp_dir.path(), password()).unwrap();
+		assert!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).is_empty());
+		assert!(matches!(
+			SyncCryptoStore::remove_key(&store, ED25519, &public.into()),
+			Err(TraitError::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn authority_keys_only_returns_usable_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), Some(FromStr::from_str("password").unwrap())).unwrap();
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let missing = sr25519::Pair::generate().0.public();
+		let candidates = vec![
+			CryptoTypePublicPair(sr25519::CRYPTO_ID, public.to_raw_vec()),
+			CryptoTypePublicPair(ed25519::CRYPTO_ID, public.to_raw_vec()),
+			CryptoTypePublicPair(sr25519::CRYPTO_ID, missing.to_raw_vec()),
+		];
+
+		assert_eq!(
+			block_on(CryptoStore::authority_keys(&store, SR25519, &candidates)).unwrap(),
+			vec![candidates[0].clone()],
+		);
+
+		// The key file is still present, but can not be used with another password.
+		let store = LocalKeystore::open(temp_dir.path(), Some(FromStr::from_str("other").unwrap())).unwrap();
+		assert_eq!(SyncCryptoStore::supported_keys(&store, SR25519, candidates.clone()).unwrap().len(), 2);
+		assert!(block_on(CryptoStore::authority_keys(&store, SR25519, &candidates)).unwrap().is_empty());
+	}
+
+	fn interrupted_store() -> (TempDir, LocalKeystore, ed25519::AppPublic, PathBuf) {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = store.0.write().generate::<ed25519::AppPair>().unwrap().public();
+		let file = store.0.read().key_file_path(public.as_slice(), ED25519).unwrap();
+		(temp_dir, store, public, file)
+	}
+
+	#[test]
+	fn recover_from_interrupted_write() {
+		let (_dir, store, public, file) = interrupted_store();
+		let copy = file.with_extension("tmp");
+
+		// The new content was written to the copy, but the key file got truncated.
+		fs::copy(&file, &copy).unwrap();
+		fs::write(&file, "\"trunc").unwrap();
+
+		assert!(store.key_pair::<ed25519::AppPair>(&public).unwrap().is_some());
+		assert_eq!(
+			store.recover_interrupted().unwrap(),
+			RecoveryReport { restored: vec![file.clone()], ..Default::default() },
+		);
+		assert!(!copy.exists());
+		assert!(store.key_pair::<ed25519::AppPair>(&public).unwrap().is_some());
+	}
+
+	#[test]
+	fn recover_discards_unfinished_copy() {
+		let (_dir, store, public, file) = interrupted_store();
+		let copy = file.with_extension("tmp");
+
+		// The copy was only partially written before the key file was touched.
+		fs::write(&copy, "\"trunc").unwrap();
+
+		assert_eq!(
+			store.recover_interrupted().unwrap(),
+			RecoveryReport { discarded: vec![copy.clone()], ..Default::default() },
+		);
+		assert!(!copy.exists());
+		assert!(store.key_pair::<ed25519::AppPair>(&public).unwrap().is_some());
+	}
+
+	#[test]
+	fn recover_from_backup() {
+		let (_dir, store, public, file) = interrupted_store();
+		let backup = file.with_extension("bak");
+		let copy = file.with_extension("tmp");
+
+		// The key file was overwritten with a secret that does not match, with a stale copy.
+		fs::copy(&file, &backup).unwrap();
+		fs::write(&copy, "\"trunc").unwrap();
+		fs::write(&file, serde_json::to_string("//Alice").unwrap()).unwrap();
+
+		assert!(store.key_pair::<ed25519::AppPair>(&public).unwrap().is_some());
+		assert_eq!(
+			store.recover_interrupted().unwrap(),
+			RecoveryReport {
+				restored: vec![file.clone()],
+				discarded: vec![copy.clone()],
+				..Default::default()
+			},
+		);
+		assert!(!backup.exists() && !copy.exists());
+		assert!(store.key_pair::<ed25519::AppPair>(&public).unwrap().is_some());
+	}
+
+	#[test]
+	fn recover_leaves_unrecoverable_files() {
+		let (_dir, store, public, file) = interrupted_store();
+		let backup = file.with_extension("bak");
+
+		fs::write(&file, "\"trunc").unwrap();
+		fs::write(&backup, "\"trunc").unwrap();
+
+		assert!(store.key_pair::<ed25519::AppPair>(&public).is_err());
+		assert_eq!(
+			store.recover_interrupted().unwrap(),
+			RecoveryReport { unresolved: vec![file.clone()], ..Default::default() },
+		);
+		assert!(file.exists() && backup.exists());
+	}
+
+	#[test]
+	fn fingerprint_only_depends_on_the_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path().join("store"), None).unwrap();
+		let other = LocalKeystore::open(temp_dir.path().join("other"), None).unwrap();
+
+		let alice = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Alice")).unwrap();
+		let bob = SyncCryptoStore::ed25519_generate_new(&other, ED25519, Some("//Bob")).unwrap();
+		assert_ne!(
+			SyncCryptoStore::fingerprint(&store).unwrap(),
+			SyncCryptoStore::fingerprint(&other).unwrap(),
+		);
+
+		// The same keys, inserted in another order and stored on disk instead of in memory.
+		SyncCryptoStore::insert_unknown(&store, ED25519, "//Bob", bob.as_ref()).unwrap();
+		SyncCryptoStore::insert_unknown(&other, SR25519, "//Alice", alice.as_ref()).unwrap();
+		assert_eq!(
+			SyncCryptoStore::fingerprint(&store).unwrap(),
+			SyncCryptoStore::fingerprint(&other).unwrap(),
+		);
+		assert_eq!(
+			block_on(CryptoStore::fingerprint(&store)).unwrap(),
+			SyncCryptoStore::fingerprint(&store).unwrap(),
+		);
+	}
+
+	#[test]
+	fn plaintext_key_file_is_upgraded_on_use() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let pair = ed25519::AppPair::from_string("//Alice", None).unwrap();
+		let file = store.0.read().key_file_path(pair.public().as_slice(), ED25519).unwrap();
+		fs::write(&file, "//Alice\n").unwrap();
+
+		assert_eq!(
+			store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().map(|p| p.public()),
+			Some(pair.public()),
+		);
+		assert_eq!(fs::read_to_string(&file).unwrap(), "\"//Alice\"");
+		assert!(!file.with_extension("tmp").exists());
+	}
+
+	#[test]
+	fn upgrade_plaintext_works() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let current = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let mut legacy = Vec::new();
+		for seed in &["//Alice", "//Bob"] {
+			let public = sr25519::AppPair::from_string(seed, None).unwrap().public();
+			let file = store.0.read().key_file_path(public.as_slice(), SR25519).unwrap();
+			fs::write(&file, seed).unwrap();
+			legacy.push(file);
+		}
+		// A plain text file that does not hold the key of its name is left alone.
+		let public = sr25519::AppPair::from_string("//Charlie", None).unwrap().public();
+		let wrong = store.0.read().key_file_path(public.as_slice(), SR25519).unwrap();
+		fs::write(&wrong, "//Dave").unwrap();
+
+		assert_eq!(store.upgrade_plaintext().unwrap(), 2);
+		for file in legacy {
+			assert!(!read_key_file(Dir::new(temp_dir.path()), &file).unwrap().1);
+		}
+		assert_eq!(fs::read_to_string(&wrong).unwrap(), "//Dave");
+		assert!(store.0.read().key_pair_by_type::<sr25519::Pair>(&current, SR25519).unwrap().is_some());
+		assert_eq!(store.upgrade_plaintext().unwrap(), 0);
+	}
+
+	#[test]
+	fn sign_empty_message() {
+		let store = LocalKeystore::in_memory();
+
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let signature = SyncCryptoStore::sign_with(&store, ED25519, &public.into(), &[])
+			.unwrap()
+			.unwrap();
+		let signature = ed25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ed25519::Pair::verify(&signature, b"", &public));
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let signature = SyncCryptoStore::sign_with(&store, SR25519, &public.into(), &[])
+			.unwrap()
+			.unwrap();
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, b"", &public));
+
+		let public = SyncCryptoStore::ecdsa_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let signature = SyncCryptoStore::sign_with(&store, TEST_KEY_TYPE, &public.clone().into(), &[])
+			.unwrap()
+			.unwrap();
+		let signature = ecdsa::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ecdsa::Pair::verify(&signature, b"", &public));
+	}
+
+	#[test]
+	fn sharded_layouts_work() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(temp_dir.path())
+			.layout(DirectoryLayout::ByKeyTypeAndPublic)
+			.open()
+			.unwrap();
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let name = hex::encode(SR25519.0) + &hex::encode(public);
+		let sharded = temp_dir.path()
+			.join(hex::encode(SR25519.0))
+			.join(hex::encode(&public.0[..1]))
+			.join(&name);
+		assert!(sharded.is_file());
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(SyncCryptoStore::has_keys(&store, &[(public.to_vec(), SR25519)]));
+
+		assert_eq!(store.migrate_layout(DirectoryLayout::Flat).unwrap(), 1);
+		assert!(temp_dir.path().join(&name).is_file());
+		assert!(!temp_dir.path().join(hex::encode(SR25519.0)).exists());
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+
+		assert_eq!(store.migrate_layout(DirectoryLayout::ByKeyType).unwrap(), 1);
+		assert!(temp_dir.path().join(hex::encode(SR25519.0)).join(&name).is_file());
+
+		let store = LocalKeystoreBuilder::new(temp_dir.path())
+			.layout(DirectoryLayout::ByKeyType)
+			.open()
+			.unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg").unwrap().is_some());
+	}
+
+	#[test]
+	fn verify_replica_compares_secrets() {
+		let temp_dir = TempDir::new().unwrap();
+		let primary = LocalKeystore::open(temp_dir.path().join("primary"), None).unwrap();
+		let replica = LocalKeystore::open(temp_dir.path().join("replica"), None).unwrap();
+
+		let alice = sr25519::AppPair::from_string("//Alice", None).unwrap().public();
+		let bob = ed25519::AppPair::from_string("//Bob", None).unwrap().public();
+		for store in &[&primary, &replica] {
+			SyncCryptoStore::insert_unknown(*store, SR25519, "//Alice", alice.as_ref()).unwrap();
+			SyncCryptoStore::insert_unknown(*store, ED25519, "//Bob", bob.as_ref()).unwrap();
+		}
+		assert!(verify_replica(&primary, &replica).unwrap().is_replica());
+
+		// Same public key in the file name, but another secret.
+		let charlie = SyncCryptoStore::ed25519_generate_new(&primary, ED25519, None).unwrap();
+		replica.set_verify_inserted_public(false);
+		SyncCryptoStore::insert_unknown(&replica, ED25519, "//Charlie", charlie.as_ref()).unwrap();
+		let dave = SyncCryptoStore::sr25519_generate_new(&replica, SR25519, None).unwrap();
+		let eve = SyncCryptoStore::sr25519_generate_new(&primary, SR25519, None).unwrap();
+
+		let report = verify_replica(&primary, &replica).unwrap();
+		assert!(!report.is_replica());
+		assert_eq!(report.missing, vec![(SR25519, eve.to_vec())]);
+		assert_eq!(report.extra, vec![(SR25519, dave.to_vec())]);
+		assert_eq!(report.mismatched, vec![(ED25519, charlie.to_vec())]);
+	}
+
+	#[test]
+	fn backup_round_trips_with_and_without_compression() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path().join("primary"), None).unwrap();
+		let alice = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let bob = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let password = SecretString::new("backup".into());
+
+		for (i, compress) in [false, true].iter().enumerate() {
+			let backup = store.export_backup(&password, *compress).unwrap();
+			let restored = LocalKeystore::open(temp_dir.path().join(i.to_string()), None).unwrap();
+
+			assert!(matches!(
+				restored.import_backup(
+					&backup,
+					&SecretString::new("wrong".into()),
+					OverwritePolicy::Error,
+				),
+				Err(Error::InvalidPassword),
+			));
+			assert_eq!(restored.import_backup(&backup, &password, OverwritePolicy::Error).unwrap(), 2);
+			assert!(verify_replica(&store, &restored).unwrap().is_replica());
+			assert!(SyncCryptoStore::has_keys(
+				&restored,
+				&[(alice.to_vec(), SR25519), (bob.to_vec(), ED25519)],
+			));
+		}
+
+		assert!(matches!(
+			store.import_backup(b"not a backup", &password, OverwritePolicy::Overwrite),
+			Err(Error::InvalidBackup(_)),
+		));
+	}
+
+	#[test]
+	fn last_used_is_recorded_when_signing() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+		assert_eq!(store.last_used(SR25519, public.as_ref()).unwrap(), None);
+
+		let before = SystemTime::now() - Duration::from_secs(1);
+		SyncCryptoStore::sign_with(&store, SR25519, &key, b"msg").unwrap().unwrap();
+		let used = store.last_used(SR25519, public.as_ref()).unwrap().unwrap();
+		assert!(used >= before);
+
+		// Survives reopening, while the key file itself is left untouched.
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(store.last_used(SR25519, public.as_ref()).unwrap(), Some(used));
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+
+		let ephemeral = SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice"))
+			.unwrap();
+		SyncCryptoStore::sign_with(&store, ED25519, &ephemeral.into(), b"msg").unwrap().unwrap();
+		assert!(store.last_used(ED25519, ephemeral.as_ref()).unwrap().is_some());
+
+		SyncCryptoStore::remove_key(&store, SR25519, &key).unwrap();
+		assert_eq!(store.last_used(SR25519, public.as_ref()).unwrap(), None);
+	}
+
+	#[test]
+	fn signing_latencies_are_reported() {
+		let store = LocalKeystore::in_memory();
+		let histogram = Arc::new(crate::SigningHistogram::default());
+		store.set_signing_metrics(histogram.clone());
+
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		SyncCryptoStore::sign_with(&store, ED25519, &ed25519.into(), b"msg").unwrap().unwrap();
+		SyncCryptoStore::sign_with(&store, ED25519, &ed25519.into(), b"msg").unwrap().unwrap();
+		let transcript = VRFTranscriptData { label: b"test", items: vec![] };
+		SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &sr25519, transcript).unwrap().unwrap();
+
+		assert_eq!(histogram.snapshot(ED25519).unwrap().count, 2);
+		assert_eq!(histogram.snapshot(SR25519).unwrap().count, 1);
+		assert_eq!(histogram.snapshot(TEST_KEY_TYPE), None);
+	}
+
+	#[test]
+	fn import_backup_respects_overwrite_policy() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path().join("primary"), None).unwrap();
+		let alice = ed25519::AppPair::from_string("//Alice", None).unwrap().public();
+		SyncCryptoStore::insert_unknown(&store, ED25519, "//Alice", alice.as_ref()).unwrap();
+		let bob = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let password = SecretString::new("backup".into());
+		let backup = store.export_backup(&password, false).unwrap();
+
+		// The target holds Alice with a different secret URI deriving to the same key.
+		let target = LocalKeystore::open(temp_dir.path().join("target"), None).unwrap();
+		let alice_seed = format!("0x{}", hex::encode(
+			ed25519::Pair::from_string("//Alice", None).unwrap().seed(),
+		));
+		SyncCryptoStore::insert_unknown(&target, ED25519, &alice_seed, alice.as_ref()).unwrap();
+		let stored = |store: &LocalKeystore| {
+			store.0.read().key_phrase_by_type(alice.as_ref(), ED25519).unwrap().unwrap().to_string()
+		};
+
+		match target.import_backup(&backup, &password, OverwritePolicy::Error) {
+			Err(Error::KeysExist(keys)) => assert_eq!(keys, vec![(ED25519, alice.to_raw_vec())]),
+			_ => panic!("Import must fail"),
+		}
+		assert!(!SyncCryptoStore::has_keys(&target, &[(bob.to_vec(), SR25519)]));
+
+		assert_eq!(target.import_backup(&backup, &password, OverwritePolicy::Skip).unwrap(), 1);
+		assert!(SyncCryptoStore::has_keys(&target, &[(bob.to_vec(), SR25519)]));
+		assert_eq!(stored(&target), alice_seed);
+
+		assert_eq!(target.import_backup(&backup, &password, OverwritePolicy::Overwrite).unwrap(), 2);
+		assert_eq!(stored(&target), "//Alice");
+	}
+
+	#[test]
+	fn preload_collects_failures() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let alice = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let bob = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let missing = vec![1u8; 32];
+
+		assert!(SyncCryptoStore::preload(
+			&store,
+			&[(SR25519, alice.to_vec()), (ED25519, bob.to_vec())],
+		).is_ok());
+
+		let keys = [(SR25519, missing.clone()), (ED25519, bob.to_vec()), (ED25519, missing)];
+		match SyncCryptoStore::preload(&store, &keys) {
+			Err(TraitError::ValidationError(message)) =>
+				assert!(message.starts_with("Failed to preload 2 keys")),
+			_ => panic!("Preload must fail"),
+		}
+	}
+
+	#[test]
+	fn keys_modified_since_skips_older_and_ephemeral_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let old = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let old_file = store.0.read().key_file_path(old.as_ref(), SR25519).unwrap();
+		let since = fs::metadata(&old_file).unwrap().modified().unwrap();
+
+		// Make sure the next file gets a later modification time on coarse file systems.
+		std::thread::sleep(Duration::from_millis(20));
+		let new = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice")).unwrap();
+		SyncCryptoStore::sign_with(&store, SR25519, &old.into(), b"msg").unwrap().unwrap();
+
+		assert_eq!(store.keys_modified_since(since).unwrap(), vec![(ED25519, new.to_vec())]);
+		assert_eq!(store.keys_modified_since(UNIX_EPOCH).unwrap().len(), 2);
+	}
+
+	#[test]
+	fn injected_rng_generates_reproducible_keys() {
+		use rand::{SeedableRng, rngs::StdRng};
+
+		let temp_dir = TempDir::new().unwrap();
+		let open = |name: &str| LocalKeystoreBuilder::new(temp_dir.path().join(name))
+			.insecure_rng(Box::new(StdRng::seed_from_u64(42)))
+			.open()
+			.unwrap();
+		let first = open("first");
+		let second = open("second");
+
+		for store in &[&first, &second] {
+			SyncCryptoStore::sr25519_generate_new(*store, SR25519, None).unwrap();
+			SyncCryptoStore::ed25519_generate_new(*store, ED25519, None).unwrap();
+		}
+		let (_, phrase) = first.generate_with_phrase(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+		let (_, other) = second.generate_with_phrase(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+
+		assert_eq!(phrase, other);
+		assert_eq!(phrase.split(' ').count(), 12);
+		assert_eq!(
+			SyncCryptoStore::fingerprint(&first).unwrap(),
+			SyncCryptoStore::fingerprint(&second).unwrap(),
+		);
+	}
+
+	#[test]
+	fn retype_key_moves_the_key_file() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(temp_dir.path())
+			.layout(DirectoryLayout::ByKeyType)
+			.open()
+			.unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, ED25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+		SyncCryptoStore::sign_with(&store, ED25519, &key, b"msg").unwrap().unwrap();
+		let used = store.last_used(ED25519, public.as_ref()).unwrap();
+
+		store.retype_key(ED25519, public.as_ref(), SR25519).unwrap();
+		assert!(SyncCryptoStore::sr25519_public_keys(&store, ED25519).is_empty());
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &key, b"msg").unwrap().is_some());
+		assert!(store.last_used(SR25519, public.as_ref()).unwrap() >= used);
+
+		assert!(matches!(
+			store.retype_key(ED25519, public.as_ref(), SR25519),
+			Err(Error::PairNotFound),
+		));
+		let phrase = store.0.read().key_phrase_by_type(public.as_ref(), SR25519).unwrap().unwrap();
+		SyncCryptoStore::insert_unknown(&store, ED25519, &phrase, public.as_ref()).unwrap();
+		assert!(matches!(
+			store.retype_key(ED25519, public.as_ref(), SR25519),
+			Err(Error::KeyCollision),
+		));
+
+		let ephemeral = SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice"))
+			.unwrap();
+		store.retype_key(ED25519, ephemeral.as_ref(), TEST_KEY_TYPE).unwrap();
+		assert!(SyncCryptoStore::has_keys(&store, &[(ephemeral.to_vec(), TEST_KEY_TYPE)]));
+	}
+
+	#[test]
+	fn with_exclusive_runs_read_modify_write_sequences() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let alice = ed25519::Pair::from_string("//Alice", None).unwrap().public();
+
+		let insert_alice = || store.with_exclusive(|locked| {
+			if locked.contains(ED25519, alice.as_ref()).unwrap() {
+				return false;
+			}
+			locked.insert(ED25519, "//Alice", alice.as_ref()).unwrap();
+			true
+		});
+		assert!(insert_alice());
+		assert!(!insert_alice());
+
+		let generated = store.with_exclusive(|locked| {
+			let public = locked.generate(SR25519, sr25519::CRYPTO_ID).unwrap();
+			assert!(locked.contains(SR25519, &public.1).unwrap());
+			public
+		});
+		assert_eq!(
+			SyncCryptoStore::sr25519_public_keys(&store, SR25519),
+			vec![sr25519::Public::from_slice(&generated.1)],
+		);
+	}
+
+	#[test]
+	fn active_window_keeps_used_keys_in_memory() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let sign = |id, key: CryptoTypePublicPair| {
+			SyncCryptoStore::sign_with(&store, id, &key, b"msg").unwrap().is_some()
+		};
+		let remove_files = || {
+			for entry in fs::read_dir(temp_dir.path()).unwrap() {
+				fs::remove_file(entry.unwrap().path()).unwrap();
+			}
+		};
+
+		store.set_active_window(Duration::from_secs(3600));
+		assert!(sign(SR25519, sr25519.into()));
+		assert!(sign(TEST_KEY_TYPE, ecdsa.clone().into()));
+		remove_files();
+		assert!(sign(SR25519, sr25519.into()));
+		assert!(sign(TEST_KEY_TYPE, ecdsa.into()));
+
+		store.set_active_window(Duration::default());
+		assert!(!sign(SR25519, sr25519.into()));
+
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		store.set_active_window(Duration::from_millis(50));
+		assert!(sign(ED25519, ed25519.into()));
+		remove_files();
+		std::thread::sleep(Duration::from_millis(100));
+		assert!(!sign(ED25519, ed25519.into()));
+	}
+
+	#[test]
+	fn debug_snapshot_contains_no_secrets() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = "snapshot password";
+		let store = LocalKeystore::open(temp_dir.path(), Some(FromStr::from_str(password).unwrap()))
+			.unwrap();
+		store.set_active_window(Duration::from_secs(60));
+
+		let (public, phrase) = store.generate_with_phrase(SR25519, sr25519::CRYPTO_ID).unwrap();
+		SyncCryptoStore::sign_with(&store, SR25519, &public, b"msg").unwrap().unwrap();
+		let seed = "0x9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+		let ephemeral = SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some(seed)).unwrap();
+
+		let snapshot = store.debug_snapshot().unwrap();
+		assert_eq!(snapshot.key_counts.get(&hex::encode(SR25519.0)), Some(&1));
+		assert_eq!(snapshot.ephemeral_keys, 1);
+		assert_eq!(snapshot.active_keys, 1);
+		assert!(snapshot.has_password);
+
+		let json = serde_json::to_string(&snapshot).unwrap();
+		let file = store.0.read().key_file_path(&public.1, SR25519).unwrap();
+		let stored = read_suri(Dir::new(temp_dir.path()), &file).unwrap();
+		let stored = stored.as_str();
+		let secret = store.key_pair::<sr25519::AppPair>(&sr25519::AppPublic::from_slice(&public.1))
+			.unwrap()
+			.unwrap()
+			.to_raw_vec();
+		for secret in &[stored, &phrase, password, seed, &seed[2..], &hex::encode(&secret)] {
+			assert!(!json.contains(*secret));
+		}
+		assert!(!json.contains(&hex::encode(ed25519::Pair::from_string(seed, None).unwrap().seed())));
+		assert!(snapshot.files.contains(&file.file_name().unwrap().to_string_lossy().into_owned()));
+		assert!(SyncCryptoStore::has_keys(&store, &[(ephemeral.to_vec(), ED25519)]));
+	}
+
+	#[test]
+	fn stale_index_is_rebuilt_and_never_hides_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		drop(store);
+
+		let index_file = temp_dir.path().join(crate::index::INDEX_FILE);
+		fs::write(&index_file, b"garbage").unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let index = KeyIndex::load(Dir::new(temp_dir.path())).unwrap();
+		assert!(index.get(SR25519, first.as_ref()).is_some());
+
+		// A key written by another instance is not in the index, but still found.
+		let other = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let second = SyncCryptoStore::ed25519_generate_new(&other, ED25519, None).unwrap();
+		assert!(SyncCryptoStore::has_keys(&store, &[
+			(first.to_raw_vec(), SR25519),
+			(second.to_raw_vec(), ED25519),
+		]));
+
+		SyncCryptoStore::remove_key(&store, SR25519, &first.into()).unwrap();
+		assert!(!SyncCryptoStore::has_keys(&store, &[(first.to_raw_vec(), SR25519)]));
+		let index = KeyIndex::load(Dir::new(temp_dir.path())).unwrap();
+		assert!(index.get(SR25519, first.as_ref()).is_none());
+	}
+
+	#[test]
+	fn sign_hook_can_refuse_to_sign() {
+		let store = LocalKeystore::in_memory();
+		store.set_sign_hook(Box::new(|_, _, msg| {
+			if msg.starts_with(b"allowed:") { Ok(()) } else { Err("unknown prefix".into()) }
+		}));
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		assert!(SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"allowed:msg")
+			.unwrap()
+			.is_some());
+		assert!(matches!(
+			SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"msg"),
+			Err(TraitError::PermissionDenied),
+		));
+		assert!(matches!(
+			store.0.read().check_sign_hook(ED25519, &public.into(), b"msg"),
+			Err(Error::PermissionDenied(reason)) if reason == "unknown prefix",
+		));
+	}
+
+	#[test]
+	fn insert_raw_seed_works() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		// Not valid UTF-8.
+		let seed = [0xff; 32];
+
+		let public = store.insert_raw_seed(ED25519, ed25519::CRYPTO_ID, &seed).unwrap();
+		let expected = ed25519::Pair::from_seed_slice(&seed).unwrap().public();
+		assert_eq!(public, expected.into());
+		let pair = store.key_pair::<ed25519::AppPair>(&expected.into()).unwrap().unwrap();
+		assert_eq!(pair.public(), expected.into());
+
+		assert!(matches!(
+			store.insert_raw_seed(ED25519, sr25519::CRYPTO_ID, &seed[..31]),
+			Err(Error::InvalidSeed),
+		));
+		assert!(matches!(
+			store.insert_raw_seed(ED25519, CryptoTypeId(*b"bls3"), &seed),
+			Err(Error::KeyNotSupported(_)),
+		));
+	}
+
+	#[test]
+	fn keys_in_several_sources_are_listed_once() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let pair = store.0.write()
+			.insert_ephemeral_from_seed_by_type::<sr25519::Pair>("//Alice", SR25519)
+			.unwrap();
+		let public = pair.public();
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Alice", public.as_ref()).unwrap();
+		let file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		let name = file.file_name().unwrap().to_str().unwrap().to_uppercase();
+		fs::copy(&file, file.with_file_name(name)).unwrap();
+
+		let keys = SyncCryptoStore::keys(&store, SR25519).unwrap();
+		assert_eq!(keys.len(), 3);
+		let key: CryptoTypePublicPair = public.into();
+		assert_eq!(
+			SyncCryptoStore::supported_keys(&store, SR25519, vec![key.clone(), key.clone()]).unwrap(),
+			vec![key],
+		);
+	}
+
+	#[test]
+	fn tiered_keystore_restores_the_last_snapshot() {
+		let durable = TempDir::new().unwrap();
+		let password = || SecretString::new("snapshot".into());
+
+		let fast = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(fast.path())
+			.durable(durable.path(), password())
+			.open()
+			.unwrap();
+		let generated = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let inserted = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Bob", inserted.as_ref()).unwrap();
+		drop(store);
+
+		// The fast directory is lost, the inserted key was never snapshotted.
+		drop(fast);
+		let fast = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(fast.path())
+			.durable(durable.path(), password())
+			.open()
+			.unwrap();
+		assert!(SyncCryptoStore::has_keys(&store, &[(generated.to_raw_vec(), SR25519)]));
+		assert!(!SyncCryptoStore::has_keys(&store, &[(inserted.to_raw_vec(), SR25519)]));
+
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Bob", inserted.as_ref()).unwrap();
+		store.snapshot_to_durable().unwrap();
+		drop(store);
+
+		let fast = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(fast.path())
+			.durable(durable.path(), password())
+			.open()
+			.unwrap();
+		assert!(SyncCryptoStore::has_keys(&store, &[(inserted.to_raw_vec(), SR25519)]));
+		assert!(LocalKeystoreBuilder::new(TempDir::new().unwrap().path())
+			.durable(durable.path(), SecretString::new("wrong".into()))
+			.open()
+			.is_err());
+	}
+
+	#[test]
+	fn interrupted_changes_are_replayed_from_the_wal() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut store = LocalKeystoreBuilder::new(temp_dir.path()).wal().open().unwrap();
+		let removed = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		assert_eq!(store.replay_wal().unwrap(), 0);
+
+		// Crash right after logging the changes.
+		let inserted = sr25519::Pair::from_string("//Alice", None).unwrap().public();
+		crate::wal::Wal::new(temp_dir.path()).append(Dir::new(temp_dir.path()), &[
+			WalRecord::Insert {
+				key_type: SR25519,
+				public: inserted.to_raw_vec(),
+				suri: "//Alice".into(),
+			},
+			WalRecord::Remove { key_type: SR25519, public: removed.to_raw_vec() },
+		]).unwrap();
+		drop(store);
+
+		let mut store = LocalKeystoreBuilder::new(temp_dir.path()).wal().open().unwrap();
+		assert!(SyncCryptoStore::has_keys(&store, &[(inserted.to_raw_vec(), SR25519)]));
+		assert!(!SyncCryptoStore::has_keys(&store, &[(removed.to_raw_vec(), SR25519)]));
+		assert_eq!(store.replay_wal().unwrap(), 0);
+		assert_eq!(
+			fs::metadata(temp_dir.path().join(crate::wal::WAL_FILE)).unwrap().len(),
+			0,
+		);
+	}
+
+	#[test]
+	fn typed_accessors_use_the_application_key_type() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = ed25519::AppPair::from_string("//Alice", None).unwrap().public();
+
+		assert!(!store.has_key(&public));
+		store.insert_key("//Alice", &public).unwrap();
+		assert!(store.has_key(&public));
+		assert!(SyncCryptoStore::has_keys(&store, &[(public.to_raw_vec(), ed25519::AppPublic::ID)]));
+		assert!(!SyncCryptoStore::has_keys(&store, &[(public.to_raw_vec(), SR25519)]));
+
+		store.remove_app_key(&public).unwrap();
+		assert!(!store.has_key(&public));
+		assert!(matches!(store.remove_app_key(&public), Err(Error::PairNotFound)));
+	}
+
+	#[test]
+	fn only_deterministic_signatures_are_cached() {
+		let store = LocalKeystore::in_memory();
+		store.set_signature_cache(1);
+		let cached = || store.0.read().signatures.lock().signatures.len();
+
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let sign = |key: CryptoTypePublicPair, msg: &[u8]| {
+			SyncCryptoStore::sign_with(&store, SR25519, &key, msg).unwrap().unwrap()
+		};
+		assert_ne!(sign(sr25519.into(), b"msg"), sign(sr25519.into(), b"msg"));
+		assert_eq!(cached(), 0);
+
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, SR25519, None).unwrap();
+		let signature = sign(ed25519.into(), b"msg");
+		assert_eq!(cached(), 1);
+		assert_eq!(sign(ed25519.into(), b"msg"), signature);
+		sign(ed25519.into(), b"other");
+		assert_eq!(cached(), 1);
+
+		SyncCryptoStore::remove_key(&store, SR25519, &ed25519.into()).unwrap();
+		assert_eq!(cached(), 0);
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &ed25519.into(), b"msg").unwrap().is_none());
+	}
+
+	#[test]
+	fn preflight_check_reports_inconsistencies() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		assert_eq!(store.preflight_check().unwrap(), PreflightReport { keys: 1, warnings: vec![] });
+
+		let file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		fs::copy(&file, file.with_extension("tmp")).unwrap();
+		fs::remove_file(temp_dir.path().join(crate::index::INDEX_FILE)).unwrap();
+		let report = store.preflight_check().unwrap();
+		assert_eq!(report.warnings.len(), 2);
+
+		let other = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+		let wrong = store.0.read().key_file_path(other.as_ref(), SR25519).unwrap();
+		fs::copy(&file, &wrong).unwrap();
+		assert!(matches!(
+			store.preflight_check(),
+			Err(Error::Inconsistent(errors)) if errors.len() == 1
+		));
+	}
+
+	#[test]
+	fn export_pjs_json_works() {
+		let store = LocalKeystore::in_memory();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let meta = PjsMeta { name: "node".into(), tags: vec!["validator".into()] };
+		let password = SecretString::new("secret".into());
+
+		let json: serde_json::Value = serde_json::from_slice(
+			&store.export_pjs_json(ED25519, public.as_ref(), &password, meta).unwrap(),
+		).unwrap();
+		assert_eq!(json["address"], public.to_ss58check());
+		assert_eq!(json["encoding"]["content"], serde_json::json!(["pkcs8", "ed25519"]));
+		assert_eq!(json["meta"]["tags"], serde_json::json!(["validator"]));
+		// Salt, scrypt parameters, nonce, tag and the 16 + 64 + 5 + 32 bytes of the envelope,
+		// 201 bytes in base64.
+		assert_eq!(json["encoded"].as_str().unwrap().len(), 268);
+
+		assert!(matches!(
+			store.export_pjs_json(ED25519, &[0; 32], &password, PjsMeta::default()),
+			Err(Error::PairNotFound)
+		));
+	}
+
+	#[test]
+	fn pjs_json_round_trips_into_another_keystore() {
+		let store = LocalKeystore::in_memory();
+		let password = SecretString::new("secret".into());
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let json = store.export_pjs_json(ED25519, public.as_ref(), &password, PjsMeta::default())
+			.unwrap();
+
+		let temp_dir = TempDir::new().unwrap();
+		let imported = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let wrong = SecretString::new("wrong".into());
+		assert!(matches!(
+			imported.import_pjs_json(ED25519, &json, &wrong),
+			Err(Error::InvalidPassword),
+		));
+		let key = imported.import_pjs_json(ED25519, &json, &password).unwrap();
+		assert_eq!(key, CryptoTypePublicPair::from(public));
+		// ed25519 signatures are deterministic, the same key gives the same signature.
+		assert_eq!(
+			SyncCryptoStore::sign_with(&imported, ED25519, &key, b"msg").unwrap(),
+			SyncCryptoStore::sign_with(&store, ED25519, &key, b"msg").unwrap(),
+		);
+
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(sr25519);
+		let json = store.export_key(SR25519, &key, "secret").unwrap();
+		assert!(matches!(
+			imported.import_key(SR25519, &json, "wrong"),
+			Err(Error::InvalidPassword),
+		));
+		assert_eq!(imported.import_key(SR25519, &json, "secret").unwrap(), key);
+		// sr25519 signatures are randomized, check the imported key against the public key.
+		let signature = SyncCryptoStore::sign_with(&imported, SR25519, &key, b"msg")
+			.unwrap()
+			.unwrap();
+		let signature = sr25519::Signature::from_slice(&signature);
+		assert!(sr25519::Pair::verify(&signature, b"msg", &sr25519));
+
+		assert!(matches!(
+			imported.import_pjs_json(SR25519, b"not json", &password),
+			Err(Error::InvalidPjsJson(_)),
+		));
+		let mut json = json;
+		json["encoding"]["content"] = serde_json::json!(["pkcs8"]);
+		assert!(matches!(
+			imported.import_key(SR25519, &json, "secret"),
+			Err(Error::InvalidPjsJson(_)),
+		));
+	}
+
+	#[test]
+	fn persisted_keys_are_limited() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		store.set_max_persisted_keys(Some(2));
+
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		assert!(matches!(
+			SyncCryptoStore::sr25519_generate_new(&store, SR25519, None),
+			Err(TraitError::ValidationError(_))
+		));
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		assert!(matches!(
+			store.0.write().insert_unknown(SR25519, "//Alice", pair.public().as_ref()),
+			Err(Error::KeyLimitReached { limit: 2 })
+		));
+
+		// Existing keys can still be replaced and keys in memory are not limited.
+		let suri = store.0.read().key_phrase_by_type(first.as_ref(), SR25519).unwrap().unwrap();
+		SyncCryptoStore::insert_unknown(&store, SR25519, &suri, first.as_ref()).unwrap();
+		SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Bob")).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn open_creates_a_private_directory() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("chains").join("keystore");
+		LocalKeystore::open(&path, None).unwrap();
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, KEYSTORE_DIR_MODE);
+
+		let file = temp_dir.path().join("file");
+		fs::write(&file, b"").unwrap();
+		assert!(matches!(LocalKeystore::open(file.join("keystore"), None), Err(Error::Io(_))));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn key_files_accessible_by_others_are_refused() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, KEY_FILE_MODE);
+		drop(store);
+
+		fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+		match LocalKeystore::open(temp_dir.path(), None) {
+			Err(error @ Error::InsecurePermissions(_)) => {
+				assert!(error.to_string().contains(&format!("chmod go-rwx {}", file.display())));
+			},
+			_ => panic!("Opening must fail"),
+		}
+		fs::set_permissions(&file, fs::Permissions::from_mode(KEY_FILE_MODE)).unwrap();
+
+		// The directory is restricted rather than refused.
+		fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+		assert!(LocalKeystore::open(temp_dir.path(), None).is_ok());
+		let mode = fs::metadata(temp_dir.path()).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, KEYSTORE_DIR_MODE);
+	}
+
+	#[test]
+	fn deterministic_sr25519_signatures() {
+		let store = LocalKeystore::in_memory();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+		let sign = || SyncCryptoStore::sign_with(&store, SR25519, &key, b"msg").unwrap().unwrap();
+
+		assert!(!store.deterministic_sr25519());
+		assert_ne!(sign(), sign());
+
+		store.set_deterministic_sr25519(true);
+		let signature = sign();
+		assert_eq!(signature, sign());
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, b"msg", &public));
+	}
+
+	#[test]
+	fn inserted_public_must_derive_from_the_suri() {
+		let store = LocalKeystore::in_memory();
+		let alice = sr25519::Pair::from_string("//Alice", None).unwrap().public();
+		let bob = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+
+		match store.0.write().insert_unknown(SR25519, "//Bob", alice.as_ref()) {
+			Err(Error::PublicKeyMismatch { expected, derived }) => {
+				assert_eq!(expected, alice.to_raw_vec());
+				assert_eq!(derived.len(), 3);
+				assert_eq!(derived[0], bob.to_raw_vec());
+			},
+			_ => panic!("Mismatching public key is rejected"),
+		}
+		assert!(SyncCryptoStore::insert_unknown(&store, SR25519, "//Bob", alice.as_ref()).is_err());
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Bob", bob.as_ref()).unwrap();
+
+		store.set_verify_inserted_public(false);
+		SyncCryptoStore::insert_unknown(&store, SR25519, "//Bob", alice.as_ref()).unwrap();
+	}
+
+	#[test]
+	fn concurrent_readers_and_writers() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = Arc::new(LocalKeystore::open(temp_dir.path(), None).unwrap());
+		let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+		let readers = (0..4).map(|_| {
+			let (store, done) = (store.clone(), done.clone());
+			std::thread::spawn(move || {
+				let mut seen = 0;
+				while !done.load(std::sync::atomic::Ordering::SeqCst) {
+					let keys = SyncCryptoStore::sr25519_public_keys(&*store, SR25519);
+					assert!(keys.len() >= seen, "Keys are never lost");
+					seen = keys.len();
+					let keys = keys.iter().map(|k| (k.to_raw_vec(), SR25519)).collect::<Vec<_>>();
+					assert!(SyncCryptoStore::has_keys(&*store, &keys));
+				}
+			})
+		}).collect::<Vec<_>>();
+
+		let generated = (0..50)
+			.map(|_| SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap())
+			.collect::<HashSet<_>>();
+		done.store(true, std::sync::atomic::Ordering::SeqCst);
+		for reader in readers {
+			reader.join().unwrap();
+		}
+
+		let keys = SyncCryptoStore::sr25519_public_keys(&*store, SR25519);
+		assert_eq!(keys.into_iter().collect::<HashSet<_>>(), generated);
+	}
+
+	#[test]
+	fn generated_keys_derive_from_the_hd_root() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(store.set_hd_root("//Alice").unwrap(), 0);
+
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let second = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		assert_eq!(first, sr25519::Pair::from_string("//Alice//0", None).unwrap().public());
+		assert_eq!(second, ed25519::Pair::from_string("//Alice//1", None).unwrap().public());
+		drop(store);
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(store.set_hd_root("//Alice").unwrap(), 2);
+		let third = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		assert_eq!(third, sr25519::Pair::from_string("//Alice//2", None).unwrap().public());
+		assert!(store.set_hd_root("not a valid phrase").is_err());
+	}
+
+	#[test]
+	fn stray_files_are_handled_by_policy() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		store.record_use(TEST_KEY_TYPE, public.as_ref());
+		drop(store);
+
+		let open = |policy| {
+			LocalKeystoreBuilder::new(temp_dir.path()).stray_file_policy(policy).open()
+		};
+		assert!(open(StrayFilePolicy::Error).is_ok());
+
+		let stray = temp_dir.path().join("notes.txt");
+		fs::write(&stray, "test").unwrap();
+		assert!(open(StrayFilePolicy::Ignore).is_ok());
+		assert!(open(StrayFilePolicy::Warn).is_ok());
+		match open(StrayFilePolicy::Error) {
+			Err(Error::StrayFiles(files)) => assert_eq!(files, vec![stray]),
+			_ => panic!("Stray files fail the open"),
+		}
+	}
+
+	#[test]
+	fn reserved_keys_are_only_stored_when_committed() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+
+		let (kept, token) = store.generate_reserved(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+		let (dropped, other) = store.generate_reserved(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+		assert_ne!(kept, dropped);
+		assert!(SyncCryptoStore::keys(&store, TEST_KEY_TYPE).unwrap().is_empty());
+		assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+		store.commit(token).unwrap();
+		store.discard(other);
+		let kept = ed25519::Public::from_slice(&kept.1);
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, TEST_KEY_TYPE), vec![kept]);
+
+		let reopened = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&reopened, TEST_KEY_TYPE), vec![kept]);
+		let (_, token) = reopened.generate_reserved(TEST_KEY_TYPE, ed25519::CRYPTO_ID).unwrap();
+		assert!(matches!(store.commit(token), Err(Error::PairNotFound)));
+	}
+
+	#[test]
+	fn notes_are_encrypted_with_the_password() {
+		let temp_dir = TempDir::new().unwrap();
+		let open = |password: &str| {
+			LocalKeystore::open(temp_dir.path(), Some(SecretString::new(password.into()))).unwrap()
+		};
+		let store = open("password");
+		let public = SyncCryptoStore::ed25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		assert_eq!(store.encrypted_note(TEST_KEY_TYPE, public.as_ref()).unwrap(), None);
+
+		let note = "controller for validator-3";
+		store.set_encrypted_note(TEST_KEY_TYPE, public.as_ref(), note).unwrap();
+		assert_eq!(store.encrypted_note(TEST_KEY_TYPE, public.as_ref()).unwrap().unwrap(), note);
+		for entry in fs::read_dir(temp_dir.path()).unwrap() {
+			let content = fs::read(entry.unwrap().path()).unwrap();
+			assert!(!content.windows(note.len()).any(|w| w == note.as_bytes()));
+		}
+		assert!(!format!("{:?}", store.debug_snapshot().unwrap()).contains(note));
+
+		let store = open("wrong");
+		assert!(matches!(
+			store.encrypted_note(TEST_KEY_TYPE, public.as_ref()),
+			Err(Error::InvalidPassword),
+		));
+	}
+
+	#[test]
+	fn upper_case_key_files_are_normalized() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let kept = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let moved = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		drop(store);
+
+		let file_name = |public: &sr25519::Public| {
+			temp_dir.path().join(hex::encode([&TEST_KEY_TYPE.0[..], public.as_ref()].concat()))
+		};
+		let upper_case = |path: &Path| {
+			path.with_file_name(path.file_name().unwrap().to_str().unwrap().to_uppercase())
+		};
+		let copy = upper_case(&file_name(&kept));
+		fs::rename(file_name(&moved), upper_case(&file_name(&moved))).unwrap();
+		let case_insensitive = is_case_insensitive(Dir::new(temp_dir.path())).unwrap();
+		if !case_insensitive {
+			// Only a case-sensitive file system can hold both names.
+			fs::write(&copy, serde_json::to_string("//Colliding").unwrap()).unwrap();
+		}
+
+		// Opening renames nothing, the key file with the upper case name is used as it is.
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert!(!file_name(&moved).exists() || case_insensitive);
+		let mut keys = SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE);
+		keys.sort();
+		let mut expected = vec![kept, moved];
+		expected.sort();
+		assert_eq!(keys, expected);
+		let key = moved.into();
+		assert!(SyncCryptoStore::sign_with(&store, TEST_KEY_TYPE, &key, b"msg").unwrap().is_some());
+
+		assert_eq!(store.normalize_file_names().unwrap(), 1);
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 2);
+		assert!(file_name(&moved).exists());
+		let key = kept.into();
+		let signature = SyncCryptoStore::sign_with(&store, TEST_KEY_TYPE, &key, b"msg")
+			.unwrap()
+			.unwrap();
+		assert!(SyncCryptoStore::verify(&store, TEST_KEY_TYPE, &key, b"msg", &signature).unwrap());
+		assert_eq!(copy.exists(), !case_insensitive);
+	}
+
+	#[test]
+	fn last_use_does_not_go_back_with_the_clock() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let on_disk = SyncCryptoStore::ed25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let in_memory =
+			SyncCryptoStore::ed25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).unwrap();
+
+		let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+		for public in [on_disk, in_memory].iter() {
+			let inner = store.0.read();
+			inner.record_use_at(public.as_ref(), TEST_KEY_TYPE, now);
+			inner.record_use_at(public.as_ref(), TEST_KEY_TYPE, now - Duration::from_secs(3600));
+			assert_eq!(inner.last_used(public.as_ref(), TEST_KEY_TYPE).unwrap(), Some(now));
+			let later = now + Duration::from_secs(1);
+			inner.record_use_at(public.as_ref(), TEST_KEY_TYPE, later);
+			assert_eq!(inner.last_used(public.as_ref(), TEST_KEY_TYPE).unwrap(), Some(later));
+		}
+	}
+
+	#[test]
+	fn keys_in_memory_can_be_encrypted() {
+		let store = LocalKeystore::in_memory();
+		let before = SyncCryptoStore::ed25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice"))
+			.unwrap();
+		store.set_encrypt_memory(true);
+		let after = SyncCryptoStore::ed25519_generate_new(&store, TEST_KEY_TYPE, Some("//Bob"))
+			.unwrap();
+		assert!(store.0.read().additional.values().all(|phrase| !phrase.starts_with("//")));
+
+		for public in [before, after].iter() {
+			let key = (*public).into();
+			let signature = SyncCryptoStore::sign_with(&store, TEST_KEY_TYPE, &key, b"msg")
+				.unwrap()
+				.unwrap();
+			let valid = SyncCryptoStore::verify(&store, TEST_KEY_TYPE, &key, b"msg", &signature);
+			assert!(valid.unwrap());
+		}
+
+		store.set_encrypt_memory(false);
+		let mut phrases = store.0.read().additional.values().cloned().collect::<Vec<_>>();
+		phrases.sort();
+		assert_eq!(phrases, vec!["//Alice".to_owned(), "//Bob".to_owned()]);
+	}
+
+	#[test]
+	fn rekey_signs_the_linkage_with_the_old_key() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let old = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+
+		let (new, signature) = store.rekey_with_linkage(TEST_KEY_TYPE, &old.into()).unwrap();
+		let new_public = sr25519::Public::from_slice(&new.1);
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE), vec![new_public]);
+		let linkage = [old.as_slice(), new_public.as_slice()].concat();
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, &linkage, &old));
+
+		assert!(matches!(
+			store.rekey_with_linkage(TEST_KEY_TYPE, &old.into()),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn opening_a_file_is_refused() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keystore");
+		fs::write(&path, b"not a keystore").unwrap();
+
+		assert!(matches!(
+			LocalKeystore::open(&path, None),
+			Err(Error::NotADirectory(p)) if p == path,
+		));
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn opening_an_unreadable_directory_is_refused() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keystore");
+		fs::create_dir(&path).unwrap();
+		fs::set_permissions(&path, fs::Permissions::from_mode(0o300)).unwrap();
+		// Permissions do not apply to a privileged user.
+		if fs::read_dir(&path).is_ok() {
+			return;
+		}
+
+		assert!(matches!(
+			LocalKeystore::open(&path, None),
+			Err(Error::DirectoryNotReadable(p)) if p == path,
+		));
+	}
+
+	#[test]
+	fn raw_files_are_listed_unparsed() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystoreBuilder::new(temp_dir.path())
+			.layout(DirectoryLayout::ByKeyType)
+			.open()
+			.unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let malformed = temp_dir.path().join("not-a-key-file");
+		fs::write(&malformed, b"garbage").unwrap();
+
+		let key_file = store.0.read().key_file_path(public.as_ref(), TEST_KEY_TYPE).unwrap();
+		let content = store.raw_file_bytes(TEST_KEY_TYPE, public.as_ref()).unwrap();
+		assert_eq!(&content[..], &fs::read(&key_file).unwrap()[..]);
+		let files = store.list_raw_files().unwrap();
+		assert!(files.contains(&(key_file, content.len() as u64)));
+		assert!(files.contains(&(malformed, 7)));
+
+		assert!(matches!(
+			store.raw_file_bytes(TEST_KEY_TYPE, &[0; 32]),
+			Err(Error::PairNotFound),
+		));
+		assert!(LocalKeystore::in_memory().list_raw_files().unwrap().is_empty());
+	}
+
+	#[test]
+	fn open_requiring_counts_usable_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = || Some(FromStr::from_str("password").unwrap());
+		let store = LocalKeystore::open(temp_dir.path(), password()).unwrap();
+		SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		drop(store);
+
+		let open = |password, required: &[_]| {
+			LocalKeystoreBuilder::new(temp_dir.path()).password(password).require(required).open()
+		};
+		let required = [(SR25519, 1), (ED25519, 1)];
+		assert!(open(password(), &required).is_ok());
+		assert!(matches!(
+			open(password(), &[(SR25519, 2), (ED25519, 1)]),
+			Err(Error::MissingRequiredKeys(missing)) if missing == vec![SR25519],
+		));
+		// The key files are present, but do not derive to their keys with another password.
+		assert!(matches!(
+			open(Some(FromStr::from_str("other").unwrap()), &required),
+			Err(Error::MissingRequiredKeys(missing)) if missing == vec![SR25519, ED25519],
+		));
+	}
+
+	#[test]
+	fn password_is_never_written_out() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = SecretString::new("correct horse battery".into());
+		let store = LocalKeystore::open(temp_dir.path(), Some(password.clone())).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		store.set_encrypted_note(ED25519, public.as_ref(), "note").unwrap();
+		let backup = store.export_backup(&password, false).unwrap();
+
+		let leaks = |content: &[u8]| {
+			content.windows(password.expose_secret().len())
+				.any(|window| window == password.expose_secret().as_bytes())
+		};
+		assert!(!leaks(&backup));
+		assert!(!leaks(format!("{:?}", store.debug_snapshot().unwrap()).as_bytes()));
+		for (path, _) in store.list_raw_files().unwrap() {
+			assert!(!leaks(&fs::read(path).unwrap()));
+		}
+	}
+
+	#[test]
+	fn node_identity_is_created_once() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert!(matches!(store.node_identity(false), Err(Error::PairNotFound)));
+
+		let identity = store.node_identity(true).unwrap();
+		assert_eq!(store.node_identity(true).unwrap(), identity);
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(store.node_identity(false).unwrap(), identity);
+		assert_eq!(
+			SyncCryptoStore::ed25519_public_keys(&store, well_known::NODE_IDENTITY),
+			vec![identity],
+		);
+
+		SyncCryptoStore::ed25519_generate_new(&store, well_known::NODE_IDENTITY, None).unwrap();
+		assert!(matches!(store.node_identity(true), Err(Error::Inconsistent(_))));
+	}
+
+	#[test]
+	fn key_file_errors_name_the_file() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let key_file = store.0.read().key_file_path(public.as_ref(), ED25519).unwrap();
+		fs::write(&key_file, b"\"unterminated").unwrap();
+
+		match store.key_pair::<ed25519::AppPair>(&public.into()) {
+			Err(Error::MalformedKeyFile { path, .. }) => assert_eq!(path, key_file),
+			_ => panic!("expected a malformed key file"),
+		}
+		let dir = Dir::new(temp_dir.path());
+		let error = TraitError::from(read_key_file(dir, &key_file).unwrap_err());
+		assert!(error.to_string().contains(&key_file.display().to_string()));
+	}
+
+	#[test]
+	fn singleton_key_types_keep_one_key() {
+		let store = Arc::new(LocalKeystore::in_memory());
+		store.set_singleton_policy(SR25519, SingletonPolicy::Singleton);
+
+		let generate = || {
+			let store = store.clone();
+			std::thread::spawn(move || {
+				SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap()
+			})
+		};
+		let (first, second) = (generate(), generate());
+		let (first, second) = (first.join().unwrap(), second.join().unwrap());
+		assert_eq!(first, second);
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&*store, SR25519), vec![first]);
+
+		SyncCryptoStore::sr25519_generate_new(&*store, ED25519, None).unwrap();
+		SyncCryptoStore::sr25519_generate_new(&*store, ED25519, None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&*store, ED25519).len(), 2);
+	}
+
+	#[test]
+	fn age_identity_is_stable_per_key() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let first = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let second = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+
+		let identity = store.age_identity_from(ED25519, first.as_ref()).unwrap();
+		let reopened = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let again = reopened.age_identity_from(ED25519, first.as_ref()).unwrap();
+		assert_eq!(identity.to_secret_string(), again.to_secret_string());
+		assert_ne!(
+			identity.public_key(),
+			store.age_identity_from(SR25519, second.as_ref()).unwrap().public_key(),
+		);
+		assert!(matches!(store.age_identity_from(ED25519, &[0; 32]), Err(Error::PairNotFound)));
+	}
+
+	#[test]
+	fn locked_keystore_does_not_sign() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = || SecretString::new("password".into());
+		let store = LocalKeystore::open(temp_dir.path(), Some(password())).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let sign = || SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"msg");
+
+		store.lock();
+		assert!(matches!(sign(), Err(TraitError::Unavailable)));
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, ED25519), vec![public]);
+		assert!(matches!(
+			store.unlock(&SecretString::new("wrong".into())),
+			Err(Error::InvalidPassword),
+		));
+		assert!(matches!(sign(), Err(TraitError::Unavailable)));
+
+		store.unlock(&password()).unwrap();
+		assert!(sign().unwrap().is_some());
+	}
+
+	#[test]
+	fn import_backup_rejects_keys_not_deriving_from_their_secret() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = SecretString::new("backup".into());
+		let entries = [
+			backup::BackupEntry {
+				key_type: ED25519,
+				public: vec![1; 32],
+				suri: Zeroizing::new("//Bob".into()),
+			},
+		];
+		let damaged = backup::seal(&entries, &password, false).unwrap();
+
+		let target = LocalKeystore::open(temp_dir.path().join("target"), None).unwrap();
+		match target.import_backup(&damaged, &password, OverwritePolicy::Overwrite) {
+			Err(Error::CorruptKeyFile { key_type, public }) => {
+				assert_eq!((key_type, public), (ED25519, vec![1; 32]));
+			},
+			_ => panic!("Import must fail"),
+		}
+
+		// Keys of a keystore with another password don't derive with the target's password.
+		let protected = temp_dir.path().join("protected");
+		let other = LocalKeystore::open(protected, Some(FromStr::from_str("other").unwrap()));
+		let other = other.unwrap();
+		let alice = SyncCryptoStore::sr25519_generate_new(&other, SR25519, None).unwrap();
+		let backup = other.export_backup(&password, false).unwrap();
+		assert!(matches!(
+			target.import_backup(&backup, &password, OverwritePolicy::Overwrite),
+			Err(Error::CorruptKeyFile { .. }),
+		));
+		assert!(!SyncCryptoStore::has_keys(&target, &[(alice.to_vec(), SR25519)]));
+		assert!(SyncCryptoStore::keys(&target, SR25519).unwrap().is_empty());
+	}
+
+	#[test]
+	fn oversized_messages_are_not_signed() {
+		let store = LocalKeystore::in_memory();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		store.set_max_sign_message_len(4);
+
+		assert!(SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"four")
+			.unwrap()
+			.is_some());
+		assert!(matches!(
+			SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"five!"),
+			Err(TraitError::ValidationError(_)),
+		));
+		assert!(matches!(
+			store.0.read().check_sign_hook(ED25519, &public.into(), b"five!"),
+			Err(Error::MessageTooLarge { len: 5, max: 4 }),
+		));
+	}
+
+	#[test]
+	fn keys_are_transferred_between_keystores() {
+		let temp_dir = TempDir::new().unwrap();
+		let source = LocalKeystore::in_memory();
+		let destination = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&source, SR25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+
+		let (challenge, state) = destination.begin_receive();
+		let response = source.respond(&challenge, SR25519, &key).unwrap();
+		assert_eq!(destination.complete_receive(state, &response).unwrap(), key);
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&destination, SR25519), vec![public]);
+
+		let missing = CryptoTypePublicPair(sr25519::CRYPTO_ID, vec![1; 32]);
+		assert!(matches!(source.respond(&challenge, SR25519, &missing), Err(Error::PairNotFound)));
+	}
+
+	#[test]
+	fn generate_default_uses_the_default_scheme() {
+		let store = LocalKeystore::in_memory();
+		let sr25519 = store.generate_default(SR25519).unwrap();
+		assert_eq!(sr25519.0, sr25519::CRYPTO_ID);
+
+		store.set_default_scheme(ed25519::CRYPTO_ID);
+		let ed25519 = store.generate_default(ED25519).unwrap();
+		assert_eq!(ed25519.0, ed25519::CRYPTO_ID);
+		assert!(SyncCryptoStore::has_keys(&store, &[(ed25519.1, ED25519)]));
+
+		store.set_default_scheme(CryptoTypeId(*b"none"));
+		assert!(matches!(store.generate_default(ED25519), Err(Error::KeyNotSupported(_))));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn open_at_uses_the_directory_of_the_file_descriptor() {
+		use std::os::unix::io::AsRawFd;
+
+		let temp_dir = TempDir::new().unwrap();
+		let dir = File::open(temp_dir.path()).unwrap();
+		let store = LocalKeystoreBuilder::at(dir.as_raw_fd()).open().unwrap();
+		drop(dir);
+
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let reopened = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&reopened, ED25519), vec![public]);
+
+		// The directory is found through the file descriptor after it was moved away.
+		let moved = TempDir::new().unwrap();
+		let path = moved.path().join("keystore");
+		fs::rename(temp_dir.path(), &path).unwrap();
+		let other = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let signature = SyncCryptoStore::sign_with(&store, ED25519, &other.into(), b"msg").unwrap();
+		assert!(signature.is_some());
+		SyncCryptoStore::remove_key(&store, ED25519, &public.into()).unwrap();
+		let reopened = LocalKeystore::open(&path, None).unwrap();
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&reopened, ED25519), vec![other]);
+		fs::create_dir(temp_dir.path()).unwrap();
+
+		let file = File::create(temp_dir.path().join("file")).unwrap();
+		assert!(matches!(
+			LocalKeystoreBuilder::at(file.as_raw_fd()).open(),
+			Err(Error::NotADirectory(_)),
+		));
+	}
+
+	#[test]
+	fn vrf_signatures_are_computed_concurrently() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = Arc::new(LocalKeystore::open(temp_dir.path(), None).unwrap());
+		// More threads may sign at once than there are cores.
+		store.set_max_concurrent_signs(None);
+		let public = SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap();
+		let transcript = || VRFTranscriptData { label: b"Test", items: vec![] };
+		let expected = SyncCryptoStore::sr25519_vrf_sign(&*store, SR25519, &public, transcript())
+			.unwrap()
+			.unwrap();
+
+		let threads = (0..4).map(|_| {
+			let store = store.clone();
+			std::thread::spawn(move || (0..8).map(|_| {
+				SyncCryptoStore::sr25519_vrf_sign(&*store, SR25519, &public, transcript())
+					.unwrap()
+					.unwrap()
+			}).collect::<Vec<_>>())
+		}).collect::<Vec<_>>();
+		let signatures = threads.into_iter()
+			.flat_map(|thread| thread.join().unwrap())
+			.map(|signature| (public, transcript(), signature))
+			.collect::<Vec<_>>();
+
+		assert!(signatures.iter().all(|(_, _, signature)| signature.output == expected.output));
+		assert!(sp_keystore::vrf::batch_verify_vrf(&signatures).into_iter().all(|valid| valid));
+	}
+
+	#[test]
+	fn errors_are_mirrored_to_the_error_sink() {
+		let store = LocalKeystore::in_memory();
+		let (sink, mut events) = mpsc::channel(0);
+		store.set_error_sink(sink);
+		let missing = CryptoTypePublicPair(ed25519::CRYPTO_ID, vec![1; 32]);
+
+		// More errors than fit into the channel, none of them blocks.
+		for _ in 0..4 {
+			assert!(matches!(
+				SyncCryptoStore::remove_key(&store, ED25519, &missing),
+				Err(TraitError::PairNotFound),
+			));
+		}
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let event = events.try_next().unwrap().unwrap();
+		assert_eq!(event.operation, "remove_key");
+		assert_eq!(event.key_type, Some(ED25519));
+		assert_eq!(event.error, TraitError::PairNotFound.to_string());
+		while let Ok(Some(event)) = events.try_next() {
+			assert_eq!(event.operation, "remove_key");
+		}
+	}
+
+	#[test]
+	fn ecdsa_derive_bip32_matches_wallets() {
+		let store = LocalKeystore::in_memory();
+		let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+			abandon abandon about";
+		let master = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, Some(phrase)).unwrap();
+
+		let child = store.ecdsa_derive_bip32(ECDSA, &master, "m/44'/60'/0'/0/0").unwrap();
+		// The first Ethereum account of the phrase.
+		let secret = "1ab42cc412b618bdea3a599e3c9bae199ebf030895b039e9db1e30dafb12b727";
+		let expected = ecdsa::Pair::from_seed_slice(&hex::decode(secret).unwrap()).unwrap();
+		assert_eq!(child, expected.public());
+		assert!(SyncCryptoStore::has_keys(&store, &[(child.to_raw_vec(), ECDSA)]));
+
+		assert!(matches!(
+			store.ecdsa_derive_bip32(ECDSA, &master, "44'/60'"),
+			Err(Error::InvalidPhrase(_)),
+		));
+		assert!(matches!(
+			store.ecdsa_derive_bip32(ECDSA, &child, "m/0"),
+			Err(Error::InvalidPhrase(_)),
+		));
+	}
+
+	#[test]
+	fn forked_process_can_not_change_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		// Pretend the keystore was opened by the parent of a fork.
+		store.0.write().opened_by = std::process::id().wrapping_add(1);
+
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg").unwrap().is_some());
+		assert!(matches!(
+			store.generate_default(SR25519),
+			Err(Error::ForkedProcess { current, .. }) if current == std::process::id(),
+		));
+		assert!(SyncCryptoStore::remove_key(&store, SR25519, &public.into()).is_err());
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		SyncCryptoStore::remove_key(&store, SR25519, &public.into()).unwrap();
+	}
+
+	#[test]
+	fn which_keys_present_keeps_the_order_of_the_queries() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let missing = ed25519::Pair::generate().0.public();
+
+		let queries = [
+			(missing.to_vec(), ED25519),
+			(sr25519.to_vec(), SR25519),
+			(sr25519.to_vec(), ED25519),
+			(ed25519.to_vec(), ED25519),
+		];
+		let present = vec![false, true, false, true];
+		assert_eq!(SyncCryptoStore::which_keys_present(&store, &queries), present);
+		assert_eq!(block_on(CryptoStore::which_keys_present(&store, &queries)), present);
+		assert!(SyncCryptoStore::which_keys_present(&store, &[]).is_empty());
+	}
+
+	#[test]
+	fn key_files_are_read_on_demand() {
+		let temp_dir = TempDir::new().unwrap();
+		// Key files that fail to parse once they are read.
+		for i in 0..500u32 {
+			let name = format!("{}{}{:056x}", hex::encode(SR25519.0), "00".repeat(4), i);
+			let path = temp_dir.path().join(name);
+			fs::write(&path, b"\"unterminated").unwrap();
+			#[cfg(unix)]
+			fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600))
+				.unwrap();
+		}
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(store.unreadable_keys(SR25519).unwrap().len(), 500);
+		assert!(SyncCryptoStore::sr25519_public_keys(&store, SR25519).is_empty());
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		assert_eq!(store.prefetch(&[ED25519]).unwrap(), 1);
+		assert!(SyncCryptoStore::sign_with(&store, ED25519, &public.into(), b"msg").is_ok());
+		assert!(matches!(store.prefetch(&[SR25519]), Err(Error::MalformedKeyFile { .. })));
+	}
+
+	#[test]
+	fn secure_delete_overwrites_key_files() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		store.set_secure_delete(true);
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		// An open file keeps its content readable after it is unlinked.
+		let mut open = File::open(&file).unwrap();
+
+		SyncCryptoStore::remove_key(&store, SR25519, &public.into()).unwrap();
+		assert!(!file.exists());
+		let mut content = Vec::new();
+		std::io::Read::read_to_end(&mut open, &mut content).unwrap();
+		assert!(!content.is_empty());
+		assert!(content.iter().all(|byte| *byte == 0));
+
+		let snapshot = store.debug_snapshot().unwrap();
+		assert!(snapshot.secure_delete);
+		if cfg!(target_os = "linux") {
+			assert!(snapshot.copy_on_write.is_some());
+		}
+	}
+
+	#[test]
+	fn password_shares_unlock_the_keystore() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = SecretString::from_str("split password").unwrap();
+		let public = {
+			let store = LocalKeystore::open(temp_dir.path(), Some(password.clone())).unwrap();
+			SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap()
+		};
+		let shares = crate::split_password(&password, 2, 3).unwrap();
+
+		let store = LocalKeystoreBuilder::new(temp_dir.path()).password_shares(2).open().unwrap();
+		assert!(matches!(store.unlock(&SecretString::from_str("").unwrap()), Err(Error::Locked)));
+		assert!(matches!(
+			SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg"),
+			Err(TraitError::Unavailable),
+		));
+		assert!(SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).is_err());
+		let pending = UnlockProgress::Pending { added: 1, threshold: 2 };
+		assert_eq!(store.add_share(&shares[2]).unwrap(), pending);
+		assert_eq!(store.add_share(&shares[2]).unwrap(), pending);
+		assert!(matches!(store.add_share(&[2, 0]), Err(Error::InvalidShare(_))));
+
+		assert_eq!(store.add_share(&shares[0]).unwrap(), UnlockProgress::Unlocked);
+		let signature = SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg").unwrap();
+		assert!(signature.is_some());
+		assert!(matches!(store.add_share(&shares[1]), Err(Error::InvalidShare(_))));
+	}
+
+	#[test]
+	fn public_for_scheme_derives_without_storing() {
+		let seed = [7u8; 32];
+		assert_eq!(
+			public_for_scheme(&seed, ed25519::CRYPTO_ID).unwrap(),
+			ed25519::Pair::from_seed(&seed).public().to_raw_vec(),
+		);
+		assert_eq!(
+			public_for_scheme(&seed, ecdsa::CRYPTO_ID).unwrap(),
+			ecdsa::Pair::from_seed(&seed).public().to_raw_vec(),
+		);
+		let sr25519 = public_for_scheme(&seed, sr25519::CRYPTO_ID).unwrap();
+		assert_eq!(sr25519, sr25519::Pair::from_seed(&seed).public().to_raw_vec());
+		assert_ne!(sr25519, public_for_scheme(&seed, ed25519::CRYPTO_ID).unwrap());
+
+		let short = &seed[..31];
+		assert!(matches!(public_for_scheme(short, ed25519::CRYPTO_ID), Err(Error::InvalidSeed)));
+		assert!(matches!(
+			public_for_scheme(&seed, CryptoTypeId(*b"none")),
+			Err(Error::UnknownCryptoType(CryptoTypeId(id))) if &id == b"none",
+		));
+	}
+
+	#[test]
+	fn invalidation_callbacks_see_changed_key_types() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let (first, second) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+		for seen in [first.clone(), second.clone()] {
+			store.on_invalidate(Box::new(move |key_type| seen.lock().push(key_type)));
+		}
+
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice")).unwrap();
+		SyncCryptoStore::sr25519_public_keys(&store, SR25519);
+		SyncCryptoStore::remove_key(&store, SR25519, &public.into()).unwrap();
+		assert_eq!(*first.lock(), vec![SR25519, ED25519, SR25519]);
+		assert_eq!(*second.lock(), *first.lock());
+	}
+
+	#[test]
+	fn network_filesystems_are_recognized() {
+		assert!(NETWORK_FILESYSTEMS.iter().any(|(magic, name)| *magic == 0x6969 && *name == "NFS"));
+		assert!(NETWORK_FILESYSTEMS.iter().all(|(magic, _)| *magic != P9_MAGIC));
+		let temp_dir = TempDir::new().unwrap();
+		assert_eq!(network_filesystem(Dir::new(temp_dir.path())), None);
+		LocalKeystore::open(temp_dir.path(), None).unwrap();
+		LocalKeystoreBuilder::new(temp_dir.path()).allow_network_fs().open().unwrap();
+	}
+
+	#[test]
+	fn paused_writes_resume_when_the_guard_is_dropped() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = Arc::new(LocalKeystore::open(temp_dir.path(), None).unwrap());
+		let key = SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap();
+
+		let guard = store.pause_writes();
+		let generating = {
+			let store = store.clone();
+			std::thread::spawn(move || {
+				SyncCryptoStore::ed25519_generate_new(&*store, ED25519, None)
+			})
+		};
+		let signature = SyncCryptoStore::sign_with(&*store, SR25519, &key.into(), b"msg").unwrap();
+		assert!(signature.is_some());
+		std::thread::sleep(Duration::from_millis(50));
+		assert!(SyncCryptoStore::ed25519_public_keys(&*store, ED25519).is_empty());
+		assert_eq!(store.last_used(SR25519, key.as_ref()).unwrap(), None);
+
+		drop(guard);
+		let generated = generating.join().unwrap().unwrap();
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&*store, ED25519), vec![generated]);
+	}
+
+	#[test]
+	fn aliases_are_unique_across_the_keystore() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let alice = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let bob = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let memory = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//M")).unwrap();
 
-		if path.exists() {
-			let file = File::open(path)?;
+		store.add_alias(SR25519, alice.as_ref(), "controller").unwrap();
+		store.add_alias(SR25519, alice.as_ref(), "stash").unwrap();
+		store.add_alias(SR25519, alice.as_ref(), "stash").unwrap();
+		assert_eq!(store.aliases(SR25519, alice.as_ref()).unwrap(), vec!["controller", "stash"]);
+		assert_eq!(store.resolve_alias("stash").unwrap(), Some((SR25519, alice.to_vec())));
+		assert_eq!(store.resolve_alias("grandpa").unwrap(), None);
 
-			serde_json::from_reader(&file).map_err(Into::into).map(Some)
-		} else {
-			Ok(None)
-		}
+		assert!(matches!(
+			store.add_alias(ED25519, bob.as_ref(), "controller"),
+			Err(Error::AliasExists(_)),
+		));
+		assert!(matches!(
+			store.add_alias(ED25519, bob.as_ref(), "a b"),
+			Err(Error::InvalidAlias(_)),
+		));
+		assert!(matches!(
+			store.add_alias(SR25519, memory.as_ref(), "memory"),
+			Err(Error::PairNotFound),
+		));
+
+		SyncCryptoStore::remove_key(&store, SR25519, &alice.into()).unwrap();
+		assert_eq!(store.resolve_alias("controller").unwrap(), None);
+		store.add_alias(ED25519, bob.as_ref(), "controller").unwrap();
 	}
 
-	/// Get a key pair for the given public key and key type.
-	fn key_pair_by_type<Pair: PairT>(
-		&self,
-		public: &Pair::Public,
-		key_type: KeyTypeId,
-	) -> Result<Option<Pair>> {
-		let phrase = if let Some(p) = self.key_phrase_by_type(public.as_slice(), key_type)? {
-			p
-		} else {
-			return Ok(None)
-		};
+	#[test]
+	fn keys_with_unreadable_secrets_are_listed() {
+		let temp_dir = TempDir::new().unwrap();
+		let password = |password: &str| Some(SecretString::new(password.into()));
+		let store = LocalKeystore::open(temp_dir.path(), password("password")).unwrap();
+		let good = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let other = LocalKeystore::open(temp_dir.path(), password("other")).unwrap();
+		let wrong = SyncCryptoStore::sr25519_generate_new(&other, SR25519, None).unwrap();
+		let torn = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let torn_file = store.0.read().key_file_path(torn.as_ref(), SR25519).unwrap();
+		fs::write(torn_file, "\"trunc").unwrap();
 
-		let pair = Pair::from_string(
-			&phrase,
-			self.password(),
-		).map_err(|_| Error::InvalidPhrase)?;
+		// The key file that fails to parse is left out of the listing.
+		let mut listed = SyncCryptoStore::sr25519_public_keys(&store, SR25519);
+		listed.sort();
+		let mut all = vec![good, wrong];
+		all.sort();
+		assert_eq!(listed, all);
+		let mut unreadable = store.unreadable_keys(SR25519).unwrap();
+		unreadable.sort();
+		let mut expected = vec![wrong.to_vec(), torn.to_vec()];
+		expected.sort();
+		assert_eq!(unreadable, expected);
 
-		if &pair.public() == public {
-			Ok(Some(pair))
-		} else {
-			Err(Error::InvalidPassword)
-		}
+		assert!(store.prefetch(&[SR25519]).is_err());
+		store.set_decrypt_failure_policy(DecryptFailurePolicy::SkipAndWarn);
+		assert_eq!(store.prefetch(&[SR25519]).unwrap(), 1);
+		let backup_password = SecretString::new("backup".into());
+		let backup = store.export_backup(&backup_password, false).unwrap();
+		let restored_dir = TempDir::new().unwrap();
+		let restored = LocalKeystore::open(restored_dir.path(), password("password")).unwrap();
+		restored.import_backup(&backup, &backup_password, OverwritePolicy::Skip).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&restored, SR25519), vec![good]);
 	}
 
-	/// Get the file path for the given public key and key type.
-	///
-	/// Returns `None` if the keystore only exists in-memory and there isn't any path to provide.
-	fn key_file_path(&self, public: &[u8], key_type: KeyTypeId) -> Option<PathBuf> {
-		let mut buf = self.path.as_ref()?.clone();
-		let key_type = hex::encode(key_type.0);
-		let key = hex::encode(public);
-		buf.push(key_type + key.as_str());
-		Some(buf)
-	}
+	#[test]
+	fn vanity_keys_have_the_address_prefix() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let network = Ss58AddressFormat::SubstrateAccount;
 
-	/// Returns a list of raw public keys filtered by `KeyTypeId`
-	fn raw_public_keys(&self, id: KeyTypeId) -> Result<Vec<Vec<u8>>> {
-		let mut public_keys: Vec<Vec<u8>> = self.additional.keys()
-			.into_iter()
-			.filter_map(|k| if k.0 == id { Some(k.1.clone()) } else { None })
-			.collect();
+		// Substrate addresses start with 5, followed by one of a few characters.
+		let public = store.generate_vanity(SR25519, sr25519::CRYPTO_ID, "5", network, 10).unwrap();
+		let public = sr25519::Public::from_slice(&public.1);
+		assert!(public.to_ss58check_with_version(network).starts_with('5'));
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
 
-		if let Some(path) = &self.path {
-			for entry in fs::read_dir(&path)? {
-				let entry = entry?;
-				let path = entry.path();
-
-				// skip directories and non-unicode file names (hex is unicode)
-				if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-					match hex::decode(name) {
-						Ok(ref hex) if hex.len() > 4 => {
-							if &hex[0..4] != &id.0 {
-								continue;
-							}
-							let public = hex[4..].to_vec();
-							public_keys.push(public);
-						}
-						_ => continue,
-					}
-				}
-			}
+		assert!(matches!(
+			store.generate_vanity(ED25519, ed25519::CRYPTO_ID, "0", network, 1000),
+			Err(Error::VanityNotFound { attempts: 1000 }),
+		));
+		assert!(matches!(
+			store.generate_vanity(ED25519, ed25519::CRYPTO_ID, "5zzzzzzzzz", network, 1000),
+			Err(Error::VanityNotFound { .. }),
+		));
+		assert!(SyncCryptoStore::ed25519_public_keys(&store, ED25519).is_empty());
+	}
+
+	#[test]
+	fn public_keys_reused_across_key_types_are_found() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let reused = sr25519::Pair::from_string("//Alice", None).unwrap().public();
+		for key_type in [ED25519, SR25519, TEST_KEY_TYPE] {
+			SyncCryptoStore::insert_unknown(&store, key_type, "//Alice", reused.as_ref()).unwrap();
 		}
+		SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		SyncCryptoStore::sr25519_generate_new(&store, ED25519, None).unwrap();
 
-		Ok(public_keys)
+		let mut key_types = vec![ED25519, SR25519, TEST_KEY_TYPE];
+		key_types.sort();
+		assert_eq!(store.find_cross_type_reuse().unwrap(), vec![(reused.to_vec(), key_types)]);
 	}
 
-	/// Get a key pair for the given public key.
-	///
-	/// Returns `Ok(None)` if the key doesn't exist, `Ok(Some(_))` if the key exists or `Err(_)` when
-	/// something failed.
-	pub fn key_pair<Pair: AppPair>(&self, public: &<Pair as AppKey>::Public) -> Result<Option<Pair>> {
-		self.key_pair_by_type::<Pair::Generic>(IsWrappedBy::from_ref(public), Pair::ID)
-			.map(|v| v.map(Into::into))
-	}
-}
+	#[test]
+	fn disk_usage_is_reported_per_kind_of_file() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(LocalKeystore::in_memory().disk_usage().unwrap(), DiskUsage::default());
 
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		store.add_alias(SR25519, public.as_ref(), "validator").unwrap();
+		let key_file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		fs::write(key_file.with_extension(RECOVERY_EXTENSIONS[0]), b"copy").unwrap();
+		fs::write(temp_dir.path().join("readme"), b"unknown").unwrap();
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use tempfile::TempDir;
-	use sp_core::{
-		Pair,
-		crypto::Ss58Codec,
-		testing::SR25519,
-	};
-	use sp_application_crypto::{ed25519, sr25519, AppPublic};
-	use std::{
-		fs,
-		str::FromStr,
-	};
+		let size = |path: PathBuf| fs::metadata(path).unwrap().len();
+		let usage = store.disk_usage().unwrap();
+		assert_eq!(usage.key_files, size(key_file.clone()));
+		assert_eq!(usage.sidecars, size(key_file.with_extension(sidecar::ALIAS_EXTENSION)));
+		assert_eq!(usage.temporary, 4);
+		assert_eq!(usage.other, 7);
+		assert_eq!(
+			usage.by_key_type.into_iter().collect::<Vec<_>>(),
+			vec![(SR25519, usage.key_files + usage.sidecars + 4)],
+		);
+		assert_eq!(usage.total, usage.key_files + usage.sidecars + usage.index + 11);
+	}
 
-	const TEST_KEY_TYPE: KeyTypeId = KeyTypeId(*b"test");
+	#[test]
+	fn kept_keys_are_evicted_when_the_clock_passes_the_window() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let clock = Arc::new(crate::MockClock::new());
+		store.set_clock(clock.clone());
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let sign = || SyncCryptoStore::sign_with(&store, SR25519, &public.into(), b"msg").unwrap();
 
-	impl KeystoreInner {
-		fn insert_ephemeral_from_seed<Pair: AppPair>(&mut self, seed: &str) -> Result<Pair> {
-			self.insert_ephemeral_from_seed_by_type::<Pair::Generic>(seed, Pair::ID).map(Into::into)
-		}
+		store.set_active_window(Duration::from_secs(60));
+		assert!(sign().is_some());
+		let millis = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap().as_millis();
+		let last_used = store.last_used(SR25519, public.as_ref()).unwrap().map(millis);
+		assert_eq!(last_used, Some(millis(clock.system_now())));
+		fs::remove_file(store.0.read().key_file_path(public.as_ref(), SR25519).unwrap()).unwrap();
 
-		fn public_keys<Public: AppPublic>(&self) -> Result<Vec<Public>> {
-			self.raw_public_keys(Public::ID)
-				.map(|v| {
-					v.into_iter()
-						.map(|k| Public::from_slice(k.as_slice()))
-						.collect()
-				})
-		}
+		clock.advance(Duration::from_secs(59));
+		assert!(sign().is_some());
+		clock.advance(Duration::from_secs(61));
+		assert!(sign().is_none());
+	}
 
-		fn generate<Pair: AppPair>(&mut self) -> Result<Pair> {
-			self.generate_by_type::<Pair::Generic>(Pair::ID).map(Into::into)
-		}
+	#[test]
+	fn key_files_are_byte_reproducible() {
+		let suri = "//Alice//\"stash\"//\u{e9}";
+		let public = sr25519::Pair::from_string(suri, None).unwrap().public();
+		let key_file = || {
+			let temp_dir = TempDir::new().unwrap();
+			let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+			SyncCryptoStore::insert_unknown(&store, SR25519, suri, public.as_ref()).unwrap();
+			let path = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+			fs::read(path).unwrap()
+		};
+
+		let content = key_file();
+		assert_eq!(content, key_file());
+		assert_eq!(content, serde_json::to_vec(suri).unwrap());
+		assert!(!content.iter().any(u8::is_ascii_whitespace));
 	}
 
 	#[test]
-	fn basic_store() {
+	fn encrypted_aliases_need_the_password() {
 		let temp_dir = TempDir::new().unwrap();
-		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
-
-		assert!(store.public_keys::<ed25519::AppPublic>().unwrap().is_empty());
+		let open = |password: &str| {
+			LocalKeystore::open(temp_dir.path(), Some(SecretString::new(password.into()))).unwrap()
+		};
+		let store = open("password");
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		store.add_alias(SR25519, public.as_ref(), "cold-storage-controller").unwrap();
+		let path = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap()
+			.with_extension(sidecar::ALIAS_EXTENSION);
 
-		let key: ed25519::AppPair = store.generate().unwrap();
-		let key2: ed25519::AppPair = store.key_pair(&key.public()).unwrap().unwrap();
+		store.set_encrypt_aliases(true).unwrap();
+		store.add_alias(SR25519, public.as_ref(), "stash").unwrap();
+		let content = fs::read(&path).unwrap();
+		assert!(!String::from_utf8_lossy(&content).contains("controller"));
+		assert_eq!(
+			store.aliases(SR25519, public.as_ref()).unwrap(),
+			vec!["cold-storage-controller".to_string(), "stash".into()],
+		);
+		let owner = Some((SR25519, public.to_vec()));
+		assert_eq!(store.resolve_alias("stash").unwrap(), owner);
 
-		assert_eq!(key.public(), key2.public());
+		let other = open("other password");
+		assert!(matches!(other.aliases(SR25519, public.as_ref()), Err(Error::InvalidPassword)));
+		assert!(matches!(
+			LocalKeystore::in_memory().set_encrypt_aliases(true),
+			Err(Error::InvalidPassword),
+		));
 
-		assert_eq!(store.public_keys::<ed25519::AppPublic>().unwrap()[0], key.public());
+		store.set_encrypt_aliases(false).unwrap();
+		assert!(String::from_utf8(fs::read(&path).unwrap()).unwrap().contains("stash"));
 	}
 
 	#[test]
-	fn has_keys_works() {
+	fn derived_keys_are_recovered_from_the_master_phrase() {
 		let temp_dir = TempDir::new().unwrap();
 		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		store.set_hd_root("//Alice").unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let (random, _) = store.generate_with_phrase(SR25519, sr25519::CRYPTO_ID).unwrap();
 
-		let key: ed25519::AppPair = store.0.write().generate().unwrap();
-		let key2 = ed25519::Pair::generate().0;
+		let index = store.recovery_index().unwrap();
+		let mut keys = index.keys.clone();
+		keys.sort();
+		let mut expected = vec![
+			(SR25519, sr25519.to_vec(), Some("//0".to_string())),
+			(ED25519, ed25519.to_vec(), Some("//1".to_string())),
+			(SR25519, random.1.clone(), None),
+		];
+		expected.sort();
+		assert_eq!(keys, expected);
+		let index = RecoveryIndex::decode(&mut &index.encode()[..]).unwrap();
 
-		assert!(
-			!SyncCryptoStore::has_keys(&store, &[(key2.public().to_vec(), ed25519::AppPublic::ID)])
-		);
+		let recovered_dir = TempDir::new().unwrap();
+		let recover = |phrase| {
+			LocalKeystore::recover_from_phrase(recovered_dir.path(), phrase, &index, None)
+		};
+		assert!(matches!(recover("//Bob"), Err(Error::PublicKeyMismatch { .. })));
+		assert_eq!(recover("//Alice").unwrap(), 2);
+		let recovered = LocalKeystore::open(recovered_dir.path(), None).unwrap();
+		assert!(SyncCryptoStore::has_key_exact(&recovered, SR25519, sr25519.as_ref()));
+		assert!(SyncCryptoStore::has_key_exact(&recovered, ED25519, ed25519.as_ref()));
+		assert!(!SyncCryptoStore::has_key_exact(&recovered, SR25519, &random.1));
+		assert_eq!(recover("//Alice").unwrap(), 2);
+	}
 
-		assert!(
-			!SyncCryptoStore::has_keys(
-				&store,
-				&[
-					(key2.public().to_vec(), ed25519::AppPublic::ID),
-					(key.public().to_raw_vec(), ed25519::AppPublic::ID),
-				],
-			)
-		);
+	#[test]
+	fn remove_keys_removes_a_batch_of_keys() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let second = SyncCryptoStore::ecdsa_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
+		let kept = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
 
-		assert!(
-			SyncCryptoStore::has_keys(&store, &[(key.public().to_raw_vec(), ed25519::AppPublic::ID)])
-		);
+		let items = [
+			(SR25519, first.to_vec()),
+			(SR25519, vec![0; 32]),
+			(TEST_KEY_TYPE, second.0.to_vec()),
+		];
+		let results = SyncCryptoStore::remove_keys(&store, &items);
+		assert!(results[0].is_ok());
+		assert!(matches!(results[1], Err(TraitError::PairNotFound)));
+		assert!(results[2].is_ok());
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![kept]);
+		assert!(SyncCryptoStore::ecdsa_public_keys(&store, TEST_KEY_TYPE).is_empty());
 	}
 
 	#[test]
-	fn test_insert_ephemeral_from_seed() {
+	fn concurrent_metadata_updates_are_not_lost() {
 		let temp_dir = TempDir::new().unwrap();
-		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+		let store = Arc::new(LocalKeystore::open(temp_dir.path(), None).unwrap());
+		let key = SyncCryptoStore::sr25519_generate_new(&*store, SR25519, None).unwrap();
+		assert_eq!(store.key_metadata(SR25519, key.as_ref()).unwrap(), KeyMetadata::default());
 
-		let pair: ed25519::AppPair = store.insert_ephemeral_from_seed(
-			"0x3d97c819d68f9bafa7d6e79cb991eebcd77d966c5334c0b94d9e1fa7ad0869dc"
-		).unwrap();
-		assert_eq!(
-			"5DKUrgFqCPV8iAXx9sjy1nyBygQCeiUYRFWurZGhnrn3HJCA",
-			pair.public().to_ss58check()
-		);
+		let threads = (0..4).map(|thread| {
+			let store = store.clone();
+			std::thread::spawn(move || for i in 0..10 {
+				store.update_metadata(SR25519, key.as_ref(), |metadata| {
+					metadata.tags.push(format!("{}-{}", thread, i));
+				}).unwrap();
+			})
+		}).collect::<Vec<_>>();
+		threads.into_iter().for_each(|thread| thread.join().unwrap());
+		store.update_metadata(SR25519, key.as_ref(), |metadata| {
+			metadata.provenance = Some("ceremony".into());
+		}).unwrap();
 
-		drop(store);
-		let store = KeystoreInner::open(temp_dir.path(), None).unwrap();
-		// Keys generated from seed should not be persisted!
-		assert!(store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().is_none());
+		let metadata = store.key_metadata(SR25519, key.as_ref()).unwrap();
+		assert_eq!(metadata.tags.len(), 40);
+		assert_eq!(metadata.provenance.as_deref(), Some("ceremony"));
+		assert!(matches!(
+			store.update_metadata(SR25519, &[0; 32], |_| ()),
+			Err(Error::PairNotFound),
+		));
 	}
 
 	#[test]
-	fn password_being_used() {
-		let password = String::from("password");
+	fn sign_with_many_signs_every_message_with_the_key() {
 		let temp_dir = TempDir::new().unwrap();
-		let mut store = KeystoreInner::open(
-			temp_dir.path(),
-			Some(FromStr::from_str(password.as_str()).unwrap()),
-		).unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+		let msgs: [&[u8]; 3] = [b"first", b"second", b"third"];
 
-		let pair: ed25519::AppPair = store.generate().unwrap();
-		assert_eq!(
-			pair.public(),
-			store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().unwrap().public(),
-		);
+		let signatures = SyncCryptoStore::sign_with_many(&store, SR25519, &key, &msgs).unwrap();
+		assert_eq!(signatures.len(), 3);
+		for (msg, signature) in msgs.iter().zip(&signatures) {
+			let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+			assert!(sr25519::Pair::verify(&signature, msg, &public));
+		}
 
-		// Without the password the key should not be retrievable
-		let store = KeystoreInner::open(temp_dir.path(), None).unwrap();
-		assert!(store.key_pair::<ed25519::AppPair>(&pair.public()).is_err());
+		let absent = CryptoTypePublicPair::from(sr25519::Pair::generate().0.public());
+		assert!(matches!(
+			SyncCryptoStore::sign_with_many(&store, SR25519, &absent, &msgs),
+			Err(TraitError::PairNotFound),
+		));
+	}
 
-		let store = KeystoreInner::open(
-			temp_dir.path(),
-			Some(FromStr::from_str(password.as_str()).unwrap()),
-		).unwrap();
-		assert_eq!(
-			pair.public(),
-			store.key_pair::<ed25519::AppPair>(&pair.public()).unwrap().unwrap().public(),
-		);
+	#[test]
+	fn interrupted_key_file_writes_leave_no_key_behind() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let file = store.0.read().key_file_path(public.as_ref(), SR25519).unwrap();
+		assert!(file.is_file() && !file.with_extension("tmp").exists());
+
+		// A new key whose write was cut short before it was moved into place.
+		let torn = sr25519::Pair::generate().0.public();
+		let torn_file = store.0.read().key_file_path(torn.as_ref(), SR25519).unwrap();
+		fs::write(torn_file.with_extension("tmp"), "\"trunc").unwrap();
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert!(store.key_pair::<sr25519::AppPair>(&torn.into()).unwrap().is_none());
 	}
 
 	#[test]
-	fn public_keys_are_returned() {
+	fn keys_not_in_keeps_the_keys_of_the_authorities() {
 		let temp_dir = TempDir::new().unwrap();
-		let mut store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let authority = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let orphan = SyncCryptoStore::ed25519_generate_new(&store, SR25519, None).unwrap();
 
-		let mut keys = Vec::new();
-		for i in 0..10 {
-			keys.push(store.generate::<ed25519::AppPair>().unwrap().public());
-			keys.push(store.insert_ephemeral_from_seed::<ed25519::AppPair>(
-				&format!("0x3d97c819d68f9bafa7d6e79cb991eebcd7{}d966c5334c0b94d9e1fa7ad0869dc", i),
-			).unwrap().public());
+		let orphans =
+			SyncCryptoStore::keys_not_in(&store, SR25519, &[authority.into()]).unwrap();
+		assert_eq!(orphans, vec![CryptoTypePublicPair::from(orphan)]);
+		for key in &orphans {
+			SyncCryptoStore::remove_key(&store, SR25519, key).unwrap();
 		}
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![authority]);
+	}
 
-		// Generate a key of a different type
-		store.generate::<sr25519::AppPair>().unwrap();
+	#[test]
+	fn keys_by_type_lists_every_key_under_its_crypto_type() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, SR25519, None).unwrap();
 
-		keys.sort();
-		let mut store_pubs = store.public_keys::<ed25519::AppPublic>().unwrap();
-		store_pubs.sort();
+		let grouped = SyncCryptoStore::keys_by_type(&store, SR25519).unwrap();
+		assert_eq!(grouped.len(), 2);
+		assert_eq!(grouped[&sr25519::CRYPTO_ID], vec![sr25519.to_raw_vec()]);
+		assert_eq!(grouped[&ecdsa::CRYPTO_ID], vec![ecdsa.to_raw_vec()]);
+	}
 
-		assert_eq!(keys, store_pubs);
+	#[test]
+	fn ensure_session_keys_only_uses_keys_of_the_crypto_type() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let gran = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let keys = SyncCryptoStore::ensure_session_keys(&store, &[(ED25519, sr25519::CRYPTO_ID)])
+			.unwrap();
+		assert_ne!(keys[0].1, gran.to_raw_vec());
+		let sr25519 = sr25519::Public::from_slice(&keys[0].1);
+		assert!(SyncCryptoStore::has_keys(&store, &[(sr25519.to_raw_vec(), ED25519)]));
+
+		// With an sr25519 and an ed25519 key, each crypto type has a single key.
+		let spec = [(ED25519, sr25519::CRYPTO_ID), (ED25519, ed25519::CRYPTO_ID)];
+		assert_eq!(
+			SyncCryptoStore::ensure_session_keys(&store, &spec).unwrap(),
+			vec![(ED25519, sr25519.to_raw_vec()), (ED25519, gran.to_raw_vec())],
+		);
 	}
 
 	#[test]
-	fn store_unknown_and_extract_it() {
+	fn sign_with_ss58_signs_with_ed25519_keys() {
 		let temp_dir = TempDir::new().unwrap();
-		let store = KeystoreInner::open(temp_dir.path(), None).unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
 
-		let secret_uri = "//Alice";
-		let key_pair = sr25519::AppPair::from_string(secret_uri, None).expect("Generates key pair");
+		let address = public.to_ss58check();
+		let signature = SyncCryptoStore::sign_with_ss58(&store, ED25519, &address, b"msg").unwrap();
+		let signature = ed25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ed25519::Pair::verify(&signature, b"msg", &public));
+	}
 
-		store.insert_unknown(
-			SR25519,
-			secret_uri,
-			key_pair.public().as_ref(),
-		).expect("Inserts unknown key");
+	#[test]
+	fn sign_with_prefix_reports_why_it_can_not_sign() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let prefix = &public.0[..4];
 
-		let store_key_pair = store.key_pair_by_type::<sr25519::AppPair>(
-			&key_pair.public(),
-			SR25519,
-		).expect("Gets key pair from keystore").unwrap();
+		let (full, signature) =
+			SyncCryptoStore::sign_with_prefix(&store, ED25519, prefix, b"msg").unwrap();
+		assert_eq!(full, public.to_raw_vec());
+		let signature = ed25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ed25519::Pair::verify(&signature, b"msg", &public));
 
-		assert_eq!(key_pair.public(), store_key_pair.public());
+		store.lock();
+		assert!(matches!(
+			SyncCryptoStore::sign_with_prefix(&store, ED25519, prefix, b"msg"),
+			Err(TraitError::Unavailable),
+		));
 	}
 
 	#[test]
-	fn store_ignores_files_with_invalid_name() {
+	fn sign_any_fails_on_a_locked_keystore() {
 		let temp_dir = TempDir::new().unwrap();
 		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
 
-		let file_name = temp_dir.path().join(hex::encode(&SR25519.0[..2]));
-		fs::write(file_name, "test").expect("Invalid file is written");
+		let (key, _) = SyncCryptoStore::sign_any(&store, ED25519, b"msg").unwrap();
+		assert_eq!(key, CryptoTypePublicPair::from(public));
+		store.lock();
+		assert!(matches!(
+			SyncCryptoStore::sign_any(&store, ED25519, b"msg"),
+			Err(TraitError::Unavailable),
+		));
+	}
 
-		assert!(
-			SyncCryptoStore::sr25519_public_keys(&store, SR25519).is_empty(),
+	#[test]
+	fn truncated_key_files_are_not_listed() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let truncated = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let file = store.0.read().key_file_path(truncated.as_ref(), SR25519).unwrap();
+		let content = fs::read(&file).unwrap();
+		fs::write(&file, &content[..content.len() / 2]).unwrap();
+
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![public]);
+		assert_eq!(
+			SyncCryptoStore::keys(&store, SR25519).unwrap(),
+			vec![
+				CryptoTypePublicPair(sr25519::CRYPTO_ID, public.to_vec()),
+				CryptoTypePublicPair(ed25519::CRYPTO_ID, public.to_vec()),
+				CryptoTypePublicPair(ecdsa::CRYPTO_ID, public.to_vec()),
+			],
 		);
+		assert_eq!(store.unreadable_keys(SR25519).unwrap(), vec![truncated.to_vec()]);
 	}
 
 	#[test]
-	fn generate_with_seed_is_not_stored() {
+	fn fuse_subtypes_are_read_from_the_mount_table() {
+		let mountinfo = "\
+			22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+			40 22 0:35 / /mnt/remote rw,nosuid shared:20 - fuse.sshfs host:/keys rw,user_id=0\n\
+			41 22 0:36 / /mnt/old rw,nosuid shared:21 - fuse sshfs#host:/keys rw,user_id=0\n\
+			42 22 0:37 / /mnt/with\\040space rw shared:22 - fuse.gocryptfs /crypt rw\n\
+			43 40 0:38 / /mnt/remote/local rw shared:23 - tmpfs tmpfs rw\n";
+		let subtype = |dir: &str| fuse_subtype(mountinfo, Path::new(dir));
+
+		assert_eq!(subtype("/mnt/remote/keystore").as_deref(), Some("sshfs"));
+		assert_eq!(subtype("/mnt/old/keystore").as_deref(), Some("sshfs"));
+		assert_eq!(subtype("/mnt/with space/keystore").as_deref(), Some("gocryptfs"));
+		assert_eq!(subtype("/mnt/remote/local/keystore"), None);
+		assert_eq!(subtype("/mnt/remoteish"), None);
+		assert!(NETWORK_FUSE_FILESYSTEMS.contains(&"sshfs"));
+		assert!(!NETWORK_FUSE_FILESYSTEMS.contains(&"gocryptfs"));
+	}
+
+	#[test]
+	fn prefetched_keys_are_kept_in_memory() {
 		let temp_dir = TempDir::new().unwrap();
 		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
-		let _alice_tmp_key = SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let sign = |id, key: CryptoTypePublicPair| {
+			SyncCryptoStore::sign_with(&store, id, &key, b"msg").unwrap().is_some()
+		};
 
-		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 1);
+		store.set_active_window(Duration::from_secs(3600));
+		assert_eq!(store.prefetch(&[SR25519, ED25519, ECDSA]).unwrap(), 3);
+		for entry in fs::read_dir(temp_dir.path()).unwrap() {
+			fs::remove_file(entry.unwrap().path()).unwrap();
+		}
 
-		drop(store);
-		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
-		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 0);
+		assert!(sign(SR25519, sr25519.into()));
+		assert!(sign(ED25519, ed25519.into()));
+		assert!(sign(ECDSA, ecdsa.into()));
 	}
 
 	#[test]
-	fn generate_can_be_fetched_in_memory() {
-		let store = LocalKeystore::in_memory();
-		SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, Some("//Alice")).unwrap();
+	fn preloaded_keys_are_kept_in_memory() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = LocalKeystore::open(temp_dir.path(), None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let sign = |id, key: CryptoTypePublicPair| {
+			SyncCryptoStore::sign_with(&store, id, &key, b"msg").unwrap().is_some()
+		};
 
-		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 1);
-		SyncCryptoStore::sr25519_generate_new(&store, TEST_KEY_TYPE, None).unwrap();
-		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, TEST_KEY_TYPE).len(), 2);
+		let keys = [(SR25519, sr25519.to_vec()), (ECDSA, ecdsa.0.to_vec())];
+		SyncCryptoStore::preload(&store, &keys).unwrap();
+		store.set_active_window(Duration::from_secs(3600));
+		SyncCryptoStore::preload(&store, &keys).unwrap();
+		for entry in fs::read_dir(temp_dir.path()).unwrap() {
+			fs::remove_file(entry.unwrap().path()).unwrap();
+		}
+
+		assert!(sign(SR25519, sr25519.into()));
+		assert!(sign(ECDSA, ecdsa.into()));
 	}
 }
@@ -0,0 +1,284 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Signing metrics.
+
+use std::{collections::{BTreeMap, HashMap}, fmt::Write, time::Duration};
+use parking_lot::Mutex;
+use sp_core::crypto::KeyTypeId;
+
+/// Receives metrics about the signing operations of a keystore.
+pub trait SigningMetrics: Send + Sync {
+	/// Called after a key of the given key type signed something.
+	///
+	/// `duration` only covers the signing itself, not looking up the key.
+	fn on_sign_complete(&self, id: KeyTypeId, duration: Duration);
+
+	/// Called after a key of the given key type was generated.
+	fn on_generate(&self, _id: KeyTypeId) {}
+
+	/// Called after a keystore operation failed, see [`KeystoreErrorEvent`].
+	fn on_error(&self, _operation: &'static str, _id: Option<KeyTypeId>) {}
+}
+
+/// An error of a keystore operation, see [`LocalKeystore::set_error_sink`].
+///
+/// [`LocalKeystore::set_error_sink`]: crate::LocalKeystore::set_error_sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeystoreErrorEvent {
+	/// The name of the operation, e.g. `sign_with`.
+	pub operation: &'static str,
+	/// The key type of the operation, if it has one.
+	pub key_type: Option<KeyTypeId>,
+	/// The error the operation returned.
+	pub error: String,
+}
+
+/// A snapshot of the signing latencies of a key type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+	/// The upper bounds of the buckets together with the number of signing operations that
+	/// took at most as long. Like prometheus buckets, the counts are cumulative.
+	pub buckets: Vec<(Duration, u64)>,
+	/// The number of signing operations.
+	pub count: u64,
+	/// The total time spent signing.
+	pub sum: Duration,
+}
+
+/// [`SigningMetrics`] that maintain a histogram of the signing latencies per key type.
+///
+/// Also counts the generated keys per key type and the errors per operation and key type, see
+/// [`SigningHistogram::render_openmetrics`].
+pub struct SigningHistogram {
+	bounds: Vec<Duration>,
+	histograms: Mutex<HashMap<KeyTypeId, Histogram>>,
+	generated: Mutex<BTreeMap<KeyTypeId, u64>>,
+	errors: Mutex<BTreeMap<(&'static str, Option<KeyTypeId>), u64>>,
+}
+
+struct Histogram {
+	/// Non-cumulative counts per bucket, the last one counts operations above all bounds.
+	counts: Vec<u64>,
+	sum: Duration,
+}
+
+impl SigningHistogram {
+	/// Create histograms with buckets of the given upper bounds.
+	///
+	/// The bounds are sorted and deduplicated.
+	pub fn new(mut bounds: Vec<Duration>) -> Self {
+		bounds.sort();
+		bounds.dedup();
+		Self {
+			bounds,
+			histograms: Mutex::new(HashMap::new()),
+			generated: Mutex::new(BTreeMap::new()),
+			errors: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	/// A snapshot of the histogram of the given key type.
+	///
+	/// Returns `None` if no key of the key type signed anything yet.
+	pub fn snapshot(&self, id: KeyTypeId) -> Option<HistogramSnapshot> {
+		let histograms = self.histograms.lock();
+		let histogram = histograms.get(&id)?;
+
+		let mut total = 0;
+		let buckets = self.bounds.iter()
+			.zip(&histogram.counts)
+			.map(|(bound, count)| {
+				total += count;
+				(*bound, total)
+			})
+			.collect();
+
+		Some(HistogramSnapshot {
+			buckets,
+			count: histogram.counts.iter().sum(),
+			sum: histogram.sum,
+		})
+	}
+
+	/// Render the metrics in the OpenMetrics text format, ready to be served on `/metrics`.
+	///
+	/// Exposes the counters `keystore_signs`, `keystore_generated_keys` and `keystore_errors`
+	/// and the histogram `keystore_sign_duration_seconds`. Samples are labeled with the key
+	/// type as its four character name, errors also with the operation; no key material ends
+	/// up in the labels.
+	pub fn render_openmetrics(&self) -> String {
+		let mut key_types = self.histograms.lock().keys().copied().collect::<Vec<_>>();
+		key_types.sort();
+		let snapshots = key_types.into_iter()
+			.filter_map(|id| Some((id, self.snapshot(id)?)))
+			.collect::<Vec<_>>();
+
+		let mut out = String::new();
+		family(&mut out, "keystore_signs", "counter", "Signatures produced.");
+		for (id, snapshot) in &snapshots {
+			sample(&mut out, "keystore_signs_total", &[("key_type", label(*id))], snapshot.count);
+		}
+
+		family(&mut out, "keystore_generated_keys", "counter", "Keys generated.");
+		for (id, count) in self.generated.lock().iter() {
+			sample(&mut out, "keystore_generated_keys_total", &[("key_type", label(*id))], count);
+		}
+
+		family(&mut out, "keystore_errors", "counter", "Failed keystore operations.");
+		for ((operation, id), count) in self.errors.lock().iter() {
+			let mut labels = vec![("operation", operation.to_string())];
+			labels.extend(id.map(|id| ("key_type", label(id))));
+			sample(&mut out, "keystore_errors_total", &labels, count);
+		}
+
+		let name = "keystore_sign_duration_seconds";
+		family(&mut out, name, "histogram", "Time spent signing.");
+		for (id, snapshot) in &snapshots {
+			let labels = [("key_type", label(*id))];
+			let bucket = |le: String| [labels[0].clone(), ("le", le)];
+			for (bound, count) in &snapshot.buckets {
+				let le = bound.as_secs_f64().to_string();
+				sample(&mut out, &format!("{}_bucket", name), &bucket(le), count);
+			}
+			let count = snapshot.count;
+			sample(&mut out, &format!("{}_bucket", name), &bucket("+Inf".into()), count);
+			sample(&mut out, &format!("{}_sum", name), &labels, snapshot.sum.as_secs_f64());
+			sample(&mut out, &format!("{}_count", name), &labels, count);
+		}
+
+		out.push_str("# EOF\n");
+		out
+	}
+}
+
+/// Write the metadata of a metric family.
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+}
+
+/// Write a sample with the given labels.
+fn sample(out: &mut String, name: &str, labels: &[(&str, String)], value: impl std::fmt::Display) {
+	let labels = labels.iter()
+		.map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+		.collect::<Vec<_>>();
+	let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+}
+
+/// The label value of a key type, its name if it is printable ASCII and hex encoded otherwise.
+fn label(id: KeyTypeId) -> String {
+	if id.0.iter().all(|byte| byte.is_ascii_graphic()) {
+		id.0.iter().map(|byte| *byte as char).collect()
+	} else {
+		format!("0x{}", hex::encode(id.0))
+	}
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Default for SigningHistogram {
+	/// Buckets from 100µs to 5s, covering in-process signing as well as slow hardware.
+	fn default() -> Self {
+		Self::new(
+			[100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000]
+				.iter()
+				.map(|micros| Duration::from_micros(*micros))
+				.collect(),
+		)
+	}
+}
+
+impl SigningMetrics for SigningHistogram {
+	fn on_sign_complete(&self, id: KeyTypeId, duration: Duration) {
+		let bucket = self.bounds.iter()
+			.position(|bound| duration <= *bound)
+			.unwrap_or(self.bounds.len());
+
+		let mut histograms = self.histograms.lock();
+		let histogram = histograms.entry(id).or_insert_with(|| Histogram {
+			counts: vec![0; self.bounds.len() + 1],
+			sum: Duration::default(),
+		});
+		histogram.counts[bucket] += 1;
+		histogram.sum += duration;
+	}
+
+	fn on_generate(&self, id: KeyTypeId) {
+		*self.generated.lock().entry(id).or_default() += 1;
+	}
+
+	fn on_error(&self, operation: &'static str, id: Option<KeyTypeId>) {
+		*self.errors.lock().entry((operation, id)).or_default() += 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::testing::{ED25519, SR25519};
+
+	#[test]
+	fn histogram_buckets_are_cumulative() {
+		let histogram = SigningHistogram::new(vec![Duration::from_millis(10), Duration::from_millis(1)]);
+		for millis in &[0, 1, 5, 20] {
+			histogram.on_sign_complete(ED25519, Duration::from_millis(*millis));
+		}
+
+		assert_eq!(histogram.snapshot(ED25519), Some(HistogramSnapshot {
+			buckets: vec![(Duration::from_millis(1), 2), (Duration::from_millis(10), 3)],
+			count: 4,
+			sum: Duration::from_millis(26),
+		}));
+		assert_eq!(histogram.snapshot(SR25519), None);
+	}
+
+	#[test]
+	fn metrics_are_rendered_as_openmetrics() {
+		let histogram = SigningHistogram::new(vec![Duration::from_millis(1)]);
+		histogram.on_sign_complete(SR25519, Duration::from_micros(500));
+		histogram.on_sign_complete(SR25519, Duration::from_millis(2));
+		histogram.on_generate(SR25519);
+		histogram.on_error("sign_with", Some(KeyTypeId([0, 1, 2, 3])));
+		histogram.on_error("fingerprint", None);
+
+		assert_eq!(histogram.render_openmetrics(), [
+			"# TYPE keystore_signs counter",
+			"# HELP keystore_signs Signatures produced.",
+			"keystore_signs_total{key_type=\"sr25\"} 2",
+			"# TYPE keystore_generated_keys counter",
+			"# HELP keystore_generated_keys Keys generated.",
+			"keystore_generated_keys_total{key_type=\"sr25\"} 1",
+			"# TYPE keystore_errors counter",
+			"# HELP keystore_errors Failed keystore operations.",
+			"keystore_errors_total{operation=\"fingerprint\"} 1",
+			"keystore_errors_total{operation=\"sign_with\",key_type=\"0x00010203\"} 1",
+			"# TYPE keystore_sign_duration_seconds histogram",
+			"# HELP keystore_sign_duration_seconds Time spent signing.",
+			"keystore_sign_duration_seconds_bucket{key_type=\"sr25\",le=\"0.001\"} 1",
+			"keystore_sign_duration_seconds_bucket{key_type=\"sr25\",le=\"+Inf\"} 2",
+			"keystore_sign_duration_seconds_sum{key_type=\"sr25\"} 0.0025",
+			"keystore_sign_duration_seconds_count{key_type=\"sr25\"} 2",
+			"# EOF",
+			"",
+		].join("\n"));
+	}
+}
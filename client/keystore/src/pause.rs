@@ -0,0 +1,139 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Pausing the writes to a keystore, see [`LocalKeystore::pause_writes`].
+//!
+//! [`LocalKeystore::pause_writes`]: crate::LocalKeystore::pause_writes
+
+use std::{sync::Arc, time::{Duration, Instant}};
+use parking_lot::{Condvar, Mutex};
+
+use crate::{Error, Result};
+
+/// How long a change waits for paused writes to resume before it fails.
+///
+/// Changes fail with [`Error::WritesPaused`] after this time instead of blocking the node,
+/// e.g. a consensus engine generating a key, for as long as a backup tool holds on to a
+/// [`WritePauseGuard`].
+pub const MAX_WRITE_PAUSE: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct GateState {
+	paused: bool,
+	in_flight: usize,
+}
+
+/// Lets changes to the keystore directory through unless the writes are paused.
+#[derive(Default)]
+pub(crate) struct WriteGate {
+	state: Mutex<GateState>,
+	changed: Condvar,
+}
+
+impl WriteGate {
+	/// Enter the gate to change the directory, waiting at most `timeout` for paused writes to
+	/// resume.
+	pub fn enter(self: &Arc<Self>, timeout: Duration) -> Result<WriteTicket> {
+		let deadline = Instant::now() + timeout;
+		let mut state = self.state.lock();
+		while state.paused {
+			if self.changed.wait_until(&mut state, deadline).timed_out() && state.paused {
+				return Err(Error::WritesPaused)
+			}
+		}
+		state.in_flight += 1;
+		Ok(WriteTicket(self.clone()))
+	}
+
+	/// Enter the gate unless the writes are paused.
+	pub fn try_enter(self: &Arc<Self>) -> Option<WriteTicket> {
+		let mut state = self.state.lock();
+		if state.paused {
+			return None
+		}
+		state.in_flight += 1;
+		Some(WriteTicket(self.clone()))
+	}
+
+	/// Pause the writes, once the writes in flight are done.
+	pub fn pause(self: &Arc<Self>) -> WritePauseGuard {
+		let mut state = self.state.lock();
+		while state.paused {
+			self.changed.wait(&mut state);
+		}
+		state.paused = true;
+		while state.in_flight > 0 {
+			self.changed.wait(&mut state);
+		}
+		WritePauseGuard(self.clone())
+	}
+
+	/// Returns `true` if the writes are paused.
+	#[cfg(test)]
+	pub fn is_paused(&self) -> bool {
+		self.state.lock().paused
+	}
+}
+
+/// A change to the keystore directory in flight, leaves the gate when dropped.
+pub(crate) struct WriteTicket(Arc<WriteGate>);
+
+impl Drop for WriteTicket {
+	fn drop(&mut self) {
+		self.0.state.lock().in_flight -= 1;
+		self.0.changed.notify_all();
+	}
+}
+
+/// Keeps the writes to a keystore paused, they resume when the guard is dropped.
+#[must_use = "writes resume as soon as the guard is dropped"]
+pub struct WritePauseGuard(Arc<WriteGate>);
+
+impl Drop for WritePauseGuard {
+	fn drop(&mut self) {
+		self.0.state.lock().paused = false;
+		self.0.changed.notify_all();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pausing_waits_for_writes_in_flight() {
+		let gate = Arc::new(WriteGate::default());
+		let ticket = gate.enter(MAX_WRITE_PAUSE).unwrap();
+
+		let pausing = {
+			let gate = gate.clone();
+			std::thread::spawn(move || gate.pause())
+		};
+		while !gate.is_paused() {
+			std::thread::yield_now();
+		}
+		assert!(gate.try_enter().is_none());
+		assert!(!pausing.is_finished());
+		drop(ticket);
+
+		let guard = pausing.join().unwrap();
+		assert!(matches!(gate.enter(Duration::from_millis(10)), Err(Error::WritesPaused)));
+		drop(guard);
+		assert!(gate.enter(Duration::from_millis(10)).is_ok());
+	}
+}
@@ -0,0 +1,309 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Export and import of keys in the keystore JSON format of polkadot-js.
+//!
+//! The secret key is wrapped in the PKCS#8 like envelope of polkadot-js and encrypted with
+//! xsalsa20-poly1305 (NaCl `secretbox`), using a key derived from the password with scrypt.
+//! The encoded field is the salt, the scrypt parameters `N`, `p` and `r` as little endian
+//! `u32`, the nonce and the encrypted envelope, in base64.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::{RngCore, rngs::OsRng};
+use scrypt::ScryptParams;
+use sp_application_crypto::{ecdsa, ed25519, sr25519};
+use sp_core::{
+	crypto::{
+		AccountId32, CryptoTypeId, ExposeSecret, KeyTypeId, Pair as PairT, SecretString, Ss58Codec,
+	},
+	hashing::blake2_256,
+};
+use xsalsa20poly1305::{
+	Key, Nonce, XSalsa20Poly1305,
+	aead::{Aead, AeadInPlace, NewAead},
+};
+use zeroize::Zeroizing;
+
+use crate::{Error, Result};
+
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const PKCS8_DIVIDER: [u8; 5] = [161, 35, 3, 33, 0];
+const SCRYPT_N: u32 = 1 << 15;
+const SCRYPT_P: u32 = 1;
+const SCRYPT_R: u32 = 8;
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Metadata shown by the polkadot-js UI for an exported key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PjsMeta {
+	/// The name of the account.
+	pub name: String,
+	/// The tags of the account.
+	pub tags: Vec<String>,
+}
+
+/// A crypto scheme supported by polkadot-js.
+pub(crate) trait PjsPair: PairT {
+	/// The name of the scheme in `encoding.content`.
+	const CONTENT: &'static str;
+	/// The length of the secret key in the format of polkadot-js.
+	const SECRET_LEN: usize;
+
+	/// The secret key in the format polkadot-js expects.
+	fn pjs_secret(&self) -> Zeroizing<Vec<u8>>;
+
+	/// The seed of the pair with the secret key in the format of polkadot-js.
+	///
+	/// Returns `None` if the secret key is malformed.
+	fn seed_from_pjs(secret: &[u8]) -> Option<Zeroizing<Vec<u8>>>;
+}
+
+impl PjsPair for sr25519::Pair {
+	const CONTENT: &'static str = "sr25519";
+	const SECRET_LEN: usize = 64;
+
+	fn pjs_secret(&self) -> Zeroizing<Vec<u8>> {
+		let raw = Zeroizing::new(self.to_raw_vec());
+		let secret = schnorrkel::SecretKey::from_bytes(&raw)
+			.expect("The raw secret of a pair is a valid secret key; qed");
+		Zeroizing::new(secret.to_ed25519_bytes().to_vec())
+	}
+
+	/// polkadot-js stores the expanded secret key in the ed25519 format, the mini secret key
+	/// it was expanded from is lost. The secret key in the format of schnorrkel is used as the
+	/// seed instead.
+	fn seed_from_pjs(secret: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+		let secret = schnorrkel::SecretKey::from_ed25519_bytes(secret).ok()?;
+		Some(Zeroizing::new(secret.to_bytes().to_vec()))
+	}
+}
+
+impl PjsPair for ed25519::Pair {
+	const CONTENT: &'static str = "ed25519";
+	const SECRET_LEN: usize = 64;
+
+	fn pjs_secret(&self) -> Zeroizing<Vec<u8>> {
+		let mut secret = Zeroizing::new(self.to_raw_vec());
+		secret.extend_from_slice(self.public().as_ref());
+		secret
+	}
+
+	fn seed_from_pjs(secret: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+		Some(Zeroizing::new(secret[..32].to_vec()))
+	}
+}
+
+impl PjsPair for ecdsa::Pair {
+	const CONTENT: &'static str = "ecdsa";
+	const SECRET_LEN: usize = 32;
+
+	fn pjs_secret(&self) -> Zeroizing<Vec<u8>> {
+		Zeroizing::new(self.to_raw_vec())
+	}
+
+	fn seed_from_pjs(secret: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+		Some(Zeroizing::new(secret.to_vec()))
+	}
+}
+
+/// Export the key pair into a polkadot-js keystore JSON file encrypted with the password.
+pub(crate) fn export<Pair: PjsPair>(
+	pair: &Pair,
+	password: &SecretString,
+	meta: PjsMeta,
+) -> Result<serde_json::Value> {
+	let public = pair.public();
+	let mut envelope = Zeroizing::new(PKCS8_HEADER.to_vec());
+	envelope.extend_from_slice(&pair.pjs_secret());
+	envelope.extend_from_slice(&PKCS8_DIVIDER);
+	envelope.extend_from_slice(public.as_ref());
+
+	let mut salt = [0u8; SALT_LEN];
+	let mut nonce = [0u8; NONCE_LEN];
+	OsRng.fill_bytes(&mut salt);
+	OsRng.fill_bytes(&mut nonce);
+	let key = derive_key(password, &salt, SCRYPT_N, SCRYPT_R, SCRYPT_P);
+
+	let mut encoded = salt.to_vec();
+	for param in &[SCRYPT_N, SCRYPT_P, SCRYPT_R] {
+		encoded.extend_from_slice(&param.to_le_bytes());
+	}
+	encoded.extend_from_slice(&nonce);
+	encoded.extend_from_slice(&secretbox(&key[..32], &nonce, &envelope));
+
+	// Public keys that are longer than an account id are hashed into one.
+	let account = if public.as_ref().len() == 32 {
+		let mut account = [0u8; 32];
+		account.copy_from_slice(public.as_ref());
+		account
+	} else {
+		blake2_256(public.as_ref())
+	};
+	let created = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	Ok(serde_json::json!({
+		"address": AccountId32::from(account).to_ss58check(),
+		"encoded": base64::encode(&encoded),
+		"encoding": {
+			"content": ["pkcs8", Pair::CONTENT],
+			"type": ["scrypt", "xsalsa20-poly1305"],
+			"version": "3",
+		},
+		"meta": {
+			"name": meta.name,
+			"tags": meta.tags,
+			"whenCreated": created.as_millis() as u64,
+		},
+	}))
+}
+
+/// Import a key pair from a polkadot-js keystore JSON file encrypted with the password.
+///
+/// Returns the crypto type and the seed of the key, the secret key for sr25519. Fails with
+/// [`Error::InvalidPassword`] if the file can not be decrypted with the password, with
+/// [`Error::KeyNotSupported`] if polkadot-js uses a crypto type that is not supported and with
+/// [`Error::InvalidPjsJson`] if the file is malformed.
+pub(crate) fn import(
+	id: KeyTypeId,
+	json: &serde_json::Value,
+	password: &SecretString,
+) -> Result<(CryptoTypeId, Zeroizing<Vec<u8>>)> {
+	let invalid = |reason: &str| Error::InvalidPjsJson(reason.into());
+	let encoding = &json["encoding"];
+	if encoding["version"] != "3" ||
+		encoding["type"] != serde_json::json!(["scrypt", "xsalsa20-poly1305"])
+	{
+		return Err(invalid("only scrypt and xsalsa20-poly1305 encrypted files are supported"));
+	}
+	if encoding["content"][0] != "pkcs8" {
+		return Err(invalid("the content is not pkcs8"));
+	}
+	let content = encoding["content"][1].as_str()
+		.ok_or_else(|| invalid("the content does not name the crypto type"))?;
+	let encoded = json["encoded"].as_str()
+		.and_then(|encoded| base64::decode(encoded).ok())
+		.ok_or_else(|| invalid("the encoded field is not base64"))?;
+
+	let header_len = SALT_LEN + 12 + NONCE_LEN;
+	if encoded.len() < header_len + TAG_LEN {
+		return Err(invalid("the encoded field is too short"));
+	}
+	let (salt, rest) = encoded.split_at(SALT_LEN);
+	let param = |i: usize| u32::from_le_bytes([rest[i], rest[i + 1], rest[i + 2], rest[i + 3]]);
+	let (n, p, r) = (param(0), param(4), param(8));
+	// Parameters asking for more work than the defaults of polkadot-js are refused, they would
+	// only slow the import down.
+	if !n.is_power_of_two() || !(2..=SCRYPT_N).contains(&n) || !(1..=SCRYPT_R).contains(&r) ||
+		!(1..=SCRYPT_P).contains(&p)
+	{
+		return Err(invalid("unsupported scrypt parameters"));
+	}
+	let mut nonce = [0u8; NONCE_LEN];
+	nonce.copy_from_slice(&rest[12..12 + NONCE_LEN]);
+	let key = derive_key(password, salt, n, r, p);
+	let envelope = secretbox_open(&key[..32], &nonce, &rest[12 + NONCE_LEN..])
+		.ok_or(Error::InvalidPassword)?;
+
+	let (scheme, seed) = if content == sr25519::Pair::CONTENT {
+		(sr25519::CRYPTO_ID, import_secret::<sr25519::Pair>(&envelope)?)
+	} else if content == ed25519::Pair::CONTENT {
+		(ed25519::CRYPTO_ID, import_secret::<ed25519::Pair>(&envelope)?)
+	} else if content == ecdsa::Pair::CONTENT {
+		(ecdsa::CRYPTO_ID, import_secret::<ecdsa::Pair>(&envelope)?)
+	} else {
+		return Err(Error::KeyNotSupported(id));
+	};
+	Ok((scheme, seed))
+}
+
+/// The seed of the key in the decrypted PKCS#8 like envelope, checked against its public key.
+fn import_secret<Pair: PjsPair>(envelope: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+	let invalid = |reason: &str| Error::InvalidPjsJson(reason.into());
+	let divider = PKCS8_HEADER.len() + Pair::SECRET_LEN;
+	if envelope.len() < divider + PKCS8_DIVIDER.len() ||
+		envelope[..PKCS8_HEADER.len()] != PKCS8_HEADER ||
+		envelope[divider..divider + PKCS8_DIVIDER.len()] != PKCS8_DIVIDER
+	{
+		return Err(invalid("the decrypted content is not a pkcs8 key"));
+	}
+	let secret = &envelope[PKCS8_HEADER.len()..divider];
+	let public = &envelope[divider + PKCS8_DIVIDER.len()..];
+
+	let seed = Pair::seed_from_pjs(secret).ok_or_else(|| invalid("the secret key is malformed"))?;
+	match Pair::from_seed_slice(&seed) {
+		Ok(pair) if pair.public().as_ref() == public => Ok(seed),
+		_ => Err(invalid("the secret key does not match the public key")),
+	}
+}
+
+/// Derive the encryption key from the password with scrypt.
+///
+/// `n` must be a power of two and the parameters small enough for scrypt to accept.
+fn derive_key(password: &SecretString, salt: &[u8], n: u32, r: u32, p: u32) -> Zeroizing<Vec<u8>> {
+	let params = ScryptParams::new(n.trailing_zeros() as u8, r, p)
+		.expect("The parameters are checked against the defaults of polkadot-js; qed");
+	let mut key = Zeroizing::new(vec![0u8; 64]);
+	scrypt::scrypt(password.expose_secret().as_bytes(), salt, &params, &mut key)
+		.expect("The output length is valid; qed");
+	key
+}
+
+/// Encrypt the message with xsalsa20-poly1305, returning the tag and the ciphertext.
+fn secretbox(key: &[u8], nonce: &[u8; NONCE_LEN], message: &[u8]) -> Vec<u8> {
+	XSalsa20Poly1305::new(Key::from_slice(key))
+		.encrypt(Nonce::from_slice(nonce), message)
+		.expect("Encrypting into a vector does not fail; qed")
+}
+
+/// Decrypt the tag and ciphertext returned by [`secretbox`].
+///
+/// Returns `None` if the tag does not authenticate the ciphertext with the key.
+fn secretbox_open(key: &[u8], nonce: &[u8; NONCE_LEN], boxed: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+	let mut message = Zeroizing::new(boxed.to_vec());
+	XSalsa20Poly1305::new(Key::from_slice(key))
+		.decrypt_in_place(Nonce::from_slice(nonce), b"", &mut *message)
+		.ok()?;
+	Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn primitives_match_reference_vectors() {
+		// RFC 7914, section 12.
+		let password = SecretString::new(String::new());
+		assert_eq!(
+			hex::encode(&derive_key(&password, b"", 16, 1, 1)[..]),
+			"77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede2144\
+			2fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906",
+		);
+		// Computed with libsodium's `crypto_secretbox_easy`.
+		let mut nonce = [0u8; NONCE_LEN];
+		nonce.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+		let boxed = secretbox(&[1; 32], &nonce, b"hello polkadot-js");
+		assert_eq!(
+			hex::encode(&boxed),
+			"285fd3ae64aaa1a9cef5d4c0469f207ae672f8107b23d0c30198105cc78ee5a82d",
+		);
+		assert_eq!(&secretbox_open(&[1; 32], &nonce, &boxed).unwrap()[..], b"hello polkadot-js");
+		assert!(secretbox_open(&[2; 32], &nonce, &boxed).is_none());
+	}
+}
@@ -0,0 +1,828 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore backed by a PKCS#11 token.
+//!
+//! [`Pkcs11Keystore`] signs with keys that never leave a hardware security module, e.g. a
+//! SoftHSM, Nitrokey or YubiHSM token. Only ed25519 and ecdsa (secp256k1) keys are supported,
+//! there is no PKCS#11 mechanism for sr25519.
+//!
+//! Keys are token objects. The label of both the public and the private key object holds the
+//! [`KeyTypeId`] and their id holds the raw public key, which is how a private key is found
+//! for a public key. Keys generated by the keystore are set up like this; keys provisioned by
+//! other tools need the same label and id to be used.
+
+use std::{
+	ffi::c_void,
+	mem,
+	path::Path,
+	ptr,
+};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use sp_core::{
+	crypto::{CryptoTypeId, CryptoTypePublicPair, ExposeSecret, KeyTypeId, Public, SecretString},
+	ecdsa, ed25519, sr25519, hashing::blake2_256, Encode,
+};
+use sp_keystore::{
+	CryptoStore, Error as TraitError, keys_fingerprint,
+	vrf::{VRFTranscriptData, VRFSignature},
+};
+
+use crate::{Result, Error};
+
+/// The parts of the PKCS#11 (v2.40) C interface used by the keystore.
+mod ffi {
+	use std::{ffi::c_void, os::raw::c_ulong};
+
+	pub type Ulong = c_ulong;
+	pub type Rv = Ulong;
+
+	pub const CKR_OK: Rv = 0x000;
+	pub const CKR_SLOT_ID_INVALID: Rv = 0x003;
+	pub const CKR_DEVICE_ERROR: Rv = 0x030;
+	pub const CKR_DEVICE_MEMORY: Rv = 0x031;
+	pub const CKR_DEVICE_REMOVED: Rv = 0x032;
+	pub const CKR_MECHANISM_INVALID: Rv = 0x070;
+	pub const CKR_PIN_INCORRECT: Rv = 0x0a0;
+	pub const CKR_PIN_EXPIRED: Rv = 0x0a3;
+	pub const CKR_PIN_LOCKED: Rv = 0x0a4;
+	pub const CKR_SESSION_CLOSED: Rv = 0x0b0;
+	pub const CKR_SESSION_HANDLE_INVALID: Rv = 0x0b3;
+	pub const CKR_TOKEN_NOT_PRESENT: Rv = 0x0e0;
+	pub const CKR_TOKEN_NOT_RECOGNIZED: Rv = 0x0e1;
+	pub const CKR_USER_ALREADY_LOGGED_IN: Rv = 0x100;
+	pub const CKR_USER_NOT_LOGGED_IN: Rv = 0x101;
+	pub const CKR_CRYPTOKI_NOT_INITIALIZED: Rv = 0x190;
+	pub const CKR_CRYPTOKI_ALREADY_INITIALIZED: Rv = 0x191;
+
+	pub const CKF_RW_SESSION: Ulong = 0x2;
+	pub const CKF_SERIAL_SESSION: Ulong = 0x4;
+	pub const CKU_USER: Ulong = 1;
+
+	pub const CKO_PUBLIC_KEY: Ulong = 2;
+	pub const CKO_PRIVATE_KEY: Ulong = 3;
+	pub const CKK_EC: Ulong = 0x03;
+	pub const CKK_EC_EDWARDS: Ulong = 0x40;
+
+	pub const CKA_CLASS: Ulong = 0x000;
+	pub const CKA_TOKEN: Ulong = 0x001;
+	pub const CKA_PRIVATE: Ulong = 0x002;
+	pub const CKA_LABEL: Ulong = 0x003;
+	pub const CKA_KEY_TYPE: Ulong = 0x100;
+	pub const CKA_ID: Ulong = 0x102;
+	pub const CKA_SENSITIVE: Ulong = 0x103;
+	pub const CKA_SIGN: Ulong = 0x108;
+	pub const CKA_VERIFY: Ulong = 0x10a;
+	pub const CKA_EC_PARAMS: Ulong = 0x180;
+	pub const CKA_EC_POINT: Ulong = 0x181;
+
+	pub const CKM_EC_KEY_PAIR_GEN: Ulong = 0x1040;
+	pub const CKM_ECDSA: Ulong = 0x1041;
+	pub const CKM_EC_EDWARDS_KEY_PAIR_GEN: Ulong = 0x1055;
+	pub const CKM_EDDSA: Ulong = 0x1057;
+
+	pub const CK_TRUE: u8 = 1;
+
+	#[repr(C)]
+	pub struct Attribute {
+		pub kind: Ulong,
+		pub value: *mut c_void,
+		pub len: Ulong,
+	}
+
+	#[repr(C)]
+	pub struct Mechanism {
+		pub mechanism: Ulong,
+		pub parameter: *mut c_void,
+		pub len: Ulong,
+	}
+
+	#[repr(C)]
+	pub struct Version {
+		pub major: u8,
+		pub minor: u8,
+	}
+
+	type Unused = Option<unsafe extern "C" fn()>;
+
+	/// `CK_FUNCTION_LIST`, up to `C_GenerateKeyPair`.
+	///
+	/// It is only ever read through the pointer handed out by the library, so the functions
+	/// after the last one used here can be left out.
+	#[repr(C)]
+	pub struct FunctionList {
+		pub version: Version,
+		pub initialize: Option<unsafe extern "C" fn(*mut c_void) -> Rv>,
+		pub finalize: Option<unsafe extern "C" fn(*mut c_void) -> Rv>,
+		_info_to_set_pin: [Unused; 10],
+		pub open_session: Option<unsafe extern "C" fn(
+			slot: Ulong,
+			flags: Ulong,
+			application: *mut c_void,
+			notify: *mut c_void,
+			session: *mut Ulong,
+		) -> Rv>,
+		pub close_session: Option<unsafe extern "C" fn(session: Ulong) -> Rv>,
+		_close_all_to_set_operation_state: [Unused; 4],
+		pub login: Option<unsafe extern "C" fn(
+			session: Ulong,
+			user: Ulong,
+			pin: *const u8,
+			len: Ulong,
+		) -> Rv>,
+		pub logout: Option<unsafe extern "C" fn(session: Ulong) -> Rv>,
+		_create_and_copy_object: [Unused; 2],
+		pub destroy_object: Option<unsafe extern "C" fn(session: Ulong, object: Ulong) -> Rv>,
+		_get_object_size: Unused,
+		pub get_attribute_value: Option<unsafe extern "C" fn(
+			session: Ulong,
+			object: Ulong,
+			template: *mut Attribute,
+			count: Ulong,
+		) -> Rv>,
+		pub set_attribute_value: Option<unsafe extern "C" fn(
+			session: Ulong,
+			object: Ulong,
+			template: *mut Attribute,
+			count: Ulong,
+		) -> Rv>,
+		pub find_objects_init: Option<unsafe extern "C" fn(
+			session: Ulong,
+			template: *mut Attribute,
+			count: Ulong,
+		) -> Rv>,
+		pub find_objects: Option<unsafe extern "C" fn(
+			session: Ulong,
+			objects: *mut Ulong,
+			max: Ulong,
+			count: *mut Ulong,
+		) -> Rv>,
+		pub find_objects_final: Option<unsafe extern "C" fn(session: Ulong) -> Rv>,
+		_encrypt_to_digest: [Unused; 13],
+		pub sign_init: Option<unsafe extern "C" fn(
+			session: Ulong,
+			mechanism: *mut Mechanism,
+			key: Ulong,
+		) -> Rv>,
+		pub sign: Option<unsafe extern "C" fn(
+			session: Ulong,
+			data: *const u8,
+			len: Ulong,
+			signature: *mut u8,
+			signature_len: *mut Ulong,
+		) -> Rv>,
+		_sign_update_to_generate_key: [Unused; 15],
+		pub generate_key_pair: Option<unsafe extern "C" fn(
+			session: Ulong,
+			mechanism: *mut Mechanism,
+			public_template: *mut Attribute,
+			public_count: Ulong,
+			private_template: *mut Attribute,
+			private_count: Ulong,
+			public_key: *mut Ulong,
+			private_key: *mut Ulong,
+		) -> Rv>,
+	}
+
+	pub type GetFunctionList = unsafe extern "C" fn(list: *mut *const FunctionList) -> Rv;
+}
+
+use ffi::{Attribute, Rv, Ulong};
+
+/// DER encoded object identifier of secp256k1, the curve of ecdsa keys.
+const SECP256K1_PARAMS: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a];
+
+/// DER encoded object identifier of ed25519 (RFC 8410).
+const ED25519_PARAMS: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+
+/// The number of object handles fetched per `C_FindObjects` call.
+const FIND_BATCH: usize = 16;
+
+/// Call a function of the token's function list.
+///
+/// Evaluates to `Err(rv)` if the call did not return `CKR_OK`.
+macro_rules! call {
+	($token:expr, $function:ident($($arg:expr),* $(,)?)) => {{
+		// Safety: the function list stays valid for as long as the library is loaded, which is
+		// as long as the token lives.
+		match unsafe { (*$token.functions).$function } {
+			// Safety: the arguments follow the PKCS#11 specification of the function.
+			Some(function) => match unsafe { function($($arg),*) } {
+				ffi::CKR_OK => Ok(()),
+				rv => Err(rv),
+			},
+			None => Err(ffi::CKR_DEVICE_ERROR),
+		}
+	}};
+}
+
+/// Build an attribute of a template that points to `value`.
+///
+/// The attribute must not outlive `value`.
+fn attribute<T: ?Sized>(kind: Ulong, value: &T) -> Attribute {
+	Attribute {
+		kind,
+		value: value as *const T as *mut c_void,
+		len: mem::size_of_val(value) as Ulong,
+	}
+}
+
+/// Returns `true` if the return value means that the token can not be used right now.
+fn is_unavailable(rv: Rv) -> bool {
+	matches!(
+		rv,
+		ffi::CKR_SLOT_ID_INVALID |
+			ffi::CKR_DEVICE_ERROR |
+			ffi::CKR_DEVICE_MEMORY |
+			ffi::CKR_DEVICE_REMOVED |
+			ffi::CKR_PIN_EXPIRED |
+			ffi::CKR_PIN_LOCKED |
+			ffi::CKR_SESSION_CLOSED |
+			ffi::CKR_SESSION_HANDLE_INVALID |
+			ffi::CKR_TOKEN_NOT_PRESENT |
+			ffi::CKR_TOKEN_NOT_RECOGNIZED |
+			ffi::CKR_USER_NOT_LOGGED_IN |
+			ffi::CKR_CRYPTOKI_NOT_INITIALIZED
+	)
+}
+
+/// Convert the return value of a failed call into a keystore error.
+fn trait_error(rv: Rv) -> TraitError {
+	if is_unavailable(rv) {
+		TraitError::Unavailable
+	} else {
+		TraitError::Other(format!("PKCS#11 call failed with {:#x}", rv))
+	}
+}
+
+/// Strip the DER octet string some tokens wrap `CKA_EC_POINT` in.
+///
+/// The point is only unwrapped if what is left has one of the `lengths` a point can have, so
+/// a raw point that happens to look like an octet string is kept.
+fn unwrap_point<'a>(point: &'a [u8], lengths: &[usize]) -> &'a [u8] {
+	match point {
+		[0x04, len, inner @ ..] if *len as usize == inner.len() && lengths.contains(&inner.len()) =>
+			inner,
+		_ => point,
+	}
+}
+
+/// Decode the public key from the `CKA_KEY_TYPE`, `CKA_EC_PARAMS` and `CKA_EC_POINT` of a
+/// public key object.
+///
+/// Returns `None` if the key is not an ed25519 or secp256k1 key.
+fn decode_public(key_type: Ulong, params: &[u8], point: &[u8]) -> Option<CryptoTypePublicPair> {
+	match key_type {
+		ffi::CKK_EC_EDWARDS => match unwrap_point(point, &[32]) {
+			point if point.len() == 32 => Some(ed25519::Public::from_slice(point).into()),
+			_ => None,
+		},
+		ffi::CKK_EC if params == SECP256K1_PARAMS => {
+			let point = unwrap_point(point, &[33, 65]);
+			let public = secp256k1::PublicKey::parse_slice(point, None).ok()?;
+			Some(ecdsa::Public::from_raw(public.serialize_compressed()).into())
+		},
+		_ => None,
+	}
+}
+
+/// Turn the plain `r || s` signature of a token into a recoverable ecdsa signature.
+///
+/// Returns `None` if the signature does not recover to `public`.
+fn recoverable_signature(
+	digest: &[u8; 32],
+	signature: &[u8],
+	public: &[u8],
+) -> Option<ecdsa::Signature> {
+	let mut signature = secp256k1::Signature::parse_slice(signature).ok()?;
+	signature.normalize_s();
+	let message = secp256k1::Message::parse(digest);
+
+	(0..4).find_map(|id| {
+		let recovery_id = secp256k1::RecoveryId::parse(id).ok()?;
+		let recovered = secp256k1::recover(&message, &signature, &recovery_id).ok()?;
+		if recovered.serialize_compressed()[..] == *public {
+			let mut raw = [0u8; 65];
+			raw[..64].copy_from_slice(&signature.serialize());
+			raw[64] = id;
+			Some(ecdsa::Signature::from_raw(raw))
+		} else {
+			None
+		}
+	})
+}
+
+/// An open, logged in session with a token.
+struct Token {
+	functions: *const ffi::FunctionList,
+	session: Ulong,
+	/// Whether the library was initialized by us and should be finalized on drop.
+	finalize: bool,
+	// Dropped last, the function pointers point into the library.
+	_library: libloading::Library,
+}
+
+// Safety: PKCS#11 libraries can be used from any thread, as long as a session is not used
+// concurrently. The token is only accessed behind a mutex.
+unsafe impl Send for Token {}
+
+impl Token {
+	fn open(library: &Path, slot: u64, pin: &SecretString) -> Result<Self> {
+		// Safety: loading a library runs its initialization code, the library is trusted as
+		// much as the key material it protects.
+		let library = unsafe { libloading::Library::new(library) }.map_err(|_| Error::Unavailable)?;
+		let mut functions = ptr::null();
+		// Safety: `C_GetFunctionList` is the one symbol every PKCS#11 library exports.
+		let rv = unsafe {
+			let get_function_list = library.get::<ffi::GetFunctionList>(b"C_GetFunctionList\0")
+				.map_err(|_| Error::Unavailable)?;
+			get_function_list(&mut functions)
+		};
+		if rv != ffi::CKR_OK || functions.is_null() {
+			return Err(Error::Unavailable);
+		}
+
+		let mut token = Token { functions, session: 0, finalize: false, _library: library };
+		token.finalize = match call!(token, initialize(ptr::null_mut())) {
+			Ok(()) => true,
+			Err(ffi::CKR_CRYPTOKI_ALREADY_INITIALIZED) => false,
+			Err(_) => return Err(Error::Unavailable),
+		};
+
+		let mut session = 0;
+		call!(token, open_session(
+			slot as Ulong,
+			ffi::CKF_SERIAL_SESSION | ffi::CKF_RW_SESSION,
+			ptr::null_mut(),
+			ptr::null_mut(),
+			&mut session,
+		)).map_err(|_| Error::Unavailable)?;
+		token.session = session;
+
+		let pin = pin.expose_secret().as_bytes();
+		match call!(token, login(token.session, ffi::CKU_USER, pin.as_ptr(), pin.len() as Ulong)) {
+			Ok(()) | Err(ffi::CKR_USER_ALREADY_LOGGED_IN) => Ok(token),
+			Err(ffi::CKR_PIN_INCORRECT) => Err(Error::InvalidPassword),
+			Err(_) => Err(Error::Unavailable),
+		}
+	}
+
+	/// Find the objects matching the template.
+	fn find(&self, template: &mut [Attribute]) -> std::result::Result<Vec<Ulong>, Rv> {
+		call!(self, find_objects_init(self.session, template.as_mut_ptr(), template.len() as Ulong))?;
+
+		let mut objects = Vec::new();
+		let found = loop {
+			let mut batch = [0; FIND_BATCH];
+			let mut count = 0;
+			if let Err(rv) = call!(self, find_objects(
+				self.session,
+				batch.as_mut_ptr(),
+				FIND_BATCH as Ulong,
+				&mut count,
+			)) {
+				break Err(rv);
+			}
+			if count == 0 {
+				break Ok(objects);
+			}
+			objects.extend_from_slice(&batch[..count as usize]);
+		};
+
+		call!(self, find_objects_final(self.session))?;
+		found
+	}
+
+	/// Read an attribute of an object.
+	fn attribute(&self, object: Ulong, kind: Ulong) -> std::result::Result<Vec<u8>, Rv> {
+		let mut template = [Attribute { kind, value: ptr::null_mut(), len: 0 }];
+		call!(self, get_attribute_value(self.session, object, template.as_mut_ptr(), 1))?;
+
+		let mut value = vec![0u8; template[0].len as usize];
+		template[0].value = value.as_mut_ptr() as *mut c_void;
+		call!(self, get_attribute_value(self.session, object, template.as_mut_ptr(), 1))?;
+		value.truncate(template[0].len as usize);
+		Ok(value)
+	}
+
+	/// List the public keys stored under the given key type.
+	fn public_keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, Rv> {
+		let class = ffi::CKO_PUBLIC_KEY;
+		let mut template = [attribute(ffi::CKA_CLASS, &class), attribute(ffi::CKA_LABEL, &id.0)];
+
+		let mut keys = Vec::new();
+		for object in self.find(&mut template)? {
+			keys.extend(self.decode_public(object)?);
+		}
+		Ok(keys)
+	}
+
+	/// List the key types and public keys of all keys.
+	///
+	/// Objects with a label that is not a key type are skipped.
+	fn all_public_keys(&self) -> std::result::Result<Vec<(KeyTypeId, Vec<u8>)>, Rv> {
+		let class = ffi::CKO_PUBLIC_KEY;
+		let mut template = [attribute(ffi::CKA_CLASS, &class)];
+
+		let mut keys = Vec::new();
+		for object in self.find(&mut template)? {
+			let label = self.attribute(object, ffi::CKA_LABEL)?;
+			if label.len() != 4 {
+				continue;
+			}
+			let mut id = KeyTypeId::default();
+			id.0.copy_from_slice(&label);
+			keys.extend(self.decode_public(object)?.map(|public| (id, public.1)));
+		}
+		Ok(keys)
+	}
+
+	/// Decode the public key of a public key object.
+	fn decode_public(&self, object: Ulong) -> std::result::Result<Option<CryptoTypePublicPair>, Rv> {
+		let key_type = self.attribute(object, ffi::CKA_KEY_TYPE)?;
+		let key_type = match key_type.get(..mem::size_of::<Ulong>()) {
+			Some(raw) => {
+				let mut bytes = [0u8; mem::size_of::<Ulong>()];
+				bytes.copy_from_slice(raw);
+				Ulong::from_ne_bytes(bytes)
+			},
+			None => return Ok(None),
+		};
+		let params = self.attribute(object, ffi::CKA_EC_PARAMS)?;
+		let point = self.attribute(object, ffi::CKA_EC_POINT)?;
+		Ok(decode_public(key_type, &params, &point))
+	}
+
+	/// Find the key objects of the given class stored under the key type for the public key.
+	fn key_objects(
+		&self,
+		class: Ulong,
+		id: KeyTypeId,
+		public: &[u8],
+	) -> std::result::Result<Vec<Ulong>, Rv> {
+		let mut template = [
+			attribute(ffi::CKA_CLASS, &class),
+			attribute(ffi::CKA_LABEL, &id.0),
+			attribute(ffi::CKA_ID, public),
+		];
+		self.find(&mut template)
+	}
+
+	/// Sign `data` with the private key of the public key.
+	///
+	/// Returns `Ok(None)` if the token has no such private key.
+	fn sign(
+		&self,
+		id: KeyTypeId,
+		public: &[u8],
+		mechanism: Ulong,
+		data: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, Rv> {
+		let key = match self.key_objects(ffi::CKO_PRIVATE_KEY, id, public)?.first() {
+			Some(key) => *key,
+			None => return Ok(None),
+		};
+
+		let mut mechanism = ffi::Mechanism { mechanism, parameter: ptr::null_mut(), len: 0 };
+		call!(self, sign_init(self.session, &mut mechanism, key))?;
+
+		// Both ed25519 and plain ecdsa signatures are 64 bytes.
+		let mut signature = vec![0u8; 64];
+		let mut len = signature.len() as Ulong;
+		call!(self, sign(
+			self.session,
+			data.as_ptr(),
+			data.len() as Ulong,
+			signature.as_mut_ptr(),
+			&mut len,
+		))?;
+		signature.truncate(len as usize);
+		Ok(Some(signature))
+	}
+
+	/// Generate a new key pair on the token and store it under the given key type.
+	fn generate(
+		&self,
+		id: KeyTypeId,
+		mechanism: Ulong,
+		params: &[u8],
+	) -> std::result::Result<CryptoTypePublicPair, Rv> {
+		let yes = ffi::CK_TRUE;
+		let mut mechanism = ffi::Mechanism { mechanism, parameter: ptr::null_mut(), len: 0 };
+		let mut public_template = [
+			attribute(ffi::CKA_TOKEN, &yes),
+			attribute(ffi::CKA_VERIFY, &yes),
+			attribute(ffi::CKA_LABEL, &id.0),
+			attribute(ffi::CKA_EC_PARAMS, params),
+		];
+		let mut private_template = [
+			attribute(ffi::CKA_TOKEN, &yes),
+			attribute(ffi::CKA_PRIVATE, &yes),
+			attribute(ffi::CKA_SENSITIVE, &yes),
+			attribute(ffi::CKA_SIGN, &yes),
+			attribute(ffi::CKA_LABEL, &id.0),
+		];
+		let (mut public_key, mut private_key) = (0, 0);
+		call!(self, generate_key_pair(
+			self.session,
+			&mut mechanism,
+			public_template.as_mut_ptr(),
+			public_template.len() as Ulong,
+			private_template.as_mut_ptr(),
+			private_template.len() as Ulong,
+			&mut public_key,
+			&mut private_key,
+		))?;
+
+		let key_type = if params == ED25519_PARAMS { ffi::CKK_EC_EDWARDS } else { ffi::CKK_EC };
+		let point = self.attribute(public_key, ffi::CKA_EC_POINT)?;
+		let public = decode_public(key_type, params, &point).ok_or(ffi::CKR_DEVICE_ERROR)?;
+
+		// Link both objects to the public key, that is how the private key is found again.
+		for object in [public_key, private_key].iter() {
+			let mut template = [attribute(ffi::CKA_ID, &public.1[..])];
+			call!(self, set_attribute_value(self.session, *object, template.as_mut_ptr(), 1))?;
+		}
+		Ok(public)
+	}
+}
+
+impl Drop for Token {
+	fn drop(&mut self) {
+		let _ = call!(self, logout(self.session));
+		let _ = call!(self, close_session(self.session));
+		if self.finalize {
+			let _ = call!(self, finalize(ptr::null_mut()));
+		}
+	}
+}
+
+/// A keystore that signs with the keys of a PKCS#11 token.
+///
+/// Keys can not be imported from a seed or a secret URI, they only ever exist on the token.
+pub struct Pkcs11Keystore {
+	token: Mutex<Token>,
+}
+
+impl Pkcs11Keystore {
+	/// Open a session with the token in `slot` of the PKCS#11 library at `library` and log in
+	/// with `pin`.
+	///
+	/// Returns [`Error::InvalidPassword`] if the token rejects the PIN and [`Error::Unavailable`]
+	/// if the library can not be loaded, the token is absent or the PIN is locked.
+	pub fn open<P: AsRef<Path>>(library: P, slot: u64, pin: SecretString) -> Result<Self> {
+		let token = Token::open(library.as_ref(), slot, &pin)?;
+		Ok(Self { token: Mutex::new(token) })
+	}
+
+	fn public_keys<T: Public>(&self, id: KeyTypeId, crypto: sp_core::crypto::CryptoTypeId) -> Vec<T> {
+		self.token.lock().public_keys(id)
+			.map(|keys| keys.into_iter()
+				.filter(|key| key.0 == crypto)
+				.map(|key| T::from_slice(&key.1))
+				.collect())
+			.unwrap_or_default()
+	}
+
+	fn generate(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+		mechanism: Ulong,
+		params: &[u8],
+	) -> std::result::Result<Vec<u8>, TraitError> {
+		if seed.is_some() {
+			return Err(TraitError::ValidationError(
+				"Keys can not be imported into a PKCS#11 token".into(),
+			));
+		}
+		match self.token.lock().generate(id, mechanism, params) {
+			Ok(public) => Ok(public.1),
+			Err(ffi::CKR_MECHANISM_INVALID) => Err(TraitError::KeyNotSupported(id)),
+			Err(rv) => Err(trait_error(rv)),
+		}
+	}
+}
+
+#[async_trait]
+impl CryptoStore for Pkcs11Keystore {
+	async fn sr25519_public_keys(&self, _id: KeyTypeId) -> Vec<sr25519::Public> {
+		Vec::new()
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		_seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		Err(TraitError::KeyNotSupported(id))
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		self.public_keys(id, ed25519::CRYPTO_ID)
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		self.generate(id, seed, ffi::CKM_EC_EDWARDS_KEY_PAIR_GEN, ED25519_PARAMS)
+			.map(|public| ed25519::Public::from_slice(&public))
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		self.public_keys(id, ecdsa::CRYPTO_ID)
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		self.generate(id, seed, ffi::CKM_EC_KEY_PAIR_GEN, SECP256K1_PARAMS)
+			.map(|public| ecdsa::Public::from_slice(&public))
+	}
+
+	async fn insert_unknown(
+		&self,
+		_id: KeyTypeId,
+		_suri: &str,
+		_public: &[u8],
+	) -> std::result::Result<(), ()> {
+		Err(())
+	}
+
+	async fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		let token = self.token.lock();
+		let mut objects = token.key_objects(ffi::CKO_PRIVATE_KEY, id, &public.1)
+			.map_err(trait_error)?;
+		if objects.is_empty() {
+			return Err(TraitError::PairNotFound);
+		}
+		objects.extend(token.key_objects(ffi::CKO_PUBLIC_KEY, id, &public.1).map_err(trait_error)?);
+		for object in objects {
+			call!(token, destroy_object(token.session, object)).map_err(trait_error)?;
+		}
+		Ok(())
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		let all_keys = self.keys(id).await?;
+		Ok(keys.into_iter().filter(|key| all_keys.contains(key)).collect())
+	}
+
+	async fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.token.lock().public_keys(id).map_err(trait_error)
+	}
+
+	/// Tokens do not implement sr25519, so only ed25519 and ecdsa are supported.
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		vec![ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID]
+	}
+
+	async fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		self.token.lock().all_public_keys().map(keys_fingerprint).map_err(trait_error)
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		let token = self.token.lock();
+		public_keys.iter().all(|(public, id)| {
+			matches!(token.key_objects(ffi::CKO_PRIVATE_KEY, *id, public), Ok(keys) if !keys.is_empty())
+		})
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		match key.0 {
+			ed25519::CRYPTO_ID => {
+				let signature = self.token.lock()
+					.sign(id, &key.1, ffi::CKM_EDDSA, msg)
+					.map_err(trait_error)?;
+				Ok(signature.map(|s| {
+					let mut raw = [0u8; 64];
+					raw.copy_from_slice(&s);
+					ed25519::Signature::from_raw(raw).encode()
+				}))
+			},
+			ecdsa::CRYPTO_ID => {
+				let public = ecdsa::Public::from_slice(&key.1);
+				match self.ecdsa_sign_prehashed(id, &public, &blake2_256(msg)).await {
+					Ok(signature) => Ok(Some(signature.encode())),
+					Err(TraitError::PairNotFound) => Ok(None),
+					Err(e) => Err(e),
+				}
+			},
+			_ => Err(TraitError::KeyNotSupported(id)),
+		}
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		_key_type: KeyTypeId,
+		_public: &sr25519::Public,
+		_transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		Ok(None)
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		let signature = self.token.lock()
+			.sign(id, public.as_ref(), ffi::CKM_ECDSA, msg)
+			.map_err(trait_error)?;
+		match signature {
+			Some(signature) => recoverable_signature(msg, &signature, public.as_ref())
+				.ok_or_else(|| TraitError::Other("Token returned an invalid signature".into())),
+			None => Err(TraitError::PairNotFound),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::Pair;
+
+	#[test]
+	fn missing_library_is_unavailable() {
+		let pin = SecretString::new("1234".into());
+		assert!(matches!(
+			Pkcs11Keystore::open("/nonexistent/libpkcs11.so", 0, pin),
+			Err(Error::Unavailable),
+		));
+	}
+
+	#[test]
+	fn public_keys_are_decoded() {
+		let ed = ed25519::Pair::generate().0.public();
+		let mut wrapped = vec![0x04, 32];
+		wrapped.extend_from_slice(ed.as_ref());
+		assert_eq!(decode_public(ffi::CKK_EC_EDWARDS, ED25519_PARAMS, &wrapped), Some(ed.into()));
+		assert_eq!(decode_public(ffi::CKK_EC_EDWARDS, ED25519_PARAMS, ed.as_ref()), Some(ed.into()));
+
+		let secret = secp256k1::SecretKey::parse(&[7u8; 32]).unwrap();
+		let full = secp256k1::PublicKey::from_secret_key(&secret).serialize();
+		let ecdsa = ecdsa::Public::from_raw(
+			secp256k1::PublicKey::from_secret_key(&secret).serialize_compressed(),
+		);
+		let mut wrapped = vec![0x04, 65];
+		wrapped.extend_from_slice(&full);
+		assert_eq!(decode_public(ffi::CKK_EC, SECP256K1_PARAMS, &wrapped), Some(ecdsa.clone().into()));
+		assert_eq!(decode_public(ffi::CKK_EC, SECP256K1_PARAMS, &full), Some(ecdsa.into()));
+
+		// Only secp256k1 is supported for ecdsa.
+		assert_eq!(decode_public(ffi::CKK_EC, &[0x06, 0x08, 1, 2, 3, 4, 5, 6, 7, 8], &full), None);
+	}
+
+	#[test]
+	fn token_signatures_are_made_recoverable() {
+		let secret = secp256k1::SecretKey::parse(&[7u8; 32]).unwrap();
+		let public = ecdsa::Public::from_raw(
+			secp256k1::PublicKey::from_secret_key(&secret).serialize_compressed(),
+		);
+		let digest = blake2_256(b"message");
+
+		// A token only returns `r || s`, without the recovery id.
+		let (signature, _) = secp256k1::sign(&secp256k1::Message::parse(&digest), &secret);
+		let signature = recoverable_signature(&digest, &signature.serialize(), public.as_ref())
+			.unwrap();
+		assert!(ecdsa::Pair::verify_prehashed(&signature, &digest, &public));
+
+		let other = ecdsa::Pair::generate().0.public();
+		let raw: &[u8] = signature.as_ref();
+		assert!(recoverable_signature(&digest, &raw[..64], other.as_ref()).is_none());
+	}
+}
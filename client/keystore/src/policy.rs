@@ -0,0 +1,132 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Policies and hooks that configure how a [`LocalKeystore`] stores, imports and uses keys.
+//!
+//! [`LocalKeystore`]: crate::LocalKeystore
+
+use sp_core::crypto::{CryptoTypePublicPair, KeyTypeId};
+
+/// A policy that secret URIs have to satisfy before they are inserted into the keystore.
+///
+/// Returns `Err(_)` with a human readable reason if the secret URI is rejected.
+pub type SuriPolicy = Box<dyn Fn(&str) -> std::result::Result<(), String> + Send + Sync>;
+
+/// A hook that inspects every message before it is signed with `sign_with`.
+///
+/// Returns `Err(_)` with a human readable reason to refuse signing the message.
+pub type SignHook = Box<
+	dyn Fn(KeyTypeId, &CryptoTypePublicPair, &[u8]) -> std::result::Result<(), String> + Send + Sync
+>;
+
+/// How the key files are laid out in the keystore directory.
+///
+/// A flat directory with tens of thousands of files is slow on some file systems, the sharded
+/// layouts spread the key files over subdirectories. The key files keep their names in all
+/// layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectoryLayout {
+	/// All key files are stored in the keystore directory.
+	Flat,
+	/// Key files are stored in a subdirectory per key type, named after the hex encoded key
+	/// type.
+	ByKeyType,
+	/// Like [`DirectoryLayout::ByKeyType`], with a second level of subdirectories named after
+	/// the hex encoded first byte of the public key.
+	ByKeyTypeAndPublic,
+}
+
+/// What to do with keys of an imported backup that already exist in the keystore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+	/// Keep the existing keys and only import the others.
+	Skip,
+	/// Replace the existing keys.
+	Overwrite,
+	/// Abort the import without importing anything, see [`Error::KeysExist`].
+	///
+	/// [`Error::KeysExist`]: crate::Error::KeysExist
+	Error,
+}
+
+/// What to do with files in the keystore directory that are not key files.
+///
+/// Key files are named after their hex encoded key type and public key, files with other names
+/// are never read by the keystore. Copies left by interrupted updates, the files recording
+/// when keys were last used, the key index and the write-ahead log are not stray files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrayFilePolicy {
+	/// Skip stray files silently.
+	Ignore,
+	/// Log a warning for every stray file.
+	Warn,
+	/// Fail to open the keystore, see [`Error::StrayFiles`].
+	///
+	/// [`Error::StrayFiles`]: crate::Error::StrayFiles
+	Error,
+}
+
+// Deriving it with `#[default]` on the variant needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for StrayFilePolicy {
+	fn default() -> Self {
+		Self::Ignore
+	}
+}
+
+/// What reading the secrets of many keys at once does with keys whose secret can not be read.
+///
+/// A key file can be unreadable, malformed or hold a secret that does not derive its public key
+/// with the password of the keystore, e.g. because it was written with another password. Listing
+/// public keys leaves out keys whose key file fails to parse and lists all other such keys, see
+/// [`LocalKeystore::unreadable_keys`].
+///
+/// [`LocalKeystore::unreadable_keys`]: crate::LocalKeystore::unreadable_keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptFailurePolicy {
+	/// Skip the key, logging a warning that names it.
+	SkipAndWarn,
+	/// Fail with the error reading the key.
+	Fail,
+}
+
+// Deriving it with `#[default]` on the variant needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for DecryptFailurePolicy {
+	fn default() -> Self {
+		Self::Fail
+	}
+}
+
+/// How many keys of a key type generating keys may create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingletonPolicy {
+	/// Every call generates a new key.
+	Multiple,
+	/// Generating returns the existing key of the crypto type, if there is one, so concurrent
+	/// calls end up with a single key.
+	Singleton,
+}
+
+// Deriving it with `#[default]` on the variant needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for SingletonPolicy {
+	fn default() -> Self {
+		Self::Multiple
+	}
+}
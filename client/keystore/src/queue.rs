@@ -0,0 +1,148 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Queue of signing requests, see [`LocalKeystore::signing_queue`].
+
+use std::collections::HashMap;
+use futures::{channel::{mpsc, oneshot}, StreamExt};
+use sp_core::crypto::{CryptoTypePublicPair, KeyTypeId};
+use sp_keystore::Error as TraitError;
+
+use crate::LocalKeystore;
+
+/// The result of a queued signing request, like the one of `sign_with`.
+pub type SignResult = std::result::Result<Option<Vec<u8>>, TraitError>;
+
+/// A request to sign a message with a key.
+pub struct SignRequest {
+	/// The key type of the key.
+	pub id: KeyTypeId,
+	/// The key to sign with.
+	pub key: CryptoTypePublicPair,
+	/// The message to sign.
+	pub msg: Vec<u8>,
+	/// Receives the result of signing the message.
+	pub responder: oneshot::Sender<SignResult>,
+}
+
+/// Pushes signing requests onto the queue of a [`SignWorker`].
+#[derive(Clone)]
+pub struct SignSender(mpsc::Sender<SignRequest>);
+
+impl SignSender {
+	pub(crate) fn new(sender: mpsc::Sender<SignRequest>) -> Self {
+		Self(sender)
+	}
+
+	/// Push the request onto the queue.
+	///
+	/// Fails with [`TraitError::Unavailable`] if the queue is full or the worker is gone, the
+	/// request is dropped then.
+	pub fn send(&mut self, request: SignRequest) -> Result<(), TraitError> {
+		self.0.try_send(request).map_err(|_| TraitError::Unavailable)
+	}
+
+	/// Push a request to sign the message with the key, returning where its result arrives.
+	pub fn sign(
+		&mut self,
+		id: KeyTypeId,
+		key: CryptoTypePublicPair,
+		msg: Vec<u8>,
+	) -> Result<oneshot::Receiver<SignResult>, TraitError> {
+		let (responder, receiver) = oneshot::channel();
+		self.send(SignRequest { id, key, msg, responder })?;
+		Ok(receiver)
+	}
+}
+
+/// Answers the requests of a signing queue, see [`LocalKeystore::signing_queue`].
+pub struct SignWorker<'a> {
+	keystore: &'a LocalKeystore,
+	requests: mpsc::Receiver<SignRequest>,
+}
+
+impl<'a> SignWorker<'a> {
+	pub(crate) fn new(keystore: &'a LocalKeystore, requests: mpsc::Receiver<SignRequest>) -> Self {
+		Self { keystore, requests }
+	}
+
+	/// Answer requests until every sender is dropped.
+	///
+	/// The requests that are queued when the worker wakes up are answered as one batch, the
+	/// requests of the batch for the same key are signed after looking the key up once.
+	pub async fn run(mut self) {
+		while let Some(request) = self.requests.next().await {
+			let mut batch = vec![request];
+			while let Ok(Some(request)) = self.requests.try_next() {
+				batch.push(request);
+			}
+			self.answer(batch);
+		}
+	}
+
+	/// Sign the messages of the batch and send the results to their responders.
+	fn answer(&self, batch: Vec<SignRequest>) {
+		let mut by_key = HashMap::<(KeyTypeId, CryptoTypePublicPair), Vec<SignRequest>>::new();
+		for request in batch {
+			by_key.entry((request.id, request.key.clone())).or_default().push(request);
+		}
+
+		for ((id, key), requests) in by_key {
+			let msgs = requests.iter().map(|r| &r.msg[..]).collect::<Vec<_>>();
+			let results = self.keystore.sign_queued(id, &key, &msgs);
+			for (request, result) in requests.into_iter().zip(results) {
+				// The requester may have stopped waiting for the result.
+				let _ = request.responder.send(result);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::testing::ED25519;
+	use sp_keystore::SyncCryptoStore;
+
+	#[test]
+	fn queued_requests_are_answered() {
+		let store = LocalKeystore::in_memory();
+		let key: CryptoTypePublicPair =
+			SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into();
+		let (mut sender, worker) = store.signing_queue(2);
+
+		// Every sender may queue one request beyond the capacity.
+		let first = sender.sign(ED25519, key.clone(), b"first".to_vec()).unwrap();
+		let second = sender.sign(ED25519, key.clone(), b"second".to_vec()).unwrap();
+		let unknown = CryptoTypePublicPair(key.0, vec![0; 32]);
+		let third = sender.sign(ED25519, unknown, b"third".to_vec()).unwrap();
+		assert!(matches!(
+			sender.sign(ED25519, key.clone(), b"fourth".to_vec()),
+			Err(TraitError::Unavailable),
+		));
+		drop(sender);
+
+		block_on(worker.run());
+		for (result, msg) in [(first, &b"first"[..]), (second, &b"second"[..])] {
+			let signature = block_on(result).unwrap().unwrap().unwrap();
+			assert!(SyncCryptoStore::verify(&store, ED25519, &key, msg, &signature).unwrap());
+		}
+		assert!(block_on(third).unwrap().unwrap().is_none());
+	}
+}
@@ -0,0 +1,126 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore backends selected by URI, e.g. from the node configuration.
+
+use std::collections::HashMap;
+use sp_keystore::CryptoStore;
+
+use crate::{Error, LocalKeystore, Result};
+
+/// Opens a keystore backend from the part of the URI after `<scheme>://`.
+pub type BackendFactory = Box<dyn Fn(&str) -> Result<Box<dyn CryptoStore>> + Send + Sync>;
+
+/// The URI scheme of the built-in backend, a [`LocalKeystore`] in the given directory.
+pub const FILE_SCHEME: &str = "file";
+
+/// The URI scheme of an [`SqliteKeystore`](crate::SqliteKeystore) in the database at the
+/// given path.
+#[cfg(feature = "sqlite")]
+pub const SQLITE_SCHEME: &str = "sqlite";
+
+/// The keystore backends that can be opened by URI.
+///
+/// A URI is `<scheme>://<location>`, the factory registered for the scheme gets the location.
+/// `file://<path>` opens a [`LocalKeystore`] without a password in the directory at `path`,
+/// with the `sqlite` feature `sqlite://<path>` opens an unencrypted database at `path`;
+/// other backends, including password protected local keystores, are added with
+/// [`BackendRegistry::register`].
+pub struct BackendRegistry {
+	factories: HashMap<String, BackendFactory>,
+}
+
+impl BackendRegistry {
+	/// A registry with the built-in `file` backend.
+	pub fn new() -> Self {
+		let mut registry = Self { factories: HashMap::new() };
+		registry.register(FILE_SCHEME, Box::new(|path| {
+			Ok(Box::new(LocalKeystore::open(path, None)?) as Box<dyn CryptoStore>)
+		}));
+		#[cfg(feature = "sqlite")]
+		registry.register(SQLITE_SCHEME, Box::new(|path| {
+			Ok(Box::new(crate::SqliteKeystore::open(path, None)?) as Box<dyn CryptoStore>)
+		}));
+		registry
+	}
+
+	/// Open the URIs with the given scheme with the factory, replacing the factory that was
+	/// registered for the scheme before, if any.
+	pub fn register(&mut self, scheme: &str, factory: BackendFactory) {
+		self.factories.insert(scheme.to_ascii_lowercase(), factory);
+	}
+
+	/// Returns the registered schemes, sorted.
+	pub fn schemes(&self) -> Vec<&str> {
+		let mut schemes = self.factories.keys().map(String::as_str).collect::<Vec<_>>();
+		schemes.sort_unstable();
+		schemes
+	}
+
+	/// Open the backend of the URI with the factory of its scheme.
+	///
+	/// Schemes are compared ignoring case. Fails with [`Error::UnknownBackend`] if the URI has
+	/// no scheme or no factory is registered for it.
+	pub fn open_from_uri(&self, uri: &str) -> Result<Box<dyn CryptoStore>> {
+		let (scheme, location) = uri.split_once("://")
+			.ok_or_else(|| Error::UnknownBackend(uri.to_owned()))?;
+		let factory = self.factories.get(&scheme.to_ascii_lowercase())
+			.ok_or_else(|| Error::UnknownBackend(uri.to_owned()))?;
+		factory(location)
+	}
+}
+
+impl Default for BackendRegistry {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::testing::SR25519;
+	use tempfile::TempDir;
+
+	#[test]
+	fn backends_are_opened_by_scheme() {
+		let temp_dir = TempDir::new().unwrap();
+		let mut registry = BackendRegistry::new();
+		registry.register("memory", Box::new(|_| {
+			Ok(Box::new(LocalKeystore::in_memory()) as Box<dyn CryptoStore>)
+		}));
+		#[cfg(not(feature = "sqlite"))]
+		assert_eq!(registry.schemes(), vec![FILE_SCHEME, "memory"]);
+		#[cfg(feature = "sqlite")]
+		assert_eq!(registry.schemes(), vec![FILE_SCHEME, "memory", SQLITE_SCHEME]);
+
+		let uri = format!("file://{}", temp_dir.path().display());
+		let store = registry.open_from_uri(&uri).unwrap();
+		let public = block_on(store.sr25519_generate_new(SR25519, None)).unwrap();
+		let reopened = registry.open_from_uri(&uri.replace("file", "FILE")).unwrap();
+		assert_eq!(block_on(reopened.sr25519_public_keys(SR25519)), vec![public]);
+
+		let memory = registry.open_from_uri("memory://").unwrap();
+		assert!(block_on(memory.sr25519_public_keys(SR25519)).is_empty());
+		for uri in ["vault://keys", "no scheme"] {
+			let result = registry.open_from_uri(uri);
+			assert!(matches!(result, Err(Error::UnknownBackend(u)) if u == uri));
+		}
+	}
+}
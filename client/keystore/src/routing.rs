@@ -0,0 +1,368 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore routing the keys of every key type to a backend.
+
+use std::collections::{BTreeMap, HashMap};
+use async_trait::async_trait;
+use sp_core::crypto::{CryptoTypeId, CryptoTypePublicPair, KeyTypeId};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+use sp_keystore::{
+	CryptoStore, Error as TraitError, SupportedKeysReport,
+	vrf::{VRFTranscriptData, VRFSignature},
+};
+
+/// A keystore that keeps the keys of each key type in its own backend.
+///
+/// Meant for deployments that mix backends of different security, e.g. consensus keys in an
+/// HSM and the other keys on disk. Every call is delegated to the backend of its key type, key
+/// types without a backend go to the default backend. Without a default backend, calls for
+/// such key types fail with [`TraitError::KeyNotSupported`]; calls that can not return an
+/// error behave as if there were no keys.
+pub struct RoutingKeystore {
+	routes: HashMap<KeyTypeId, Box<dyn CryptoStore>>,
+	default: Option<Box<dyn CryptoStore>>,
+}
+
+impl RoutingKeystore {
+	/// Route the keys of the given key types to their backends and all other keys to
+	/// `default`, if any.
+	pub fn new(
+		routes: HashMap<KeyTypeId, Box<dyn CryptoStore>>,
+		default: Option<Box<dyn CryptoStore>>,
+	) -> Self {
+		Self { routes, default }
+	}
+
+	/// Returns the key types that have their own backend.
+	pub fn routed_key_types(&self) -> Vec<KeyTypeId> {
+		let mut key_types = self.routes.keys().copied().collect::<Vec<_>>();
+		key_types.sort();
+		key_types
+	}
+
+	/// Returns the backend of the key type.
+	fn backend(&self, id: KeyTypeId) -> Result<&dyn CryptoStore, TraitError> {
+		self.routes.get(&id)
+			.or(self.default.as_ref())
+			.map(|backend| &**backend)
+			.ok_or(TraitError::KeyNotSupported(id))
+	}
+}
+
+#[async_trait]
+impl CryptoStore for RoutingKeystore {
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		match self.backend(id) {
+			Ok(backend) => backend.sr25519_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<sr25519::Public, TraitError> {
+		self.backend(id)?.sr25519_generate_new(id, seed).await
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		match self.backend(id) {
+			Ok(backend) => backend.ed25519_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ed25519::Public, TraitError> {
+		self.backend(id)?.ed25519_generate_new(id, seed).await
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		match self.backend(id) {
+			Ok(backend) => backend.ecdsa_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ecdsa::Public, TraitError> {
+		self.backend(id)?.ecdsa_generate_new(id, seed).await
+	}
+
+	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> Result<(), ()> {
+		self.backend(id).map_err(|_| ())?.insert_unknown(id, suri, public).await
+	}
+
+	async fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), TraitError> {
+		self.backend(id)?.remove_key(id, public).await
+	}
+
+	async fn remove_keys(&self, items: &[(KeyTypeId, Vec<u8>)]) -> Vec<Result<(), TraitError>> {
+		let mut by_type = BTreeMap::<KeyTypeId, Vec<(KeyTypeId, Vec<u8>)>>::new();
+		for item in items {
+			by_type.entry(item.0).or_default().push(item.clone());
+		}
+
+		let mut removed = HashMap::new();
+		for (id, items) in by_type {
+			let results = match self.backend(id) {
+				Ok(backend) => backend.remove_keys(&items).await,
+				Err(_) => items.iter().map(|_| Err(TraitError::KeyNotSupported(id))).collect(),
+			};
+			removed.insert(id, results.into_iter());
+		}
+		items.iter()
+			.map(|(id, _)| {
+				removed.get_mut(id).and_then(Iterator::next).expect("One result per item; qed")
+			})
+			.collect()
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.backend(id)?.supported_keys(id, keys).await
+	}
+
+	async fn supported_keys_detailed(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<SupportedKeysReport, TraitError> {
+		self.backend(id)?.supported_keys_detailed(id, keys).await
+	}
+
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.backend(id)?.authority_keys(id, candidates).await
+	}
+
+	async fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.backend(id)?.keys_not_in(id, authorities).await
+	}
+
+	async fn keys_by_type(
+		&self,
+		id: KeyTypeId,
+	) -> Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, TraitError> {
+		self.backend(id)?.keys_by_type(id).await
+	}
+
+	async fn vrf_capable_keys(&self, id: KeyTypeId) -> Result<Vec<sr25519::Public>, TraitError> {
+		self.backend(id)?.vrf_capable_keys(id).await
+	}
+
+	async fn keys(&self, id: KeyTypeId) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.backend(id)?.keys(id).await
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		for (public, id) in public_keys {
+			match self.backend(*id) {
+				Ok(backend) if backend.has_keys(&[(public.clone(), *id)]).await => (),
+				_ => return false,
+			}
+		}
+		true
+	}
+
+	async fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		match self.backend(id) {
+			Ok(backend) => backend.has_key_exact(id, public).await,
+			Err(_) => false,
+		}
+	}
+
+	/// The schemes supported by any backend.
+	///
+	/// A scheme may still be unsupported for the key types routed to other backends.
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		let mut schemes = Vec::new();
+		for backend in self.routes.values().chain(self.default.iter()) {
+			schemes.extend(backend.supported_schemes().await);
+		}
+		schemes.sort();
+		schemes.dedup();
+		schemes
+	}
+
+	/// The routed key types and the key types of the default backend that are not routed.
+	async fn key_types(&self) -> Result<Vec<KeyTypeId>, TraitError> {
+		let mut key_types = self.routed_key_types();
+		if let Some(default) = &self.default {
+			let unrouted = default.key_types().await?;
+			key_types.extend(unrouted.into_iter().filter(|id| !self.routes.contains_key(id)));
+		}
+		key_types.sort();
+		Ok(key_types)
+	}
+
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> Result<(), TraitError> {
+		let mut by_type = BTreeMap::<KeyTypeId, Vec<(KeyTypeId, Vec<u8>)>>::new();
+		for key in keys {
+			by_type.entry(key.0).or_default().push(key.clone());
+		}
+
+		let mut failures = Vec::new();
+		for (id, keys) in by_type {
+			let result = match self.backend(id) {
+				Ok(backend) => backend.preload(&keys).await,
+				Err(e) => Err(e),
+			};
+			if let Err(e) = result {
+				failures.push(e.to_string());
+			}
+		}
+
+		if failures.is_empty() {
+			Ok(())
+		} else {
+			Err(TraitError::ValidationError(failures.join("; ")))
+		}
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> Result<Option<Vec<u8>>, TraitError> {
+		self.backend(id)?.sign_with(id, key, msg).await
+	}
+
+	async fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> Result<Vec<Vec<u8>>, TraitError> {
+		self.backend(id)?.sign_with_many(id, key, msgs).await
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> Result<Option<VRFSignature>, TraitError> {
+		self.backend(key_type)?.sr25519_vrf_sign(key_type, public, transcript_data).await
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> Result<ecdsa::Signature, TraitError> {
+		self.backend(id)?.ecdsa_sign_prehashed(id, public, msg).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use futures::executor::block_on;
+	use sp_core::testing::{ED25519, SR25519};
+	use crate::{LocalKeystore, Operation, Scope, ScopedKeystore};
+
+	/// A backend sharing the keys of the given keystore.
+	fn shared(store: &Arc<LocalKeystore>) -> Box<dyn CryptoStore> {
+		let scope = [SR25519, ED25519].iter().fold(Scope::new(), |scope, id| {
+			scope.allow(*id, Operation::List)
+				.allow(*id, Operation::Generate)
+				.allow(*id, Operation::Sign)
+		});
+		Box::new(ScopedKeystore::new(store.clone(), scope))
+	}
+
+	#[test]
+	fn keys_are_routed_by_key_type() {
+		let hsm = Arc::new(LocalKeystore::in_memory());
+		let disk = Arc::new(LocalKeystore::in_memory());
+		let routes = || vec![(SR25519, shared(&hsm))].into_iter().collect::<HashMap<_, _>>();
+
+		let store = RoutingKeystore::new(routes(), Some(shared(&disk)));
+		assert_eq!(store.routed_key_types(), vec![SR25519]);
+		let sr25519 = block_on(store.sr25519_generate_new(SR25519, None)).unwrap();
+		let ed25519 = block_on(store.ed25519_generate_new(ED25519, None)).unwrap();
+		assert_eq!(block_on(hsm.sr25519_public_keys(SR25519)), vec![sr25519]);
+		assert!(block_on(disk.sr25519_public_keys(SR25519)).is_empty());
+		assert_eq!(block_on(disk.ed25519_public_keys(ED25519)), vec![ed25519]);
+		assert!(block_on(store.sign_with(ED25519, &ed25519.into(), b"msg")).unwrap().is_some());
+		let keys = [(sr25519.to_vec(), SR25519), (ed25519.to_vec(), ED25519)];
+		assert!(block_on(store.has_keys(&keys)));
+
+		let store = RoutingKeystore::new(routes(), None);
+		assert!(matches!(
+			block_on(store.ed25519_generate_new(ED25519, None)),
+			Err(TraitError::KeyNotSupported(id)) if id == ED25519,
+		));
+		assert!(block_on(store.ed25519_public_keys(ED25519)).is_empty());
+	}
+
+	#[test]
+	fn fingerprint_covers_the_keys_calls_are_routed_to() {
+		let hsm = LocalKeystore::in_memory();
+		let disk = LocalKeystore::in_memory();
+		let sr25519 = block_on(hsm.sr25519_generate_new(SR25519, None)).unwrap();
+		let ed25519 = block_on(disk.ed25519_generate_new(ED25519, None)).unwrap();
+		// Shadowed by the route of its key type.
+		block_on(disk.sr25519_generate_new(SR25519, None)).unwrap();
+
+		let routes = vec![(SR25519, Box::new(hsm) as Box<dyn CryptoStore>)].into_iter().collect();
+		let store = RoutingKeystore::new(routes, Some(Box::new(disk)));
+		assert_eq!(block_on(store.key_types()).unwrap(), vec![ED25519, SR25519]);
+		assert_eq!(
+			block_on(store.fingerprint()).unwrap(),
+			sp_keystore::keys_fingerprint(vec![
+				(SR25519, sr25519.to_vec()),
+				(ED25519, ed25519.to_vec()),
+			]),
+		);
+	}
+
+	#[test]
+	fn supported_schemes_are_those_of_any_backend() {
+		let local = Arc::new(LocalKeystore::in_memory());
+		let empty = RoutingKeystore::new(HashMap::new(), None);
+		assert!(block_on(empty.supported_schemes()).is_empty());
+
+		let store = RoutingKeystore::new(HashMap::new(), Some(shared(&local)));
+		let mut expected = vec![sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID];
+		expected.sort();
+		assert_eq!(block_on(store.supported_schemes()), expected);
+	}
+}
@@ -0,0 +1,332 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore access restricted to a scope of operations.
+
+use std::{collections::{BTreeSet, HashMap}, sync::Arc};
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use sp_core::crypto::{CryptoTypeId, CryptoTypePublicPair, KeyTypeId};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+use sp_keystore::{
+	CryptoStore, Error as TraitError, SupportedKeysReport,
+	vrf::{VRFTranscriptData, VRFSignature},
+};
+
+/// An operation on the keys of a key type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
+pub enum Operation {
+	/// List the public keys and check which keys exist.
+	List,
+	/// Generate or insert keys.
+	Generate,
+	/// Remove keys.
+	Remove,
+	/// Sign messages, including pre-hashed ecdsa messages.
+	Sign,
+	/// Produce VRF signatures.
+	VrfSign,
+}
+
+/// The operations a [`ScopedKeystore`] allows, per key type.
+///
+/// The scope is SCALE encodable, so it can be attached to an authentication token.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Scope {
+	allowed: BTreeSet<(KeyTypeId, Operation)>,
+}
+
+impl Scope {
+	/// Create a scope that allows nothing.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Allow the operation on the keys of the key type.
+	pub fn allow(mut self, id: KeyTypeId, operation: Operation) -> Self {
+		self.allowed.insert((id, operation));
+		self
+	}
+
+	/// Returns `true` if the operation is allowed on the keys of the key type.
+	pub fn allows(&self, id: KeyTypeId, operation: Operation) -> bool {
+		self.allowed.contains(&(id, operation))
+	}
+}
+
+/// A keystore that only allows the operations of a [`Scope`].
+///
+/// Every call is checked against the scope before it is delegated to the wrapped keystore.
+/// Disallowed calls fail with [`TraitError::PermissionDenied`]; calls that can not return an
+/// error behave as if there were no keys. The fingerprint covers the keys of all key types and
+/// is therefore never allowed.
+pub struct ScopedKeystore {
+	inner: Arc<dyn CryptoStore>,
+	scope: Scope,
+}
+
+impl ScopedKeystore {
+	/// Restrict the wrapped keystore to the given scope.
+	pub fn new(inner: Arc<dyn CryptoStore>, scope: Scope) -> Self {
+		Self { inner, scope }
+	}
+
+	/// The scope of this keystore.
+	pub fn scope(&self) -> &Scope {
+		&self.scope
+	}
+
+	fn check(&self, id: KeyTypeId, operation: Operation) -> Result<(), TraitError> {
+		if self.scope.allows(id, operation) {
+			Ok(())
+		} else {
+			Err(TraitError::PermissionDenied)
+		}
+	}
+}
+
+#[async_trait]
+impl CryptoStore for ScopedKeystore {
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		match self.check(id, Operation::List) {
+			Ok(()) => self.inner.sr25519_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<sr25519::Public, TraitError> {
+		self.check(id, Operation::Generate)?;
+		self.inner.sr25519_generate_new(id, seed).await
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		match self.check(id, Operation::List) {
+			Ok(()) => self.inner.ed25519_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ed25519::Public, TraitError> {
+		self.check(id, Operation::Generate)?;
+		self.inner.ed25519_generate_new(id, seed).await
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		match self.check(id, Operation::List) {
+			Ok(()) => self.inner.ecdsa_public_keys(id).await,
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ecdsa::Public, TraitError> {
+		self.check(id, Operation::Generate)?;
+		self.inner.ecdsa_generate_new(id, seed).await
+	}
+
+	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> Result<(), ()> {
+		self.check(id, Operation::Generate).map_err(|_| ())?;
+		self.inner.insert_unknown(id, suri, public).await
+	}
+
+	async fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), TraitError> {
+		self.check(id, Operation::Remove)?;
+		self.inner.remove_key(id, public).await
+	}
+
+	async fn remove_keys(&self, items: &[(KeyTypeId, Vec<u8>)]) -> Vec<Result<(), TraitError>> {
+		let allowed = items.iter()
+			.filter(|(id, _)| self.scope.allows(*id, Operation::Remove))
+			.cloned()
+			.collect::<Vec<_>>();
+		let mut removed = self.inner.remove_keys(&allowed).await.into_iter();
+		items.iter()
+			.map(|(id, _)| {
+				self.check(*id, Operation::Remove)?;
+				removed.next().expect("One result per allowed item; qed")
+			})
+			.collect()
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.supported_keys(id, keys).await
+	}
+
+	async fn supported_keys_detailed(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<SupportedKeysReport, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.supported_keys_detailed(id, keys).await
+	}
+
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.authority_keys(id, candidates).await
+	}
+
+	async fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.keys_not_in(id, authorities).await
+	}
+
+	async fn keys_by_type(
+		&self,
+		id: KeyTypeId,
+	) -> Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.keys_by_type(id).await
+	}
+
+	async fn vrf_capable_keys(&self, id: KeyTypeId) -> Result<Vec<sr25519::Public>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.vrf_capable_keys(id).await
+	}
+
+	async fn keys(&self, id: KeyTypeId) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.check(id, Operation::List)?;
+		self.inner.keys(id).await
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		public_keys.iter().all(|(_, id)| self.scope.allows(*id, Operation::List)) &&
+			self.inner.has_keys(public_keys).await
+	}
+
+	async fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		self.scope.allows(id, Operation::List) && self.inner.has_key_exact(id, public).await
+	}
+
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		self.inner.supported_schemes().await
+	}
+
+	async fn fingerprint(&self) -> Result<[u8; 32], TraitError> {
+		Err(TraitError::PermissionDenied)
+	}
+
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> Result<(), TraitError> {
+		for (id, _) in keys {
+			self.check(*id, Operation::Sign)?;
+		}
+		self.inner.preload(keys).await
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> Result<Option<Vec<u8>>, TraitError> {
+		self.check(id, Operation::Sign)?;
+		self.inner.sign_with(id, key, msg).await
+	}
+
+	async fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> Result<Vec<Vec<u8>>, TraitError> {
+		self.check(id, Operation::Sign)?;
+		self.inner.sign_with_many(id, key, msgs).await
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> Result<Option<VRFSignature>, TraitError> {
+		self.check(key_type, Operation::VrfSign)?;
+		self.inner.sr25519_vrf_sign(key_type, public, transcript_data).await
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> Result<ecdsa::Signature, TraitError> {
+		self.check(id, Operation::Sign)?;
+		self.inner.ecdsa_sign_prehashed(id, public, msg).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::testing::{ED25519, SR25519};
+	use crate::LocalKeystore;
+
+	#[test]
+	fn scope_is_enforced() {
+		let store = Arc::new(LocalKeystore::in_memory());
+		let public = block_on(store.ed25519_generate_new(ED25519, None)).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+
+		let scope = Scope::new().allow(ED25519, Operation::Sign);
+		let scoped = ScopedKeystore::new(store.clone(), scope.clone());
+
+		assert!(block_on(scoped.sign_with(ED25519, &key, b"msg")).unwrap().is_some());
+		assert!(matches!(
+			block_on(scoped.sign_with(SR25519, &key, b"msg")),
+			Err(TraitError::PermissionDenied),
+		));
+		assert!(matches!(
+			block_on(scoped.ed25519_generate_new(ED25519, None)),
+			Err(TraitError::PermissionDenied),
+		));
+		assert!(matches!(
+			block_on(scoped.remove_key(ED25519, &key)),
+			Err(TraitError::PermissionDenied),
+		));
+		assert!(block_on(scoped.ed25519_public_keys(ED25519)).is_empty());
+		assert!(!block_on(scoped.has_keys(&[(public.to_vec(), ED25519)])));
+
+		let scope = Scope::decode(&mut &scope.encode()[..]).unwrap();
+		assert!(scope.allows(ED25519, Operation::Sign));
+		assert!(!scope.allows(ED25519, Operation::List));
+	}
+}
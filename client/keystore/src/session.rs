@@ -0,0 +1,166 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Session keys bundles, as submitted to the runtime after rotating keys.
+
+use sp_core::crypto::{CryptoTypeId, KeyTypeId};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+use sp_keystore::SyncCryptoStore;
+
+use crate::{Error, Result, well_known::ConsensusKeyType};
+
+/// The session keys of a runtime, with a key of the given crypto type for each key type.
+///
+/// The keys are listed in the order the runtime declares them in its `SessionKeys`; the
+/// encoding of the bundle is the concatenation of their public keys in that order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionKeys {
+	keys: Vec<(KeyTypeId, CryptoTypeId)>,
+}
+
+impl SessionKeys {
+	/// An empty bundle.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Append a key of the given key type and crypto type.
+	pub fn with(mut self, key_type: KeyTypeId, crypto: CryptoTypeId) -> Self {
+		self.keys.push((key_type, crypto));
+		self
+	}
+
+	/// Append the key of a consensus engine, with the crypto type the engine uses.
+	pub fn with_consensus(self, key_type: ConsensusKeyType) -> Self {
+		self.with(key_type.key_type(), key_type.crypto())
+	}
+
+	/// The key types and crypto types of the keys, in order.
+	pub fn keys(&self) -> &[(KeyTypeId, CryptoTypeId)] {
+		&self.keys
+	}
+
+	/// Returns the encoded bundle of the keys in the keystore, generating the missing ones.
+	///
+	/// Fails with [`Error::InvalidSessionKey`] naming the key type if a key type is listed
+	/// twice, the crypto type is not supported or the keystore holds several keys of the
+	/// crypto type, as it is not clear which one of them is meant.
+	pub fn generate(&self, store: &dyn SyncCryptoStore) -> Result<Vec<u8>> {
+		self.encode_with(store, true)
+	}
+
+	/// Returns the encoded bundle of the keys in the keystore, without generating any.
+	///
+	/// Fails like [`SessionKeys::generate`], and with [`Error::MissingRequiredKeys`] naming
+	/// the key types without a key.
+	pub fn encode(&self, store: &dyn SyncCryptoStore) -> Result<Vec<u8>> {
+		self.encode_with(store, false)
+	}
+
+	fn encode_with(&self, store: &dyn SyncCryptoStore, generate: bool) -> Result<Vec<u8>> {
+		let mut encoded = Vec::new();
+		let mut missing = Vec::new();
+		for (index, (key_type, crypto)) in self.keys.iter().enumerate() {
+			let invalid = |reason: String| Error::InvalidSessionKey { key_type: *key_type, reason };
+			if self.keys[..index].iter().any(|(other, _)| other == key_type) {
+				return Err(invalid("listed more than once".into()));
+			}
+			if ![sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID].contains(crypto) {
+				return Err(invalid(format!("crypto type {:?} is not supported", crypto)));
+			}
+
+			let mut existing = SyncCryptoStore::keys(store, *key_type)
+				.map_err(|e| invalid(e.to_string()))?
+				.into_iter()
+				.filter(|key| key.0 == *crypto)
+				.map(|key| key.1)
+				.collect::<Vec<_>>();
+			let public = match existing.len() {
+				0 if generate => generate_key(store, *key_type, *crypto)
+					.map_err(|e| invalid(e.to_string()))?,
+				0 => {
+					missing.push(*key_type);
+					continue;
+				},
+				1 => existing.remove(0),
+				count => return Err(invalid(format!(
+					"{} keys of crypto type {:?}, expected one",
+					count,
+					crypto,
+				))),
+			};
+			encoded.extend_from_slice(&public);
+		}
+
+		if missing.is_empty() {
+			Ok(encoded)
+		} else {
+			Err(Error::MissingRequiredKeys(missing))
+		}
+	}
+}
+
+/// Generate a key of a supported crypto type, returning its raw public key.
+fn generate_key(
+	store: &dyn SyncCryptoStore,
+	key_type: KeyTypeId,
+	crypto: CryptoTypeId,
+) -> std::result::Result<Vec<u8>, sp_keystore::Error> {
+	use SyncCryptoStore as Store;
+	Ok(match crypto {
+		sr25519::CRYPTO_ID => Store::sr25519_generate_new(store, key_type, None)?.to_vec(),
+		ed25519::CRYPTO_ID => Store::ed25519_generate_new(store, key_type, None)?.to_vec(),
+		_ => Store::ecdsa_generate_new(store, key_type, None)?.0.to_vec(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::testing::{ECDSA, SR25519};
+	use crate::{LocalKeystore, well_known::{BABE, GRANDPA}};
+
+	#[test]
+	fn session_keys_are_concatenated_in_order() {
+		let store = LocalKeystore::in_memory();
+		let keys = SessionKeys::new()
+			.with_consensus(ConsensusKeyType::Grandpa)
+			.with_consensus(ConsensusKeyType::Babe)
+			.with(ECDSA, ecdsa::CRYPTO_ID);
+		assert!(matches!(keys.encode(&store), Err(Error::MissingRequiredKeys(missing))
+			if missing == vec![GRANDPA, BABE, ECDSA]));
+
+		let encoded = keys.generate(&store).unwrap();
+		let grandpa = SyncCryptoStore::ed25519_public_keys(&store, GRANDPA);
+		let babe = SyncCryptoStore::sr25519_public_keys(&store, BABE);
+		let ecdsa = SyncCryptoStore::ecdsa_public_keys(&store, ECDSA);
+		let expected = [&grandpa[0].0[..], &babe[0].0[..], &ecdsa[0].0[..]].concat();
+		assert_eq!(encoded, expected);
+		assert_eq!(keys.generate(&store).unwrap(), expected);
+		assert_eq!(keys.encode(&store).unwrap(), expected);
+
+		SyncCryptoStore::sr25519_generate_new(&store, BABE, None).unwrap();
+		assert!(matches!(keys.encode(&store), Err(Error::InvalidSessionKey { key_type, .. })
+			if key_type == BABE));
+		let twice = SessionKeys::new()
+			.with(SR25519, sr25519::CRYPTO_ID)
+			.with(SR25519, ed25519::CRYPTO_ID);
+		assert!(matches!(twice.generate(&store), Err(Error::InvalidSessionKey { key_type, .. })
+			if key_type == SR25519));
+	}
+}
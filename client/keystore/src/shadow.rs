@@ -0,0 +1,312 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore that validates operations without performing them.
+
+use std::{collections::HashMap, sync::Arc};
+use async_trait::async_trait;
+use sp_core::crypto::{CryptoTypeId, CryptoTypePublicPair, KeyTypeId, Public};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+use sp_keystore::{
+	CryptoStore, SyncCryptoStore, Error as TraitError, SupportedKeysReport,
+	vrf::{VRFTranscriptData, VRFSignature},
+};
+
+use crate::LocalKeystore;
+
+/// Key type of the throwaway keys a [`ShadowKeystore`] signs with.
+const THROWAWAY: KeyTypeId = KeyTypeId(*b"shdw");
+
+/// A keystore running a backend in shadow, for staging it under real traffic.
+///
+/// Listing and checking keys is delegated to the wrapped keystore. Operations that would change
+/// its keys or use its secrets are only validated against it and logged:
+///
+/// - generating and inserting keys checks that the wrapped keystore supports the crypto type,
+///   then creates the key in memory only, it is not listed;
+/// - removing keys checks that the key exists and removes it from neither keystore, except for
+///   keys created in memory;
+/// - signing checks that the key exists and signs with a throwaway key of the same crypto type.
+///
+/// **The signatures are made with throwaway keys and do not verify against the requested
+/// keys. They must never be used in production.**
+pub struct ShadowKeystore {
+	inner: Arc<dyn CryptoStore>,
+	scratch: LocalKeystore,
+	throwaway: Vec<CryptoTypePublicPair>,
+}
+
+impl ShadowKeystore {
+	/// Run the wrapped keystore in shadow.
+	pub fn new(inner: Arc<dyn CryptoStore>) -> Self {
+		let scratch = LocalKeystore::in_memory();
+		let expect = "generating keys in memory does not fail; qed";
+		let throwaway = vec![
+			SyncCryptoStore::sr25519_generate_new(&scratch, THROWAWAY, None).expect(expect).into(),
+			SyncCryptoStore::ed25519_generate_new(&scratch, THROWAWAY, None).expect(expect).into(),
+			SyncCryptoStore::ecdsa_generate_new(&scratch, THROWAWAY, None).expect(expect).into(),
+		];
+		Self { inner, scratch, throwaway }
+	}
+
+	/// Returns `true` if the key exists in the wrapped keystore or was created in memory.
+	async fn knows(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		let key = [(public.to_vec(), id)];
+		self.inner.has_keys(&key).await || SyncCryptoStore::has_keys(&self.scratch, &key)
+	}
+
+	/// Fails with [`TraitError::KeyNotSupported`] if the wrapped keystore does not support the
+	/// crypto type.
+	async fn check_scheme(&self, id: KeyTypeId, crypto: CryptoTypeId) -> Result<(), TraitError> {
+		if self.inner.supported_schemes().await.contains(&crypto) {
+			Ok(())
+		} else {
+			Err(TraitError::KeyNotSupported(id))
+		}
+	}
+
+	/// The throwaway key of the crypto type.
+	fn throwaway(
+		&self,
+		id: KeyTypeId,
+		crypto: CryptoTypeId,
+	) -> Result<&CryptoTypePublicPair, TraitError> {
+		self.throwaway.iter().find(|key| key.0 == crypto).ok_or(TraitError::KeyNotSupported(id))
+	}
+
+	fn log(&self, operation: &str, id: KeyTypeId, key: &str) {
+		log::info!(
+			target: "keystore",
+			"Shadow keystore would {} {} of type {:?}", operation, key, id,
+		);
+	}
+}
+
+fn describe(key: &CryptoTypePublicPair) -> String {
+	format!("the {} key {}", String::from_utf8_lossy(&key.0 .0), hex::encode(&key.1))
+}
+
+#[async_trait]
+impl CryptoStore for ShadowKeystore {
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		self.inner.sr25519_public_keys(id).await
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<sr25519::Public, TraitError> {
+		self.check_scheme(id, sr25519::CRYPTO_ID).await?;
+		self.log("generate", id, "a new sr25519 key");
+		SyncCryptoStore::sr25519_generate_new(&self.scratch, id, seed)
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		self.inner.ed25519_public_keys(id).await
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ed25519::Public, TraitError> {
+		self.check_scheme(id, ed25519::CRYPTO_ID).await?;
+		self.log("generate", id, "a new ed25519 key");
+		SyncCryptoStore::ed25519_generate_new(&self.scratch, id, seed)
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		self.inner.ecdsa_public_keys(id).await
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> Result<ecdsa::Public, TraitError> {
+		self.check_scheme(id, ecdsa::CRYPTO_ID).await?;
+		self.log("generate", id, "a new ecdsa key");
+		SyncCryptoStore::ecdsa_generate_new(&self.scratch, id, seed)
+	}
+
+	/// The secret URI is checked to derive to the public key.
+	async fn insert_unknown(&self, id: KeyTypeId, suri: &str, public: &[u8]) -> Result<(), ()> {
+		SyncCryptoStore::insert_unknown(&self.scratch, id, suri, public)?;
+		self.log("insert", id, &format!("the key {}", hex::encode(public)));
+		Ok(())
+	}
+
+	async fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), TraitError> {
+		if SyncCryptoStore::has_keys(&self.scratch, &[(public.1.clone(), id)]) {
+			return SyncCryptoStore::remove_key(&self.scratch, id, public);
+		}
+		if !self.inner.has_keys(&[(public.1.clone(), id)]).await {
+			return Err(TraitError::PairNotFound);
+		}
+		self.log("remove", id, &describe(public));
+		Ok(())
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.inner.supported_keys(id, keys).await
+	}
+
+	async fn supported_keys_detailed(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<SupportedKeysReport, TraitError> {
+		self.inner.supported_keys_detailed(id, keys).await
+	}
+
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.inner.authority_keys(id, candidates).await
+	}
+
+	async fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.inner.keys_not_in(id, authorities).await
+	}
+
+	async fn keys_by_type(
+		&self,
+		id: KeyTypeId,
+	) -> Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, TraitError> {
+		self.inner.keys_by_type(id).await
+	}
+
+	async fn vrf_capable_keys(&self, id: KeyTypeId) -> Result<Vec<sr25519::Public>, TraitError> {
+		self.inner.vrf_capable_keys(id).await
+	}
+
+	async fn keys(&self, id: KeyTypeId) -> Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.inner.keys(id).await
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		self.inner.has_keys(public_keys).await
+	}
+
+	async fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		self.inner.has_key_exact(id, public).await
+	}
+
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		self.inner.supported_schemes().await
+	}
+
+	async fn key_types(&self) -> Result<Vec<KeyTypeId>, TraitError> {
+		self.inner.key_types().await
+	}
+
+	async fn fingerprint(&self) -> Result<[u8; 32], TraitError> {
+		self.inner.fingerprint().await
+	}
+
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> Result<(), TraitError> {
+		self.inner.preload(keys).await
+	}
+
+	/// Signs with the throwaway key of the crypto type, the signature does not verify against
+	/// `key`.
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> Result<Option<Vec<u8>>, TraitError> {
+		let throwaway = self.throwaway(id, key.0)?;
+		if !self.knows(id, &key.1).await {
+			return Ok(None);
+		}
+		self.log("sign with", id, &describe(key));
+		SyncCryptoStore::sign_with(&self.scratch, THROWAWAY, throwaway, msg)
+	}
+
+	/// Signs with the throwaway sr25519 key, the signature does not verify against `public`.
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> Result<Option<VRFSignature>, TraitError> {
+		if !self.knows(key_type, public.as_ref()).await {
+			return Ok(None);
+		}
+		self.log("VRF sign with", key_type, &describe(&(*public).into()));
+		let throwaway = self.throwaway(key_type, sr25519::CRYPTO_ID)?;
+		let throwaway = sr25519::Public::from_slice(&throwaway.1);
+		SyncCryptoStore::sr25519_vrf_sign(&self.scratch, THROWAWAY, &throwaway, transcript_data)
+	}
+
+	/// Signs with the throwaway ecdsa key, the signature does not verify against `public`.
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> Result<ecdsa::Signature, TraitError> {
+		if !self.knows(id, public.as_ref()).await {
+			return Err(TraitError::PairNotFound);
+		}
+		self.log("sign a hash with", id, &describe(&public.clone().into()));
+		let throwaway = self.throwaway(id, ecdsa::CRYPTO_ID)?;
+		let throwaway = ecdsa::Public::from_slice(&throwaway.1);
+		SyncCryptoStore::ecdsa_sign_prehashed(&self.scratch, THROWAWAY, &throwaway, msg)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::{Pair, testing::ED25519};
+
+	#[test]
+	fn nothing_is_persisted_and_signatures_are_throwaway() {
+		let store = Arc::new(LocalKeystore::in_memory());
+		let public = SyncCryptoStore::ed25519_generate_new(&*store, ED25519, None).unwrap();
+		let real = CryptoTypePublicPair::from(public);
+		let shadow = ShadowKeystore::new(store.clone());
+
+		let generated = block_on(shadow.ed25519_generate_new(ED25519, None)).unwrap();
+		assert_eq!(block_on(shadow.ed25519_public_keys(ED25519)), vec![public]);
+		assert!(block_on(shadow.sign_with(ED25519, &generated.into(), b"msg")).unwrap().is_some());
+
+		let signature = block_on(shadow.sign_with(ED25519, &real, b"msg")).unwrap().unwrap();
+		let signature = ed25519::Signature::from_slice(&signature);
+		assert!(!ed25519::Pair::verify(&signature, b"msg", &public));
+		let unknown = CryptoTypePublicPair(ed25519::CRYPTO_ID, vec![7; 32]);
+		assert_eq!(block_on(shadow.sign_with(ED25519, &unknown, b"msg")).unwrap(), None);
+
+		block_on(shadow.remove_key(ED25519, &real)).unwrap();
+		assert!(SyncCryptoStore::has_keys(&*store, &[(real.1, ED25519)]));
+	}
+}
@@ -0,0 +1,178 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Shamir secret sharing of keystore passwords.
+//!
+//! The shares are made and combined by the `sharks` crate: every byte of the secret is the
+//! constant term of its own random polynomial over GF(2^8) of degree `threshold - 1`. A share
+//! is the threshold, its index and the values of the polynomials at the index, so any
+//! `threshold` shares interpolate the secret and fewer reveal nothing about it.
+
+use std::convert::TryFrom;
+use sharks::{Share, Sharks};
+use sp_core::crypto::{ExposeSecret, SecretString};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{Error, Result};
+
+/// Split the password into `shares` shares, any `threshold` of which reconstruct it.
+///
+/// Needs `2 <= threshold <= shares <= 255`. The shares must be handed to different operators
+/// and are zeroized on drop; see [`LocalKeystoreBuilder::password_shares`] for unlocking a
+/// keystore with them.
+///
+/// [`LocalKeystoreBuilder::password_shares`]: crate::LocalKeystoreBuilder::password_shares
+pub fn split_password(
+	password: &SecretString,
+	threshold: u8,
+	shares: u8,
+) -> Result<Vec<Zeroizing<Vec<u8>>>> {
+	let secret = password.expose_secret().as_bytes();
+	if threshold < 2 || threshold > shares {
+		return Err(Error::InvalidShare(format!(
+			"Threshold {} of {} shares is not between 2 and the number of shares",
+			threshold,
+			shares,
+		)));
+	}
+	if secret.is_empty() {
+		return Err(Error::InvalidShare("An empty password can not be split".into()));
+	}
+
+	// The dealer numbers the shares from 1.
+	let split = Sharks(threshold).dealer(secret)
+		.take(shares as usize)
+		.map(|share| {
+			let encoded = Zeroizing::new(Vec::from(&share));
+			let mut share = Zeroizing::new(Vec::with_capacity(encoded.len() + 1));
+			share.push(threshold);
+			share.extend_from_slice(&encoded);
+			share
+		})
+		.collect();
+	Ok(split)
+}
+
+/// Reconstruct the secret from `threshold` shares made by [`split_password`].
+pub(crate) fn combine(shares: &[Zeroizing<Vec<u8>>]) -> Result<Zeroizing<Vec<u8>>> {
+	let invalid = |reason: &str| Error::InvalidShare(reason.into());
+	let first = shares.first().ok_or_else(|| invalid("No shares"))?;
+	if shares.iter().any(|share| share.len() != first.len() || share[0] != first[0]) {
+		return Err(invalid("The shares are not of the same password"));
+	}
+
+	let shares = shares.iter()
+		.map(|share| Share::try_from(&share[1..]).map_err(invalid))
+		.collect::<Result<Vec<_>>>()?;
+	Sharks(first[0]).recover(&shares).map(Zeroizing::new).map_err(invalid)
+}
+
+/// Validate a share and return its threshold and index.
+pub(crate) fn parse(share: &[u8]) -> Result<(u8, u8)> {
+	match share {
+		[threshold, index, _, ..] if *threshold >= 2 && *index != 0 => Ok((*threshold, *index)),
+		_ => Err(Error::InvalidShare("Malformed share".into())),
+	}
+}
+
+/// Shares collected to reconstruct the password of a keystore.
+pub(crate) struct PendingShares {
+	threshold: u8,
+	shares: Vec<Zeroizing<Vec<u8>>>,
+}
+
+impl PendingShares {
+	/// Collect `threshold` shares.
+	pub fn new(threshold: u8) -> Self {
+		Self { threshold, shares: Vec::new() }
+	}
+
+	/// Add a share, returning the reconstructed password once there are enough of them.
+	///
+	/// A share with an index that was already added is ignored.
+	pub fn add(&mut self, share: &[u8]) -> Result<Option<SecretString>> {
+		let (threshold, index) = parse(share)?;
+		if threshold != self.threshold {
+			return Err(Error::InvalidShare(format!(
+				"Share of a {} share threshold, {} are required",
+				threshold,
+				self.threshold,
+			)));
+		}
+		if !self.shares.iter().any(|added| added[1] == index) {
+			self.shares.push(Zeroizing::new(share.to_vec()));
+		}
+		if self.shares.len() < self.threshold as usize {
+			return Ok(None);
+		}
+
+		let secret = combine(&self.shares);
+		self.shares.clear();
+		let mut secret = secret?;
+		match String::from_utf8(std::mem::take(&mut *secret)) {
+			Ok(password) => Ok(Some(SecretString::new(password))),
+			Err(e) => {
+				e.into_bytes().zeroize();
+				Err(Error::InvalidShare("The shares do not reconstruct a password".into()))
+			},
+		}
+	}
+
+	/// The number of distinct shares added so far.
+	pub fn added(&self) -> usize {
+		self.shares.len()
+	}
+
+	/// The number of shares needed.
+	pub fn threshold(&self) -> usize {
+		self.threshold as usize
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::str::FromStr;
+
+	#[test]
+	fn any_threshold_shares_reconstruct_the_password() {
+		// "A" as the constant term of `0x41 + x` and `0x41 + 0x57x + 0x83x^2`, in GF(2^8) with
+		// the AES polynomial.
+		let line = [Zeroizing::new(vec![2, 1, 0x40]), Zeroizing::new(vec![2, 2, 0x43])];
+		assert_eq!(&combine(&line).unwrap()[..], b"A");
+		let parabola = [
+			Zeroizing::new(vec![3, 1, 0x95]),
+			Zeroizing::new(vec![3, 2, 0xd5]),
+			Zeroizing::new(vec![3, 3, 0x01]),
+		];
+		assert_eq!(&combine(&parabola).unwrap()[..], b"A");
+
+		let password = SecretString::from_str("correct horse battery staple").unwrap();
+		let shares = split_password(&password, 3, 5).unwrap();
+		assert_eq!(shares.len(), 5);
+		for subset in &[[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+			let subset = subset.iter().map(|i| shares[*i].clone()).collect::<Vec<_>>();
+			assert_eq!(&combine(&subset).unwrap()[..], password.expose_secret().as_bytes());
+		}
+		let two = [shares[0].clone(), shares[1].clone()];
+		assert!(matches!(combine(&two), Err(Error::InvalidShare(_))));
+
+		assert!(matches!(split_password(&password, 1, 5), Err(Error::InvalidShare(_))));
+		assert!(matches!(split_password(&password, 4, 3), Err(Error::InvalidShare(_))));
+	}
+}
@@ -0,0 +1,178 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The sidecar files next to a key file: when the key was last used (`.used`), its encrypted
+//! note (`.note`), its aliases (`.alias`) and its metadata (`.meta`).
+//!
+//! The functions take the path of the key file the sidecar belongs to. Sidecar files are never
+//! read to find keys, a missing sidecar file reads as empty.
+
+use std::{io, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+use sp_core::crypto::SecretString;
+
+use crate::{Error, Result, backup, dir::Dir};
+
+/// Extension of the sidecar file next to a key file that records when the key was last used.
+///
+/// The key file itself is never touched when signing, so recording a use is a cheap write of a
+/// small file that does not involve the secret.
+pub(crate) const LAST_USED_EXTENSION: &str = "used";
+
+/// Extension of the sidecar file next to a key file that holds its encrypted note.
+pub(crate) const NOTE_EXTENSION: &str = "note";
+
+/// Extension of the sidecar file next to a key file that holds its aliases, one per line.
+pub(crate) const ALIAS_EXTENSION: &str = "alias";
+
+/// Extension of the sidecar file next to a key file that holds its metadata as JSON.
+pub(crate) const METADATA_EXTENSION: &str = "meta";
+
+/// Extensions of the sidecar files that belong to a key file.
+pub(crate) const SIDECAR_EXTENSIONS: [&str; 4] =
+	[LAST_USED_EXTENSION, NOTE_EXTENSION, ALIAS_EXTENSION, METADATA_EXTENSION];
+
+/// What tools record about a key, see [`LocalKeystore::update_metadata`].
+///
+/// Stored as JSON next to the key file.
+///
+/// [`LocalKeystore::update_metadata`]: crate::LocalKeystore::update_metadata
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct KeyMetadata {
+	/// Free form tags of the key.
+	pub tags: Vec<String>,
+	/// Where the key comes from, e.g. the tool or ceremony that created it.
+	pub provenance: Option<String>,
+}
+
+/// Read and decrypt the note of the key file at `key_file`.
+pub(crate) fn read_note(
+	dir: &Dir,
+	key_file: &Path,
+	password: Option<&SecretString>,
+) -> Result<Option<String>> {
+	let sealed = match dir.read(key_file.with_extension(NOTE_EXTENSION)) {
+		Ok(sealed) => sealed,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	let note = backup::decrypt(&sealed, password.ok_or(Error::InvalidPassword)?)?;
+	String::from_utf8(note.to_vec())
+		.map(Some)
+		.map_err(|_| Error::InvalidBackup("malformed note".into()))
+}
+
+/// Encrypt the note of the key file at `key_file` and write it next to it.
+pub(crate) fn write_note(
+	dir: &Dir,
+	key_file: &Path,
+	password: Option<&SecretString>,
+	note: &str,
+) -> Result<()> {
+	let sealed = backup::encrypt(note.as_bytes(), password.ok_or(Error::InvalidPassword)?, false)?;
+	dir.write(key_file.with_extension(NOTE_EXTENSION), sealed)?;
+	Ok(())
+}
+
+/// Read the aliases of the key file at `key_file`, decrypting them if they are encrypted.
+pub(crate) fn read_aliases(
+	dir: &Dir,
+	key_file: &Path,
+	password: Option<&SecretString>,
+) -> Result<Vec<String>> {
+	let path = key_file.with_extension(ALIAS_EXTENSION);
+	let content = match dir.read(&path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(Error::File { path, error: e }),
+	};
+	let content = if backup::is_encrypted(&content) {
+		backup::decrypt(&content, password.ok_or(Error::InvalidPassword)?)?.to_vec()
+	} else {
+		content
+	};
+	let aliases = String::from_utf8(content).map_err(|_| {
+		let error = io::Error::new(io::ErrorKind::InvalidData, "aliases are not UTF-8");
+		Error::File { path: path.clone(), error }
+	})?;
+	Ok(aliases.lines().map(String::from).collect())
+}
+
+/// Returns `true` if the key file at `key_file` has aliases.
+pub(crate) fn has_aliases(dir: &Dir, key_file: &Path) -> bool {
+	dir.exists(key_file.with_extension(ALIAS_EXTENSION))
+}
+
+/// Write the aliases of the key file at `key_file`, encrypted with `password` if it is given.
+pub(crate) fn write_aliases(
+	dir: &Dir,
+	key_file: &Path,
+	aliases: &[String],
+	password: Option<&SecretString>,
+) -> Result<()> {
+	let path = key_file.with_extension(ALIAS_EXTENSION);
+	let content = aliases.join("\n");
+	match password {
+		Some(password) => dir.write(path, backup::encrypt(content.as_bytes(), password, false)?)?,
+		None => dir.write(path, content)?,
+	}
+	Ok(())
+}
+
+/// Read the metadata of the key file at `key_file`.
+pub(crate) fn read_metadata(dir: &Dir, key_file: &Path) -> Result<KeyMetadata> {
+	let path = key_file.with_extension(METADATA_EXTENSION);
+	match dir.read(&path) {
+		Ok(content) => Ok(serde_json::from_slice(&content)?),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KeyMetadata::default()),
+		Err(e) => Err(Error::File { path, error: e }),
+	}
+}
+
+/// Write the metadata of the key file at `key_file`.
+pub(crate) fn write_metadata(dir: &Dir, key_file: &Path, metadata: &KeyMetadata) -> Result<()> {
+	dir.write(key_file.with_extension(METADATA_EXTENSION), serde_json::to_vec(metadata)?)?;
+	Ok(())
+}
+
+/// Returns when the key of the key file at `key_file` was last used to sign.
+pub(crate) fn read_last_used(dir: &Dir, key_file: &Path) -> Result<Option<SystemTime>> {
+	match dir.read_to_string(key_file.with_extension(LAST_USED_EXTENSION)) {
+		Ok(millis) => millis.trim().parse::<u64>()
+			.map(|millis| Some(UNIX_EPOCH + Duration::from_millis(millis)))
+			.map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e))),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Record that the key of the key file at `key_file` was used to sign at `when`.
+///
+/// The sidecar file is not synced, and failing to write it is only logged.
+pub(crate) fn write_last_used(dir: &Dir, key_file: &Path, when: SystemTime) {
+	let millis = when.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+	let path = key_file.with_extension(LAST_USED_EXTENSION);
+	if let Err(e) = dir.write(&path, millis.to_string()) {
+		log::debug!(
+			target: "keystore",
+			"Failed to record the use of the key in {}: {}",
+			path.display(),
+			e,
+		);
+	}
+}
@@ -0,0 +1,719 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore backed by an SQLite database.
+//!
+//! All keys are rows of one table keyed by `(key_type, public)`, holding the crypto type of the
+//! key, its secret URI and free-form metadata. With a password the secret URIs are encrypted
+//! with ChaCha20-Poly1305 under a key derived from the password with PBKDF2, every row with its
+//! own nonce and its key type and public key as associated data. The salt of the key and a
+//! value to check the password against are stored in the `settings` table.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+use async_trait::async_trait;
+use chacha20poly1305::{
+	ChaCha20Poly1305, Key, Nonce,
+	aead::{Aead, NewAead, Payload},
+};
+use codec::Encode;
+use parking_lot::Mutex;
+use rand::{RngCore, rngs::OsRng};
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior, params};
+use sp_application_crypto::{ecdsa, ed25519, sr25519};
+use sp_core::crypto::{
+	CryptoTypeId, CryptoTypePublicPair, KeyTypeId, Pair as PairT, SecretString,
+};
+use sp_keystore::{
+	CryptoStore, Error as TraitError, SyncCryptoStore,
+	vrf::{VRFSignature, VRFTranscriptData, make_transcript},
+};
+use zeroize::Zeroizing;
+
+use crate::{Error, Result, backup, local::public_from_suri};
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS keys (
+		key_type BLOB NOT NULL,
+		public BLOB NOT NULL,
+		crypto_type BLOB NOT NULL,
+		seed BLOB NOT NULL,
+		metadata TEXT NOT NULL DEFAULT '',
+		PRIMARY KEY (key_type, public)
+	);
+	CREATE TABLE IF NOT EXISTS settings (
+		name TEXT PRIMARY KEY,
+		value BLOB NOT NULL
+	);
+";
+/// Name of the setting holding the salt, the PBKDF2 rounds and the password check value.
+const ENCRYPTION: &str = "encryption";
+/// Associated data of the password check value.
+const CHECK: &[u8] = b"sqlite keystore password check";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// How long to wait for another process to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A keystore holding its keys in an SQLite database.
+///
+/// Every write runs in its own transaction, so a key is either stored completely or not at all,
+/// and [`SqliteKeystore::insert_keys`] stores several keys atomically. The connection is shared
+/// by all threads of the process, other processes are kept out by the locking of SQLite.
+pub struct SqliteKeystore {
+	connection: Mutex<Connection>,
+	key: Option<Zeroizing<[u8; 32]>>,
+}
+
+impl SqliteKeystore {
+	/// Open the database at the given path, creating it if it does not exist.
+	///
+	/// A new database is encrypted if a password is given. Fails with
+	/// [`Error::InvalidPassword`] if the password does not match the one of the database, or
+	/// if one is given for a database that holds unencrypted keys.
+	pub fn open<T: AsRef<Path>>(path: T, password: Option<SecretString>) -> Result<Self> {
+		let mut connection = Connection::open(path)?;
+		connection.busy_timeout(BUSY_TIMEOUT)?;
+		let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+		transaction.execute_batch(SCHEMA)?;
+		let key = database_key(&transaction, password.as_ref())?;
+		transaction.commit()?;
+		Ok(Self { connection: Mutex::new(connection), key })
+	}
+
+	/// Insert the keys, given as key type, secret URI and public key, all at once.
+	///
+	/// Either all keys are stored or, if one fails, none. Existing keys with the same key type
+	/// and public key are replaced. Fails with [`Error::PublicKeyMismatch`] if a secret URI
+	/// does not derive to its public key.
+	pub fn insert_keys(&self, keys: &[(KeyTypeId, &str, &[u8])]) -> Result<()> {
+		let rows = keys.iter()
+			.map(|(key_type, suri, public)| {
+				let key = CryptoTypePublicPair(crypto_type_of(suri, public)?, public.to_vec());
+				Ok((*key_type, key, *suri))
+			})
+			.collect::<Result<Vec<_>>>()?;
+		self.write(|transaction| {
+			rows.iter().try_for_each(|(key_type, key, suri)| {
+				self.insert(transaction, *key_type, key, suri)
+			})
+		})
+	}
+
+	/// Returns the metadata of the key, `None` if there is no such key.
+	pub fn metadata(&self, key_type: KeyTypeId, public: &[u8]) -> Result<Option<String>> {
+		Ok(self.connection.lock().query_row(
+			"SELECT metadata FROM keys WHERE key_type = ?1 AND public = ?2",
+			params![&key_type.0[..], public],
+			|row| row.get(0),
+		).optional()?)
+	}
+
+	/// Replace the metadata of the key.
+	///
+	/// Fails with [`Error::PairNotFound`] if there is no such key.
+	pub fn set_metadata(&self, key_type: KeyTypeId, public: &[u8], metadata: &str) -> Result<()> {
+		self.write(|transaction| {
+			let updated = transaction.execute(
+				"UPDATE keys SET metadata = ?3 WHERE key_type = ?1 AND public = ?2",
+				params![&key_type.0[..], public, metadata],
+			)?;
+			if updated == 0 {
+				return Err(Error::PairNotFound);
+			}
+			Ok(())
+		})
+	}
+
+	/// Run the writes in one transaction, committing it if they succeed.
+	fn write<R>(&self, write: impl FnOnce(&Transaction) -> Result<R>) -> Result<R> {
+		let mut connection = self.connection.lock();
+		let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+		let result = write(&transaction)?;
+		transaction.commit()?;
+		Ok(result)
+	}
+
+	/// Store the key, replacing the key with the same key type and public key.
+	fn insert(
+		&self,
+		transaction: &Transaction,
+		key_type: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		suri: &str,
+	) -> Result<()> {
+		transaction.execute(
+			"INSERT INTO keys (key_type, public, crypto_type, seed) VALUES (?1, ?2, ?3, ?4)
+			ON CONFLICT (key_type, public) DO UPDATE
+			SET crypto_type = excluded.crypto_type, seed = excluded.seed",
+			params![&key_type.0[..], &key.1, &key.0.0[..], self.seal(key_type, &key.1, suri)],
+		)?;
+		Ok(())
+	}
+
+	/// Encrypt the secret URI of a key if the database is encrypted.
+	fn seal(&self, key_type: KeyTypeId, public: &[u8], suri: &str) -> Vec<u8> {
+		match &self.key {
+			Some(key) => seal(key, &[&key_type.0[..], public].concat(), suri.as_bytes()),
+			None => suri.as_bytes().to_vec(),
+		}
+	}
+
+	/// Returns the secret URI of the key if it is of the given crypto type.
+	fn suri(
+		&self,
+		key_type: KeyTypeId,
+		key: &CryptoTypePublicPair,
+	) -> Result<Option<Zeroizing<String>>> {
+		let seed: Option<Vec<u8>> = self.connection.lock().query_row(
+			"SELECT seed FROM keys WHERE key_type = ?1 AND public = ?2 AND crypto_type = ?3",
+			params![&key_type.0[..], &key.1, &key.0.0[..]],
+			|row| row.get(0),
+		).optional()?;
+		let seed = match (seed, &self.key) {
+			(Some(seed), Some(db_key)) => {
+				unseal(db_key, &[&key_type.0[..], &key.1[..]].concat(), &seed)?
+			},
+			(Some(seed), None) => Zeroizing::new(seed),
+			(None, _) => return Ok(None),
+		};
+		let suri = String::from_utf8(seed.to_vec()).map_err(|_| Error::InvalidSeed)?;
+		Ok(Some(Zeroizing::new(suri)))
+	}
+
+	/// Returns the key pair of the given crypto type.
+	fn key_pair<P: PairT>(
+		&self,
+		key_type: KeyTypeId,
+		key: &CryptoTypePublicPair,
+	) -> Result<Option<P>> {
+		self.suri(key_type, key)?
+			.map(|suri| P::from_string(&suri, None).map_err(|_| Error::InvalidSeed))
+			.transpose()
+	}
+
+	/// Generate a key or derive it from the seed, and store it.
+	fn generate<P: PairT>(&self, key_type: KeyTypeId, seed: Option<&str>) -> Result<P::Public>
+	where
+		P::Public: Into<CryptoTypePublicPair>,
+	{
+		let (pair, suri) = match seed {
+			Some(seed) => {
+				let pair = P::from_string(seed, None).map_err(|_| Error::InvalidSeed)?;
+				(pair, Zeroizing::new(seed.to_owned()))
+			},
+			None => {
+				let (pair, phrase, _) = P::generate_with_phrase(None);
+				(pair, Zeroizing::new(phrase))
+			},
+		};
+		let public = pair.public();
+		let key: CryptoTypePublicPair = public.clone().into();
+		self.write(|transaction| self.insert(transaction, key_type, &key, &suri))?;
+		Ok(public)
+	}
+
+	/// Returns the public keys of the given crypto type.
+	fn public_keys<T: for<'a> std::convert::TryFrom<&'a [u8]>>(
+		&self,
+		key_type: KeyTypeId,
+		crypto_type: CryptoTypeId,
+	) -> Vec<T> {
+		let keys = self.keys_where(key_type, Some(crypto_type)).unwrap_or_else(|e| {
+			log::warn!(target: "keystore", "Failed to list the keys of {:?}: {}", key_type, e);
+			Vec::new()
+		});
+		keys.into_iter().filter_map(|key| T::try_from(&key.1[..]).ok()).collect()
+	}
+
+	/// Returns the keys of the key type, only those of the crypto type if one is given.
+	fn keys_where(
+		&self,
+		key_type: KeyTypeId,
+		crypto_type: Option<CryptoTypeId>,
+	) -> Result<Vec<CryptoTypePublicPair>> {
+		let connection = self.connection.lock();
+		let mut statement = connection.prepare(
+			"SELECT crypto_type, public FROM keys
+			WHERE key_type = ?1 AND (?2 IS NULL OR crypto_type = ?2)
+			ORDER BY crypto_type, public",
+		)?;
+		let crypto_type = crypto_type.map(|id| id.0.to_vec());
+		let rows = statement.query_map(params![&key_type.0[..], crypto_type], |row| {
+			Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+		})?;
+		let mut keys = Vec::new();
+		for row in rows {
+			let (crypto_type, public) = row?;
+			keys.push(CryptoTypePublicPair(CryptoTypeId(fixed(&crypto_type)?), public));
+		}
+		Ok(keys)
+	}
+
+	/// Returns `true` if a key with the key type and public key exists.
+	fn contains(&self, key_type: KeyTypeId, public: &[u8]) -> Result<bool> {
+		Ok(self.connection.lock().query_row(
+			"SELECT 1 FROM keys WHERE key_type = ?1 AND public = ?2",
+			params![&key_type.0[..], public],
+			|_| Ok(()),
+		).optional()?.is_some())
+	}
+}
+
+/// Returns the key the secret URIs of the database are encrypted with, `None` if they are not
+/// encrypted.
+///
+/// Sets the password of a database without keys.
+fn database_key(
+	transaction: &Transaction,
+	password: Option<&SecretString>,
+) -> Result<Option<Zeroizing<[u8; 32]>>> {
+	let encryption: Option<Vec<u8>> = transaction.query_row(
+		"SELECT value FROM settings WHERE name = ?1",
+		params![ENCRYPTION],
+		|row| row.get(0),
+	).optional()?;
+	let password = match (encryption, password) {
+		(None, None) => return Ok(None),
+		(Some(_), None) => return Err(Error::InvalidPassword),
+		(Some(encryption), Some(password)) => {
+			if encryption.len() < SALT_LEN + 4 {
+				return Err(Error::InvalidPassword);
+			}
+			let (salt, rest) = encryption.split_at(SALT_LEN);
+			let (rounds, check) = rest.split_at(4);
+			let key = backup::derive_key(password, salt, u32::from_le_bytes(fixed(rounds)?));
+			unseal(&key, CHECK, check)?;
+			return Ok(Some(key));
+		},
+		(None, Some(password)) => password,
+	};
+
+	let keys: i64 = transaction.query_row(
+		"SELECT COUNT(*) FROM keys",
+		params![],
+		|row| row.get(0),
+	)?;
+	if keys > 0 {
+		return Err(Error::InvalidPassword);
+	}
+	let mut salt = [0u8; SALT_LEN];
+	OsRng.fill_bytes(&mut salt);
+	let key = backup::derive_key(password, &salt, backup::KDF_ROUNDS);
+	let rounds = backup::KDF_ROUNDS.to_le_bytes();
+	let encryption = [&salt[..], &rounds[..], &seal(&key, CHECK, &[])].concat();
+	transaction.execute(
+		"INSERT INTO settings (name, value) VALUES (?1, ?2)",
+		params![ENCRYPTION, encryption],
+	)?;
+	Ok(Some(key))
+}
+
+/// Encrypt the data, returning the nonce followed by the ciphertext.
+fn seal(key: &[u8; 32], aad: &[u8], plain: &[u8]) -> Vec<u8> {
+	let mut nonce = [0u8; NONCE_LEN];
+	OsRng.fill_bytes(&mut nonce);
+	let sealed = ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+		.encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad })
+		.expect("Encrypting a secret URI of a valid length never fails; qed");
+	[&nonce[..], &sealed[..]].concat()
+}
+
+/// Decrypt data encrypted with [`seal`].
+///
+/// Fails with [`Error::InvalidPassword`] if it can not be decrypted with the key.
+fn unseal(key: &[u8; 32], aad: &[u8], sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+	if sealed.len() < NONCE_LEN {
+		return Err(Error::InvalidPassword);
+	}
+	let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+	ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+		.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+		.map(Zeroizing::new)
+		.map_err(|_| Error::InvalidPassword)
+}
+
+/// Returns the crypto type the secret URI derives to the public key with.
+fn crypto_type_of(suri: &str, public: &[u8]) -> Result<CryptoTypeId> {
+	let derived = [sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID].iter()
+		.filter_map(|scheme| public_from_suri(*scheme, suri, None)?.ok())
+		.collect::<Vec<_>>();
+	match derived.iter().find(|key| key.1 == public) {
+		Some(key) => Ok(key.0),
+		None => Err(Error::PublicKeyMismatch {
+			expected: public.to_vec(),
+			derived: derived.into_iter().map(|key| key.1).collect(),
+		}),
+	}
+}
+
+/// Convert a column to an array of the expected length.
+fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+	let mut array = [0u8; N];
+	if bytes.len() != N {
+		let column = format!("column of {} bytes where {} are expected", bytes.len(), N);
+		return Err(Error::Inconsistent(vec![column]));
+	}
+	array.copy_from_slice(bytes);
+	Ok(array)
+}
+
+#[async_trait]
+impl CryptoStore for SqliteKeystore {
+	async fn keys(
+		&self,
+		id: KeyTypeId,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::keys(self, id)
+	}
+
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		SyncCryptoStore::sr25519_public_keys(self, id)
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		SyncCryptoStore::sr25519_generate_new(self, id, seed)
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		SyncCryptoStore::ed25519_public_keys(self, id)
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		SyncCryptoStore::ed25519_generate_new(self, id, seed)
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		SyncCryptoStore::ecdsa_public_keys(self, id)
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		SyncCryptoStore::ecdsa_generate_new(self, id, seed)
+	}
+
+	async fn insert_unknown(
+		&self,
+		id: KeyTypeId,
+		suri: &str,
+		public: &[u8],
+	) -> std::result::Result<(), ()> {
+		SyncCryptoStore::insert_unknown(self, id, suri, public)
+	}
+
+	async fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		SyncCryptoStore::remove_key(self, id, public)
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		SyncCryptoStore::has_keys(self, public_keys)
+	}
+
+	async fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		SyncCryptoStore::key_types(self)
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		SyncCryptoStore::supported_keys(self, id, keys)
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		SyncCryptoStore::sign_with(self, id, key, msg)
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		SyncCryptoStore::sr25519_vrf_sign(self, key_type, public, transcript_data)
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		SyncCryptoStore::ecdsa_sign_prehashed(self, id, public, msg)
+	}
+}
+
+impl SyncCryptoStore for SqliteKeystore {
+	fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		Ok(self.keys_where(id, None)?)
+	}
+
+	fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		self.public_keys(id, sr25519::CRYPTO_ID)
+	}
+
+	fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		Ok(self.generate::<sr25519::Pair>(id, seed)?)
+	}
+
+	fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		self.public_keys(id, ed25519::CRYPTO_ID)
+	}
+
+	fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		Ok(self.generate::<ed25519::Pair>(id, seed)?)
+	}
+
+	fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		self.public_keys(id, ecdsa::CRYPTO_ID)
+	}
+
+	fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		Ok(self.generate::<ecdsa::Pair>(id, seed)?)
+	}
+
+	fn insert_unknown(
+		&self,
+		key_type: KeyTypeId,
+		suri: &str,
+		public: &[u8],
+	) -> std::result::Result<(), ()> {
+		self.insert_keys(&[(key_type, suri, public)]).map_err(|_| ())
+	}
+
+	fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		Ok(self.write(|transaction| {
+			let removed = transaction.execute(
+				"DELETE FROM keys WHERE key_type = ?1 AND public = ?2 AND crypto_type = ?3",
+				params![&id.0[..], &public.1, &public.0.0[..]],
+			)?;
+			if removed == 0 {
+				return Err(Error::PairNotFound);
+			}
+			Ok(())
+		})?)
+	}
+
+	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		public_keys.iter().all(|(public, key_type)| {
+			self.contains(*key_type, public).unwrap_or_else(|e| {
+				log::warn!(target: "keystore", "Failed to look up a key of {:?}: {}", key_type, e);
+				false
+			})
+		})
+	}
+
+	fn key_types(&self) -> std::result::Result<Vec<KeyTypeId>, TraitError> {
+		let connection = self.connection.lock();
+		let mut statement = connection
+			.prepare("SELECT DISTINCT key_type FROM keys ORDER BY key_type")
+			.map_err(Error::from)?;
+		let rows = statement.query_map(params![], |row| row.get::<_, Vec<u8>>(0))
+			.map_err(Error::from)?;
+		let mut key_types = Vec::new();
+		for row in rows {
+			key_types.push(KeyTypeId(fixed(&row.map_err(Error::from)?)?));
+		}
+		Ok(key_types)
+	}
+
+	fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		let all_keys = SyncCryptoStore::keys(self, id)?.into_iter().collect::<HashSet<_>>();
+		Ok(keys.into_iter().filter(|key| all_keys.contains(key)).collect())
+	}
+
+	fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		let signature = match key.0 {
+			ed25519::CRYPTO_ID => self.key_pair::<ed25519::Pair>(id, key)?
+				.map(|pair| pair.sign(msg).encode()),
+			sr25519::CRYPTO_ID => self.key_pair::<sr25519::Pair>(id, key)?
+				.map(|pair| pair.sign(msg).encode()),
+			ecdsa::CRYPTO_ID => self.key_pair::<ecdsa::Pair>(id, key)?
+				.map(|pair| pair.sign(msg).encode()),
+			_ => return Err(TraitError::KeyNotSupported(id)),
+		};
+		Ok(signature)
+	}
+
+	fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		let pair = match self.key_pair::<sr25519::Pair>(key_type, &public.into())? {
+			Some(pair) => pair,
+			None => return Ok(None),
+		};
+		let (inout, proof, _) = pair.as_ref().vrf_sign(make_transcript(transcript_data));
+		Ok(Some(VRFSignature { output: inout.to_output(), proof }))
+	}
+
+	fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		let pair = self.key_pair::<ecdsa::Pair>(id, &public.into())?
+			.ok_or(TraitError::PairNotFound)?;
+		Ok(pair.sign_prehashed(msg))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use sp_core::crypto::Public;
+	use sp_core::testing::{ECDSA, ED25519, SR25519};
+	use tempfile::TempDir;
+
+	#[test]
+	fn keys_are_stored_in_the_database() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keys.db");
+		let store = SqliteKeystore::open(&path, None).unwrap();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = block_on(CryptoStore::ed25519_generate_new(&store, ED25519, Some("//Alice")))
+			.unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		drop(store);
+
+		let store = SqliteKeystore::open(&path, None).unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![sr25519]);
+		assert!(SyncCryptoStore::sr25519_public_keys(&store, ED25519).is_empty());
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, ED25519), vec![ed25519]);
+		assert_eq!(
+			SyncCryptoStore::key_types(&store).unwrap(),
+			vec![ECDSA, ED25519, SR25519],
+		);
+
+		let msg = b"message";
+		let signature = SyncCryptoStore::sign_with(&store, ECDSA, &(&ecdsa).into(), msg)
+			.unwrap()
+			.unwrap();
+		let signature = <ecdsa::Signature as codec::Decode>::decode(&mut &signature[..]).unwrap();
+		assert!(ecdsa::Pair::verify(&signature, msg, &ecdsa));
+		assert!(SyncCryptoStore::sign_with(&store, SR25519, &ed25519.into(), msg)
+			.unwrap()
+			.is_none());
+
+		SyncCryptoStore::remove_key(&store, SR25519, &sr25519.into()).unwrap();
+		assert!(!SyncCryptoStore::has_keys(&store, &[(sr25519.to_raw_vec(), SR25519)]));
+		let removed = SyncCryptoStore::remove_key(&store, SR25519, &sr25519.into());
+		assert!(matches!(removed, Err(TraitError::PairNotFound)));
+	}
+
+	#[test]
+	fn secret_uris_are_encrypted_with_the_password() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keys.db");
+		let password = || Some(SecretString::new("password".into()));
+		let store = SqliteKeystore::open(&path, password()).unwrap();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Alice"))
+			.unwrap();
+		drop(store);
+
+		let seed: Vec<u8> = Connection::open(&path).unwrap()
+			.query_row("SELECT seed FROM keys", params![], |row| row.get(0))
+			.unwrap();
+		assert!(!seed.windows(5).any(|window| window == b"Alice"));
+
+		let wrong = SqliteKeystore::open(&path, Some(SecretString::new("wrong".into())));
+		assert!(matches!(wrong, Err(Error::InvalidPassword)));
+		assert!(matches!(SqliteKeystore::open(&path, None), Err(Error::InvalidPassword)));
+
+		let store = SqliteKeystore::open(&path, password()).unwrap();
+		let transcript = VRFTranscriptData { label: b"Test", items: vec![] };
+		assert!(SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &public, transcript)
+			.unwrap()
+			.is_some());
+	}
+
+	#[test]
+	fn inserting_several_keys_is_atomic() {
+		let temp_dir = TempDir::new().unwrap();
+		let store = SqliteKeystore::open(temp_dir.path().join("keys.db"), None).unwrap();
+		let alice = sr25519::Pair::from_string("//Alice", None).unwrap().public();
+		let bob = ed25519::Pair::from_string("//Bob", None).unwrap().public();
+
+		let mismatch = store.insert_keys(&[
+			(SR25519, "//Alice", alice.as_ref()),
+			(ED25519, "//Bob", alice.as_ref()),
+		]);
+		assert!(matches!(mismatch, Err(Error::PublicKeyMismatch { .. })));
+		assert!(SyncCryptoStore::key_types(&store).unwrap().is_empty());
+
+		store.insert_keys(&[(SR25519, "//Alice", alice.as_ref()), (ED25519, "//Bob", bob.as_ref())])
+			.unwrap();
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519), vec![alice]);
+		assert_eq!(SyncCryptoStore::ed25519_public_keys(&store, ED25519), vec![bob]);
+
+		assert_eq!(store.metadata(SR25519, alice.as_ref()).unwrap().as_deref(), Some(""));
+		store.set_metadata(SR25519, alice.as_ref(), "validator").unwrap();
+		assert_eq!(store.metadata(SR25519, alice.as_ref()).unwrap().as_deref(), Some("validator"));
+		let missing = store.set_metadata(ED25519, alice.as_ref(), "validator");
+		assert!(matches!(missing, Err(Error::PairNotFound)));
+	}
+}
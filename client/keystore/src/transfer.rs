@@ -0,0 +1,192 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Transfer of keys between keystores, encrypted to an ephemeral key of the destination.
+//!
+//! The destination sends a [`TransferChallenge`] holding an ephemeral X25519 public key. The
+//! source answers with its own ephemeral X25519 public key, a nonce and the key encrypted with
+//! ChaCha20-Poly1305. The encryption key is HMAC-SHA256, keyed by a domain separator, of the
+//! shared secret and both ephemeral public keys, which are also authenticated along with the
+//! key. Both ephemeral secrets are discarded after a single transfer.
+
+use chacha20poly1305::{
+	ChaCha20Poly1305, Key, Nonce,
+	aead::{Aead, NewAead, Payload},
+};
+use codec::{Decode, Encode, Input, Output};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{RngCore, rngs::OsRng};
+use sha2::Sha256;
+use sp_core::crypto::{CryptoTypePublicPair, KeyTypeId};
+use zeroize::Zeroizing;
+
+use crate::{Result, Error, age::{x25519_public, x25519_shared}};
+
+/// Domain separator of the key derivation, never used for anything else.
+const DOMAIN: &[u8] = b"substrate-keystore/transfer/v1";
+const PUBLIC_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// The request of the destination of a transfer, see [`LocalKeystore::begin_receive`].
+///
+/// [`LocalKeystore::begin_receive`]: crate::LocalKeystore::begin_receive
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct TransferChallenge {
+	ephemeral: [u8; PUBLIC_LEN],
+}
+
+/// The ephemeral secret of the destination of a transfer, needed to decrypt the response.
+///
+/// It is consumed by [`LocalKeystore::complete_receive`] and never leaves the process.
+///
+/// [`LocalKeystore::complete_receive`]: crate::LocalKeystore::complete_receive
+pub struct TransferState {
+	secret: Zeroizing<[u8; 32]>,
+	ephemeral: [u8; PUBLIC_LEN],
+}
+
+/// The key being transferred.
+pub(crate) struct TransferPayload {
+	pub key_type: KeyTypeId,
+	pub public: CryptoTypePublicPair,
+	pub suri: Zeroizing<String>,
+}
+
+impl Encode for TransferPayload {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.key_type.encode_to(dest);
+		self.public.encode_to(dest);
+		self.suri.as_str().encode_to(dest);
+	}
+}
+
+impl Decode for TransferPayload {
+	fn decode<I: Input>(input: &mut I) -> std::result::Result<Self, codec::Error> {
+		Ok(TransferPayload {
+			key_type: Decode::decode(input)?,
+			public: Decode::decode(input)?,
+			suri: Zeroizing::new(Decode::decode(input)?),
+		})
+	}
+}
+
+fn invalid(reason: &str) -> Error {
+	Error::InvalidTransfer(reason.into())
+}
+
+fn ephemeral_secret() -> Zeroizing<[u8; 32]> {
+	let mut secret = Zeroizing::new([0u8; 32]);
+	OsRng.fill_bytes(&mut secret[..]);
+	secret
+}
+
+/// The encryption key of a transfer, given the shared secret and the source's and the
+/// destination's ephemeral public keys.
+fn derive_key(shared: &[u8; 32], source: &[u8], destination: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+	// The shared secret of a public key of low order is zero, it would not be secret.
+	if shared.iter().all(|byte| *byte == 0) {
+		return Err(invalid("weak ephemeral key"));
+	}
+	let mut mac = Hmac::<Sha256>::new_varkey(DOMAIN).expect("HMAC takes keys of any size; qed");
+	mac.update(&shared[..]);
+	mac.update(source);
+	mac.update(destination);
+	let mut key = Zeroizing::new([0u8; 32]);
+	key.copy_from_slice(&mac.finalize().into_bytes());
+	Ok(key)
+}
+
+/// Start a transfer on the destination.
+pub(crate) fn begin() -> (TransferChallenge, TransferState) {
+	let secret = ephemeral_secret();
+	let ephemeral = x25519_public(&secret);
+	(TransferChallenge { ephemeral }, TransferState { secret, ephemeral })
+}
+
+/// Encrypt the key to the destination that sent the challenge.
+pub(crate) fn seal(challenge: &TransferChallenge, payload: &TransferPayload) -> Result<Vec<u8>> {
+	let secret = ephemeral_secret();
+	let ephemeral = x25519_public(&secret);
+	let key = derive_key(
+		&x25519_shared(&secret, &challenge.ephemeral),
+		&ephemeral,
+		&challenge.ephemeral,
+	)?;
+
+	let mut nonce = [0u8; NONCE_LEN];
+	OsRng.fill_bytes(&mut nonce);
+	let aad = [&ephemeral[..], &challenge.ephemeral[..]].concat();
+	let plain = Zeroizing::new(payload.encode());
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+	let encrypted = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: &plain, aad: &aad })
+		.map_err(|_| invalid("encryption failed"))?;
+
+	let mut response = Vec::with_capacity(PUBLIC_LEN + NONCE_LEN + encrypted.len());
+	response.extend_from_slice(&ephemeral);
+	response.extend_from_slice(&nonce);
+	response.extend_from_slice(&encrypted);
+	Ok(response)
+}
+
+/// Decrypt the response of the source.
+pub(crate) fn open(state: TransferState, response: &[u8]) -> Result<TransferPayload> {
+	if response.len() < PUBLIC_LEN + NONCE_LEN {
+		return Err(invalid("response too short"));
+	}
+	let (source, rest) = response.split_at(PUBLIC_LEN);
+	let (nonce, encrypted) = rest.split_at(NONCE_LEN);
+	let mut ephemeral = [0u8; PUBLIC_LEN];
+	ephemeral.copy_from_slice(source);
+	let key = derive_key(&x25519_shared(&state.secret, &ephemeral), source, &state.ephemeral)?;
+
+	let aad = [source, &state.ephemeral[..]].concat();
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+	let plain = Zeroizing::new(
+		cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: encrypted, aad: &aad })
+			.map_err(|_| invalid("authentication failed"))?
+	);
+	TransferPayload::decode(&mut &plain[..]).map_err(|_| invalid("malformed key"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::{ed25519, testing::ED25519};
+
+	#[test]
+	fn only_the_destination_can_decrypt() {
+		let payload = || TransferPayload {
+			key_type: ED25519,
+			public: CryptoTypePublicPair(ed25519::CRYPTO_ID, vec![1; 32]),
+			suri: Zeroizing::new("//Alice".into()),
+		};
+		let (challenge, state) = begin();
+		let response = seal(&challenge, &payload()).unwrap();
+		assert!(!response.windows(7).any(|window| window == b"//Alice"));
+
+		let (_, other) = begin();
+		assert!(matches!(open(other, &response), Err(Error::InvalidTransfer(_))));
+		let opened = open(state, &response).unwrap();
+		assert_eq!(opened.key_type, ED25519);
+		assert_eq!(opened.public, payload().public);
+		assert_eq!(opened.suri.as_str(), "//Alice");
+
+		let weak = TransferChallenge { ephemeral: [0; PUBLIC_LEN] };
+		assert!(matches!(seal(&weak, &payload()), Err(Error::InvalidTransfer(_))));
+	}
+}
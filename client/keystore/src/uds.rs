@@ -0,0 +1,718 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keystore access over a Unix domain socket.
+//!
+//! [`UdsKeystoreServer`] serves any [`CryptoStore`] on a local socket and [`UdsKeystoreClient`]
+//! implements [`CryptoStore`] by forwarding every call to such a server. Only local sockets are
+//! supported to keep the attack surface small.
+//!
+//! Every message is a little endian `u32` length followed by the SCALE encoded request or
+//! response. A client sends one request at a time and waits for its response.
+
+use std::{
+	collections::HashSet,
+	fs,
+	io::{self, Read, Write},
+	os::unix::{fs::{DirBuilderExt, PermissionsExt}, net::{UnixListener, UnixStream}},
+	path::{Path, PathBuf},
+	sync::Arc,
+	thread,
+	time::Duration,
+};
+use async_io::{Async, Timer};
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use futures::{
+	executor::block_on,
+	future::{self, Either},
+	io::{AsyncReadExt, AsyncWriteExt},
+};
+use parking_lot::Mutex;
+use schnorrkel::vrf::{VRFOutput, VRFProof};
+use sp_core::crypto::{CryptoTypeId, CryptoTypePublicPair, KeyTypeId};
+use sp_keystore::{
+	CryptoStore,
+	Error as TraitError,
+	vrf::{VRFTranscriptData, VRFTranscriptValue, VRFSignature},
+};
+use sp_application_crypto::{ed25519, sr25519, ecdsa};
+
+use crate::{Error, Result};
+
+/// Maximum length of a single message.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Maximum number of distinct VRF transcript labels a server keeps.
+///
+/// Transcript labels are `&'static`, so every distinct label received is kept for the lifetime
+/// of the server.
+const MAX_TRANSCRIPT_LABELS: usize = 1024;
+
+/// Permissions of the socket file.
+const SOCKET_MODE: u32 = 0o600;
+
+/// Permissions of the directory the socket is bound in before it is moved into place.
+const BIND_DIR_MODE: u32 = 0o700;
+
+/// How long a client waits for the answer to a call by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request sent from the client to the server.
+#[derive(Encode, Decode)]
+enum Request {
+	Keys(KeyTypeId),
+	Sr25519PublicKeys(KeyTypeId),
+	Sr25519GenerateNew(KeyTypeId, Option<String>),
+	Ed25519PublicKeys(KeyTypeId),
+	Ed25519GenerateNew(KeyTypeId, Option<String>),
+	EcdsaPublicKeys(KeyTypeId),
+	EcdsaGenerateNew(KeyTypeId, Option<String>),
+	InsertUnknown(KeyTypeId, String, Vec<u8>),
+	RemoveKey(KeyTypeId, CryptoTypePublicPair),
+	Fingerprint,
+	Preload(Vec<(KeyTypeId, Vec<u8>)>),
+	SupportedKeys(KeyTypeId, Vec<CryptoTypePublicPair>),
+	HasKeys(Vec<(Vec<u8>, KeyTypeId)>),
+	SignWith(KeyTypeId, CryptoTypePublicPair, Vec<u8>),
+	Sr25519VrfSign(KeyTypeId, sr25519::Public, Transcript),
+	EcdsaSignPrehashed(KeyTypeId, ecdsa::Public, [u8; 32]),
+	SupportedSchemes,
+}
+
+/// [`VRFTranscriptData`] with owned labels.
+#[derive(Encode, Decode)]
+struct Transcript {
+	label: Vec<u8>,
+	items: Vec<(Vec<u8>, VRFTranscriptValue)>,
+}
+
+impl From<VRFTranscriptData> for Transcript {
+	fn from(data: VRFTranscriptData) -> Self {
+		Self {
+			label: data.label.to_vec(),
+			items: data.items.into_iter().map(|(l, v)| (l.as_bytes().to_vec(), v)).collect(),
+		}
+	}
+}
+
+/// [`VRFSignature`] in its byte representation.
+#[derive(Encode, Decode)]
+struct Signature {
+	output: [u8; 32],
+	proof: [u8; 64],
+}
+
+/// [`TraitError`] as sent over the socket.
+#[derive(Encode, Decode)]
+enum RemoteError {
+	KeyNotSupported(KeyTypeId),
+	ValidationError(String),
+	Unavailable,
+	PairNotFound,
+	PermissionDenied,
+	VrfNotAvailable,
+	Other(String),
+	AmbiguousPrefix,
+	UnknownCryptoType(CryptoTypeId),
+	ReadOnly,
+}
+
+impl From<TraitError> for RemoteError {
+	fn from(error: TraitError) -> Self {
+		match error {
+			TraitError::KeyNotSupported(id) => RemoteError::KeyNotSupported(id),
+			TraitError::ValidationError(e) => RemoteError::ValidationError(e),
+			TraitError::Unavailable => RemoteError::Unavailable,
+			TraitError::PairNotFound => RemoteError::PairNotFound,
+			TraitError::PermissionDenied => RemoteError::PermissionDenied,
+			TraitError::VrfNotAvailable => RemoteError::VrfNotAvailable,
+			TraitError::Other(e) => RemoteError::Other(e),
+			TraitError::AmbiguousPrefix => RemoteError::AmbiguousPrefix,
+			TraitError::UnknownCryptoType(id) => RemoteError::UnknownCryptoType(id),
+			TraitError::ReadOnly => RemoteError::ReadOnly,
+		}
+	}
+}
+
+impl From<RemoteError> for TraitError {
+	fn from(error: RemoteError) -> Self {
+		match error {
+			RemoteError::KeyNotSupported(id) => TraitError::KeyNotSupported(id),
+			RemoteError::ValidationError(e) => TraitError::ValidationError(e),
+			RemoteError::Unavailable => TraitError::Unavailable,
+			RemoteError::PairNotFound => TraitError::PairNotFound,
+			RemoteError::PermissionDenied => TraitError::PermissionDenied,
+			RemoteError::VrfNotAvailable => TraitError::VrfNotAvailable,
+			RemoteError::Other(e) => TraitError::Other(e),
+			RemoteError::AmbiguousPrefix => TraitError::AmbiguousPrefix,
+			RemoteError::UnknownCryptoType(id) => TraitError::UnknownCryptoType(id),
+			RemoteError::ReadOnly => TraitError::ReadOnly,
+		}
+	}
+}
+
+/// Write a single length prefixed message.
+fn write_message(stream: &mut UnixStream, message: &[u8]) -> io::Result<()> {
+	if message.len() > MAX_MESSAGE_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Message too large"));
+	}
+	stream.write_all(&(message.len() as u32).to_le_bytes())?;
+	stream.write_all(message)?;
+	stream.flush()
+}
+
+/// Read a single length prefixed message.
+///
+/// Returns `Ok(None)` if the other side closed the connection.
+fn read_message(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+	let mut len = [0u8; 4];
+	match stream.read_exact(&mut len) {
+		Ok(()) => (),
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e),
+	}
+
+	let len = u32::from_le_bytes(len) as usize;
+	if len > MAX_MESSAGE_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too large"));
+	}
+	let mut message = vec![0u8; len];
+	stream.read_exact(&mut message)?;
+	Ok(Some(message))
+}
+
+/// Like [`write_message`], without blocking the thread.
+async fn send_message(stream: &mut Async<UnixStream>, message: &[u8]) -> io::Result<()> {
+	if message.len() > MAX_MESSAGE_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "Message too large"));
+	}
+	stream.write_all(&(message.len() as u32).to_le_bytes()).await?;
+	stream.write_all(message).await?;
+	stream.flush().await
+}
+
+/// Like [`read_message`], without blocking the thread.
+async fn receive_message(stream: &mut Async<UnixStream>) -> io::Result<Option<Vec<u8>>> {
+	let mut len = [0u8; 4];
+	match stream.read_exact(&mut len).await {
+		Ok(()) => (),
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e),
+	}
+
+	let len = u32::from_le_bytes(len) as usize;
+	if len > MAX_MESSAGE_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too large"));
+	}
+	let mut message = vec![0u8; len];
+	stream.read_exact(&mut message).await?;
+	Ok(Some(message))
+}
+
+/// Serves a [`CryptoStore`] on a Unix domain socket.
+pub struct UdsKeystoreServer {
+	listener: UnixListener,
+	store: Arc<dyn CryptoStore>,
+	labels: Arc<Mutex<HashSet<&'static [u8]>>>,
+}
+
+impl UdsKeystoreServer {
+	/// Bind a new server to the socket at `path`.
+	///
+	/// The socket file must not exist yet and is made accessible by the owner only. It is bound
+	/// in a directory next to it that only the owner can enter and linked into place once its
+	/// permissions are restricted, so nobody else can connect in between.
+	pub fn bind<P: AsRef<Path>>(path: P, store: Arc<dyn CryptoStore>) -> io::Result<Self> {
+		let path = path.as_ref();
+		let name = path.file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No socket file name"))?;
+		let mut dir_name = std::ffi::OsString::from(".");
+		dir_name.push(name);
+		dir_name.push(format!(".{:016x}", rand::random::<u64>()));
+		let dir = path.with_file_name(dir_name);
+		fs::DirBuilder::new().mode(BIND_DIR_MODE).create(&dir)?;
+
+		let bound = dir.join("socket");
+		let listener = UnixListener::bind(&bound).and_then(|listener| {
+			fs::set_permissions(&bound, fs::Permissions::from_mode(SOCKET_MODE))?;
+			// Unlike renaming, linking fails if the socket file exists.
+			fs::hard_link(&bound, path)?;
+			Ok(listener)
+		});
+		let _ = fs::remove_file(&bound);
+		let _ = fs::remove_dir(&dir);
+
+		Ok(Self { listener: listener?, store, labels: Default::default() })
+	}
+
+	/// Accept and serve connections, each on its own thread.
+	///
+	/// Only returns if accepting a connection failed.
+	pub fn run(self) -> io::Result<()> {
+		loop {
+			let (stream, _) = self.listener.accept()?;
+			let store = self.store.clone();
+			let labels = self.labels.clone();
+			thread::spawn(move || serve(stream, &*store, &labels));
+		}
+	}
+}
+
+/// Serve requests of a single connection until it is closed.
+fn serve(
+	mut stream: UnixStream,
+	store: &dyn CryptoStore,
+	labels: &Mutex<HashSet<&'static [u8]>>,
+) -> io::Result<()> {
+	while let Some(message) = read_message(&mut stream)? {
+		let request = Request::decode(&mut &message[..])
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+		let response = block_on(handle(store, labels, request));
+		write_message(&mut stream, &response)?;
+	}
+	Ok(())
+}
+
+/// Keep the given label for the lifetime of the process.
+fn intern(
+	labels: &Mutex<HashSet<&'static [u8]>>,
+	label: Vec<u8>,
+) -> std::result::Result<&'static [u8], RemoteError> {
+	let mut labels = labels.lock();
+	if let Some(label) = labels.get(&label[..]) {
+		return Ok(label);
+	}
+	if labels.len() >= MAX_TRANSCRIPT_LABELS {
+		return Err(RemoteError::Other("Too many distinct VRF transcript labels".into()));
+	}
+
+	let label: &'static [u8] = Box::leak(label.into_boxed_slice());
+	labels.insert(label);
+	Ok(label)
+}
+
+/// Convert the received transcript back into [`VRFTranscriptData`].
+fn transcript_data(
+	labels: &Mutex<HashSet<&'static [u8]>>,
+	transcript: Transcript,
+) -> std::result::Result<VRFTranscriptData, RemoteError> {
+	let label = intern(labels, transcript.label)?;
+	let items = transcript.items.into_iter()
+		.map(|(label, value)| {
+			let label = String::from_utf8(label)
+				.map_err(|_| RemoteError::ValidationError("Invalid transcript item label".into()))?;
+			let label = intern(labels, label.into_bytes())?;
+			let label = std::str::from_utf8(label).expect("Interned from a valid `String`; qed");
+			Ok((label, value))
+		})
+		.collect::<std::result::Result<_, RemoteError>>()?;
+
+	Ok(VRFTranscriptData { label, items })
+}
+
+/// Handle a single request and return the encoded response.
+async fn handle(
+	store: &dyn CryptoStore,
+	labels: &Mutex<HashSet<&'static [u8]>>,
+	request: Request,
+) -> Vec<u8> {
+	match request {
+		Request::Keys(id) =>
+			store.keys(id).await.map_err(RemoteError::from).encode(),
+		Request::Sr25519PublicKeys(id) =>
+			store.sr25519_public_keys(id).await.encode(),
+		Request::Sr25519GenerateNew(id, seed) =>
+			store.sr25519_generate_new(id, seed.as_deref()).await.map_err(RemoteError::from).encode(),
+		Request::Ed25519PublicKeys(id) =>
+			store.ed25519_public_keys(id).await.encode(),
+		Request::Ed25519GenerateNew(id, seed) =>
+			store.ed25519_generate_new(id, seed.as_deref()).await.map_err(RemoteError::from).encode(),
+		Request::EcdsaPublicKeys(id) =>
+			store.ecdsa_public_keys(id).await.encode(),
+		Request::EcdsaGenerateNew(id, seed) =>
+			store.ecdsa_generate_new(id, seed.as_deref()).await.map_err(RemoteError::from).encode(),
+		Request::InsertUnknown(id, suri, public) =>
+			store.insert_unknown(id, &suri, &public).await.encode(),
+		Request::RemoveKey(id, public) =>
+			store.remove_key(id, &public).await.map_err(RemoteError::from).encode(),
+		Request::Fingerprint =>
+			store.fingerprint().await.map_err(RemoteError::from).encode(),
+		Request::Preload(keys) =>
+			store.preload(&keys).await.map_err(RemoteError::from).encode(),
+		Request::SupportedKeys(id, keys) =>
+			store.supported_keys(id, keys).await.map_err(RemoteError::from).encode(),
+		Request::HasKeys(public_keys) =>
+			store.has_keys(&public_keys).await.encode(),
+		Request::SignWith(id, key, msg) =>
+			store.sign_with(id, &key, &msg).await.map_err(RemoteError::from).encode(),
+		Request::Sr25519VrfSign(id, public, transcript) => {
+			let result = match transcript_data(labels, transcript) {
+				Ok(data) => store.sr25519_vrf_sign(id, &public, data).await
+					.map(|signature| signature.map(|s| Signature {
+						output: s.output.to_bytes(),
+						proof: s.proof.to_bytes(),
+					}))
+					.map_err(RemoteError::from),
+				Err(e) => Err(e),
+			};
+			result.encode()
+		},
+		Request::EcdsaSignPrehashed(id, public, msg) =>
+			store.ecdsa_sign_prehashed(id, &public, &msg).await.map_err(RemoteError::from).encode(),
+		Request::SupportedSchemes =>
+			store.supported_schemes().await.encode(),
+	}
+}
+
+/// A [`CryptoStore`] that forwards every call to a [`UdsKeystoreServer`].
+///
+/// A lost connection is re-established on the next call. Calls that fail because the server
+/// can not be reached or do not answer in time return [`TraitError::Unavailable`], calls that
+/// can not return an error behave as if the keystore was empty. Calls wait for the server
+/// without blocking their thread, one after the other.
+pub struct UdsKeystoreClient {
+	path: PathBuf,
+	stream: async_lock::Mutex<Option<Async<UnixStream>>>,
+	timeout: Duration,
+	read_only: bool,
+}
+
+impl UdsKeystoreClient {
+	/// Connect to the server listening on the socket at `path`.
+	///
+	/// Returns [`Error::Unavailable`] if the server can not be reached.
+	pub fn connect<P: Into<PathBuf>>(path: P) -> Result<Self> {
+		let path = path.into();
+		let stream = UnixStream::connect(&path)
+			.and_then(Async::new)
+			.map_err(|_| Error::Unavailable)?;
+		Ok(Self {
+			path,
+			stream: async_lock::Mutex::new(Some(stream)),
+			timeout: DEFAULT_TIMEOUT,
+			read_only: false,
+		})
+	}
+
+	/// Give up on calls the server does not answer within `timeout`.
+	///
+	/// Defaults to 10 seconds. The time a call waits for earlier calls to finish is not counted.
+	pub fn with_timeout(self, timeout: Duration) -> Self {
+		Self { timeout, ..self }
+	}
+
+	/// Reject calls that generate, insert or remove keys without forwarding them, so the remote
+	/// keystore is only used to sign.
+	///
+	/// Rejected calls return [`TraitError::ReadOnly`].
+	pub fn with_read_only(self, read_only: bool) -> Self {
+		Self { read_only, ..self }
+	}
+
+	/// Returns [`TraitError::ReadOnly`] if the client is read-only.
+	fn check_writable(&self) -> std::result::Result<(), TraitError> {
+		if self.read_only {
+			Err(TraitError::ReadOnly)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Send a request and decode the response.
+	async fn call<T: Decode>(&self, request: Request) -> std::result::Result<T, TraitError> {
+		let mut stream = self.stream.lock().await;
+		let exchange = Box::pin(exchange(&self.path, &mut stream, request));
+		let response = match future::select(exchange, Timer::after(self.timeout)).await {
+			Either::Left((response, _)) => response,
+			Either::Right(_) => None,
+		};
+
+		match response.and_then(|response| T::decode(&mut &response[..]).ok()) {
+			Some(response) => Ok(response),
+			None => {
+				// The connection is in an unknown state, e.g. if the response may still come.
+				*stream = None;
+				Err(TraitError::Unavailable)
+			},
+		}
+	}
+
+	/// Send a request whose response carries a [`RemoteError`].
+	async fn call_fallible<T: Decode>(
+		&self,
+		request: Request,
+	) -> std::result::Result<T, TraitError> {
+		self.call::<std::result::Result<T, RemoteError>>(request).await?.map_err(Into::into)
+	}
+}
+
+/// Send a request, connecting first if there is no connection, and read the response.
+///
+/// Returns `None` if the server can not be reached or does not answer.
+async fn exchange(
+	path: &Path,
+	stream: &mut Option<Async<UnixStream>>,
+	request: Request,
+) -> Option<Vec<u8>> {
+	if stream.is_none() {
+		*stream = Some(Async::<UnixStream>::connect(path).await.ok()?);
+	}
+	let connection = stream.as_mut()?;
+	send_message(connection, &request.encode()).await.ok()?;
+	receive_message(connection).await.ok().flatten()
+}
+
+#[async_trait]
+impl CryptoStore for UdsKeystoreClient {
+	async fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public> {
+		self.call(Request::Sr25519PublicKeys(id)).await.unwrap_or_default()
+	}
+
+	async fn sr25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<sr25519::Public, TraitError> {
+		self.check_writable()?;
+		self.call_fallible(Request::Sr25519GenerateNew(id, seed.map(Into::into))).await
+	}
+
+	async fn ed25519_public_keys(&self, id: KeyTypeId) -> Vec<ed25519::Public> {
+		self.call(Request::Ed25519PublicKeys(id)).await.unwrap_or_default()
+	}
+
+	async fn ed25519_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ed25519::Public, TraitError> {
+		self.check_writable()?;
+		self.call_fallible(Request::Ed25519GenerateNew(id, seed.map(Into::into))).await
+	}
+
+	async fn ecdsa_public_keys(&self, id: KeyTypeId) -> Vec<ecdsa::Public> {
+		self.call(Request::EcdsaPublicKeys(id)).await.unwrap_or_default()
+	}
+
+	async fn ecdsa_generate_new(
+		&self,
+		id: KeyTypeId,
+		seed: Option<&str>,
+	) -> std::result::Result<ecdsa::Public, TraitError> {
+		self.check_writable()?;
+		self.call_fallible(Request::EcdsaGenerateNew(id, seed.map(Into::into))).await
+	}
+
+	async fn insert_unknown(
+		&self,
+		id: KeyTypeId,
+		suri: &str,
+		public: &[u8],
+	) -> std::result::Result<(), ()> {
+		self.check_writable().map_err(|_| ())?;
+		self.call(Request::InsertUnknown(id, suri.into(), public.to_vec())).await.unwrap_or(Err(()))
+	}
+
+	async fn remove_key(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+	) -> std::result::Result<(), TraitError> {
+		self.check_writable()?;
+		self.call_fallible(Request::RemoveKey(id, public.clone())).await
+	}
+
+	async fn supported_keys(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.call_fallible(Request::SupportedKeys(id, keys)).await
+	}
+
+	async fn keys(&self, id: KeyTypeId) -> std::result::Result<Vec<CryptoTypePublicPair>, TraitError> {
+		self.call_fallible(Request::Keys(id)).await
+	}
+
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		self.call(Request::SupportedSchemes).await.unwrap_or_default()
+	}
+
+	async fn fingerprint(&self) -> std::result::Result<[u8; 32], TraitError> {
+		self.call_fallible(Request::Fingerprint).await
+	}
+
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> std::result::Result<(), TraitError> {
+		self.call_fallible(Request::Preload(keys.to_vec())).await
+	}
+
+	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
+		self.call(Request::HasKeys(public_keys.to_vec())).await.unwrap_or(false)
+	}
+
+	async fn sign_with(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+	) -> std::result::Result<Option<Vec<u8>>, TraitError> {
+		self.call_fallible(Request::SignWith(id, key.clone(), msg.to_vec())).await
+	}
+
+	async fn sr25519_vrf_sign(
+		&self,
+		key_type: KeyTypeId,
+		public: &sr25519::Public,
+		transcript_data: VRFTranscriptData,
+	) -> std::result::Result<Option<VRFSignature>, TraitError> {
+		let signature: Option<Signature> = self.call_fallible(
+			Request::Sr25519VrfSign(key_type, *public, transcript_data.into()),
+		).await?;
+
+		signature.map(|s| {
+			let output = VRFOutput::from_bytes(&s.output)
+				.map_err(|e| TraitError::Other(e.to_string()))?;
+			let proof = VRFProof::from_bytes(&s.proof)
+				.map_err(|e| TraitError::Other(e.to_string()))?;
+			Ok(VRFSignature { output, proof })
+		}).transpose()
+	}
+
+	async fn ecdsa_sign_prehashed(
+		&self,
+		id: KeyTypeId,
+		public: &ecdsa::Public,
+		msg: &[u8; 32],
+	) -> std::result::Result<ecdsa::Signature, TraitError> {
+		self.call_fallible(Request::EcdsaSignPrehashed(id, public.clone(), *msg)).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::FutureExt;
+	use tempfile::TempDir;
+	use sp_core::{Pair, testing::SR25519};
+	use crate::LocalKeystore;
+
+	fn serve_in_memory(dir: &TempDir) -> PathBuf {
+		let path = dir.path().join("keystore.sock");
+		let server = UdsKeystoreServer::bind(&path, Arc::new(LocalKeystore::in_memory())).unwrap();
+		thread::spawn(move || server.run());
+		path
+	}
+
+	#[test]
+	fn sign_with_round_trip() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = serve_in_memory(&temp_dir);
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, SOCKET_MODE);
+
+		let client = UdsKeystoreClient::connect(&path).unwrap();
+		let public = block_on(client.sr25519_generate_new(SR25519, None)).unwrap();
+		assert_eq!(block_on(client.sr25519_public_keys(SR25519)), vec![public]);
+
+		let signature = block_on(client.sign_with(SR25519, &public.into(), b"message"))
+			.unwrap()
+			.unwrap();
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, b"message", &public));
+
+		let transcript = VRFTranscriptData {
+			label: b"Test",
+			items: vec![("one", VRFTranscriptValue::U64(1))],
+		};
+		assert!(block_on(client.sr25519_vrf_sign(SR25519, &public, transcript)).unwrap().is_some());
+	}
+
+	#[test]
+	fn unreachable_server_is_unavailable() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keystore.sock");
+
+		assert!(matches!(UdsKeystoreClient::connect(&path), Err(Error::Unavailable)));
+
+		let client = UdsKeystoreClient {
+			path,
+			stream: async_lock::Mutex::new(None),
+			timeout: DEFAULT_TIMEOUT,
+			read_only: false,
+		};
+		assert!(matches!(
+			block_on(client.keys(SR25519)),
+			Err(TraitError::Unavailable),
+		));
+		assert!(block_on(client.sr25519_public_keys(SR25519)).is_empty());
+	}
+
+	#[test]
+	fn unanswered_calls_time_out() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = temp_dir.path().join("keystore.sock");
+		let listener = UnixListener::bind(&path).unwrap();
+		// A server that accepts the connection but never answers.
+		thread::spawn(move || {
+			let _connection = listener.accept().unwrap();
+			thread::park();
+		});
+
+		let client = UdsKeystoreClient::connect(&path).unwrap()
+			.with_timeout(Duration::from_millis(100));
+		// Waiting for the answer does not block the thread.
+		assert!(client.keys(SR25519).now_or_never().is_none());
+		assert!(matches!(block_on(client.keys(SR25519)), Err(TraitError::Unavailable)));
+	}
+
+	#[test]
+	fn socket_is_bound_privately() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = serve_in_memory(&temp_dir);
+
+		let entries = fs::read_dir(temp_dir.path()).unwrap()
+			.map(|entry| entry.unwrap().path())
+			.collect::<Vec<_>>();
+		assert_eq!(entries, vec![path.clone()]);
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, SOCKET_MODE);
+
+		let store = Arc::new(LocalKeystore::in_memory());
+		assert!(UdsKeystoreServer::bind(&path, store).is_err());
+		assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+	}
+
+	#[test]
+	fn read_only_client_does_not_forward_writes() {
+		let temp_dir = TempDir::new().unwrap();
+		let path = serve_in_memory(&temp_dir);
+		let writer = UdsKeystoreClient::connect(&path).unwrap();
+		let public = block_on(writer.sr25519_generate_new(SR25519, None)).unwrap();
+
+		let client = UdsKeystoreClient::connect(&path).unwrap().with_read_only(true);
+		assert!(matches!(
+			block_on(client.sr25519_generate_new(SR25519, None)),
+			Err(TraitError::ReadOnly),
+		));
+		assert!(matches!(
+			block_on(client.remove_key(SR25519, &public.into())),
+			Err(TraitError::ReadOnly),
+		));
+		assert!(block_on(client.sign_with(SR25519, &public.into(), b"message")).unwrap().is_some());
+		assert_eq!(block_on(client.sr25519_public_keys(SR25519)), vec![public]);
+	}
+}
@@ -0,0 +1,158 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Write-ahead log of the changes to the key files.
+//!
+//! Every record is its length as little endian `u32`, the SCALE encoded record and the
+//! blake2-128 hash of the encoded record. A record that is cut short or does not match its hash
+//! was being appended when the process crashed, so the change it describes never happened and
+//! it is ignored along with anything after it.
+
+use std::{io::{self, Write}, path::{Path, PathBuf}};
+use codec::{Decode, Encode};
+use sp_core::{crypto::KeyTypeId, hashing::blake2_128};
+use zeroize::Zeroize;
+
+use crate::{Result, dir::Dir};
+
+/// Name of the write-ahead log in the keystore directory.
+pub(crate) const WAL_FILE: &str = "keystore.wal";
+
+const LEN_SIZE: usize = 4;
+const HASH_SIZE: usize = 16;
+
+/// A change to the key files.
+#[derive(PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) enum WalRecord {
+	/// The key file of the key is written with the secret URI.
+	Insert { key_type: KeyTypeId, public: Vec<u8>, suri: String },
+	/// The key file of the key is removed.
+	Remove { key_type: KeyTypeId, public: Vec<u8> },
+}
+
+impl Drop for WalRecord {
+	fn drop(&mut self) {
+		if let WalRecord::Insert { suri, .. } = self {
+			suri.zeroize();
+		}
+	}
+}
+
+/// The write-ahead log of a keystore directory.
+pub(crate) struct Wal {
+	path: PathBuf,
+}
+
+impl Wal {
+	/// The write-ahead log in the given keystore directory.
+	pub fn new(dir: &Path) -> Self {
+		Self { path: dir.join(WAL_FILE) }
+	}
+
+	/// Append the records and sync them to disk.
+	pub fn append(&self, dir: Dir, records: &[WalRecord]) -> Result<()> {
+		let mut content = Vec::new();
+		for record in records {
+			let encoded = record.encode();
+			content.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+			content.extend_from_slice(&encoded);
+			content.extend_from_slice(&blake2_128(&encoded));
+		}
+
+		let mut file = dir.open_append(&self.path)?;
+		file.write_all(&content)?;
+		file.sync_data()?;
+		content.zeroize();
+		Ok(())
+	}
+
+	/// Returns the complete records in the log.
+	pub fn read(&self, dir: Dir) -> Result<Vec<WalRecord>> {
+		let mut content = match dir.read(&self.path) {
+			Ok(content) => content,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e) => return Err(e.into()),
+		};
+
+		let mut records = Vec::new();
+		let mut rest = &content[..];
+		while rest.len() >= LEN_SIZE {
+			let mut len = [0u8; LEN_SIZE];
+			len.copy_from_slice(&rest[..LEN_SIZE]);
+			let len = u32::from_le_bytes(len) as usize;
+			let body = &rest[LEN_SIZE..];
+			if body.len() < len + HASH_SIZE {
+				break;
+			}
+			let (encoded, hash) = (&body[..len], &body[len..len + HASH_SIZE]);
+			if blake2_128(encoded) != hash {
+				break;
+			}
+			match WalRecord::decode(&mut &encoded[..]) {
+				Ok(record) => records.push(record),
+				Err(_) => break,
+			}
+			rest = &body[len + HASH_SIZE..];
+		}
+		content.zeroize();
+		Ok(records)
+	}
+
+	/// Empty the log, after all records were applied.
+	pub fn checkpoint(&self, dir: Dir) -> Result<()> {
+		match dir.open_write(&self.path) {
+			Ok(file) => {
+				file.set_len(0)?;
+				file.sync_data()?;
+				Ok(())
+			},
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e.into()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs::OpenOptions;
+	use tempfile::TempDir;
+
+	#[test]
+	fn torn_records_are_ignored() {
+		let temp_dir = TempDir::new().unwrap();
+		let wal = Wal::new(temp_dir.path());
+		assert!(wal.read(Dir::new(temp_dir.path())).unwrap().is_empty());
+
+		let insert = || WalRecord::Insert {
+			key_type: KeyTypeId(*b"test"),
+			public: vec![1; 32],
+			suri: "//Alice".into(),
+		};
+		let remove = || WalRecord::Remove { key_type: KeyTypeId(*b"test"), public: vec![2; 32] };
+		wal.append(Dir::new(temp_dir.path()), &[insert(), remove()]).unwrap();
+		// A record whose append was interrupted.
+		let mut file = OpenOptions::new().append(true).open(temp_dir.path().join(WAL_FILE)).unwrap();
+		file.write_all(&[100, 0, 0, 0, 1, 2, 3]).unwrap();
+
+		assert_eq!(wal.read(Dir::new(temp_dir.path())).unwrap(), vec![insert(), remove()]);
+		wal.checkpoint(Dir::new(temp_dir.path())).unwrap();
+		assert!(wal.read(Dir::new(temp_dir.path())).unwrap().is_empty());
+	}
+}
@@ -0,0 +1,116 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Key types of the common consensus engines.
+//!
+//! Passing a [`ConsensusKeyType`] instead of a raw [`KeyTypeId`] rules out mixing up the ids
+//! of different engines.
+
+use std::convert::TryFrom;
+use sp_core::crypto::{CryptoTypeId, KeyTypeId};
+use sp_application_crypto::{ed25519, sr25519};
+
+pub use sp_core::crypto::key_types::{AURA, AUTHORITY_DISCOVERY, BABE, GRANDPA, IM_ONLINE};
+
+/// Key type of the ed25519 key the node identifies itself with on the network, `nodi`.
+///
+/// Reserved for [`LocalKeystore::node_identity`](crate::LocalKeystore::node_identity), which
+/// expects a single key of this type.
+pub const NODE_IDENTITY: KeyTypeId = KeyTypeId(*b"nodi");
+
+/// The key type of a consensus engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConsensusKeyType {
+	/// BABE block production, `babe`.
+	Babe,
+	/// GRANDPA finality, `gran`.
+	Grandpa,
+	/// Aura block production, `aura`.
+	Aura,
+	/// ImOnline heartbeats, `imon`.
+	ImOnline,
+	/// Authority discovery, `audi`.
+	AuthorityDiscovery,
+}
+
+impl ConsensusKeyType {
+	/// All consensus key types.
+	pub const ALL: [ConsensusKeyType; 5] = [
+		ConsensusKeyType::Babe,
+		ConsensusKeyType::Grandpa,
+		ConsensusKeyType::Aura,
+		ConsensusKeyType::ImOnline,
+		ConsensusKeyType::AuthorityDiscovery,
+	];
+
+	/// The id of the key type.
+	pub fn key_type(self) -> KeyTypeId {
+		match self {
+			ConsensusKeyType::Babe => BABE,
+			ConsensusKeyType::Grandpa => GRANDPA,
+			ConsensusKeyType::Aura => AURA,
+			ConsensusKeyType::ImOnline => IM_ONLINE,
+			ConsensusKeyType::AuthorityDiscovery => AUTHORITY_DISCOVERY,
+		}
+	}
+
+	/// The crypto type the engine uses for its keys.
+	pub fn crypto(self) -> CryptoTypeId {
+		match self {
+			ConsensusKeyType::Grandpa => ed25519::CRYPTO_ID,
+			_ => sr25519::CRYPTO_ID,
+		}
+	}
+}
+
+impl From<ConsensusKeyType> for KeyTypeId {
+	fn from(key_type: ConsensusKeyType) -> Self {
+		key_type.key_type()
+	}
+}
+
+impl TryFrom<KeyTypeId> for ConsensusKeyType {
+	type Error = ();
+
+	fn try_from(id: KeyTypeId) -> Result<Self, ()> {
+		Self::ALL.iter().copied().find(|key_type| key_type.key_type() == id).ok_or(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::crypto::Public;
+	use crate::LocalKeystore;
+
+	#[test]
+	fn consensus_key_types_convert() {
+		for key_type in &ConsensusKeyType::ALL {
+			assert_eq!(ConsensusKeyType::try_from(KeyTypeId::from(*key_type)), Ok(*key_type));
+		}
+		assert_eq!(KeyTypeId::from(ConsensusKeyType::Grandpa), KeyTypeId(*b"gran"));
+		assert_eq!(ConsensusKeyType::try_from(KeyTypeId(*b"acco")), Err(()));
+
+		let store = LocalKeystore::in_memory();
+		let babe = store.generate_babe().unwrap();
+		let grandpa = store.generate_consensus(ConsensusKeyType::Grandpa).unwrap();
+		assert_eq!(store.babe_keys(), vec![babe]);
+		assert_eq!(grandpa.0, ed25519::CRYPTO_ID);
+		assert_eq!(store.grandpa_keys(), vec![ed25519::Public::from_slice(&grandpa.1)]);
+	}
+}
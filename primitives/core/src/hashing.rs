@@ -49,6 +49,26 @@ pub fn blake2_256(data: &[u8]) -> [u8; 32] {
 	r
 }
 
+/// Do a Blake2 256-bit hash of everything the reader returns and return result.
+///
+/// The data is hashed as it is read, so it is never held in memory as a whole.
+#[cfg(feature = "std")]
+pub fn blake2_256_reader<R: std::io::Read + ?Sized>(reader: &mut R) -> std::io::Result<[u8; 32]> {
+	let mut hasher = blake2_rfc::blake2b::Blake2b::new(32);
+	let mut buffer = vec![0u8; 64 * 1024];
+	loop {
+		match reader.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(read) => hasher.update(&buffer[..read]),
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+	let mut r = [0; 32];
+	r.copy_from_slice(hasher.finalize().as_bytes());
+	Ok(r)
+}
+
 /// Do a Blake2 128-bit hash and place result in `dest`.
 pub fn blake2_128_into(data: &[u8], dest: &mut [u8; 16]) {
 	dest.copy_from_slice(blake2_rfc::blake2b::blake2b(16, &[], data).as_bytes());
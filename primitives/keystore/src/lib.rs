@@ -19,13 +19,21 @@
 pub mod testing;
 pub mod vrf;
 
-use std::sync::Arc;
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	convert::TryFrom,
+	io,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 use async_trait::async_trait;
+use codec::{Decode, Encode};
 use futures::{executor::block_on, future::join_all};
 use sp_core::{
-	crypto::{KeyTypeId, CryptoTypePublicPair},
-	ed25519, sr25519, ecdsa,
+	crypto::{KeyTypeId, CryptoTypeId, CryptoTypePublicPair, Pair, Public, Ss58Codec},
+	ed25519, sr25519, ecdsa, hashing::{blake2_256, blake2_256_reader}, hexdisplay::HexDisplay,
 };
+use zeroize::Zeroize;
 use crate::vrf::{VRFTranscriptData, VRFSignature};
 
 /// CryptoStore error
@@ -40,12 +48,35 @@ pub enum Error {
 	/// Keystore unavailable
 	#[display(fmt="Keystore unavailable")]
 	Unavailable,
+	/// The requested key pair does not exist
+	#[display(fmt="Key pair not found")]
+	PairNotFound,
+	/// The operation is not permitted
+	#[display(fmt="Permission denied")]
+	PermissionDenied,
+	/// The sr25519 key exists, but the keystore can not produce a VRF signature with it
+	#[display(fmt="VRF signing is not available for this key")]
+	VrfNotAvailable,
+	/// Several keys have the given public key prefix
+	#[display(fmt="Public key prefix matches several keys")]
+	AmbiguousPrefix,
+	/// The crypto type is not known
+	#[display(fmt="Unknown crypto type: {:?}", _0)]
+	UnknownCryptoType(CryptoTypeId),
+	/// The keystore can not be modified
+	#[display(fmt="Keystore is read-only")]
+	ReadOnly,
 	/// Programming errors
 	#[display(fmt="An unknown keystore error occurred: {}", _0)]
 	Other(String)
 }
 
 /// Something that generates, stores and provides access to keys.
+///
+/// The methods of every crypto scheme are part of the trait regardless of how the crate is
+/// built, so the trait stays object safe and downstream code compiles the same way with any
+/// set of features. A scheme that is optional is gated in the implementations only: without
+/// its feature, its methods return [`Error::KeyNotSupported`] (or no keys).
 #[async_trait]
 pub trait CryptoStore: Send + Sync {
 	/// Returns all sr25519 public keys for the given key type.
@@ -98,6 +129,37 @@ pub trait CryptoStore: Send + Sync {
 		public: &[u8]
 	) -> Result<(), ()>;
 
+	/// Remove a key.
+	///
+	/// Removes the private key that matches the given public key and key type, so it can not
+	/// be used to sign anymore.
+	///
+	/// Returns [`Error::PairNotFound`] if the key doesn't exist.
+	async fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), Error>;
+
+	/// Remove several keys.
+	///
+	/// Removes the key with each of the given key types and raw public keys, like
+	/// [`CryptoStore::remove_key`] does, e.g. to prune the keys found by
+	/// [`CryptoStore::keys_not_in`]. Returns the result of each removal in the order of `items`;
+	/// a failed removal, like [`Error::PairNotFound`] for a missing key, does not stop the others.
+	async fn remove_keys(&self, items: &[(KeyTypeId, Vec<u8>)]) -> Vec<Result<(), Error>> {
+		let mut results = Vec::with_capacity(items.len());
+		for (id, public) in items {
+			let key = match self.keys(*id).await {
+				Ok(keys) => keys.into_iter()
+					.find(|key| key.1 == *public)
+					.ok_or(Error::PairNotFound),
+				Err(e) => Err(e),
+			};
+			results.push(match key {
+				Ok(key) => self.remove_key(*id, &key).await,
+				Err(e) => Err(e),
+			});
+		}
+		results
+	}
+
 	/// Find intersection between provided keys and supported keys
 	///
 	/// Provided a list of (CryptoTypeId,[u8]) pairs, this would return
@@ -107,16 +169,287 @@ pub trait CryptoStore: Send + Sync {
 		id: KeyTypeId,
 		keys: Vec<CryptoTypePublicPair>
 	) -> Result<Vec<CryptoTypePublicPair>, Error>;
+
+	/// Find which of the provided keys are supported, key by key
+	///
+	/// Unlike [`CryptoStore::supported_keys`], a failed lookup does not fail the whole query:
+	/// the keys that could not be looked up, e.g. because a remote backend is partially
+	/// unavailable, are reported as undetermined, so callers do not mistake an error for "no
+	/// keys". The default implementation asks for the keys one by one if asking for all of
+	/// them fails.
+	async fn supported_keys_detailed(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<SupportedKeysReport, Error> {
+		let mut report = SupportedKeysReport::default();
+		match self.supported_keys(id, keys.clone()).await {
+			Ok(supported) => report.add(keys, &supported),
+			Err(_) => for key in keys {
+				match self.supported_keys(id, vec![key.clone()]).await {
+					Ok(supported) => report.add(vec![key], &supported),
+					Err(e) => report.undetermined.push((key, e)),
+				}
+			},
+		}
+		Ok(report)
+	}
+
+	/// Find the authority keys this keystore can sign with
+	///
+	/// Provided a list of candidate authority keys, this returns the ones the keystore holds
+	/// a usable private key for. Unlike [`CryptoStore::supported_keys`], implementations
+	/// should confirm that the secret can actually be loaded, not only that it is present.
+	async fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, Error> {
+		self.supported_keys(id, candidates.to_vec()).await
+	}
+
+	/// Find the keys of the key type that are not in the authority set
+	///
+	/// The complement of [`CryptoStore::supported_keys`]: returns the stored keys, see
+	/// [`CryptoStore::keys`], whose public key is not among `authorities`. Meant for reviewing
+	/// keys that no longer belong to any authority before removing them with
+	/// [`CryptoStore::remove_key`]. Public keys are compared whatever their crypto type, since a
+	/// keystore may list a key under several crypto types.
+	async fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, Error> {
+		Ok(not_in(self.keys(id).await?, authorities))
+	}
+
+	/// Find the sr25519 keys this keystore can produce VRF signatures with
+	///
+	/// Returns the keys of [`CryptoStore::sr25519_public_keys`] that
+	/// [`CryptoStore::sr25519_vrf_sign`] does not fail with [`Error::VrfNotAvailable`] for, so
+	/// block authoring candidates can be filtered up front. Keystores that can produce VRF
+	/// signatures with all of their sr25519 keys keep the default.
+	async fn vrf_capable_keys(&self, id: KeyTypeId) -> Result<Vec<sr25519::Public>, Error> {
+		Ok(self.sr25519_public_keys(id).await)
+	}
+
+	/// Find the smallest set of keys reaching the threshold of an authority set
+	///
+	/// Returns `threshold` distinct keys of `authorities` that this keystore can sign with, see
+	/// [`CryptoStore::authority_keys`], in the order of `authorities`. Returns `None` if the
+	/// keystore holds fewer than `threshold` of them.
+	async fn quorum_keys(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+		threshold: usize,
+	) -> Result<Option<Vec<CryptoTypePublicPair>>, Error> {
+		let owned = self.authority_keys(id, authorities).await?;
+		Ok(quorum_of(authorities, &owned, threshold))
+	}
+
+	/// Pick one of the candidate keys, using the seed as entropy
+	///
+	/// Only the candidates this keystore can sign with are eligible, see
+	/// [`CryptoStore::authority_keys`]. The same seed picks the same key out of the same keys,
+	/// while different seeds, e.g. the hashes of successive blocks, spread the picks evenly
+	/// over them. Returns `None` if no candidate is held or the keys can not be looked up.
+	async fn pick_key(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+		seed: &[u8],
+	) -> Option<CryptoTypePublicPair> {
+		pick_of(self.authority_keys(id, candidates).await.ok()?, seed)
+	}
+
 	/// List all supported keys
 	///
 	/// Returns a set of public keys the signer supports.
 	async fn keys(&self, id: KeyTypeId) -> Result<Vec<CryptoTypePublicPair>, Error>;
 
+	/// List the keys grouped by crypto type
+	///
+	/// The public keys of [`CryptoStore::keys`], keyed by their crypto type. Crypto types without
+	/// keys are left out.
+	async fn keys_by_type(
+		&self,
+		id: KeyTypeId,
+	) -> Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, Error> {
+		Ok(group_by_type(self.keys(id).await?))
+	}
+
+	/// Session keys manifest
+	///
+	/// Returns a JSON object mapping the name of every given key type to the hex encoded public
+	/// key of its key, as submitted on-chain to register the session keys. Every key type must
+	/// have exactly one key; otherwise an [`Error::ValidationError`] names the first key type
+	/// that does not.
+	async fn session_keys_manifest(&self, types: &[KeyTypeId]) -> Result<String, Error> {
+		let mut manifest = BTreeMap::new();
+		for id in types {
+			let (name, public) = manifest_entry(*id, self.keys(*id).await?)?;
+			manifest.insert(name, public);
+		}
+		let entries = manifest.iter()
+			.map(|(name, public)| format!("{}:{}", json_string(name), json_string(public)))
+			.collect::<Vec<_>>();
+		Ok(format!("{{{}}}", entries.join(",")))
+	}
+
+	/// Ensure the session keys exist
+	///
+	/// For every key type and crypto type of `spec`, uses the single existing key or generates
+	/// one if there is none, and returns the public keys in the order of `spec`. Running it
+	/// again generates nothing. If an entry fails, e.g. because there are several keys or the
+	/// crypto type is not supported, the keys generated so far are removed again.
+	async fn ensure_session_keys(
+		&self,
+		spec: &[(KeyTypeId, CryptoTypeId)],
+	) -> Result<Vec<(KeyTypeId, Vec<u8>)>, Error> {
+		let mut keys = Vec::with_capacity(spec.len());
+		let mut generated = Vec::new();
+		let result = async {
+			for &(id, scheme) in spec {
+				let listed: Vec<Vec<u8>> = match scheme {
+					sr25519::CRYPTO_ID =>
+						self.sr25519_public_keys(id).await.iter().map(|k| k.to_raw_vec()).collect(),
+					ed25519::CRYPTO_ID =>
+						self.ed25519_public_keys(id).await.iter().map(|k| k.to_raw_vec()).collect(),
+					ecdsa::CRYPTO_ID =>
+						self.ecdsa_public_keys(id).await.iter().map(|k| k.to_raw_vec()).collect(),
+					_ => return Err(Error::UnknownCryptoType(scheme)),
+				};
+				// Keystores may list keys of other crypto types, only keep those that sign with
+				// this one.
+				let listed = listed.into_iter()
+					.map(|public| CryptoTypePublicPair(scheme, public))
+					.collect::<Vec<_>>();
+				let existing = self.authority_keys(id, &listed).await?;
+				let public = match existing.as_slice() {
+					[public] => public.1.clone(),
+					[] => {
+						let public = match scheme {
+							sr25519::CRYPTO_ID =>
+								self.sr25519_generate_new(id, None).await?.to_raw_vec(),
+							ed25519::CRYPTO_ID =>
+								self.ed25519_generate_new(id, None).await?.to_raw_vec(),
+							_ => self.ecdsa_generate_new(id, None).await?.to_raw_vec(),
+						};
+						generated.push((id, CryptoTypePublicPair(scheme, public.clone())));
+						public
+					},
+					_ => return Err(several_session_keys(id, scheme)),
+				};
+				keys.push((id, public));
+			}
+			Ok(())
+		}.await;
+
+		if let Err(e) = result {
+			for (id, key) in generated.iter().rev() {
+				let _ = self.remove_key(*id, key).await;
+			}
+			return Err(e);
+		}
+		Ok(keys)
+	}
+
 	/// Checks if the private keys for the given public key and key type combinations exist.
 	///
 	/// Returns `true` iff all private keys could be found.
 	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool;
 
+	/// Checks if the private key for the given public key and key type exists.
+	///
+	/// The preferred check for a single key in hot paths like block authoring: keystores can
+	/// look the key up directly, without the query list of [`CryptoStore::has_keys`].
+	async fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		self.has_keys(&[(public.to_vec(), id)]).await
+	}
+
+	/// Find the authorities this keystore holds the private keys of
+	///
+	/// Checks every candidate like [`CryptoStore::has_keys`] does and returns the found ones,
+	/// in the order of `candidates`.
+	async fn owned_authorities(
+		&self,
+		candidates: &[(Vec<u8>, KeyTypeId)],
+	) -> Result<Vec<(Vec<u8>, KeyTypeId)>, Error> {
+		let mut owned = Vec::new();
+		for candidate in candidates {
+			if self.has_keys(std::slice::from_ref(candidate)).await {
+				owned.push(candidate.clone());
+			}
+		}
+		Ok(owned)
+	}
+
+	/// Check which of the keys this keystore holds the private keys of
+	///
+	/// Returns whether each query is present like [`CryptoStore::has_keys`] checks it, in the
+	/// order of `queries`.
+	async fn which_keys_present(&self, queries: &[(Vec<u8>, KeyTypeId)]) -> Vec<bool> {
+		let mut present = Vec::with_capacity(queries.len());
+		for query in queries {
+			present.push(self.has_keys(std::slice::from_ref(query)).await);
+		}
+		present
+	}
+
+	/// Check which of the keys this keystore holds the private keys of, along with the keys
+	///
+	/// Like [`CryptoStore::which_keys_present`], but every result is paired with its query, in
+	/// the order of `public_keys`, duplicates included.
+	async fn has_keys_detailed(
+		&self,
+		public_keys: &[(Vec<u8>, KeyTypeId)],
+	) -> Vec<((Vec<u8>, KeyTypeId), bool)> {
+		let present = self.which_keys_present(public_keys).await;
+		public_keys.iter().cloned().zip(present).collect()
+	}
+
+	/// Supported crypto schemes
+	///
+	/// Returns the crypto types the keystore can generate keys and sign with, so callers can
+	/// offer only those. Keystores that support every scheme of the trait keep the default.
+	async fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		vec![sr25519::CRYPTO_ID, ed25519::CRYPTO_ID, ecdsa::CRYPTO_ID]
+	}
+
+	/// List the key types
+	///
+	/// Returns every key type the keystore holds keys of. Only keystores that can list all
+	/// their keys support it; the others return [`Error::ValidationError`].
+	async fn key_types(&self) -> Result<Vec<KeyTypeId>, Error> {
+		Err(Error::ValidationError("Listing all keys is not supported".into()))
+	}
+
+	/// Fingerprint of all keys
+	///
+	/// Returns a hash of the key types and public keys of all keys in the keystore, see
+	/// [`keys_fingerprint`]. Keystores holding the same keys have the same fingerprint. The
+	/// default lists the keys of every type of [`Self::key_types`].
+	async fn fingerprint(&self) -> Result<[u8; 32], Error> {
+		let mut keys = Vec::new();
+		for id in self.key_types().await? {
+			keys.extend(self.keys(id).await?.into_iter().map(|key| (id, key.1)));
+		}
+		Ok(keys_fingerprint(keys))
+	}
+
+	/// Preload keys
+	///
+	/// Prepares the given keys for signing, so the first signature with them is not delayed by
+	/// reading and deriving the key. Keystores that have nothing to prepare do nothing.
+	///
+	/// Every key is attempted; failures are collected and returned together as one
+	/// [`Error::ValidationError`] once all keys were tried.
+	async fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> Result<(), Error> {
+		let _ = keys;
+		Ok(())
+	}
+
 	/// Sign with key
 	///
 	/// Signs a message with the private key that matches
@@ -124,6 +457,10 @@ pub trait CryptoStore: Send + Sync {
 	///
 	/// Returns the SCALE encoded signature if key is found and supported, `None` if the key doesn't
 	/// exist or an error when something failed.
+	///
+	/// The message may be empty. ed25519, sr25519 and ecdsa (which signs the blake2 hash of the
+	/// message) sign it like any other message; a crypto type that can not sign an empty message
+	/// returns [`Error::ValidationError`].
 	async fn sign_with(
 		&self,
 		id: KeyTypeId,
@@ -157,6 +494,72 @@ pub trait CryptoStore: Send + Sync {
 		Ok(None)
 	}
 
+	/// Sign with the key that has the given prefix
+	///
+	/// Finds the unique key of the given key type whose raw public key starts with `prefix` and
+	/// signs the message with it, for callers that refer to keys by a truncated public key.
+	///
+	/// Returns the full raw public key and the SCALE encoded signature. Fails with
+	/// [`Error::PairNotFound`] if no key has the prefix and with [`Error::AmbiguousPrefix`] if
+	/// several keys have it.
+	async fn sign_with_prefix(
+		&self,
+		id: KeyTypeId,
+		prefix: &[u8],
+		msg: &[u8],
+	) -> Result<(Vec<u8>, Vec<u8>), Error> {
+		// Keystores may list a key under crypto types it does not sign with, only those it
+		// signs with are tried.
+		let keys = keys_with_prefix(self.keys(id).await?, prefix)?;
+		for key in self.authority_keys(id, &keys).await? {
+			if let Some(signature) = self.sign_with(id, &key, msg).await? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok((key.1, signature))
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign with the only key of the key type
+	///
+	/// Signs the message with the single key of the given key type, whatever its crypto type,
+	/// for callers that only need a verifiable signature of an identity. Returns the key with
+	/// its crypto type and the SCALE encoded signature. Fails with [`Error::PairNotFound`] if
+	/// there is no key and with [`Error::ValidationError`] if there are several.
+	async fn sign_any(
+		&self,
+		id: KeyTypeId,
+		msg: &[u8],
+	) -> Result<(CryptoTypePublicPair, Vec<u8>), Error> {
+		// Keystores may list a key under crypto types it does not sign with, only those it
+		// signs with are tried.
+		let keys = only_key(id, self.keys(id).await?)?;
+		for key in self.authority_keys(id, &keys).await? {
+			if let Some(signature) = self.sign_with(id, &key, msg).await? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok((key, signature))
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign a message with a one-time key.
+	///
+	/// Generates a fresh key of the given crypto type in memory, signs the message with it and
+	/// drops the secret right away. Nothing is stored, so the key can never be used again.
+	///
+	/// Returns the raw public key and the SCALE encoded signature, or
+	/// [`Error::ValidationError`] if the crypto type is not supported.
+	async fn sign_ephemeral(
+		&self,
+		scheme: CryptoTypeId,
+		msg: &[u8],
+	) -> Result<(Vec<u8>, Vec<u8>), Error> {
+		sign_with_one_time_key(scheme, msg)
+	}
+
 	/// Sign with all keys
 	///
 	/// Provided a list of public keys, sign a message with
@@ -176,6 +579,23 @@ pub trait CryptoStore: Send + Sync {
 		Ok(join_all(futs).await)
 	}
 
+	/// Sign with the held keys
+	///
+	/// Signs the message with every listed key the keystore holds, e.g. to contribute the
+	/// signatures of all signatories of a multi-signature account the node has keys for.
+	///
+	/// Returns the keys and their SCALE encoded signatures, in the order they are listed. Keys
+	/// the keystore doesn't hold are skipped, any other failure is returned.
+	async fn sign_multi(
+		&self,
+		id: KeyTypeId,
+		keys: &[CryptoTypePublicPair],
+		msg: &[u8],
+	) -> Result<Vec<(CryptoTypePublicPair, Vec<u8>)>, Error> {
+		let signatures = join_all(keys.iter().map(|k| self.sign_with(id, k, msg))).await;
+		held_signatures(keys, signatures)
+	}
+
 	/// Generate VRF signature for given transcript data.
 	///
 	/// Receives KeyTypeId and Public key to be able to map
@@ -187,7 +607,9 @@ pub trait CryptoStore: Send + Sync {
 	/// inside the `VRFSignature` container struct.
 	///
 	/// This function will return `None` if the given `key_type` and `public` combination
-	/// doesn't exist in the keystore or an `Err` when something failed.
+	/// doesn't exist in the keystore or an `Err` when something failed. If the key exists,
+	/// but the keystore can not produce a VRF signature with it, [`Error::VrfNotAvailable`]
+	/// is returned.
 	async fn sr25519_vrf_sign(
 		&self,
 		key_type: KeyTypeId,
@@ -204,15 +626,214 @@ pub trait CryptoStore: Send + Sync {
 	/// The `msg` argument provided should be a hashed message for which an
 	/// ECDSA signature should be generated. 
 	/// 
-	/// Returns an [`ecdsa::Signature`] or `None` in case the given `id` and
-	/// `public` combination doesn't exist in the keystore. An `Err` will be
+	/// Returns the recoverable [`ecdsa::Signature`], [`Error::PairNotFound`] in case the given
+	/// `id` and `public` combination doesn't exist in the keystore and
+	/// [`Error::KeyNotSupported`] if the stored key is not an ecdsa key. Other errors are
 	/// returned if generating the signature itself failed.
 	async fn ecdsa_sign_prehashed(
 		&self,
 		id: KeyTypeId,
 		public: &ecdsa::Public,
 		msg: &[u8; 32],
-	) -> Result<Option<ecdsa::Signature>, Error>;
+	) -> Result<ecdsa::Signature, Error>;
+
+	/// Sign a message with an ecdsa key, encoding the signature in the given format.
+	///
+	/// Signs like [`Self::sign_with`] does for ecdsa keys, see [`EcdsaSigFormat`] for the
+	/// encodings. Returns [`Error::PairNotFound`] if the key doesn't exist.
+	async fn ecdsa_sign_as(
+		&self,
+		id: KeyTypeId,
+		key: &ecdsa::Public,
+		msg: &[u8],
+		format: EcdsaSigFormat,
+	) -> Result<Vec<u8>, Error> {
+		let signature = self.sign_with(id, &key.clone().into(), msg).await?;
+		encode_ecdsa_signature(signature, format)
+	}
+
+	/// Sign a message with scheme specific options
+	///
+	/// Signs like [`Self::sign_with`], changed by the options, see [`SignOptions`]. Options that
+	/// do not apply to the crypto type of the key, or that the keystore does not implement, fail
+	/// with [`Error::KeyNotSupported`]. Returns [`Error::PairNotFound`] if the key doesn't exist.
+	async fn sign_with_opts(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+		opts: SignOptions,
+	) -> Result<Vec<u8>, Error> {
+		opts.check(id, key.0)?;
+		let signature = if opts.prehashed {
+			let (public, hash) = prehashed_ecdsa(key, msg)?;
+			Some(self.ecdsa_sign_prehashed(id, &public, &hash).await?.encode())
+		} else {
+			self.sign_with(id, key, msg).await?
+		};
+		opts.encode(signature)
+	}
+
+	/// Sign with the key of an SS58 address
+	///
+	/// The address may use any network prefix. Signs like [`CryptoStore::sign_with`] with the
+	/// key of the key type whose public key the address encodes. Returns
+	/// [`Error::ValidationError`] if the address can not be parsed and [`Error::PairNotFound`]
+	/// if the keystore has no such key.
+	async fn sign_with_ss58(
+		&self,
+		id: KeyTypeId,
+		address: &str,
+		msg: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		// The address does not tell sr25519 and ed25519 keys apart, try the crypto types the
+		// keystore can sign with.
+		let candidates = ss58_keys(address)?;
+		for key in self.authority_keys(id, &candidates).await? {
+			if let Some(signature) = self.sign_with(id, &key, msg).await? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok(signature)
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign many messages with one key
+	///
+	/// Signs every message like [`CryptoStore::sign_with`] and returns the SCALE encoded
+	/// signatures in the order of the messages. Returns [`Error::PairNotFound`] before signing
+	/// anything if the keystore has no such key. Keystores that load the key from storage
+	/// should override this to load it only once.
+	async fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> Result<Vec<Vec<u8>>, Error> {
+		if !self.has_keys(&[(key.1.clone(), id)]).await {
+			return Err(Error::PairNotFound);
+		}
+		let mut signatures = Vec::with_capacity(msgs.len());
+		for msg in msgs {
+			signatures.push(self.sign_with(id, key, msg).await?.ok_or(Error::PairNotFound)?);
+		}
+		Ok(signatures)
+	}
+
+	/// Verify a signature
+	///
+	/// Checks the SCALE encoded signature, as returned by [`Self::sign_with`], of the message
+	/// with the public key, dispatching on its crypto type like `sign_with` does. No secret is
+	/// involved, so every keystore verifies the same way and `id` only names the key type the
+	/// key is used under.
+	///
+	/// Returns whether the signature is valid; a signature that can not be decoded is invalid.
+	/// Fails with [`Error::UnknownCryptoType`] if the crypto type is not supported.
+	async fn verify(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		msg: &[u8],
+		signature: &[u8],
+	) -> Result<bool, Error> {
+		let _ = id;
+		verify_signature(public, msg, signature)
+	}
+
+	/// Sign a content hash
+	///
+	/// Signs the 32 byte hash of some content, e.g. a build artifact, so signatures can be
+	/// stored and looked up by the hash. The keystore never sees the content itself. To keep
+	/// these signatures apart from the ones of [`Self::sign_with`], the signed message is the
+	/// hash prefixed with [`CONTENT_SIGNING_DOMAIN`]; use [`verify_content`] to verify them.
+	///
+	/// Returns the SCALE encoded signature or [`Error::PairNotFound`] if the key doesn't exist.
+	async fn sign_content(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		content_hash: [u8; 32],
+	) -> Result<Vec<u8>, Error> {
+		self.sign_with(id, key, &content_message(&content_hash)).await?.ok_or(Error::PairNotFound)
+	}
+
+	/// Prove possession of the secret key of a key
+	///
+	/// Signs the public key and the challenge of a key registry, prefixed with
+	/// [`PROOF_OF_POSSESSION_DOMAIN`], so the proof can not be replayed as an ordinary
+	/// signature; use [`verify_proof_of_possession`] to verify it. Registries ask for this to
+	/// rule out rogue key attacks.
+	///
+	/// Returns the SCALE encoded signature or [`Error::PairNotFound`] if the key doesn't exist.
+	async fn proof_of_possession(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		challenge: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		self.sign_with(id, public, &possession_message(public, challenge)).await?
+			.ok_or(Error::PairNotFound)
+	}
+
+	/// Sign a message read from a reader
+	///
+	/// The message is streamed through blake2-256, so it is never held in memory as a whole.
+	/// ecdsa signs the hash, which is the same signature [`Self::sign_with`] makes for the
+	/// whole message. ed25519 and sr25519 sign the full message, so they sign the hash like
+	/// [`Self::sign_content`] does instead, prefixed with [`CONTENT_SIGNING_DOMAIN`]; use
+	/// [`verify_content`] with the hash of the message to verify their signatures.
+	///
+	/// Returns the SCALE encoded signature or [`Error::PairNotFound`] if the key doesn't exist.
+	async fn sign_reader(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		reader: &mut (dyn io::Read + Send),
+	) -> Result<Vec<u8>, Error> {
+		let hash = blake2_256_reader(reader).map_err(|e| Error::Other(e.to_string()))?;
+		if key.0 != ecdsa::CRYPTO_ID {
+			return self.sign_content(id, key, hash).await;
+		}
+		let public = ecdsa::Public::try_from(&key.1[..])
+			.map_err(|_| Error::ValidationError("Invalid ecdsa public key".into()))?;
+		Ok(self.ecdsa_sign_prehashed(id, &public, &hash).await?.encode())
+	}
+
+	/// Measure how fast the keystore signs with the given key.
+	///
+	/// Signs a fixed message `iterations` times. One signature is made before measuring, so
+	/// loading the key is not part of the report. For a remote keystore the report includes the
+	/// round trips. Returns [`Error::PairNotFound`] if the key doesn't exist.
+	async fn benchmark_sign(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		iterations: usize,
+	) -> Result<BenchReport, Error> {
+		self.sign_with(id, key, BENCHMARK_MESSAGE).await?.ok_or(Error::PairNotFound)?;
+		let start = Instant::now();
+		for _ in 0..iterations {
+			self.sign_with(id, key, BENCHMARK_MESSAGE).await?;
+		}
+		Ok(BenchReport::new(iterations, start.elapsed()))
+	}
+
+	/// Attest the contents of the keystore
+	///
+	/// Signs the sorted list of the key types and public keys of all keys with the given key,
+	/// see [`Attestation`], so anyone can check which keys the keystore claimed to hold. Only
+	/// keystores that can list all their keys support it; the others return
+	/// [`Error::ValidationError`]. Returns [`Error::PairNotFound`] if the signing key doesn't
+	/// exist.
+	async fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> Result<Attestation, Error> {
+		let _ = (signing_id, signing_key);
+		Err(Error::ValidationError("Listing all keys is not supported".into()))
+	}
 }
 
 /// Sync version of the CryptoStore
@@ -223,6 +844,12 @@ pub trait CryptoStore: Send + Sync {
 /// will be removed as soon as the internal usage has transitioned successfully.
 /// If you are starting out building something new **do not use this**,
 /// instead, use [`CryptoStore`].
+///
+/// Every method of [`CryptoStore`] has a counterpart of the same name here, so callers that
+/// only have a `SyncCryptoStore` are not cut off from any operation. Where possible the
+/// counterpart defaults to running the async method with `block_on`, so the two can not drift
+/// apart; methods whose async default is built from other trait methods repeat it with the
+/// sync methods instead.
 pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 	/// Returns all sr25519 public keys for the given key type.
 	fn sr25519_public_keys(&self, id: KeyTypeId) -> Vec<sr25519::Public>;
@@ -274,6 +901,23 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 	/// `Err` if there's some sort of weird filesystem error, but should generally be `Ok`.
 	fn insert_unknown(&self, key_type: KeyTypeId, suri: &str, public: &[u8]) -> Result<(), ()>;
 
+	/// Remove a key.
+	///
+	/// Removes the private key that matches the given public key and key type, so it can not
+	/// be used to sign anymore.
+	///
+	/// Returns [`Error::PairNotFound`] if the key doesn't exist.
+	fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), Error> {
+		block_on(CryptoStore::remove_key(self, id, public))
+	}
+
+	/// Remove several keys.
+	///
+	/// See [`CryptoStore::remove_keys`].
+	fn remove_keys(&self, items: &[(KeyTypeId, Vec<u8>)]) -> Vec<Result<(), Error>> {
+		block_on(CryptoStore::remove_keys(self, items))
+	}
+
 	/// Find intersection between provided keys and supported keys
 	///
 	/// Provided a list of (CryptoTypeId,[u8]) pairs, this would return
@@ -284,6 +928,75 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 		keys: Vec<CryptoTypePublicPair>
 	) -> Result<Vec<CryptoTypePublicPair>, Error>;
 
+	/// Find which of the provided keys are supported, key by key
+	///
+	/// See [`CryptoStore::supported_keys_detailed`].
+	fn supported_keys_detailed(
+		&self,
+		id: KeyTypeId,
+		keys: Vec<CryptoTypePublicPair>,
+	) -> Result<SupportedKeysReport, Error> {
+		block_on(CryptoStore::supported_keys_detailed(self, id, keys))
+	}
+
+	/// Find the authority keys this keystore can sign with
+	///
+	/// Provided a list of candidate authority keys, this returns the ones the keystore holds
+	/// a usable private key for, see [`CryptoStore::authority_keys`].
+	fn authority_keys(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, Error> {
+		block_on(CryptoStore::authority_keys(self, id, candidates))
+	}
+
+	/// Find the keys of the key type that are not in the authority set
+	///
+	/// See [`CryptoStore::keys_not_in`].
+	fn keys_not_in(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+	) -> Result<Vec<CryptoTypePublicPair>, Error> {
+		Ok(not_in(SyncCryptoStore::keys(self, id)?, authorities))
+	}
+
+	/// Find the sr25519 keys this keystore can produce VRF signatures with
+	///
+	/// See [`CryptoStore::vrf_capable_keys`].
+	fn vrf_capable_keys(&self, id: KeyTypeId) -> Result<Vec<sr25519::Public>, Error> {
+		block_on(CryptoStore::vrf_capable_keys(self, id))
+	}
+
+	/// Find the smallest set of keys reaching the threshold of an authority set
+	///
+	/// See [`CryptoStore::quorum_keys`].
+	fn quorum_keys(
+		&self,
+		id: KeyTypeId,
+		authorities: &[CryptoTypePublicPair],
+		threshold: usize,
+	) -> Result<Option<Vec<CryptoTypePublicPair>>, Error> {
+		let owned = SyncCryptoStore::authority_keys(self, id, authorities)?;
+		Ok(quorum_of(authorities, &owned, threshold))
+	}
+
+	/// Pick one of the candidate keys, using the seed as entropy
+	///
+	/// Only the candidates this keystore can sign with are eligible, see
+	/// [`CryptoStore::authority_keys`]. The same seed picks the same key out of the same keys,
+	/// while different seeds, e.g. the hashes of successive blocks, spread the picks evenly
+	/// over them. Returns `None` if no candidate is held or the keys can not be looked up.
+	fn pick_key(
+		&self,
+		id: KeyTypeId,
+		candidates: &[CryptoTypePublicPair],
+		seed: &[u8],
+	) -> Option<CryptoTypePublicPair> {
+		pick_of(SyncCryptoStore::authority_keys(self, id, candidates).ok()?, seed)
+	}
+
 	/// List all supported keys
 	///
 	/// Returns a set of public keys the signer supports.
@@ -291,11 +1004,117 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 		block_on(CryptoStore::keys(self, id))
 	}
 
+	/// List the keys grouped by crypto type
+	///
+	/// The public keys of [`CryptoStore::keys`], keyed by their crypto type. Crypto types without
+	/// keys are left out.
+	fn keys_by_type(&self, id: KeyTypeId) -> Result<HashMap<CryptoTypeId, Vec<Vec<u8>>>, Error> {
+		block_on(CryptoStore::keys_by_type(self, id))
+	}
+
+	/// Session keys manifest
+	///
+	/// Returns a JSON object mapping the name of every given key type to the hex encoded public
+	/// key of its key, as submitted on-chain to register the session keys. Every key type must
+	/// have exactly one key; otherwise an [`Error::ValidationError`] names the first key type
+	/// that does not.
+	fn session_keys_manifest(&self, types: &[KeyTypeId]) -> Result<String, Error> {
+		block_on(CryptoStore::session_keys_manifest(self, types))
+	}
+
+	/// Ensure the session keys exist
+	///
+	/// For every key type and crypto type of `spec`, uses the single existing key or generates
+	/// one if there is none, and returns the public keys in the order of `spec`. Running it
+	/// again generates nothing. If an entry fails, e.g. because there are several keys or the
+	/// crypto type is not supported, the keys generated so far are removed again.
+	fn ensure_session_keys(
+		&self,
+		spec: &[(KeyTypeId, CryptoTypeId)],
+	) -> Result<Vec<(KeyTypeId, Vec<u8>)>, Error> {
+		block_on(CryptoStore::ensure_session_keys(self, spec))
+	}
+
 	/// Checks if the private keys for the given public key and key type combinations exist.
 	///
 	/// Returns `true` iff all private keys could be found.
 	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool;
 
+	/// Checks if the private key for the given public key and key type exists.
+	///
+	/// See [`CryptoStore::has_key_exact`].
+	fn has_key_exact(&self, id: KeyTypeId, public: &[u8]) -> bool {
+		SyncCryptoStore::has_keys(self, &[(public.to_vec(), id)])
+	}
+
+	/// Find the authorities this keystore holds the private keys of
+	///
+	/// See [`CryptoStore::owned_authorities`].
+	fn owned_authorities(
+		&self,
+		candidates: &[(Vec<u8>, KeyTypeId)],
+	) -> Result<Vec<(Vec<u8>, KeyTypeId)>, Error> {
+		Ok(candidates.iter()
+			.filter(|candidate| SyncCryptoStore::has_keys(self, std::slice::from_ref(*candidate)))
+			.cloned()
+			.collect())
+	}
+
+	/// Check which of the keys this keystore holds the private keys of
+	///
+	/// See [`CryptoStore::which_keys_present`].
+	fn which_keys_present(&self, queries: &[(Vec<u8>, KeyTypeId)]) -> Vec<bool> {
+		queries.iter()
+			.map(|query| SyncCryptoStore::has_keys(self, std::slice::from_ref(query)))
+			.collect()
+	}
+
+	/// Check which of the keys this keystore holds the private keys of, along with the keys
+	///
+	/// See [`CryptoStore::has_keys_detailed`].
+	fn has_keys_detailed(
+		&self,
+		public_keys: &[(Vec<u8>, KeyTypeId)],
+	) -> Vec<((Vec<u8>, KeyTypeId), bool)> {
+		let present = SyncCryptoStore::which_keys_present(self, public_keys);
+		public_keys.iter().cloned().zip(present).collect()
+	}
+
+	/// Supported crypto schemes
+	///
+	/// Returns the crypto types the keystore can generate keys and sign with, so callers can
+	/// offer only those. Keystores that support every scheme of the trait keep the default.
+	fn supported_schemes(&self) -> Vec<CryptoTypeId> {
+		block_on(CryptoStore::supported_schemes(self))
+	}
+
+	/// List the key types
+	///
+	/// Returns every key type the keystore holds keys of. Only keystores that can list all
+	/// their keys support it; the others return [`Error::ValidationError`].
+	fn key_types(&self) -> Result<Vec<KeyTypeId>, Error> {
+		block_on(CryptoStore::key_types(self))
+	}
+
+	/// Fingerprint of all keys
+	///
+	/// Returns a hash of the key types and public keys of all keys in the keystore, see
+	/// [`keys_fingerprint`]. Keystores holding the same keys have the same fingerprint.
+	fn fingerprint(&self) -> Result<[u8; 32], Error> {
+		block_on(CryptoStore::fingerprint(self))
+	}
+
+	/// Preload keys
+	///
+	/// Prepares the given keys for signing, so the first signature with them is not delayed by
+	/// reading and deriving the key. Keystores that have nothing to prepare do nothing.
+	///
+	/// Every key is attempted; failures are collected and returned together as one
+	/// [`Error::ValidationError`] once all keys were tried.
+	fn preload(&self, keys: &[(KeyTypeId, Vec<u8>)]) -> Result<(), Error> {
+		block_on(CryptoStore::preload(self, keys))
+	}
+
 	/// Sign with key
 	///
 	/// Signs a message with the private key that matches
@@ -303,6 +1122,10 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 	///
 	/// Returns the SCALE encoded signature if key is found and supported, `None` if the key doesn't
 	/// exist or an error when something failed.
+	///
+	/// The message may be empty. ed25519, sr25519 and ecdsa (which signs the blake2 hash of the
+	/// message) sign it like any other message; a crypto type that can not sign an empty message
+	/// returns [`Error::ValidationError`].
 	fn sign_with(
 		&self,
 		id: KeyTypeId,
@@ -338,6 +1161,64 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 		Ok(None)
 	}
 
+	/// Sign with the key that has the given prefix
+	///
+	/// Finds the unique key of the given key type whose raw public key starts with `prefix` and
+	/// signs the message with it, for callers that refer to keys by a truncated public key.
+	///
+	/// Returns the full raw public key and the SCALE encoded signature. Fails with
+	/// [`Error::PairNotFound`] if no key has the prefix and with [`Error::AmbiguousPrefix`] if
+	/// several keys have it.
+	fn sign_with_prefix(
+		&self,
+		id: KeyTypeId,
+		prefix: &[u8],
+		msg: &[u8],
+	) -> Result<(Vec<u8>, Vec<u8>), Error> {
+		let keys = keys_with_prefix(SyncCryptoStore::keys(self, id)?, prefix)?;
+		for key in SyncCryptoStore::authority_keys(self, id, &keys)? {
+			if let Some(signature) = SyncCryptoStore::sign_with(self, id, &key, msg)? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok((key.1, signature))
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign with the only key of the key type
+	///
+	/// Signs the message with the single key of the given key type, whatever its crypto type,
+	/// for callers that only need a verifiable signature of an identity. Returns the key with
+	/// its crypto type and the SCALE encoded signature. Fails with [`Error::PairNotFound`] if
+	/// there is no key and with [`Error::ValidationError`] if there are several.
+	fn sign_any(
+		&self,
+		id: KeyTypeId,
+		msg: &[u8],
+	) -> Result<(CryptoTypePublicPair, Vec<u8>), Error> {
+		let keys = only_key(id, SyncCryptoStore::keys(self, id)?)?;
+		for key in SyncCryptoStore::authority_keys(self, id, &keys)? {
+			if let Some(signature) = SyncCryptoStore::sign_with(self, id, &key, msg)? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok((key, signature))
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign a message with a one-time key.
+	///
+	/// Generates a fresh key of the given crypto type in memory, signs the message with it and
+	/// drops the secret right away. Nothing is stored, so the key can never be used again.
+	///
+	/// Returns the raw public key and the SCALE encoded signature, or
+	/// [`Error::ValidationError`] if the crypto type is not supported.
+	fn sign_ephemeral(&self, scheme: CryptoTypeId, msg: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+		sign_with_one_time_key(scheme, msg)
+	}
+
 	/// Sign with all keys
 	///
 	/// Provided a list of public keys, sign a message with
@@ -354,6 +1235,22 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 		Ok(keys.iter().map(|k| SyncCryptoStore::sign_with(self, id, k, msg)).collect())
 	}
 
+	/// Sign with the held keys
+	///
+	/// Signs the message with every listed key the keystore holds, see
+	/// [`CryptoStore::sign_multi`].
+	fn sign_multi(
+		&self,
+		id: KeyTypeId,
+		keys: &[CryptoTypePublicPair],
+		msg: &[u8],
+	) -> Result<Vec<(CryptoTypePublicPair, Vec<u8>)>, Error> {
+		let signatures = keys.iter()
+			.map(|k| SyncCryptoStore::sign_with(self, id, k, msg))
+			.collect();
+		held_signatures(keys, signatures)
+	}
+
 	/// Generate VRF signature for given transcript data.
 	///
 	/// Receives KeyTypeId and Public key to be able to map
@@ -365,7 +1262,9 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 	/// inside the `VRFSignature` container struct.
 	///
 	/// This function will return `None` if the given `key_type` and `public` combination
-	/// doesn't exist in the keystore or an `Err` when something failed.
+	/// doesn't exist in the keystore or an `Err` when something failed. If the key exists,
+	/// but the keystore can not produce a VRF signature with it, [`Error::VrfNotAvailable`]
+	/// is returned.
 	fn sr25519_vrf_sign(
 		&self,
 		key_type: KeyTypeId,
@@ -382,15 +1281,640 @@ pub trait SyncCryptoStore: CryptoStore + Send + Sync {
 	/// The `msg` argument provided should be a hashed message for which an
 	/// ECDSA signature should be generated. 
 	/// 
-	/// Returns an [`ecdsa::Signature`] or `None` in case the given `id` and
-	/// `public` combination doesn't exist in the keystore. An `Err` will be
-	/// returned if generating the signature itself failed.
+	/// See [`CryptoStore::ecdsa_sign_prehashed`].
 	fn ecdsa_sign_prehashed(
 		&self,
 		id: KeyTypeId,
 		public: &ecdsa::Public,
 		msg: &[u8; 32],
-	) -> Result<Option<ecdsa::Signature>, Error>;
+	) -> Result<ecdsa::Signature, Error>;
+
+	/// Sign a message with an ecdsa key, encoding the signature in the given format.
+	///
+	/// Signs like [`Self::sign_with`] does for ecdsa keys, see [`EcdsaSigFormat`] for the
+	/// encodings. Returns [`Error::PairNotFound`] if the key doesn't exist.
+	fn ecdsa_sign_as(
+		&self,
+		id: KeyTypeId,
+		key: &ecdsa::Public,
+		msg: &[u8],
+		format: EcdsaSigFormat,
+	) -> Result<Vec<u8>, Error> {
+		let signature = SyncCryptoStore::sign_with(self, id, &key.clone().into(), msg)?;
+		encode_ecdsa_signature(signature, format)
+	}
+
+	/// Sign a message with scheme specific options
+	///
+	/// See [`CryptoStore::sign_with_opts`].
+	fn sign_with_opts(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msg: &[u8],
+		opts: SignOptions,
+	) -> Result<Vec<u8>, Error> {
+		opts.check(id, key.0)?;
+		let signature = if opts.prehashed {
+			let (public, hash) = prehashed_ecdsa(key, msg)?;
+			Some(SyncCryptoStore::ecdsa_sign_prehashed(self, id, &public, &hash)?.encode())
+		} else {
+			SyncCryptoStore::sign_with(self, id, key, msg)?
+		};
+		opts.encode(signature)
+	}
+
+	/// Sign with the key of an SS58 address
+	///
+	/// See [`CryptoStore::sign_with_ss58`].
+	fn sign_with_ss58(&self, id: KeyTypeId, address: &str, msg: &[u8]) -> Result<Vec<u8>, Error> {
+		let candidates = ss58_keys(address)?;
+		for key in SyncCryptoStore::authority_keys(self, id, &candidates)? {
+			if let Some(signature) = SyncCryptoStore::sign_with(self, id, &key, msg)? {
+				if verify_signature(&key, msg, &signature)? {
+					return Ok(signature)
+				}
+			}
+		}
+		Err(Error::PairNotFound)
+	}
+
+	/// Sign many messages with one key
+	///
+	/// See [`CryptoStore::sign_with_many`].
+	fn sign_with_many(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		msgs: &[&[u8]],
+	) -> Result<Vec<Vec<u8>>, Error> {
+		if !SyncCryptoStore::has_keys(self, &[(key.1.clone(), id)]) {
+			return Err(Error::PairNotFound);
+		}
+		msgs.iter()
+			.map(|msg| SyncCryptoStore::sign_with(self, id, key, msg)?.ok_or(Error::PairNotFound))
+			.collect()
+	}
+
+	/// Verify a signature
+	///
+	/// Checks the SCALE encoded signature of the message with the public key, see
+	/// [`CryptoStore::verify`].
+	fn verify(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		msg: &[u8],
+		signature: &[u8],
+	) -> Result<bool, Error> {
+		block_on(CryptoStore::verify(self, id, public, msg, signature))
+	}
+
+	/// Sign a content hash
+	///
+	/// Signs the hash prefixed with [`CONTENT_SIGNING_DOMAIN`], see
+	/// [`CryptoStore::sign_content`]. Returns [`Error::PairNotFound`] if the key doesn't exist.
+	fn sign_content(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		content_hash: [u8; 32],
+	) -> Result<Vec<u8>, Error> {
+		SyncCryptoStore::sign_with(self, id, key, &content_message(&content_hash))?
+			.ok_or(Error::PairNotFound)
+	}
+
+	/// Prove possession of the secret key of a key
+	///
+	/// See [`CryptoStore::proof_of_possession`].
+	fn proof_of_possession(
+		&self,
+		id: KeyTypeId,
+		public: &CryptoTypePublicPair,
+		challenge: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		SyncCryptoStore::sign_with(self, id, public, &possession_message(public, challenge))?
+			.ok_or(Error::PairNotFound)
+	}
+
+	/// Sign a message read from a reader
+	///
+	/// See [`CryptoStore::sign_reader`].
+	fn sign_reader(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		reader: &mut (dyn io::Read + Send),
+	) -> Result<Vec<u8>, Error> {
+		block_on(CryptoStore::sign_reader(self, id, key, reader))
+	}
+
+	/// Measure how fast the keystore signs with the given key.
+	///
+	/// Signs a fixed message `iterations` times. One signature is made before measuring, so
+	/// loading the key is not part of the report. Returns [`Error::PairNotFound`] if the key
+	/// doesn't exist.
+	fn benchmark_sign(
+		&self,
+		id: KeyTypeId,
+		key: &CryptoTypePublicPair,
+		iterations: usize,
+	) -> Result<BenchReport, Error> {
+		SyncCryptoStore::sign_with(self, id, key, BENCHMARK_MESSAGE)?.ok_or(Error::PairNotFound)?;
+		let start = Instant::now();
+		for _ in 0..iterations {
+			SyncCryptoStore::sign_with(self, id, key, BENCHMARK_MESSAGE)?;
+		}
+		Ok(BenchReport::new(iterations, start.elapsed()))
+	}
+
+	/// Attest the contents of the keystore
+	///
+	/// Signs the sorted list of the key types and public keys of all keys with the given key,
+	/// see [`Attestation`], so anyone can check which keys the keystore claimed to hold. Only
+	/// keystores that can list all their keys support it; the others return
+	/// [`Error::ValidationError`]. Returns [`Error::PairNotFound`] if the signing key doesn't
+	/// exist.
+	fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> Result<Attestation, Error> {
+		block_on(CryptoStore::attest_contents(self, signing_id, signing_key))
+	}
+}
+
+/// The keys found by `supported_keys_detailed`.
+#[derive(Debug, Default)]
+pub struct SupportedKeysReport {
+	/// The keys the keystore holds.
+	pub supported: Vec<CryptoTypePublicPair>,
+	/// The keys the keystore confirmed it does not hold.
+	pub unsupported: Vec<CryptoTypePublicPair>,
+	/// The keys that could not be looked up, with the error of the lookup.
+	pub undetermined: Vec<(CryptoTypePublicPair, Error)>,
+}
+
+impl SupportedKeysReport {
+	/// Sort the queried keys by whether they are among the supported keys.
+	fn add(&mut self, keys: Vec<CryptoTypePublicPair>, supported: &[CryptoTypePublicPair]) {
+		for key in keys {
+			if supported.contains(&key) {
+				self.supported.push(key);
+			} else {
+				self.unsupported.push(key);
+			}
+		}
+	}
+}
+
+/// The message signed by `benchmark_sign`.
+const BENCHMARK_MESSAGE: &[u8] = b"keystore signing benchmark";
+
+/// Signing throughput measured by `benchmark_sign`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+	/// The number of measured signatures.
+	pub iterations: usize,
+	/// The time all measured signatures took.
+	pub total: Duration,
+	/// The average time of one signature.
+	pub per_signature: Duration,
+	/// Signatures per second.
+	pub ops_per_sec: f64,
+}
+
+impl BenchReport {
+	fn new(iterations: usize, total: Duration) -> Self {
+		let secs = total.as_secs_f64();
+		Self {
+			iterations,
+			total,
+			per_signature: if iterations == 0 {
+				Duration::default()
+			} else {
+				Duration::from_secs_f64(secs / iterations as f64)
+			},
+			ops_per_sec: if secs > 0.0 { iterations as f64 / secs } else { 0.0 },
+		}
+	}
+}
+
+fn not_in(
+	keys: Vec<CryptoTypePublicPair>,
+	authorities: &[CryptoTypePublicPair],
+) -> Vec<CryptoTypePublicPair> {
+	let authorities = authorities.iter().map(|key| &key.1).collect::<BTreeSet<_>>();
+	keys.into_iter().filter(|key| !authorities.contains(&key.1)).collect()
+}
+
+/// The keys an SS58 address with any network prefix may belong to.
+///
+/// Addresses of sr25519 and ed25519 keys encode 32 bytes, those of ecdsa keys 33.
+fn ss58_keys(address: &str) -> Result<Vec<CryptoTypePublicPair>, Error> {
+	if let Ok((public, _)) = sr25519::Public::from_ss58check_with_version(address) {
+		let public = public.to_vec();
+		return Ok(vec![
+			CryptoTypePublicPair(sr25519::CRYPTO_ID, public.clone()),
+			CryptoTypePublicPair(ed25519::CRYPTO_ID, public),
+		]);
+	}
+	ecdsa::Public::from_ss58check_with_version(address)
+		.map(|(public, _)| vec![CryptoTypePublicPair(ecdsa::CRYPTO_ID, public.0.to_vec())])
+		.map_err(|e| Error::ValidationError(format!("invalid SS58 address {}: {:?}", address, e)))
+}
+
+fn group_by_type(keys: Vec<CryptoTypePublicPair>) -> HashMap<CryptoTypeId, Vec<Vec<u8>>> {
+	let mut grouped = HashMap::<_, Vec<_>>::new();
+	for CryptoTypePublicPair(crypto, public) in keys {
+		grouped.entry(crypto).or_default().push(public);
+	}
+	grouped
+}
+
+/// Prefix of the messages signed by `attest_contents`.
+///
+/// The signed message is this prefix followed by the [`keys_fingerprint`] of the attested keys.
+pub const ATTESTATION_DOMAIN: &[u8] = b"substrate-keystore-attestation:";
+
+/// A signed statement of the keys a keystore holds, made by `attest_contents`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Attestation {
+	/// The key types and public keys of all keys, sorted.
+	pub keys: Vec<(KeyTypeId, Vec<u8>)>,
+	/// The key that signed the attestation.
+	pub signer: CryptoTypePublicPair,
+	/// The SCALE encoded signature of [`Attestation::message`].
+	pub signature: Vec<u8>,
+}
+
+impl Attestation {
+	/// Attest the keys with a signature made by `sign`, which gets the message to sign.
+	///
+	/// For implementations of `attest_contents`. `sign` returning `None` is reported as
+	/// [`Error::PairNotFound`].
+	pub fn sign<I, F>(keys: I, signer: CryptoTypePublicPair, sign: F) -> Result<Self, Error>
+	where
+		I: IntoIterator<Item = (KeyTypeId, Vec<u8>)>,
+		F: FnOnce(&[u8]) -> Result<Option<Vec<u8>>, Error>,
+	{
+		let keys = keys.into_iter().collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+		let signature = sign(&attestation_message(&keys))?.ok_or(Error::PairNotFound)?;
+		Ok(Self { keys, signer, signature })
+	}
+
+	/// The signed message.
+	pub fn message(&self) -> Vec<u8> {
+		attestation_message(&self.keys)
+	}
+
+	/// Check the signature of the attestation.
+	///
+	/// Returns `false` if the signature is invalid or the crypto type of the signer is not
+	/// supported.
+	pub fn verify(&self) -> bool {
+		verify_signature(&self.signer, &self.message(), &self.signature).unwrap_or(false)
+	}
+}
+
+fn attestation_message(keys: &[(KeyTypeId, Vec<u8>)]) -> Vec<u8> {
+	let mut msg = ATTESTATION_DOMAIN.to_vec();
+	msg.extend_from_slice(&keys_fingerprint(keys.iter().cloned()));
+	msg
+}
+
+/// Encodings of ecdsa signatures.
+///
+/// `r` and `s` are the 32 byte big endian scalars of the signature and `v` is the recovery id,
+/// `0` or `1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcdsaSigFormat {
+	/// `r || s`, 64 bytes.
+	Compact64,
+	/// The ASN.1 DER encoding `SEQUENCE { INTEGER r, INTEGER s }`, at most 72 bytes. Both
+	/// integers are minimal, leading zero bytes are stripped and a zero byte is prepended if
+	/// the highest bit is set.
+	DerEncoded,
+	/// `r || s || v`, 65 bytes, the encoding of [`ecdsa::Signature`].
+	Recoverable65,
+}
+
+/// Scheme specific options of `sign_with_opts`.
+///
+/// The default options sign like `sign_with`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SignOptions {
+	/// The signing context of sr25519 signatures, instead of the `substrate` context.
+	///
+	/// Only for keystores that implement it, which none of the keystores of this crate do.
+	pub context: Option<Vec<u8>>,
+	/// The message is the 32 byte hash to sign as is, only for ecdsa, see
+	/// `ecdsa_sign_prehashed`.
+	pub prehashed: bool,
+	/// The encoding of ecdsa signatures, instead of SCALE.
+	pub ecdsa_format: Option<EcdsaSigFormat>,
+}
+
+impl SignOptions {
+	/// Fail with [`Error::KeyNotSupported`] unless the default implementation of
+	/// `sign_with_opts` can sign with the options and the crypto type.
+	fn check(&self, id: KeyTypeId, crypto: CryptoTypeId) -> Result<(), Error> {
+		let ecdsa_only = self.prehashed || self.ecdsa_format.is_some();
+		if self.context.is_some() || (ecdsa_only && crypto != ecdsa::CRYPTO_ID) {
+			return Err(Error::KeyNotSupported(id))
+		}
+		Ok(())
+	}
+
+	/// Encode the SCALE encoded signature as the options ask for.
+	fn encode(&self, signature: Option<Vec<u8>>) -> Result<Vec<u8>, Error> {
+		match self.ecdsa_format {
+			Some(format) => encode_ecdsa_signature(signature, format),
+			None => signature.ok_or(Error::PairNotFound),
+		}
+	}
+}
+
+/// The ecdsa key and the 32 byte hash of a prehashed signature.
+fn prehashed_ecdsa(
+	key: &CryptoTypePublicPair,
+	msg: &[u8],
+) -> Result<(ecdsa::Public, [u8; 32]), Error> {
+	let public = ecdsa::Public::try_from(&key.1[..])
+		.map_err(|_| Error::ValidationError("Invalid ecdsa public key".into()))?;
+	let hash = <[u8; 32]>::try_from(msg)
+		.map_err(|_| Error::ValidationError("A prehashed message must be 32 bytes".into()))?;
+	Ok((public, hash))
+}
+
+/// Encode the SCALE encoded ecdsa signature returned by `sign_with` in the given format.
+fn encode_ecdsa_signature(
+	signature: Option<Vec<u8>>,
+	format: EcdsaSigFormat,
+) -> Result<Vec<u8>, Error> {
+	let signature = signature.ok_or(Error::PairNotFound)?;
+	let signature = ecdsa::Signature::decode(&mut &signature[..])
+		.map_err(|e| Error::Other(e.to_string()))?;
+	let bytes: &[u8] = signature.as_ref();
+
+	Ok(match format {
+		EcdsaSigFormat::Recoverable65 => bytes.to_vec(),
+		EcdsaSigFormat::Compact64 => bytes[..64].to_vec(),
+		EcdsaSigFormat::DerEncoded => {
+			let integer = |scalar: &[u8]| {
+				let start = scalar.iter().position(|b| *b != 0).unwrap_or(scalar.len() - 1);
+				let scalar = &scalar[start..];
+				let mut integer = vec![0x02];
+				if scalar[0] & 0x80 != 0 {
+					integer.push(scalar.len() as u8 + 1);
+					integer.push(0);
+				} else {
+					integer.push(scalar.len() as u8);
+				}
+				integer.extend_from_slice(scalar);
+				integer
+			};
+			let (r, s) = (integer(&bytes[..32]), integer(&bytes[32..64]));
+			let mut der = vec![0x30, (r.len() + s.len()) as u8];
+			der.extend(r);
+			der.extend(s);
+			der
+		},
+	})
+}
+
+/// Prefix of the messages signed by `sign_content`.
+///
+/// The signed message is this prefix followed by the 32 byte content hash, which is not hashed
+/// again. ecdsa signs the blake2 hash of the message, like it does in `sign_with`.
+pub const CONTENT_SIGNING_DOMAIN: &[u8] = b"substrate-keystore-content:";
+
+/// Prefix of the messages signed by `proof_of_possession`.
+///
+/// The signed message is this prefix followed by the crypto type, the raw public key and the
+/// challenge. ecdsa signs the blake2 hash of the message, like it does in `sign_with`.
+pub const PROOF_OF_POSSESSION_DOMAIN: &[u8] = b"substrate-keystore-pop:";
+
+fn possession_message(public: &CryptoTypePublicPair, challenge: &[u8]) -> Vec<u8> {
+	let mut msg = PROOF_OF_POSSESSION_DOMAIN.to_vec();
+	msg.extend_from_slice(&public.0 .0);
+	msg.extend_from_slice(&public.1);
+	msg.extend_from_slice(challenge);
+	msg
+}
+
+/// Verify a proof made by `proof_of_possession`.
+///
+/// Returns `false` if the proof is invalid, can not be decoded or the crypto type of the key
+/// is not supported.
+pub fn verify_proof_of_possession(
+	public: &CryptoTypePublicPair,
+	challenge: &[u8],
+	proof: &[u8],
+) -> bool {
+	verify_signature(public, &possession_message(public, challenge), proof).unwrap_or(false)
+}
+
+fn content_message(content_hash: &[u8; 32]) -> Vec<u8> {
+	let mut msg = CONTENT_SIGNING_DOMAIN.to_vec();
+	msg.extend_from_slice(content_hash);
+	msg
+}
+
+/// Verify a signature made by `sign_content`.
+///
+/// Takes the SCALE encoded signature. Returns `false` if the signature is invalid, can not be
+/// decoded or the crypto type of the key is not supported.
+pub fn verify_content(
+	key: &CryptoTypePublicPair,
+	content_hash: &[u8; 32],
+	signature: &[u8],
+) -> bool {
+	verify_signature(key, &content_message(content_hash), signature).unwrap_or(false)
+}
+
+/// Verify a SCALE encoded signature of the message, as made by `sign_with`.
+///
+/// Returns [`Error::UnknownCryptoType`] if the crypto type of the key is not supported.
+pub fn verify_signature(
+	key: &CryptoTypePublicPair,
+	msg: &[u8],
+	signature: &[u8],
+) -> Result<bool, Error> {
+	fn verify<P: Pair>(public: &[u8], msg: &[u8], signature: &[u8]) -> bool
+	where
+		P::Public: for<'a> TryFrom<&'a [u8]>,
+		P::Signature: Decode,
+	{
+		match (P::Public::try_from(public), P::Signature::decode(&mut &signature[..])) {
+			(Ok(public), Ok(signature)) => P::verify(&signature, msg, &public),
+			_ => false,
+		}
+	}
+
+	match key.0 {
+		ed25519::CRYPTO_ID => Ok(verify::<ed25519::Pair>(&key.1, msg, signature)),
+		sr25519::CRYPTO_ID => Ok(verify::<sr25519::Pair>(&key.1, msg, signature)),
+		ecdsa::CRYPTO_ID => Ok(verify::<ecdsa::Pair>(&key.1, msg, signature)),
+		scheme => Err(Error::UnknownCryptoType(scheme)),
+	}
+}
+
+/// The first `threshold` distinct authorities that are owned, if there are as many.
+fn quorum_of(
+	authorities: &[CryptoTypePublicPair],
+	owned: &[CryptoTypePublicPair],
+	threshold: usize,
+) -> Option<Vec<CryptoTypePublicPair>> {
+	let mut quorum = Vec::with_capacity(threshold);
+	for key in authorities.iter().filter(|key| owned.contains(key)) {
+		if quorum.len() == threshold {
+			break;
+		}
+		if !quorum.contains(key) {
+			quorum.push(key.clone());
+		}
+	}
+	if quorum.len() == threshold {
+		Some(quorum)
+	} else {
+		None
+	}
+}
+
+/// The name and hex encoded public key of the only key of the key type.
+///
+/// Keystores that do not know the crypto type of a key list it with every crypto type, so the
+/// keys are counted by their raw public key.
+fn manifest_entry(
+	id: KeyTypeId,
+	keys: Vec<CryptoTypePublicPair>,
+) -> Result<(String, String), Error> {
+	let name = std::str::from_utf8(&id.0)
+		.map_err(|_| Error::ValidationError(format!("Key type {:?} has no name", id)))?;
+	let mut publics = keys.into_iter().map(|key| key.1).collect::<BTreeSet<_>>().into_iter();
+	match (publics.next(), publics.next()) {
+		(Some(public), None) => Ok((name.into(), format!("0x{}", HexDisplay::from(&public)))),
+		(None, _) => Err(Error::ValidationError(format!("No key of type {}", name))),
+		(Some(_), Some(_)) => Err(Error::ValidationError(format!("Several keys of type {}", name))),
+	}
+}
+
+/// The JSON string literal of the value.
+fn json_string(value: &str) -> String {
+	let mut literal = String::with_capacity(value.len() + 2);
+	literal.push('"');
+	for c in value.chars() {
+		match c {
+			'"' | '\\' => {
+				literal.push('\\');
+				literal.push(c);
+			},
+			c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+			c => literal.push(c),
+		}
+	}
+	literal.push('"');
+	literal
+}
+
+/// The error for a session key type with several keys of the crypto type.
+fn several_session_keys(id: KeyTypeId, scheme: CryptoTypeId) -> Error {
+	Error::ValidationError(format!("Several keys of type {:?} and crypto type {:?}", id, scheme))
+}
+
+/// The key with the lowest hash of the seed and the key.
+///
+/// Every key is equally likely to have the lowest hash for a given seed, and the pick does not
+/// depend on the order of the keys.
+fn pick_of(keys: Vec<CryptoTypePublicPair>, seed: &[u8]) -> Option<CryptoTypePublicPair> {
+	keys.into_iter().min_by_key(|key| blake2_256(&(seed, key).encode()))
+}
+
+/// Compute the fingerprint of a set of keys.
+///
+/// The keys are sorted and deduplicated before they are hashed, so the fingerprint only depends
+/// on which keys are in the set and not on the order they are provided in.
+pub fn keys_fingerprint<I: IntoIterator<Item = (KeyTypeId, Vec<u8>)>>(keys: I) -> [u8; 32] {
+	let keys = keys.into_iter().collect::<BTreeSet<_>>();
+	blake2_256(&keys.into_iter().collect::<Vec<_>>().encode())
+}
+
+/// Returns the keys whose raw public key starts with the prefix.
+///
+/// The keys may be of several crypto types, but they all share a single raw public key. Keystores
+/// that do not know the crypto type of a key list it with every crypto type, the ones the key
+/// has the wrong length for are skipped.
+fn keys_with_prefix(
+	keys: Vec<CryptoTypePublicPair>,
+	prefix: &[u8],
+) -> Result<Vec<CryptoTypePublicPair>, Error> {
+	let has_valid_length = |key: &CryptoTypePublicPair| match key.0 {
+		sr25519::CRYPTO_ID => key.1.len() == std::mem::size_of::<sr25519::Public>(),
+		ed25519::CRYPTO_ID => key.1.len() == std::mem::size_of::<ed25519::Public>(),
+		ecdsa::CRYPTO_ID => key.1.len() == std::mem::size_of::<ecdsa::Public>(),
+		_ => true,
+	};
+	let keys = keys.into_iter()
+		.filter(|key| key.1.starts_with(prefix) && has_valid_length(key))
+		.collect::<Vec<_>>();
+	match keys.first() {
+		None => Err(Error::PairNotFound),
+		Some(first) if keys.iter().any(|key| key.1 != first.1) => Err(Error::AmbiguousPrefix),
+		Some(_) => Ok(keys),
+	}
+}
+
+/// Returns the crypto types of the only raw public key of the key type.
+///
+/// Keystores that do not know the crypto type of a key list it with every crypto type, so
+/// signatures made with them must be verified to find the actual one.
+fn only_key(
+	id: KeyTypeId,
+	keys: Vec<CryptoTypePublicPair>,
+) -> Result<Vec<CryptoTypePublicPair>, Error> {
+	keys_with_prefix(keys, &[]).map_err(|e| match e {
+		Error::AmbiguousPrefix => Error::ValidationError(format!("Several keys of type {:?}", id)),
+		e => e,
+	})
+}
+
+/// The results of signing with several keys.
+type Signatures = Vec<Result<Option<Vec<u8>>, Error>>;
+
+/// Pair the keys with their signatures, skipping the keys that were not found.
+fn held_signatures(
+	keys: &[CryptoTypePublicPair],
+	signatures: Signatures,
+) -> Result<Vec<(CryptoTypePublicPair, Vec<u8>)>, Error> {
+	let mut held = Vec::new();
+	for (key, signature) in keys.iter().zip(signatures) {
+		match signature {
+			Ok(Some(signature)) => held.push((key.clone(), signature)),
+			Ok(None) | Err(Error::PairNotFound) => (),
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(held)
+}
+
+/// Generate a key of the given crypto type, sign the message with it and drop the key.
+fn sign_with_one_time_key(scheme: CryptoTypeId, msg: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+	fn sign<P: Pair>(msg: &[u8]) -> (Vec<u8>, Vec<u8>)
+	where
+		P::Signature: Encode,
+	{
+		let (pair, mut seed) = P::generate();
+		seed.as_mut().zeroize();
+		(pair.public().to_raw_vec(), pair.sign(msg).encode())
+	}
+
+	match scheme {
+		ed25519::CRYPTO_ID => Ok(sign::<ed25519::Pair>(msg)),
+		sr25519::CRYPTO_ID => Ok(sign::<sr25519::Pair>(msg)),
+		ecdsa::CRYPTO_ID => Ok(sign::<ecdsa::Pair>(msg)),
+		_ => Err(Error::ValidationError(format!("Crypto type {:?} is not supported", scheme))),
+	}
 }
 
 /// A pointer to a keystore.
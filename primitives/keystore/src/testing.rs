@@ -24,7 +24,7 @@ use sp_core::{
 };
 
 use crate::{
-	{CryptoStore, SyncCryptoStorePtr, Error, SyncCryptoStore},
+	{CryptoStore, SyncCryptoStorePtr, Error, SyncCryptoStore, Attestation},
 	vrf::{VRFTranscriptData, VRFSignature, make_transcript},
 };
 use std::{collections::{HashMap, HashSet}, sync::Arc};
@@ -116,10 +116,26 @@ impl CryptoStore for KeyStore {
 		SyncCryptoStore::insert_unknown(self, id, suri, public)
 	}
 
+	async fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), Error> {
+		SyncCryptoStore::remove_key(self, id, public)
+	}
+
 	async fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
 		SyncCryptoStore::has_keys(self, public_keys)
 	}
 
+	async fn key_types(&self) -> Result<Vec<KeyTypeId>, Error> {
+		SyncCryptoStore::key_types(self)
+	}
+
+	async fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> Result<Attestation, Error> {
+		SyncCryptoStore::attest_contents(self, signing_id, signing_key)
+	}
+
 	async fn supported_keys(
 		&self,
 		id: KeyTypeId,
@@ -151,7 +167,7 @@ impl CryptoStore for KeyStore {
 		id: KeyTypeId,
 		public: &ecdsa::Public,
 		msg: &[u8; 32],
-	) -> Result<Option<ecdsa::Signature>, Error> {
+	) -> Result<ecdsa::Signature, Error> {
 		SyncCryptoStore::ecdsa_sign_prehashed(self, id, public, msg)
 	}
 }
@@ -270,10 +286,36 @@ impl SyncCryptoStore for KeyStore {
 		Ok(())
 	}
 
+	fn remove_key(&self, id: KeyTypeId, public: &CryptoTypePublicPair) -> Result<(), Error> {
+		self.keys.write()
+			.get_mut(&id)
+			.and_then(|keys| keys.remove(&public.1))
+			.map(|_| ())
+			.ok_or(Error::PairNotFound)
+	}
+
 	fn has_keys(&self, public_keys: &[(Vec<u8>, KeyTypeId)]) -> bool {
 		public_keys.iter().all(|(k, t)| self.keys.read().get(&t).and_then(|s| s.get(k)).is_some())
 	}
 
+	fn key_types(&self) -> Result<Vec<KeyTypeId>, Error> {
+		let keys = self.keys.read();
+		Ok(keys.iter().filter(|(_, keys)| !keys.is_empty()).map(|(id, _)| *id).collect())
+	}
+
+	fn attest_contents(
+		&self,
+		signing_id: KeyTypeId,
+		signing_key: &CryptoTypePublicPair,
+	) -> Result<Attestation, Error> {
+		let keys = self.keys.read().iter()
+			.flat_map(|(id, keys)| keys.keys().map(move |public| (*id, public.clone())))
+			.collect::<Vec<_>>();
+		Attestation::sign(keys, signing_key.clone(), |msg| {
+			SyncCryptoStore::sign_with(self, signing_id, signing_key, msg)
+		})
+	}
+
 	fn supported_keys(
 		&self,
 		id: KeyTypeId,
@@ -341,9 +383,9 @@ impl SyncCryptoStore for KeyStore {
 		id: KeyTypeId,
 		public: &ecdsa::Public,
 		msg: &[u8; 32],
-	) -> Result<Option<ecdsa::Signature>, Error> {
-		let pair = self.ecdsa_key_pair(id, public);
-		pair.map(|k| k.sign_prehashed(msg)).map(Ok).transpose()
+	) -> Result<ecdsa::Signature, Error> {
+		let pair = self.ecdsa_key_pair(id, public).ok_or(Error::PairNotFound)?;
+		Ok(pair.sign_prehashed(msg))
 	}
 }
 
@@ -363,7 +405,9 @@ impl Into<Arc<dyn CryptoStore>> for KeyStore {
 mod tests {
 	use super::*;
 	use sp_core::{sr25519, testing::{ED25519, SR25519, ECDSA}};
-	use crate::{SyncCryptoStore, vrf::VRFTranscriptValue};
+	use crate::{SyncCryptoStore, vrf::{VRFTranscriptValue, batch_verify_vrf}};
+	use codec::Decode;
+	use sp_core::crypto::CryptoTypeId;
 
 	#[test]
 	fn store_key_and_extract() {
@@ -447,14 +491,570 @@ mod tests {
 		let msg = sp_core::keccak_256(b"this should be a hashed message");
 		
 		// no key in key store
-		let res = SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &pair.public(), &msg).unwrap();
-		assert!(res.is_none());
+		let res = SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &pair.public(), &msg);
+		assert!(matches!(res, Err(Error::PairNotFound)));
 
 		// insert key, sign again
 		let res = SyncCryptoStore::insert_unknown(&store, ECDSA, suri, pair.public().as_ref()).unwrap();
 		assert_eq!((), res);
 
 		let res = SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &pair.public(), &msg).unwrap();
-		assert!(res.is_some());		
+		assert_eq!(res.recover_prehashed(&msg), Some(pair.public()));
+	}
+
+	#[test]
+	fn sign_ephemeral_uses_a_fresh_key() {
+		let store = KeyStore::new();
+		let msg = b"session";
+
+		let (public, signature) = SyncCryptoStore::sign_ephemeral(&store, ed25519::CRYPTO_ID, msg)
+			.unwrap();
+		let public = ed25519::Public::from_slice(&public);
+		let signature = ed25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ed25519::Pair::verify(&signature, msg, &public));
+
+		let (other, _) = SyncCryptoStore::sign_ephemeral(&store, ed25519::CRYPTO_ID, msg).unwrap();
+		assert_ne!(other, public.to_vec());
+		assert!(SyncCryptoStore::keys(&store, ED25519).unwrap().is_empty());
+
+		assert!(SyncCryptoStore::sign_ephemeral(&store, CryptoTypeId(*b"none"), msg).is_err());
+	}
+
+	#[test]
+	fn batch_verify_vrf_reports_each_signature() {
+		let store = KeyStore::new();
+		let alice = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Alice")).unwrap();
+		let bob = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Bob")).unwrap();
+		let transcript = |slot| VRFTranscriptData {
+			label: b"Test",
+			items: vec![("slot", VRFTranscriptValue::U64(slot))],
+		};
+		let sign = |public, slot| {
+			SyncCryptoStore::sr25519_vrf_sign(&store, SR25519, &public, transcript(slot))
+				.unwrap()
+				.unwrap()
+		};
+
+		let items = vec![
+			(alice, transcript(1), sign(alice, 1)),
+			// Signed by Alice, claimed by Bob.
+			(bob, transcript(2), sign(alice, 2)),
+			(bob, transcript(3), sign(bob, 3)),
+			// Signed for another transcript.
+			(alice, transcript(4), sign(alice, 5)),
+		];
+		assert_eq!(batch_verify_vrf(&items), vec![true, false, true, false]);
+		assert!(batch_verify_vrf(&[]).is_empty());
+	}
+
+	#[test]
+	fn ecdsa_sign_as_encodes_all_formats() {
+		use crate::EcdsaSigFormat;
+
+		let store = KeyStore::new();
+		let public = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let msg = b"bridge";
+		let sign = |format| SyncCryptoStore::ecdsa_sign_as(&store, ECDSA, &public, msg, format)
+			.unwrap();
+
+		let recoverable = sign(EcdsaSigFormat::Recoverable65);
+		assert_eq!(recoverable.len(), 65);
+		let signature = ecdsa::Signature::from_slice(&recoverable);
+		assert!(ecdsa::Pair::verify(&signature, msg, &public));
+
+		let compact = sign(EcdsaSigFormat::Compact64);
+		assert_eq!(&compact[..], &recoverable[..64]);
+
+		let der = sign(EcdsaSigFormat::DerEncoded);
+		let parsed = secp256k1::Signature::parse_der(&der).unwrap();
+		assert_eq!(&parsed.serialize()[..], &compact[..]);
+		assert_eq!(parsed.serialize_der().as_ref(), &der[..]);
+
+		assert!(matches!(
+			SyncCryptoStore::ecdsa_sign_as(
+				&store,
+				ECDSA,
+				&ecdsa::Public::default(),
+				msg,
+				EcdsaSigFormat::Compact64,
+			),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn benchmark_sign_reports_throughput() {
+		let store = KeyStore::new();
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let report = SyncCryptoStore::benchmark_sign(&store, ED25519, &public.into(), 10).unwrap();
+		assert_eq!(report.iterations, 10);
+		assert!(report.per_signature <= report.total);
+		assert!(report.ops_per_sec > 0.0);
+
+		let report = futures::executor::block_on(
+			CryptoStore::benchmark_sign(&store, ED25519, &public.into(), 0),
+		).unwrap();
+		assert_eq!(report.per_signature, std::time::Duration::default());
+
+		assert!(matches!(
+			SyncCryptoStore::benchmark_sign(&store, ED25519, &ed25519::Public::default().into(), 1),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn sign_with_prefix_needs_a_unique_key() {
+		let store = KeyStore::new();
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Alice")).unwrap();
+		let second = SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some("//Bob")).unwrap();
+		assert_ne!(first.0[0], second.0[0]);
+
+		let (public, signature) =
+			SyncCryptoStore::sign_with_prefix(&store, SR25519, &first.0[..1], b"msg").unwrap();
+		assert_eq!(public, first.to_raw_vec());
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, b"msg", &first));
+
+		assert!(matches!(
+			SyncCryptoStore::sign_with_prefix(&store, SR25519, &[], b"msg"),
+			Err(Error::AmbiguousPrefix),
+		));
+		assert!(matches!(
+			SyncCryptoStore::sign_with_prefix(&store, ED25519, &[], b"msg"),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn sign_content_is_domain_separated() {
+		let store = KeyStore::new();
+		let hash = [7u8; 32];
+		for &(id, ref key) in [
+			(SR25519, SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap().into()),
+			(ED25519, SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into()),
+			(ECDSA, SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap().into()),
+		].iter() {
+			let signature = SyncCryptoStore::sign_content(&store, id, key, hash).unwrap();
+			assert!(crate::verify_content(key, &hash, &signature));
+			assert!(!crate::verify_content(key, &[8; 32], &signature));
+
+			let plain = SyncCryptoStore::sign_with(&store, id, key, &hash).unwrap().unwrap();
+			assert!(!crate::verify_content(key, &hash, &plain));
+		}
+
+		let missing = ed25519::Public::default().into();
+		assert!(matches!(
+			SyncCryptoStore::sign_content(&store, ED25519, &missing, hash),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn proof_of_possession_is_bound_to_key_and_challenge() {
+		let store = KeyStore::new();
+		let alice = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into();
+		let bob = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into();
+
+		let proof = SyncCryptoStore::proof_of_possession(&store, ED25519, &alice, b"nonce")
+			.unwrap();
+		assert!(crate::verify_proof_of_possession(&alice, b"nonce", &proof));
+		assert!(!crate::verify_proof_of_possession(&alice, b"other", &proof));
+		assert!(!crate::verify_proof_of_possession(&bob, b"nonce", &proof));
+		let plain = SyncCryptoStore::sign_with(&store, ED25519, &alice, b"nonce").unwrap().unwrap();
+		assert!(!crate::verify_proof_of_possession(&alice, b"nonce", &plain));
+
+		let missing = ed25519::Public::default().into();
+		assert!(matches!(
+			SyncCryptoStore::proof_of_possession(&store, ED25519, &missing, b"nonce"),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn sign_reader_signs_the_hash_of_the_message() {
+		let store = KeyStore::new();
+		let msg = (0..200 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+		let hash = sp_core::hashing::blake2_256(&msg);
+
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap().into();
+		let signature = SyncCryptoStore::sign_reader(&store, ECDSA, &ecdsa, &mut &msg[..]).unwrap();
+		assert!(crate::verify_signature(&ecdsa, &msg, &signature).unwrap());
+
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap().into();
+		let signature = SyncCryptoStore::sign_reader(&store, SR25519, &sr25519, &mut &msg[..]);
+		assert!(crate::verify_content(&sr25519, &hash, &signature.unwrap()));
+
+		let missing = ed25519::Public::default().into();
+		assert!(matches!(
+			SyncCryptoStore::sign_reader(&store, ED25519, &missing, &mut &msg[..]),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn sign_multi_skips_missing_keys() {
+		let store = KeyStore::new();
+		let generate = |seed| SyncCryptoStore::sr25519_generate_new(&store, SR25519, Some(seed));
+		let (alice, bob) = (generate("//Alice").unwrap(), generate("//Bob").unwrap());
+		let missing = sr25519::Pair::from_string("//Charlie", None).unwrap().public();
+
+		let keys = vec![alice.into(), missing.into(), bob.into()];
+		let signatures = SyncCryptoStore::sign_multi(&store, SR25519, &keys, b"msg").unwrap();
+		assert_eq!(signatures.len(), 2);
+		for ((key, signature), public) in signatures.iter().zip(&[alice, bob]) {
+			assert_eq!(key, &CryptoTypePublicPair::from(*public));
+			let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+			assert!(sr25519::Pair::verify(&signature, b"msg", public));
+		}
+	}
+
+	#[test]
+	fn traits_are_object_safe() {
+		let store = Arc::new(KeyStore::new());
+		let async_store: Arc<dyn CryptoStore> = store.clone();
+		let sync_store: Arc<dyn SyncCryptoStore> = store;
+
+		let public = SyncCryptoStore::sr25519_generate_new(&*sync_store, SR25519, None).unwrap();
+		let candidates = [public.into()];
+		let keys = SyncCryptoStore::authority_keys(&*sync_store, SR25519, &candidates).unwrap();
+		assert_eq!(keys, candidates);
+		let keys = futures::executor::block_on(
+			CryptoStore::authority_keys(&*async_store, SR25519, &candidates),
+		).unwrap();
+		assert_eq!(keys, candidates);
+	}
+
+	#[test]
+	fn verify_dispatches_on_the_crypto_type() {
+		let store = KeyStore::new();
+		let public = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let key = CryptoTypePublicPair::from(public);
+		let signature = SyncCryptoStore::sign_with(&store, ECDSA, &key, b"msg").unwrap().unwrap();
+
+		assert!(SyncCryptoStore::verify(&store, ECDSA, &key, b"msg", &signature).unwrap());
+		assert!(!SyncCryptoStore::verify(&store, ECDSA, &key, b"other", &signature).unwrap());
+		assert!(!SyncCryptoStore::verify(&store, ECDSA, &key, b"msg", &signature[1..]).unwrap());
+
+		let unknown = CryptoTypePublicPair(CryptoTypeId(*b"test"), key.1);
+		assert!(matches!(
+			futures::executor::block_on(CryptoStore::verify(&store, ECDSA, &unknown, b"msg", &[])),
+			Err(Error::UnknownCryptoType(id)) if id == CryptoTypeId(*b"test"),
+		));
+	}
+
+	#[test]
+	fn owned_authorities_are_the_found_candidates() {
+		let store = KeyStore::new();
+		let owned = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let other = sr25519::Pair::generate().0.public();
+
+		let candidates = vec![
+			(other.to_raw_vec(), SR25519),
+			(owned.to_raw_vec(), ED25519),
+			(owned.to_raw_vec(), SR25519),
+		];
+		assert_eq!(
+			SyncCryptoStore::owned_authorities(&store, &candidates).unwrap(),
+			vec![(owned.to_raw_vec(), SR25519)],
+		);
+	}
+
+	#[test]
+	fn quorum_keys_reach_the_threshold() {
+		let store = KeyStore::new();
+		let generate = || -> CryptoTypePublicPair {
+			SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into()
+		};
+		let (first, second) = (generate(), generate());
+		let other = ed25519::Pair::generate().0.public().into();
+		let authorities = vec![other, first.clone(), first.clone(), second.clone()];
+
+		let quorum = |threshold| {
+			SyncCryptoStore::quorum_keys(&store, ED25519, &authorities, threshold)
+		};
+		assert_eq!(quorum(0).unwrap(), Some(vec![]));
+		assert_eq!(quorum(1).unwrap(), Some(vec![first.clone()]));
+		assert_eq!(quorum(2).unwrap(), Some(vec![first, second]));
+		assert_eq!(quorum(3).unwrap(), None);
+	}
+
+	#[test]
+	fn pick_key_is_seeded_and_spread_over_held_keys() {
+		let store = KeyStore::new();
+		let held = (0..4)
+			.map(|_| SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap().into())
+			.collect::<Vec<CryptoTypePublicPair>>();
+		let mut candidates = held.clone();
+		candidates.push(ed25519::Pair::generate().0.public().into());
+
+		let pick = |seed: u32| {
+			SyncCryptoStore::pick_key(&store, ED25519, &candidates, &seed.to_le_bytes()).unwrap()
+		};
+		assert_eq!(pick(7), pick(7));
+		let picked = (0..64).map(pick).collect::<HashSet<_>>();
+		assert_eq!(picked, held.into_iter().collect());
+		assert_eq!(SyncCryptoStore::pick_key(&store, ED25519, &candidates[4..], b"seed"), None);
+	}
+
+	#[test]
+	fn fingerprint_defaults_to_the_keys_of_all_key_types() {
+		let store = KeyStore::new();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let expected = crate::keys_fingerprint(vec![
+			(ED25519, ed25519.to_raw_vec()),
+			(SR25519, sr25519.to_raw_vec()),
+		]);
+		assert_eq!(SyncCryptoStore::fingerprint(&store).unwrap(), expected);
+		assert_ne!(SyncCryptoStore::fingerprint(&KeyStore::new()).unwrap(), expected);
+	}
+
+	#[test]
+	fn session_keys_manifest_needs_one_key_per_type() {
+		let store = KeyStore::new();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let manifest = SyncCryptoStore::session_keys_manifest(&store, &[SR25519, ED25519]).unwrap();
+		let hex = |public: &[u8]| sp_core::hexdisplay::HexDisplay::from(&public).to_string();
+		let expected = format!(r#"{{"ed25":"0x{}","sr25":"0x{}"}}"#, hex(&ed25519), hex(&sr25519));
+		assert_eq!(manifest, expected);
+		let quoted = KeyTypeId(*b"a\"\\\n");
+		let public = SyncCryptoStore::ed25519_generate_new(&store, quoted, None).unwrap();
+		assert_eq!(
+			SyncCryptoStore::session_keys_manifest(&store, &[quoted]).unwrap(),
+			format!(r#"{{"a\"\\\u000a":"0x{}"}}"#, hex(&public)),
+		);
+
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		assert!(matches!(
+			SyncCryptoStore::session_keys_manifest(&store, &[SR25519, ED25519]),
+			Err(Error::ValidationError(e)) if e == "Several keys of type ed25",
+		));
+		assert!(matches!(
+			SyncCryptoStore::session_keys_manifest(&store, &[KeyTypeId(*b"babe")]),
+			Err(Error::ValidationError(e)) if e == "No key of type babe",
+		));
+	}
+
+	#[test]
+	fn sign_any_uses_the_crypto_type_of_the_only_key() {
+		let store = KeyStore::new();
+		assert!(matches!(
+			SyncCryptoStore::sign_any(&store, ED25519, b"msg"),
+			Err(Error::PairNotFound),
+		));
+
+		let public = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let (key, signature) = SyncCryptoStore::sign_any(&store, ED25519, b"msg").unwrap();
+		assert_eq!(key, CryptoTypePublicPair::from(public));
+		let signature = ed25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ed25519::Pair::verify(&signature, b"msg", &public));
+
+		SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		assert!(matches!(
+			SyncCryptoStore::sign_any(&store, ED25519, b"msg"),
+			Err(Error::ValidationError(_)),
+		));
+	}
+
+	#[test]
+	fn ensure_session_keys_is_idempotent_and_rolls_back() {
+		let store = KeyStore::new();
+		let existing = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let spec = [(SR25519, sr25519::CRYPTO_ID), (ED25519, ed25519::CRYPTO_ID)];
+
+		let keys = SyncCryptoStore::ensure_session_keys(&store, &spec).unwrap();
+		assert_eq!(keys[1], (ED25519, existing.to_raw_vec()));
+		assert_eq!(SyncCryptoStore::sr25519_public_keys(&store, SR25519).len(), 1);
+		assert_eq!(SyncCryptoStore::ensure_session_keys(&store, &spec).unwrap(), keys);
+
+		let spec = [(ECDSA, ecdsa::CRYPTO_ID), (ECDSA, CryptoTypeId(*b"none"))];
+		assert!(matches!(
+			SyncCryptoStore::ensure_session_keys(&store, &spec),
+			Err(Error::UnknownCryptoType(_)),
+		));
+		assert!(SyncCryptoStore::ecdsa_public_keys(&store, ECDSA).is_empty());
+	}
+
+	#[test]
+	fn attestation_covers_all_keys_and_verifies() {
+		let store = KeyStore::new();
+		let signer = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let other = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let signer = CryptoTypePublicPair::from(signer);
+
+		let mut attestation = SyncCryptoStore::attest_contents(&store, ED25519, &signer).unwrap();
+		let mut expected = vec![(ED25519, signer.1.clone()), (SR25519, other.to_raw_vec())];
+		expected.sort();
+		assert_eq!(attestation.keys, expected);
+		assert!(attestation.verify());
+
+		attestation.keys.pop();
+		assert!(!attestation.verify());
+		assert!(matches!(
+			SyncCryptoStore::attest_contents(&store, SR25519, &signer),
+			Err(Error::PairNotFound),
+		));
+	}
+
+	#[test]
+	fn keys_by_type_groups_keys() {
+		let store = KeyStore::new();
+		assert!(SyncCryptoStore::keys_by_type(&store, SR25519).unwrap().is_empty());
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+
+		let keys = SyncCryptoStore::keys(&store, SR25519).unwrap();
+		let grouped = SyncCryptoStore::keys_by_type(&store, SR25519).unwrap();
+		assert_eq!(grouped[&sr25519::CRYPTO_ID], vec![public.to_raw_vec()]);
+		assert_eq!(grouped.values().map(Vec::len).sum::<usize>(), keys.len());
+		assert!(keys.iter().all(|key| grouped[&key.0].contains(&key.1)));
+	}
+
+	#[test]
+	fn supported_keys_detailed_sorts_the_queried_keys() {
+		let store = KeyStore::new();
+		let held = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let other = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+
+		let report = SyncCryptoStore::supported_keys_detailed(
+			&store,
+			SR25519,
+			vec![held.into(), other.into()],
+		).unwrap();
+		assert_eq!(report.supported, vec![held.into()]);
+		assert_eq!(report.unsupported, vec![other.into()]);
+		assert!(report.undetermined.is_empty());
+	}
+
+	#[test]
+	fn keys_not_in_returns_the_keys_outside_the_authority_set() {
+		let store = KeyStore::new();
+		let authority = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let orphan = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let absent = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+
+		let authorities = [authority.into(), absent.into()];
+		let orphans = SyncCryptoStore::keys_not_in(&store, SR25519, &authorities).unwrap();
+		assert!(orphans.contains(&orphan.into()));
+		assert!(!orphans.contains(&authority.into()));
+		let all = SyncCryptoStore::keys(&store, SR25519).unwrap();
+		let orphans = futures::executor::block_on(CryptoStore::keys_not_in(&store, SR25519, &all));
+		assert!(orphans.unwrap().is_empty());
+	}
+
+	#[test]
+	fn sign_with_opts_applies_the_scheme_options() {
+		use crate::{EcdsaSigFormat, SignOptions};
+
+		let store = KeyStore::new();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let ed25519 = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+		let sign = |id, key: CryptoTypePublicPair, msg: &[u8], opts| {
+			SyncCryptoStore::sign_with_opts(&store, id, &key, msg, opts)
+		};
+
+		assert_eq!(
+			sign(ED25519, ed25519.into(), b"msg", SignOptions::default()).unwrap(),
+			SyncCryptoStore::sign_with(&store, ED25519, &ed25519.into(), b"msg").unwrap().unwrap(),
+		);
+		let hash = sp_core::hashing::blake2_256(b"msg");
+		let opts = SignOptions {
+			prehashed: true,
+			ecdsa_format: Some(EcdsaSigFormat::Compact64),
+			..Default::default()
+		};
+		let compact = sign(ECDSA, ecdsa.clone().into(), &hash, opts.clone()).unwrap();
+		let prehashed =
+			SyncCryptoStore::ecdsa_sign_prehashed(&store, ECDSA, &ecdsa, &hash).unwrap();
+		assert_eq!(&compact[..], &prehashed.0[..64]);
+
+		assert!(matches!(
+			sign(ED25519, ed25519.into(), &hash, opts),
+			Err(Error::KeyNotSupported(id)) if id == ED25519,
+		));
+		let context = SignOptions { context: Some(b"other".to_vec()), ..Default::default() };
+		assert!(matches!(
+			sign(ECDSA, ecdsa.into(), b"msg", context),
+			Err(Error::KeyNotSupported(_)),
+		));
+	}
+
+	#[test]
+	fn has_key_exact_finds_a_single_key() {
+		let store = KeyStore::new();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+
+		assert!(SyncCryptoStore::has_key_exact(&store, SR25519, public.as_ref()));
+		assert!(!SyncCryptoStore::has_key_exact(&store, ED25519, public.as_ref()));
+		let unknown = CryptoStore::has_key_exact(&store, SR25519, &[0; 32]);
+		assert!(!futures::executor::block_on(unknown));
+	}
+
+	#[test]
+	fn remove_keys_reports_each_key() {
+		let store = KeyStore::new();
+		let first = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let second = SyncCryptoStore::ed25519_generate_new(&store, ED25519, None).unwrap();
+
+		let items = [(SR25519, first.to_vec()), (SR25519, vec![0; 32]), (ED25519, second.to_vec())];
+		let results = SyncCryptoStore::remove_keys(&store, &items);
+		assert!(results[0].is_ok());
+		assert!(matches!(results[1], Err(Error::PairNotFound)));
+		assert!(results[2].is_ok());
+		assert!(SyncCryptoStore::keys(&store, SR25519).unwrap().is_empty());
+		assert!(SyncCryptoStore::keys(&store, ED25519).unwrap().is_empty());
+	}
+
+	#[test]
+	fn sign_with_ss58_finds_the_key_of_the_address() {
+		use sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+
+		let store = KeyStore::new();
+		let sr25519 = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let ecdsa = SyncCryptoStore::ecdsa_generate_new(&store, ECDSA, None).unwrap();
+		let msg = b"ss58";
+
+		let address = sr25519.to_ss58check_with_version(Ss58AddressFormat::KusamaAccount);
+		let signature = SyncCryptoStore::sign_with_ss58(&store, SR25519, &address, msg).unwrap();
+		let signature = sr25519::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(sr25519::Pair::verify(&signature, msg, &sr25519));
+		let address = ecdsa.to_ss58check();
+		let signature = futures::executor::block_on(
+			CryptoStore::sign_with_ss58(&store, ECDSA, &address, msg),
+		).unwrap();
+		let signature = ecdsa::Signature::decode(&mut &signature[..]).unwrap();
+		assert!(ecdsa::Pair::verify(&signature, msg, &ecdsa));
+
+		let absent = sr25519::Pair::from_string("//Bob", None).unwrap().public().to_ss58check();
+		assert!(matches!(
+			SyncCryptoStore::sign_with_ss58(&store, SR25519, &absent, msg),
+			Err(Error::PairNotFound),
+		));
+		assert!(matches!(
+			SyncCryptoStore::sign_with_ss58(&store, SR25519, "not an address", msg),
+			Err(Error::ValidationError(_)),
+		));
+	}
+
+	#[test]
+	fn sign_with_many_needs_the_key_even_without_messages() {
+		let store = KeyStore::new();
+		let public = SyncCryptoStore::sr25519_generate_new(&store, SR25519, None).unwrap();
+		let absent = sr25519::Pair::from_string("//Bob", None).unwrap().public();
+
+		let (public, absent) = (public.into(), absent.into());
+
+		assert!(SyncCryptoStore::sign_with_many(&store, SR25519, &public, &[]).unwrap().is_empty());
+		assert!(matches!(
+			SyncCryptoStore::sign_with_many(&store, SR25519, &absent, &[]),
+			Err(Error::PairNotFound),
+		));
+		assert!(matches!(
+			futures::executor::block_on(CryptoStore::sign_with_many(&store, SR25519, &absent, &[])),
+			Err(Error::PairNotFound),
+		));
 	}
 }
@@ -17,13 +17,14 @@
 
 //! VRF-specifc data types and helpers
 
-use codec::Encode;
+use codec::{Decode, Encode};
 use merlin::Transcript;
-use schnorrkel::vrf::{VRFOutput, VRFProof};
+use schnorrkel::{PublicKey, vrf::{VRFOutput, VRFProof}};
+use sp_core::sr25519;
 
 /// An enum whose variants represent possible
 /// accepted values to construct the VRF transcript
-#[derive(Clone, Encode)]
+#[derive(Clone, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub enum VRFTranscriptValue {
 	/// Value is an array of bytes
@@ -65,6 +66,30 @@ pub fn make_transcript(data: VRFTranscriptData) -> Transcript {
 	transcript
 }
 
+/// Verify many VRF signatures.
+///
+/// Returns whether each signature is valid for its public key and transcript, in the order of
+/// `items`.
+///
+/// The signatures produced by keystores carry the short proof form, which schnorrkel's batch
+/// verification can not take: batching needs the larger batchable proofs, and recovering those
+/// is exactly the work of a single verification. Every signature is therefore checked on its
+/// own.
+pub fn batch_verify_vrf(
+	items: &[(sr25519::Public, VRFTranscriptData, VRFSignature)],
+) -> Vec<bool> {
+	items.iter()
+		.map(|(public, data, signature)| {
+			PublicKey::from_bytes(public.as_ref())
+				.and_then(|public| public.vrf_verify(
+					make_transcript(data.clone()),
+					&signature.output,
+					&signature.proof,
+				))
+				.is_ok()
+		})
+		.collect()
+}
 
 #[cfg(test)]
 mod tests {